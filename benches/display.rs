@@ -0,0 +1,40 @@
+//! Rendering benchmarks: `to_string()` per query vs a reused buffer.
+//!
+//! Run with `cargo bench --bench display`.
+
+use criterion::{Criterion, black_box, criterion_group, criterion_main};
+use rusty_promql_parser::expr;
+
+const QUERY: &str = r#"
+histogram_quantile(0.99,
+  sum by (le, job) (
+    rate(http_request_duration_seconds_bucket{job=~"api|web", code!~"5.."}[5m] offset 1h)
+  )
+)
+> on (job) group_left (team)
+  max_over_time(
+    (sum without (instance) (rate(http_requests_total{env="prod"}[1m])) / 1024)[30m:1m] @ 1609459200
+  )
+or
+  label_replace(vector(1), "job", "$1", "instance", "(.*):.*") * -3.5e3
+"#;
+
+fn bench_display(c: &mut Criterion) {
+    let (_, ast) = expr(QUERY).expect("benchmark query parses");
+
+    c.bench_function("to_string", |b| {
+        b.iter(|| black_box(&ast).to_string());
+    });
+
+    let mut buf = String::new();
+    c.bench_function("write_to_reused_buffer", |b| {
+        b.iter(|| {
+            buf.clear();
+            black_box(&ast).write_to(&mut buf);
+            black_box(buf.len());
+        });
+    });
+}
+
+criterion_group!(benches, bench_display);
+criterion_main!(benches);
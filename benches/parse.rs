@@ -0,0 +1,54 @@
+//! Parsing benchmarks over a corpus dominated by lone selectors, as found
+//! in dashboards and alerting rules.
+//!
+//! `parse_with_options` takes a fast path for such queries; `expr` always
+//! goes through the general expression parser. Run with
+//! `cargo bench --bench parse`.
+
+use criterion::{Criterion, black_box, criterion_group, criterion_main};
+use rusty_promql_parser::{ParseOptions, expr, parse_with_options};
+
+const SELECTOR: &str = r#"http_requests_total{job="api", code=~"5..", method!="OPTIONS"}"#;
+
+const CORPUS: &[&str] = &[
+    "up",
+    "node_load1",
+    "process_resident_memory_bytes",
+    r#"up{job="prometheus"}"#,
+    r#"kube_pod_status_ready{namespace="default", condition="true"}"#,
+    SELECTOR,
+    "instance:node_cpu_utilisation:rate5m",
+    r#"node_filesystem_avail_bytes{mountpoint="/", fstype!="tmpfs"}"#,
+    // Not eligible for the fast path
+    "sum by (job) (rate(http_requests_total[5m]))",
+    "rate(node_network_receive_bytes_total[1m]) > 1e6",
+];
+
+fn bench_parse(c: &mut Criterion) {
+    let options = ParseOptions::default();
+
+    c.bench_function("selector/parse_with_options", |b| {
+        b.iter(|| black_box(parse_with_options(black_box(SELECTOR), &options).unwrap()));
+    });
+    c.bench_function("selector/expr", |b| {
+        b.iter(|| black_box(expr(black_box(SELECTOR)).unwrap()));
+    });
+
+    c.bench_function("corpus/parse_with_options", |b| {
+        b.iter(|| {
+            for query in CORPUS {
+                black_box(parse_with_options(black_box(query), &options).unwrap());
+            }
+        });
+    });
+    c.bench_function("corpus/expr", |b| {
+        b.iter(|| {
+            for query in CORPUS {
+                black_box(expr(black_box(query)).unwrap());
+            }
+        });
+    });
+}
+
+criterion_group!(benches, bench_parse);
+criterion_main!(benches);
@@ -0,0 +1,22 @@
+//! Uses the parser API from a `no_std` crate. See the README.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::string::{String, ToString};
+
+use rusty_promql_parser::{ParseOptions, lint, parse_with_options, validate};
+
+/// Parse, validate and lint a query, returning its canonical form or the
+/// first problem found
+pub fn check(query: &str) -> Result<String, String> {
+    let expr = parse_with_options(query, &ParseOptions::default()).map_err(|e| e.to_string())?;
+    if let Err(errors) = validate(&expr) {
+        return Err(errors[0].to_string());
+    }
+    if let Some(lint) = lint(&expr).first() {
+        return Err(lint.to_string());
+    }
+    Ok(expr.to_string())
+}
@@ -24,17 +24,37 @@
 //! println!("{}", ast); // Prints: sum(rate(http_requests[5m]))
 //! ```
 
-use std::fmt;
-
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::ControlFlow;
+use core::str::FromStr;
+
+use crate::error::ParseError;
 use crate::lexer::duration::Duration;
-use crate::parser::aggregation::Grouping;
-use crate::parser::selector::{AtModifier, MatrixSelector, VectorSelector};
+use crate::lexer::string::DoubleQuoted;
+use crate::parser::aggregation::{Grouping, GroupingAction, LabelList};
+use crate::parser::function::{Function, ValueType, get_function};
+use crate::parser::selector::{
+    AtModifier, CardinalityClass, LabelMatchOp, LabelMatcher, MatrixSelector, VectorSelector,
+};
+
+/// Largest integer that an `f64` represents exactly (2^53)
+const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_992.0;
 
 /// Root expression type for PromQL AST
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Expr {
     /// Numeric literal: `42`, `3.14`, `0x1F`, `1e-10`, `Inf`, `NaN`
-    Number(f64),
+    ///
+    /// A leading sign is not part of the literal: `-42` and `-Inf` parse as
+    /// [`Expr::Unary`] around a positive number. The literal keeps its
+    /// spelling, so `0x1F` prints as written rather than as `31`.
+    Number(NumberLiteral),
 
     /// String literal: `"hello"`, `'world'`, `` `raw` ``
     String(String),
@@ -64,7 +84,78 @@ pub enum Expr {
     Subquery(Box<SubqueryExpr>),
 }
 
+/// The variant of an [`Expr`], without its contents
+///
+/// Useful as a key for counting or dispatching on node types; see
+/// [`Expr::kind`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ExprKind {
+    /// [`Expr::Number`]
+    Number,
+    /// [`Expr::String`]
+    String,
+    /// [`Expr::VectorSelector`]
+    VectorSelector,
+    /// [`Expr::MatrixSelector`]
+    MatrixSelector,
+    /// [`Expr::Call`]
+    Call,
+    /// [`Expr::Aggregation`]
+    Aggregation,
+    /// [`Expr::Binary`]
+    Binary,
+    /// [`Expr::Unary`]
+    Unary,
+    /// [`Expr::Paren`]
+    Paren,
+    /// [`Expr::Subquery`]
+    Subquery,
+}
+
+impl fmt::Display for ExprKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ExprKind::Number => "number literal",
+            ExprKind::String => "string literal",
+            ExprKind::VectorSelector => "vector selector",
+            ExprKind::MatrixSelector => "matrix selector",
+            ExprKind::Call => "function call",
+            ExprKind::Aggregation => "aggregation",
+            ExprKind::Binary => "binary expression",
+            ExprKind::Unary => "unary expression",
+            ExprKind::Paren => "parenthesized expression",
+            ExprKind::Subquery => "subquery",
+        };
+        write!(f, "{}", name)
+    }
+}
+
 impl Expr {
+    /// Get the kind of this expression node
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::{ExprKind, expr};
+    ///
+    /// let (_, ast) = expr("sum(up)").unwrap();
+    /// assert_eq!(ast.kind(), ExprKind::Aggregation);
+    /// ```
+    pub fn kind(&self) -> ExprKind {
+        match self {
+            Expr::Number(_) => ExprKind::Number,
+            Expr::String(_) => ExprKind::String,
+            Expr::VectorSelector(_) => ExprKind::VectorSelector,
+            Expr::MatrixSelector(_) => ExprKind::MatrixSelector,
+            Expr::Call(_) => ExprKind::Call,
+            Expr::Aggregation(_) => ExprKind::Aggregation,
+            Expr::Binary(_) => ExprKind::Binary,
+            Expr::Unary(_) => ExprKind::Unary,
+            Expr::Paren(_) => ExprKind::Paren,
+            Expr::Subquery(_) => ExprKind::Subquery,
+        }
+    }
+
     /// Check if this is a scalar expression (number literal)
     pub fn is_scalar(&self) -> bool {
         matches!(self, Expr::Number(_))
@@ -92,6 +183,82 @@ impl Expr {
         matches!(self, Expr::MatrixSelector(_) | Expr::Subquery(_))
     }
 
+    /// Infer the type of value this expression evaluates to.
+    ///
+    /// The type follows from the expression's shape alone:
+    ///
+    /// - Number and string literals are scalars and strings
+    /// - Vector selectors and aggregations are instant vectors
+    /// - Matrix selectors and subqueries are range vectors
+    /// - Function calls have the function's declared return type; unknown
+    ///   functions are assumed to return an instant vector
+    /// - Binary expressions are scalars when both operands are scalars, and
+    ///   instant vectors as soon as either operand is a vector. This holds for
+    ///   arithmetic, comparisons (with or without `bool`) and set operators
+    /// - Unary and parenthesized expressions have the type of their operand
+    ///
+    /// No validation is done: operands of invalid types, such as a string in
+    /// `"a" + 1` or the scalar in `1[5m:]`, still yield a type. The
+    /// [`validate()`](crate::validate()) pass reports those.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::expr;
+    /// use rusty_promql_parser::parser::function::ValueType;
+    ///
+    /// let (_, ast) = expr("scalar(sum(up)) * 2").unwrap();
+    /// assert_eq!(ast.value_type(), ValueType::Scalar);
+    ///
+    /// let (_, ast) = expr("rate(x[5m])[1h:]").unwrap();
+    /// assert_eq!(ast.value_type(), ValueType::Matrix);
+    /// ```
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            Expr::Number(_) => ValueType::Scalar,
+            Expr::String(_) => ValueType::String,
+            Expr::VectorSelector(_) | Expr::Aggregation(_) => ValueType::Vector,
+            Expr::MatrixSelector(_) | Expr::Subquery(_) => ValueType::Matrix,
+            // Unknown functions are assumed to return an instant vector
+            Expr::Call(c) => c
+                .known_function()
+                .map_or(ValueType::Vector, |f| f.return_type),
+            Expr::Binary(b) => {
+                if b.lhs.value_type() == ValueType::Scalar
+                    && b.rhs.value_type() == ValueType::Scalar
+                {
+                    ValueType::Scalar
+                } else {
+                    ValueType::Vector
+                }
+            }
+            Expr::Unary(u) => u.expr.value_type(),
+            Expr::Paren(e) => e.value_type(),
+        }
+    }
+
+    /// Check if the whole expression is a single instant vector selector.
+    ///
+    /// The selector may have `offset` and `@` modifiers and be wrapped in
+    /// parentheses, but there are no functions, operators or aggregations.
+    /// Matrix selectors and subqueries do not count. Such queries only read
+    /// series, so they can be answered straight from an index or cache.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::expr;
+    ///
+    /// let (_, ast) = expr(r#"up{job="x"} offset 5m"#).unwrap();
+    /// assert!(ast.is_selector_only());
+    ///
+    /// let (_, ast) = expr("rate(up[5m])").unwrap();
+    /// assert!(!ast.is_selector_only());
+    /// ```
+    pub fn is_selector_only(&self) -> bool {
+        matches!(self.unwrap_parens(), Expr::VectorSelector(_))
+    }
+
     /// Unwrap parentheses to get the inner expression
     pub fn unwrap_parens(&self) -> &Expr {
         match self {
@@ -99,290 +266,1808 @@ impl Expr {
             other => other,
         }
     }
-}
 
-impl fmt::Display for Expr {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    /// Get the direct sub-expressions of this expression, in source order.
+    ///
+    /// These are the arguments of a call, the parameter (if any) and body of
+    /// an aggregation, both operands of a binary expression, and the inner
+    /// expression of a unary expression, parentheses or subquery. Numbers,
+    /// strings and selectors have no children.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::expr;
+    ///
+    /// let (_, ast) = expr("topk(3, rate(x[5m])) > 1").unwrap();
+    /// let children = ast.children();
+    /// assert_eq!(children.len(), 2);
+    /// assert_eq!(children[0].to_string(), "topk(3, rate(x[5m]))");
+    /// assert_eq!(children[0].children().len(), 2);
+    /// ```
+    pub fn children(&self) -> Vec<&Expr> {
         match self {
-            Expr::Number(n) => {
-                if n.is_nan() {
-                    write!(f, "NaN")
-                } else if n.is_infinite() {
-                    if *n > 0.0 {
-                        write!(f, "Inf")
-                    } else {
-                        write!(f, "-Inf")
-                    }
-                } else {
-                    write!(f, "{}", n)
-                }
-            }
-            Expr::String(s) => write!(f, "\"{}\"", s.escape_default()),
-            Expr::VectorSelector(v) => write!(f, "{}", v),
-            Expr::MatrixSelector(m) => write!(f, "{}", m),
-            Expr::Call(c) => write!(f, "{}", c),
-            Expr::Aggregation(a) => write!(f, "{}", a),
-            Expr::Binary(b) => write!(f, "{}", b),
-            Expr::Unary(u) => write!(f, "{}", u),
-            Expr::Paren(e) => write!(f, "({})", e),
-            Expr::Subquery(s) => write!(f, "{}", s),
+            Expr::Number(_)
+            | Expr::String(_)
+            | Expr::VectorSelector(_)
+            | Expr::MatrixSelector(_) => Vec::new(),
+            Expr::Call(c) => c.args.iter().collect(),
+            Expr::Aggregation(a) => a.param.iter().chain(core::iter::once(&a.expr)).collect(),
+            Expr::Binary(b) => vec![&b.lhs, &b.rhs],
+            Expr::Unary(u) => vec![&u.expr],
+            Expr::Paren(e) => vec![e.as_ref()],
+            Expr::Subquery(s) => vec![&s.expr],
         }
     }
-}
-
-/// Function call expression
-#[derive(Debug, Clone, PartialEq)]
-pub struct Call {
-    /// Function name
-    pub name: String,
-    /// Function arguments
-    pub args: Vec<Expr>,
-}
 
-impl Call {
-    /// Create a new function call
-    pub fn new(name: impl Into<String>, args: Vec<Expr>) -> Self {
-        Self {
-            name: name.into(),
-            args,
+    /// Get mutable references to the direct sub-expressions of this
+    /// expression, in the same order as [`Expr::children`]
+    pub fn children_mut(&mut self) -> Vec<&mut Expr> {
+        match self {
+            Expr::Number(_)
+            | Expr::String(_)
+            | Expr::VectorSelector(_)
+            | Expr::MatrixSelector(_) => Vec::new(),
+            Expr::Call(c) => c.args.iter_mut().collect(),
+            Expr::Aggregation(a) => a
+                .param
+                .iter_mut()
+                .chain(core::iter::once(&mut a.expr))
+                .collect(),
+            Expr::Binary(b) => vec![&mut b.lhs, &mut b.rhs],
+            Expr::Unary(u) => vec![&mut u.expr],
+            Expr::Paren(e) => vec![e.as_mut()],
+            Expr::Subquery(s) => vec![&mut s.expr],
         }
     }
-}
 
-impl fmt::Display for Call {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}(", self.name)?;
-        for (i, arg) in self.args.iter().enumerate() {
-            if i > 0 {
-                write!(f, ", ")?;
-            }
-            write!(f, "{}", arg)?;
+    /// Visit every node mutably, depth-first in source order, until `f`
+    /// returns [`ControlFlow::Break`].
+    ///
+    /// Each node is passed to `f` before its children, so children of a node
+    /// replaced by `f` are those of the replacement. The traversal uses an
+    /// explicit stack rather than recursion, so deeply nested expressions
+    /// cannot overflow the call stack.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use std::ops::ControlFlow;
+    /// use rusty_promql_parser::{Expr, expr};
+    ///
+    /// let (_, mut ast) = expr("a + b + c").unwrap();
+    /// // Rename the first selector only
+    /// ast.depth_first_mut(|e| match e {
+    ///     Expr::VectorSelector(s) => {
+    ///         s.name = Some("x".to_string());
+    ///         ControlFlow::Break(())
+    ///     }
+    ///     _ => ControlFlow::Continue(()),
+    /// });
+    /// assert_eq!(ast.to_string(), "x + b + c");
+    /// ```
+    pub fn depth_first_mut<B>(
+        &mut self,
+        mut f: impl FnMut(&mut Expr) -> ControlFlow<B>,
+    ) -> ControlFlow<B> {
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            f(node)?;
+            let children = node.children_mut();
+            stack.extend(children.into_iter().rev());
         }
-        write!(f, ")")
+        ControlFlow::Continue(())
     }
-}
-
-/// Aggregation expression
-#[derive(Debug, Clone, PartialEq)]
-pub struct Aggregation {
-    /// The aggregation operator name
-    pub op: String,
-    /// The expression to aggregate
-    pub expr: Expr,
-    /// Parameter for parametric aggregations (topk, quantile, etc.)
-    pub param: Option<Expr>,
-    /// Optional grouping clause (by/without)
-    pub grouping: Option<Grouping>,
-}
 
-impl Aggregation {
-    /// Create a new aggregation
-    pub fn new(op: impl Into<String>, expr: Expr) -> Self {
-        Self {
-            op: op.into(),
-            expr,
-            param: None,
-            grouping: None,
-        }
+    /// Collect all vector selectors in the expression tree, in source order.
+    ///
+    /// This includes the selectors wrapped by matrix selectors.
+    pub fn vector_selectors(&self) -> Vec<&VectorSelector> {
+        let mut selectors = Vec::new();
+        self.collect_vector_selectors(&mut selectors);
+        selectors
     }
 
-    /// Create a new aggregation with a parameter
-    pub fn with_param(op: impl Into<String>, param: Expr, expr: Expr) -> Self {
-        Self {
-            op: op.into(),
-            expr,
-            param: Some(param),
-            grouping: None,
+    fn collect_vector_selectors<'a>(&'a self, out: &mut Vec<&'a VectorSelector>) {
+        match self {
+            Expr::VectorSelector(v) => out.push(v),
+            Expr::MatrixSelector(m) => out.push(&m.selector),
+            other => {
+                for child in other.children() {
+                    child.collect_vector_selectors(out);
+                }
+            }
         }
     }
 
-    /// Set the grouping clause
-    pub fn with_grouping(mut self, grouping: Grouping) -> Self {
-        self.grouping = Some(grouping);
-        self
+    /// Call `f` with every vector selector, in source order, and the path
+    /// from this expression to the node holding it.
+    ///
+    /// Like [`Expr::vector_selectors`], this includes the selectors wrapped
+    /// by matrix selectors; their path leads to the matrix selector. The
+    /// path lets a policy check point at the offending part of a query.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::expr;
+    ///
+    /// let (_, ast) = expr(r#"errors / rate(requests{tenant=""}[5m])"#).unwrap();
+    /// let mut paths = Vec::new();
+    /// ast.walk_selectors_with_path(|path, selector| {
+    ///     let path: Vec<String> = path.iter().map(|s| s.to_string()).collect();
+    ///     paths.push(format!("{}: {}", path.join("."), selector));
+    /// });
+    /// assert_eq!(paths, ["lhs: errors", r#"rhs.arg[0]: requests{tenant=""}"#]);
+    /// ```
+    pub fn walk_selectors_with_path(&self, mut f: impl FnMut(&[PathSegment], &VectorSelector)) {
+        self.walk_selectors_at(&mut Vec::new(), &mut f);
     }
-}
 
-impl fmt::Display for Aggregation {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.op)?;
-        if let Some(ref grouping) = self.grouping {
-            write!(f, " {} ", grouping)?;
-        }
-        write!(f, "(")?;
-        if let Some(ref param) = self.param {
-            write!(f, "{}, ", param)?;
+    fn walk_selectors_at(
+        &self,
+        path: &mut Vec<PathSegment>,
+        f: &mut impl FnMut(&[PathSegment], &VectorSelector),
+    ) {
+        let mut visit = |segment, child: &Expr| {
+            path.push(segment);
+            child.walk_selectors_at(path, f);
+            path.pop();
+        };
+        match self {
+            Expr::Number(_) | Expr::String(_) => {}
+            Expr::VectorSelector(v) => f(path, v),
+            Expr::MatrixSelector(m) => f(path, &m.selector),
+            Expr::Call(c) => {
+                for (i, arg) in c.args.iter().enumerate() {
+                    visit(PathSegment::Arg(i), arg);
+                }
+            }
+            Expr::Aggregation(a) => {
+                if let Some(param) = &a.param {
+                    visit(PathSegment::Param, param);
+                }
+                visit(PathSegment::Aggregated, &a.expr);
+            }
+            Expr::Binary(b) => {
+                visit(PathSegment::Lhs, &b.lhs);
+                visit(PathSegment::Rhs, &b.rhs);
+            }
+            Expr::Unary(u) => visit(PathSegment::Operand, &u.expr),
+            Expr::Paren(e) => visit(PathSegment::Paren, e),
+            Expr::Subquery(s) => visit(PathSegment::Subquery, &s.expr),
         }
-        write!(f, "{})", self.expr)
     }
-}
-
-/// Binary operators
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum BinaryOp {
-    // Arithmetic
-    Add,   // +
-    Sub,   // -
-    Mul,   // *
-    Div,   // /
-    Mod,   // %
-    Pow,   // ^
-    Atan2, // atan2
-
-    // Comparison
-    Eq, // ==
-    Ne, // !=
-    Lt, // <
-    Le, // <=
-    Gt, // >
-    Ge, // >=
 
-    // Set operations
-    And,    // and
-    Or,     // or
-    Unless, // unless
-}
+    /// Collect all matrix selectors in the expression tree, in source order
+    pub fn matrix_selectors(&self) -> Vec<&MatrixSelector> {
+        let mut selectors = Vec::new();
+        self.collect_matrix_selectors(&mut selectors);
+        selectors
+    }
 
-impl BinaryOp {
-    /// Get the operator as a string
-    pub fn as_str(&self) -> &'static str {
+    fn collect_matrix_selectors<'a>(&'a self, out: &mut Vec<&'a MatrixSelector>) {
         match self {
-            BinaryOp::Add => "+",
-            BinaryOp::Sub => "-",
-            BinaryOp::Mul => "*",
-            BinaryOp::Div => "/",
-            BinaryOp::Mod => "%",
-            BinaryOp::Pow => "^",
-            BinaryOp::Atan2 => "atan2",
-            BinaryOp::Eq => "==",
-            BinaryOp::Ne => "!=",
-            BinaryOp::Lt => "<",
-            BinaryOp::Le => "<=",
-            BinaryOp::Gt => ">",
-            BinaryOp::Ge => ">=",
-            BinaryOp::And => "and",
-            BinaryOp::Or => "or",
-            BinaryOp::Unless => "unless",
+            Expr::MatrixSelector(m) => out.push(m),
+            other => {
+                for child in other.children() {
+                    child.collect_matrix_selectors(out);
+                }
+            }
         }
     }
 
-    /// Get the precedence of this operator (higher = binds tighter)
-    pub fn precedence(&self) -> u8 {
-        match self {
-            BinaryOp::Or => 1,                     // Lowest
-            BinaryOp::And | BinaryOp::Unless => 2, // Set intersection/difference
-            BinaryOp::Eq
-            | BinaryOp::Ne
-            | BinaryOp::Lt
-            | BinaryOp::Le
-            | BinaryOp::Gt
-            | BinaryOp::Ge => 3, // Comparison
-            BinaryOp::Add | BinaryOp::Sub => 4,    // Addition/subtraction
-            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod | BinaryOp::Atan2 => 5, // Multiplication/division
-            BinaryOp::Pow => 6,                                                   // Highest
+    /// Collect the metric names the expression reads.
+    ///
+    /// A selector names a metric with its name (`foo`) or with a
+    /// `__name__="foo"` matcher. Selectors that match names by regex or not
+    /// at all are skipped, so the set can be incomplete for such queries.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::expr;
+    ///
+    /// let (_, ast) = expr(r#"rate(foo[5m]) / {__name__="bar"} + on() foo"#).unwrap();
+    /// assert_eq!(ast.metric_names().into_iter().collect::<Vec<_>>(), ["bar", "foo"]);
+    /// ```
+    pub fn metric_names(&self) -> BTreeSet<&str> {
+        let mut names = BTreeSet::new();
+        for selector in self.vector_selectors() {
+            names.extend(selector.name.as_deref());
+            names.extend(
+                selector
+                    .matchers
+                    .iter()
+                    .filter(|m| m.name == "__name__" && m.op == LabelMatchOp::Equal)
+                    .map(|m| m.value.as_str()),
+            );
         }
+        names
     }
 
-    /// Check if this operator is right-associative
-    pub fn is_right_associative(&self) -> bool {
-        matches!(self, BinaryOp::Pow)
+    /// Check whether any selector reads the metric `name`.
+    ///
+    /// Like [`Expr::metric_names`], this recognizes the metric name of a
+    /// selector and `__name__="name"` matchers.
+    pub fn contains_metric(&self, name: &str) -> bool {
+        self.vector_selectors()
+            .into_iter()
+            .any(|selector| selects_metric(selector, name))
     }
 
-    /// Check if this is a comparison operator
-    pub fn is_comparison(&self) -> bool {
-        matches!(
-            self,
-            BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge
-        )
+    /// Collect the vector selectors that read the metric `name`, in source
+    /// order.
+    ///
+    /// Like [`Expr::vector_selectors`], this includes the selectors wrapped
+    /// by matrix selectors.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::expr;
+    ///
+    /// let (_, ast) = expr(r#"rate(foo{job="a"}[5m]) / bar + {__name__="foo"}"#).unwrap();
+    /// let selectors = ast.selectors_for_metric("foo");
+    /// assert_eq!(selectors.len(), 2);
+    /// assert_eq!(selectors[0].to_string(), r#"foo{job="a"}"#);
+    /// assert!(!ast.contains_metric("baz"));
+    /// ```
+    pub fn selectors_for_metric(&self, name: &str) -> Vec<&VectorSelector> {
+        self.vector_selectors()
+            .into_iter()
+            .filter(|selector| selects_metric(selector, name))
+            .collect()
     }
 
-    /// Check if this is a set operator
-    pub fn is_set_operator(&self) -> bool {
-        matches!(self, BinaryOp::And | BinaryOp::Or | BinaryOp::Unless)
+    /// Collect mutable references to all vector selectors, in source order.
+    ///
+    /// Like [`Expr::vector_selectors`], this includes the selectors wrapped
+    /// by matrix selectors.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::expr;
+    /// use rusty_promql_parser::lexer::duration::Duration;
+    ///
+    /// let (_, mut ast) = expr("rate(foo[5m]) / bar").unwrap();
+    /// for selector in ast.vector_selectors_mut() {
+    ///     selector.offset = Some(Duration::from_secs(3600));
+    /// }
+    /// assert_eq!(ast.to_string(), "rate(foo[5m] offset 1h) / bar offset 1h");
+    /// ```
+    pub fn vector_selectors_mut(&mut self) -> Vec<&mut VectorSelector> {
+        let mut selectors = Vec::new();
+        self.collect_vector_selectors_mut(&mut selectors);
+        selectors
     }
 
-    /// Check if this is an arithmetic operator
-    pub fn is_arithmetic(&self) -> bool {
-        matches!(
-            self,
-            BinaryOp::Add
-                | BinaryOp::Sub
-                | BinaryOp::Mul
-                | BinaryOp::Div
-                | BinaryOp::Mod
-                | BinaryOp::Pow
-                | BinaryOp::Atan2
-        )
+    fn collect_vector_selectors_mut<'a>(&'a mut self, out: &mut Vec<&'a mut VectorSelector>) {
+        match self {
+            Expr::VectorSelector(v) => out.push(v),
+            Expr::MatrixSelector(m) => out.push(&mut m.selector),
+            other => {
+                for child in other.children_mut() {
+                    child.collect_vector_selectors_mut(out);
+                }
+            }
+        }
     }
-}
 
-impl fmt::Display for BinaryOp {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.as_str())
+    /// Collect mutable references to all matrix selectors, in source order.
+    pub fn matrix_selectors_mut(&mut self) -> Vec<&mut MatrixSelector> {
+        let mut selectors = Vec::new();
+        self.collect_matrix_selectors_mut(&mut selectors);
+        selectors
     }
-}
-
-/// Vector matching for binary operations
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum VectorMatchingOp {
-    On,       // on (label1, label2)
-    Ignoring, // ignoring (label1, label2)
-}
 
-impl fmt::Display for VectorMatchingOp {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    fn collect_matrix_selectors_mut<'a>(&'a mut self, out: &mut Vec<&'a mut MatrixSelector>) {
         match self {
-            VectorMatchingOp::On => write!(f, "on"),
-            VectorMatchingOp::Ignoring => write!(f, "ignoring"),
+            Expr::MatrixSelector(m) => out.push(m),
+            other => {
+                for child in other.children_mut() {
+                    child.collect_matrix_selectors_mut(out);
+                }
+            }
         }
     }
-}
 
-/// Group modifier side
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum GroupSide {
-    Left,  // group_left
-    Right, // group_right
-}
+    /// Get the matcher sets of all series touched by this expression.
+    ///
+    /// Returns one matcher set per distinct selector, suitable for the
+    /// `match[]` parameters of the Prometheus `/api/v1/series` endpoint.
+    /// Each set includes the `__name__` matcher of named selectors and is
+    /// sorted by label name, so selectors differing only in matcher order
+    /// are reported once.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::expr;
+    ///
+    /// let (_, ast) = expr(r#"rate(foo{job="a"}[5m]) / foo{job="a"}"#).unwrap();
+    /// let sets = ast.series_matchers();
+    /// assert_eq!(sets.len(), 1);
+    /// assert_eq!(sets[0][0].to_string(), r#"__name__="foo""#);
+    /// ```
+    pub fn series_matchers(&self) -> Vec<Vec<LabelMatcher>> {
+        let mut sets: Vec<Vec<LabelMatcher>> = Vec::new();
+        for selector in self.vector_selectors() {
+            let mut matchers = selector.all_matchers();
+            matchers.sort_by(|a, b| {
+                (&a.name, a.op.as_str(), &a.value).cmp(&(&b.name, b.op.as_str(), &b.value))
+            });
+            if !sets.contains(&matchers) {
+                sets.push(matchers);
+            }
+        }
+        sets
+    }
 
-impl fmt::Display for GroupSide {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    /// Roughly classify how many series the whole query touches.
+    ///
+    /// This is the highest [`VectorSelector::cardinality_class`] among the
+    /// query's selectors, or [`CardinalityClass::Low`] for a query without
+    /// selectors. Like the per-selector class, it is a heuristic that ignores
+    /// functions and aggregations, which change the output size but not the
+    /// number of series read.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::expr;
+    /// use rusty_promql_parser::parser::selector::CardinalityClass;
+    ///
+    /// let (_, ast) = expr(r#"rate(cpu{pod="api-0"}[5m]) / on() group_left sum(cpu)"#).unwrap();
+    /// assert_eq!(ast.estimate_series_cardinality_class(), CardinalityClass::High);
+    /// ```
+    pub fn estimate_series_cardinality_class(&self) -> CardinalityClass {
+        self.vector_selectors()
+            .iter()
+            .map(|s| s.cardinality_class())
+            .max()
+            .unwrap_or(CardinalityClass::Low)
+    }
+
+    /// Append the PromQL text of this expression to `buf`.
+    ///
+    /// `Display` renders through the same code, so the output is identical,
+    /// but this writes straight into a caller-provided buffer instead of
+    /// allocating a new `String` like `to_string()` does. Reusing one buffer
+    /// avoids an allocation per rendered query when serializing many
+    /// expressions.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::expr;
+    ///
+    /// let mut buf = String::new();
+    /// for query in ["up", "rate(x[5m])"] {
+    ///     let (_, ast) = expr(query).unwrap();
+    ///     buf.clear();
+    ///     ast.write_to(&mut buf);
+    ///     assert_eq!(buf, query);
+    /// }
+    /// ```
+    pub fn write_to(&self, buf: &mut String) {
+        // Writing to a String never fails
+        let _ = self.write_into(buf);
+    }
+
+    /// Write the PromQL text of this expression to any [`fmt::Write`] sink.
+    ///
+    /// Both [`write_to`](Expr::write_to) and `Display` go through here.
+    fn write_into<W: fmt::Write>(&self, w: &mut W) -> fmt::Result {
         match self {
-            GroupSide::Left => write!(f, "group_left"),
-            GroupSide::Right => write!(f, "group_right"),
+            Expr::Number(n) => write!(w, "{}", n),
+            Expr::String(s) => write!(w, "{}", DoubleQuoted(s)),
+            Expr::VectorSelector(v) => write!(w, "{}", v),
+            Expr::MatrixSelector(m) => write!(w, "{}", m),
+            Expr::Call(c) => write!(w, "{}", c),
+            Expr::Aggregation(a) => write!(w, "{}", a),
+            Expr::Binary(b) => write!(w, "{}", b),
+            Expr::Unary(u) => write!(w, "{}", u),
+            Expr::Paren(e) => {
+                w.write_char('(')?;
+                e.write_into(w)?;
+                w.write_char(')')
+            }
+            Expr::Subquery(s) => write!(w, "{}", s),
         }
     }
-}
 
-/// Group modifier for many-to-one/one-to-many matching
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct GroupModifier {
-    /// Which side to group (left or right)
-    pub side: GroupSide,
-    /// Additional labels to include from the "one" side
-    pub labels: Vec<String>,
-}
+    /// Compute a 64-bit content hash that stays the same across process
+    /// restarts and platforms, e.g. to key a persistent query cache.
+    ///
+    /// The hash covers the tree with all [`Expr::Paren`] nodes removed, so
+    /// `(a + b)` and `a + b` hash alike, as do queries differing only in
+    /// whitespace or keyword case. Operand order still matters: apply
+    /// [`canonicalize_commutative`](Expr::canonicalize_commutative) first
+    /// to also identify `a + b` with `b + a`.
+    ///
+    /// The algorithm is 64-bit FNV-1a over a fixed encoding of the tree.
+    /// Unlike [`Hash`](core::hash::Hash) with the standard library's
+    /// randomized hasher, the value is stable within a major version of
+    /// this crate.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::expr;
+    ///
+    /// let (_, a) = expr("(sum by (job) (rate(x[5m])))").unwrap();
+    /// let (_, b) = expr("SUM(rate(x[5m])) BY (job)").unwrap();
+    /// assert_eq!(a.hash_stable(), b.hash_stable());
+    /// ```
+    pub fn hash_stable(&self) -> u64 {
+        let mut hasher = StableHasher::new();
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            // Writing to the hasher never fails
+            let _ = hasher.write_node(node);
+            stack.extend(node.children().into_iter().rev());
+        }
+        hasher.0
+    }
 
-impl fmt::Display for GroupModifier {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.side)?;
-        if !self.labels.is_empty() {
-            write!(f, " (")?;
-            for (i, label) in self.labels.iter().enumerate() {
-                if i > 0 {
-                    write!(f, ", ")?;
+    /// Render the expression with every label matcher value replaced by
+    /// `<redacted>`, so that query shapes can be logged without the label
+    /// values, which may hold personal data.
+    ///
+    /// Metric names, including `__name__` matcher values, label names,
+    /// operators and the rest of the structure are kept. String literals,
+    /// such as the arguments of `label_replace`, are not matcher values and
+    /// are kept too.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::expr;
+    ///
+    /// let (_, ast) = expr(r#"rate(up{user="alice", job=~"api.*"}[5m])"#).unwrap();
+    /// assert_eq!(
+    ///     ast.redacted_to_string(),
+    ///     r#"rate(up{user="<redacted>", job=~"<redacted>"}[5m])"#
+    /// );
+    /// ```
+    pub fn redacted_to_string(&self) -> String {
+        let mut redacted = self.clone();
+        for selector in redacted.vector_selectors_mut() {
+            for matcher in &mut selector.matchers {
+                if matcher.name != "__name__" {
+                    matcher.value = String::from("<redacted>");
                 }
-                write!(f, "{}", label)?;
             }
-            write!(f, ")")?;
         }
-        Ok(())
+        redacted.to_string()
     }
-}
 
-/// Vector matching specification for binary operations
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct VectorMatching {
+    /// Render the expression in a normalized form, so that equivalent
+    /// queries written differently produce the same string. Use this for
+    /// cache keys and deduplication.
+    ///
+    /// Normalization covers exactly the following:
+    ///
+    /// - Whitespace, keyword case and string quoting follow [`Display`](fmt::Display):
+    ///   single spaces around operators, lowercase keywords, and string
+    ///   literals and matcher values in double quotes with canonical escapes
+    /// - Numbers are printed from their value, so `0x10`, `16.0` and `1.6e1`
+    ///   all become `16`
+    /// - Aggregation grouping clauses are printed before the aggregated
+    ///   expression: `sum(x) by (job)` becomes `sum by (job) (x)`
+    /// - Parentheses are kept only where operator precedence or
+    ///   associativity requires them: `((a)) + (b * c)` becomes `a + b * c`
+    ///   but `(a + b) * c` is kept
+    /// - Label matchers of each selector are sorted by label name, then by
+    ///   operator and value
+    ///
+    /// Operand order, grouping label order and the `offset` and `@`
+    /// modifiers are left as written. Apply
+    /// [`canonicalize_commutative`](Expr::canonicalize_commutative) first to
+    /// also identify `a + b` with `b + a`.
+    ///
+    /// The result is valid PromQL and parses back to the same expression.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::expr;
+    ///
+    /// let (_, a) = expr(r#"SUM((rate(x{job="api",env='prod'}[5m]))) BY (job) > 0x10"#).unwrap();
+    /// let (_, b) = expr(r#"sum by (job) (rate(x{env="prod", job="api"}[5m])) > 16"#).unwrap();
+    /// assert_eq!(a.canonical_string(), b.canonical_string());
+    /// assert_eq!(
+    ///     a.canonical_string(),
+    ///     r#"sum by (job) (rate(x{env="prod", job="api"}[5m])) > 16"#
+    /// );
+    /// ```
+    pub fn canonical_string(&self) -> String {
+        self.clone().canonicalized().to_string()
+    }
+
+    /// Rebuild the tree behind [`Expr::canonical_string`]: sort matchers and
+    /// keep only the parentheses needed to preserve its structure
+    fn canonicalized(self) -> Expr {
+        let mut e = self;
+        while let Expr::Paren(inner) = e {
+            e = *inner;
+        }
+        for child in e.children_mut() {
+            let owned = core::mem::replace(child, Expr::Number(NumberLiteral::new(0.0)));
+            *child = owned.canonicalized();
+        }
+        match &mut e {
+            Expr::Number(n) => n.raw = None,
+            Expr::VectorSelector(v) => sort_matchers(&mut v.matchers),
+            Expr::MatrixSelector(m) => sort_matchers(&mut m.selector.matchers),
+            Expr::Binary(b) => {
+                if needs_parens(&b.lhs, b.op, true) {
+                    wrap_in_parens(&mut b.lhs);
+                }
+                if needs_parens(&b.rhs, b.op, false) {
+                    wrap_in_parens(&mut b.rhs);
+                }
+            }
+            // The operand of a unary operator is a single postfix expression
+            Expr::Unary(u) => {
+                if matches!(u.expr, Expr::Binary(_)) {
+                    wrap_in_parens(&mut u.expr);
+                }
+            }
+            // `a + b[5m:]` is a subquery over `b` only
+            Expr::Subquery(s) => {
+                if matches!(s.expr, Expr::Binary(_) | Expr::Unary(_)) {
+                    wrap_in_parens(&mut s.expr);
+                }
+            }
+            _ => {}
+        }
+        e
+    }
+
+    /// Count how often each function is called in the expression tree.
+    ///
+    /// When `include_aggregations` is set, aggregation operators such as
+    /// `sum` or `topk` are counted as well, under their lowercase name.
+    /// The map is sorted by function name.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::expr;
+    ///
+    /// let (_, ast) = expr("sum(rate(a[5m])) / sum(rate(b[5m]))").unwrap();
+    /// let usage = ast.function_usage(false);
+    /// assert_eq!(usage["rate"], 2);
+    /// assert!(!usage.contains_key("sum"));
+    /// ```
+    pub fn function_usage(&self, include_aggregations: bool) -> BTreeMap<String, usize> {
+        let mut usage = BTreeMap::new();
+        self.collect_function_usage(include_aggregations, &mut usage);
+        usage
+    }
+
+    fn collect_function_usage(
+        &self,
+        include_aggregations: bool,
+        out: &mut BTreeMap<String, usize>,
+    ) {
+        match self {
+            Expr::Call(c) => *out.entry(c.name.clone()).or_default() += 1,
+            Expr::Aggregation(a) if include_aggregations => {
+                *out.entry(a.op.to_ascii_lowercase()).or_default() += 1
+            }
+            _ => {}
+        }
+        for child in self.children() {
+            child.collect_function_usage(include_aggregations, out);
+        }
+    }
+
+    /// Count how often each binary operator is used in the expression tree.
+    ///
+    /// Together with [`function_usage`](Expr::function_usage) this gives a
+    /// profile of the query features in use. The map is sorted with
+    /// arithmetic operators first, then comparisons, then set operators.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::{BinaryOp, expr};
+    ///
+    /// let (_, ast) = expr("a / b * 100 > 5 and c / d").unwrap();
+    /// let counts = ast.operand_count_by_op();
+    /// assert_eq!(counts[&BinaryOp::Div], 2);
+    /// assert_eq!(counts[&BinaryOp::And], 1);
+    /// assert!(!counts.contains_key(&BinaryOp::Add));
+    /// ```
+    pub fn operand_count_by_op(&self) -> BTreeMap<BinaryOp, usize> {
+        let mut counts = BTreeMap::new();
+        self.collect_operand_count_by_op(&mut counts);
+        counts
+    }
+
+    fn collect_operand_count_by_op(&self, out: &mut BTreeMap<BinaryOp, usize>) {
+        if let Expr::Binary(b) = self {
+            *out.entry(b.op).or_default() += 1;
+        }
+        for child in self.children() {
+            child.collect_operand_count_by_op(out);
+        }
+    }
+
+    /// Fill in `step` on every subquery that omits it.
+    ///
+    /// A subquery like `[5m:]` is evaluated at the default evaluation
+    /// interval. When that interval is known, resolving it makes otherwise
+    /// equivalent queries print identically, e.g. for use as a cache key.
+    /// Explicit steps are left untouched.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::expr;
+    /// use rusty_promql_parser::lexer::duration::Duration;
+    ///
+    /// let (_, ast) = expr("max_over_time(rate(x[1m])[5m:])").unwrap();
+    /// let ast = ast.with_default_subquery_step(Duration::from_secs(60));
+    /// assert_eq!(ast.to_string(), "max_over_time(rate(x[1m])[5m:1m])");
+    /// ```
+    pub fn with_default_subquery_step(mut self, step: Duration) -> Self {
+        self.fill_subquery_steps(step);
+        self
+    }
+
+    fn fill_subquery_steps(&mut self, step: Duration) {
+        if let Expr::Subquery(s) = self {
+            s.step.get_or_insert(step);
+        }
+        for child in self.children_mut() {
+            child.fill_subquery_steps(step);
+        }
+    }
+
+    /// Shift the whole query back in time by adding `delta` to offsets.
+    ///
+    /// Every selector, at any nesting level, gets `delta` added to its
+    /// offset, or an offset of `delta` if it has none; an offset that
+    /// becomes zero is removed. This includes selectors inside subqueries.
+    /// The offsets of subqueries themselves are left alone, since shifting
+    /// them as well would move the selectors inside twice.
+    ///
+    /// A negative `delta` can turn a positive offset negative, making that
+    /// selector look forward in time. This is allowed, as in Prometheus.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::{Duration, expr};
+    ///
+    /// let (_, mut ast) = expr("rate(a[5m]) / rate(b[5m] offset 1d)").unwrap();
+    /// ast.rewrite_offsets(Duration::from_secs(7 * 86400));
+    /// assert_eq!(ast.to_string(), "rate(a[5m] offset 1w) / rate(b[5m] offset 1w1d)");
+    ///
+    /// let (_, mut ast) = expr("max_over_time(rate(x[5m])[1h:])").unwrap();
+    /// ast.rewrite_offsets(Duration::from_secs(86400));
+    /// assert_eq!(ast.to_string(), "max_over_time(rate(x[5m] offset 1d)[1h:])");
+    /// ```
+    pub fn rewrite_offsets(&mut self, delta: Duration) {
+        fn shift(offset: &mut Option<Duration>, delta: Duration) {
+            let current = offset.map_or(0, |d| d.as_millis());
+            let shifted = current.saturating_add(delta.as_millis());
+            *offset = (shifted != 0).then_some(Duration::from_millis(shifted));
+        }
+
+        match self {
+            Expr::VectorSelector(vs) => shift(&mut vs.offset, delta),
+            Expr::MatrixSelector(ms) => shift(&mut ms.selector.offset, delta),
+            _ => {
+                for child in self.children_mut() {
+                    child.rewrite_offsets(delta);
+                }
+            }
+        }
+    }
+
+    /// The offset of a selector or subquery, or `None` if it has none or
+    /// the expression takes no modifiers.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::{Duration, expr};
+    ///
+    /// let (_, ast) = expr("x[5m] offset 1h").unwrap();
+    /// assert_eq!(ast.offset(), Some(Duration::from_secs(3600)));
+    ///
+    /// let (_, ast) = expr("sum(x offset 1h)").unwrap();
+    /// assert_eq!(ast.offset(), None);
+    /// ```
+    pub fn offset(&self) -> Option<Duration> {
+        match self {
+            Expr::VectorSelector(vs) => vs.offset,
+            Expr::MatrixSelector(ms) => ms.selector.offset,
+            Expr::Subquery(sq) => sq.offset,
+            _ => None,
+        }
+    }
+
+    /// The `@` modifier of a selector or subquery, or `None` if it has none
+    /// or the expression takes no modifiers.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::expr;
+    /// use rusty_promql_parser::parser::selector::AtModifier;
+    ///
+    /// let (_, ast) = expr("rate(x[5m])[1h:] @ end()").unwrap();
+    /// assert_eq!(ast.at(), Some(&AtModifier::End));
+    ///
+    /// let (_, ast) = expr("up").unwrap();
+    /// assert_eq!(ast.at(), None);
+    /// ```
+    pub fn at(&self) -> Option<&AtModifier> {
+        match self {
+            Expr::VectorSelector(vs) => vs.at.as_ref(),
+            Expr::MatrixSelector(ms) => ms.selector.at.as_ref(),
+            Expr::Subquery(sq) => sq.at.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Set the offset of a selector or subquery, replacing any offset it
+    /// already has.
+    ///
+    /// Only vector selectors, matrix selectors and subqueries take
+    /// modifiers; any other expression is returned as an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::{Duration, expr};
+    ///
+    /// let (_, ast) = expr("rate(x[5m])[1h:]").unwrap();
+    /// let ast = ast.with_offset(Duration::from_secs(300)).unwrap();
+    /// assert_eq!(ast.to_string(), "rate(x[5m])[1h:] offset 5m");
+    ///
+    /// let (_, ast) = expr("sum(x)").unwrap();
+    /// assert!(ast.with_offset(Duration::from_secs(300)).is_err());
+    /// ```
+    pub fn with_offset(mut self, offset: Duration) -> Result<Self, ModifierError> {
+        match &mut self {
+            Expr::VectorSelector(vs) => vs.offset = Some(offset),
+            Expr::MatrixSelector(ms) => ms.selector.offset = Some(offset),
+            Expr::Subquery(sq) => sq.offset = Some(offset),
+            _ => return Err(ModifierError::new("offset", self.kind())),
+        }
+        Ok(self)
+    }
+
+    /// Set the `@` modifier of a selector or subquery, replacing any `@`
+    /// modifier it already has.
+    ///
+    /// Only vector selectors, matrix selectors and subqueries take
+    /// modifiers; any other expression is returned as an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::expr;
+    /// use rusty_promql_parser::parser::selector::AtModifier;
+    ///
+    /// let (_, ast) = expr("up offset 1m").unwrap();
+    /// let ast = ast.with_at(AtModifier::End).unwrap();
+    /// assert_eq!(ast.to_string(), "up @ end() offset 1m");
+    ///
+    /// let (_, ast) = expr("42").unwrap();
+    /// let err = ast.with_at(AtModifier::End).unwrap_err();
+    /// assert_eq!(err.to_string(), "the @ modifier cannot be applied to a number literal");
+    /// ```
+    pub fn with_at(mut self, at: AtModifier) -> Result<Self, ModifierError> {
+        match &mut self {
+            Expr::VectorSelector(vs) => vs.at = Some(at),
+            Expr::MatrixSelector(ms) => ms.selector.at = Some(at),
+            Expr::Subquery(sq) => sq.at = Some(at),
+            _ => return Err(ModifierError::new("@", self.kind())),
+        }
+        Ok(self)
+    }
+
+    /// Sort the operands of commutative operators into a deterministic order.
+    ///
+    /// Chains of the same commutative operator ([`BinaryOp::is_commutative`])
+    /// with the same modifier, such as `c + a + b`, are flattened and their
+    /// operands ordered by their printed form, so queries that differ only in
+    /// operand order become equal. Operands that are binary expressions of
+    /// equal or lower precedence are parenthesized so the result prints and
+    /// reparses unambiguously. Non-commutative operators (`-`, `/`,
+    /// comparisons, `unless`, ...) and matches with `group_left` or
+    /// `group_right` keep their operand order.
+    ///
+    /// Intended for deduplication: for `and` and `or`, swapping operands
+    /// can change which side's samples are returned.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::expr;
+    ///
+    /// let (_, a) = expr("b + a").unwrap();
+    /// let (_, b) = expr("a + b").unwrap();
+    /// assert_eq!(a.canonicalize_commutative(), b.canonicalize_commutative());
+    ///
+    /// let (_, ast) = expr("c * (b - a) * a").unwrap();
+    /// assert_eq!(ast.canonicalize_commutative().to_string(), "(b - a) * a * c");
+    /// ```
+    pub fn canonicalize_commutative(mut self) -> Self {
+        let Expr::Binary(b) = &self else {
+            for child in self.children_mut() {
+                let owned = core::mem::replace(child, Expr::Number(NumberLiteral::new(0.0)));
+                *child = owned.canonicalize_commutative();
+            }
+            return self;
+        };
+        let op = b.op;
+        let modifier = b.modifier.clone();
+        let has_group = modifier
+            .as_ref()
+            .and_then(|m| m.matching.as_ref())
+            .is_some_and(|m| m.group.is_some());
+        if !op.is_commutative() || has_group {
+            for child in self.children_mut() {
+                let owned = core::mem::replace(child, Expr::Number(NumberLiteral::new(0.0)));
+                *child = owned.canonicalize_commutative();
+            }
+            return self;
+        }
+
+        let mut operands = Vec::new();
+        self.collect_chain_operands(op, &modifier, &mut operands);
+        let mut operands: Vec<Expr> = operands
+            .into_iter()
+            .map(|operand| match operand.canonicalize_commutative() {
+                Expr::Binary(inner) if inner.op.precedence() <= op.precedence() => {
+                    Expr::Paren(Box::new(Expr::Binary(inner)))
+                }
+                other => other,
+            })
+            .collect();
+        operands.sort_by_cached_key(|e| e.to_string());
+
+        let mut operands = operands.into_iter();
+        let first = operands.next().expect("a chain has at least two operands");
+        operands.fold(first, |lhs, rhs| {
+            Expr::Binary(Box::new(BinaryExpr {
+                op,
+                lhs,
+                rhs,
+                modifier: modifier.clone(),
+            }))
+        })
+    }
+
+    /// Split an unparenthesized chain of `op` with `modifier` into its operands
+    fn collect_chain_operands(
+        self,
+        op: BinaryOp,
+        modifier: &Option<BinaryModifier>,
+        out: &mut Vec<Expr>,
+    ) {
+        match self {
+            Expr::Binary(b) if b.op == op && &b.modifier == modifier => {
+                let BinaryExpr { lhs, rhs, .. } = *b;
+                lhs.collect_chain_operands(op, modifier, out);
+                rhs.collect_chain_operands(op, modifier, out);
+            }
+            other => out.push(other),
+        }
+    }
+
+    /// Fold unary operators on number literals into the literals.
+    ///
+    /// The parser reads `-5` as a unary minus over `5`; this pass turns it
+    /// into the number `-5` and drops unary plus entirely. A minus over
+    /// anything other than a number literal, such as `-x`, is kept since it
+    /// cannot be folded.
+    ///
+    /// The result prints the same way, but reparsing it gives back the
+    /// unary form.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::{Expr, expr};
+    ///
+    /// let (_, ast) = expr("-5").unwrap();
+    /// assert_eq!(ast.simplify_negations(), Expr::Number((-5.0).into()));
+    /// ```
+    pub fn simplify_negations(mut self) -> Self {
+        for child in self.children_mut() {
+            let owned = core::mem::replace(child, Expr::Number(NumberLiteral::new(0.0)));
+            *child = owned.simplify_negations();
+        }
+        match self {
+            Expr::Unary(u) => match *u {
+                UnaryExpr {
+                    op: UnaryOp::Plus,
+                    expr,
+                } => expr,
+                UnaryExpr {
+                    op: UnaryOp::Minus,
+                    expr: Expr::Number(n),
+                } => Expr::Number(NumberLiteral::new(-n.value)),
+                u => Expr::Unary(Box::new(u)),
+            },
+            other => other,
+        }
+    }
+
+    /// Remove `ignoring()` modifiers with an empty label list.
+    ///
+    /// Without a matching modifier, a binary operation matches series on all
+    /// of their labels, which is exactly what `ignoring()` (ignore no labels)
+    /// does, so the modifier is a no-op and can go. `on()` is different: it
+    /// matches on *no* labels, pairing every series on one side with every
+    /// series on the other, and is always kept. So is `ignoring()` followed
+    /// by `group_left` or `group_right`, which allows many-to-one matching.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::expr;
+    ///
+    /// let (_, ast) = expr("a + ignoring() b > bool on() c").unwrap();
+    /// assert_eq!(ast.remove_empty_ignoring().to_string(), "a + b > bool on () c");
+    /// ```
+    pub fn remove_empty_ignoring(mut self) -> Self {
+        let _ = self.depth_first_mut(|e| {
+            if let Expr::Binary(b) = e
+                && let Some(modifier) = &mut b.modifier
+                && let Some(VectorMatching {
+                    op: VectorMatchingOp::Ignoring,
+                    labels,
+                    group: None,
+                }) = &modifier.matching
+                && labels.is_empty()
+            {
+                modifier.matching = None;
+                if !modifier.return_bool {
+                    b.modifier = None;
+                }
+            }
+            ControlFlow::<()>::Continue(())
+        });
+        self
+    }
+
+    /// Check whether two queries could be meaningfully compared, e.g. plotted
+    /// against each other on a dashboard.
+    ///
+    /// This is a heuristic based on the query text alone:
+    ///
+    /// - Both must evaluate to the same [`ValueType`] (see
+    ///   [`Expr::value_type`]).
+    ///   Scalars are always comparable with each other, as are strings.
+    /// - Aggregations with `by` (or none) yield exactly the grouping labels,
+    ///   so two of them are comparable when they group by the same labels,
+    ///   and never comparable with a query that keeps series labels.
+    ///   Selecting aggregations (`topk`, ...) keep their input's labels.
+    /// - Otherwise, a label that every selector of both queries pins with an
+    ///   equality matcher, including the metric name, must share a value on
+    ///   both sides. `a{job="x"}` and `a{job="y"}` select disjoint series.
+    ///
+    /// Offsets and `@` modifiers are ignored, so a query is comparable with
+    /// itself shifted in time.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::expr;
+    ///
+    /// let (_, now) = expr("rate(http_requests_total[5m])").unwrap();
+    /// let (_, last_week) = expr("rate(http_requests_total[5m] offset 1w)").unwrap();
+    /// assert!(now.is_comparable_with(&last_week));
+    ///
+    /// let (_, total) = expr("sum(rate(http_requests_total[5m]))").unwrap();
+    /// assert!(!now.is_comparable_with(&total));
+    /// ```
+    pub fn is_comparable_with(&self, other: &Expr) -> bool {
+        let value_type = self.value_type();
+        if value_type != other.value_type() {
+            return false;
+        }
+        if matches!(value_type, ValueType::Scalar | ValueType::String) {
+            return true;
+        }
+        match (self.output_grouping(), other.output_grouping()) {
+            (Some(a), Some(b)) => a == b,
+            (None, None) => {
+                let (a, b) = (self.pinned_labels(), other.pinned_labels());
+                a.iter()
+                    .all(|(name, values)| b.get(name).is_none_or(|v| !v.is_disjoint(values)))
+            }
+            _ => false,
+        }
+    }
+
+    /// The labels of the result series when they are fixed by an
+    /// aggregation, or `None` when the result keeps series labels
+    fn output_grouping(&self) -> Option<BTreeSet<&str>> {
+        match self {
+            Expr::Aggregation(a) => match a.op.to_ascii_lowercase().as_str() {
+                "topk" | "bottomk" | "limitk" | "limit_ratio" => a.expr.output_grouping(),
+                _ => match &a.grouping {
+                    None => Some(BTreeSet::new()),
+                    Some(g) if g.action == GroupingAction::By => {
+                        Some(g.labels.iter().map(String::as_str).collect())
+                    }
+                    Some(_) => None,
+                },
+            },
+            // The result of a vector operation has the labels of its vector
+            // side, or of the left-hand side when both are vectors
+            Expr::Binary(b) if b.lhs.value_type() == ValueType::Scalar => b.rhs.output_grouping(),
+            Expr::Binary(b) => b.lhs.output_grouping(),
+            Expr::Paren(e) => e.output_grouping(),
+            Expr::Unary(u) => u.expr.output_grouping(),
+            Expr::Subquery(s) => s.expr.output_grouping(),
+            Expr::Call(c) => c
+                .args
+                .iter()
+                .find(|arg| matches!(arg.value_type(), ValueType::Vector | ValueType::Matrix))
+                .and_then(Expr::output_grouping),
+            _ => None,
+        }
+    }
+
+    /// Labels pinned by an equality matcher in every selector, with the
+    /// values they are pinned to
+    fn pinned_labels(&self) -> BTreeMap<String, BTreeSet<String>> {
+        let selectors = self.vector_selectors();
+        let mut pinned: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        for (i, selector) in selectors.iter().enumerate() {
+            let mut values: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+            for m in selector.all_matchers() {
+                if m.op == LabelMatchOp::Equal {
+                    values.entry(m.name).or_default().insert(m.value);
+                }
+            }
+            if i == 0 {
+                pinned = values;
+            } else {
+                pinned.retain(|name, set| match values.remove(name) {
+                    Some(more) => {
+                        set.extend(more);
+                        true
+                    }
+                    None => false,
+                });
+            }
+        }
+        pinned
+    }
+
+    /// Check if this is a comparison between constants that always holds.
+    ///
+    /// Only a top-level comparison (ignoring parentheses) whose operands fold
+    /// to numbers is considered; anything involving a selector or function
+    /// call returns `false`. A constant alert condition is almost always a
+    /// mistake.
+    ///
+    /// Without the `std` feature, `^` and `atan2` are not folded.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::expr;
+    ///
+    /// let (_, ast) = expr("1 == bool 1").unwrap();
+    /// assert!(ast.is_trivially_true());
+    ///
+    /// let (_, ast) = expr("up == 0").unwrap();
+    /// assert!(!ast.is_trivially_true());
+    /// ```
+    pub fn is_trivially_true(&self) -> bool {
+        self.constant_condition() == Some(true)
+    }
+
+    /// Check if this is a comparison between constants that never holds.
+    ///
+    /// See [`Expr::is_trivially_true`] for which expressions are considered.
+    pub fn is_trivially_false(&self) -> bool {
+        self.constant_condition() == Some(false)
+    }
+
+    /// Evaluate a top-level comparison between constant operands
+    fn constant_condition(&self) -> Option<bool> {
+        match self.unwrap_parens() {
+            Expr::Binary(b) if b.op.is_comparison() => {
+                let lhs = b.lhs.constant_value()?;
+                let rhs = b.rhs.constant_value()?;
+                Some(compare(b.op, lhs, rhs))
+            }
+            _ => None,
+        }
+    }
+
+    /// Fold an expression built only from number literals to its value
+    pub(crate) fn constant_value(&self) -> Option<f64> {
+        match self {
+            Expr::Number(n) => Some(n.value),
+            Expr::Paren(e) => e.constant_value(),
+            Expr::Unary(u) => {
+                let v = u.expr.constant_value()?;
+                match u.op {
+                    UnaryOp::Minus => Some(-v),
+                    UnaryOp::Plus => Some(v),
+                }
+            }
+            Expr::Binary(b) => {
+                let lhs = b.lhs.constant_value()?;
+                let rhs = b.rhs.constant_value()?;
+                match b.op {
+                    BinaryOp::Add => Some(lhs + rhs),
+                    BinaryOp::Sub => Some(lhs - rhs),
+                    BinaryOp::Mul => Some(lhs * rhs),
+                    BinaryOp::Div => Some(lhs / rhs),
+                    BinaryOp::Mod => Some(lhs % rhs),
+                    // `core` has no floating point math functions
+                    #[cfg(feature = "std")]
+                    BinaryOp::Pow => Some(lhs.powf(rhs)),
+                    #[cfg(feature = "std")]
+                    BinaryOp::Atan2 => Some(lhs.atan2(rhs)),
+                    // Only `bool` comparisons yield a scalar
+                    op if op.is_comparison()
+                        && b.modifier.as_ref().is_some_and(|m| m.return_bool) =>
+                    {
+                        Some(if compare(op, lhs, rhs) { 1.0 } else { 0.0 })
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Apply a comparison operator to two scalars
+fn compare(op: BinaryOp, lhs: f64, rhs: f64) -> bool {
+    match op {
+        BinaryOp::Eq => lhs == rhs,
+        BinaryOp::Ne => lhs != rhs,
+        BinaryOp::Lt => lhs < rhs,
+        BinaryOp::Le => lhs <= rhs,
+        BinaryOp::Gt => lhs > rhs,
+        BinaryOp::Ge => lhs >= rhs,
+        _ => false,
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_into(f)
+    }
+}
+
+impl FromStr for Expr {
+    type Err = ParseError;
+
+    /// Parse a complete query, like [`parse`](crate::parse).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::Expr;
+    ///
+    /// let ast: Expr = "sum(rate(x[5m]))".parse().unwrap();
+    /// assert_eq!(ast.to_string(), "sum(rate(x[5m]))");
+    ///
+    /// let err = "sum(x) foo".parse::<Expr>().unwrap_err();
+    /// assert_eq!(err.offset, 7);
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        crate::parser::options::parse(s)
+    }
+}
+
+/// One step from an expression to one of its sub-expressions.
+///
+/// A sequence of segments, as passed by [`Expr::walk_selectors_with_path`],
+/// locates a node within a query. Segments display as `lhs`, `rhs`,
+/// `arg[N]`, `param`, `aggregated`, `operand`, `paren` and `subquery`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PathSegment {
+    /// Left-hand side of a binary expression
+    Lhs,
+    /// Right-hand side of a binary expression
+    Rhs,
+    /// Function argument, by position
+    Arg(usize),
+    /// Parameter of an aggregation, like `5` in `topk(5, x)`
+    Param,
+    /// Expression being aggregated
+    Aggregated,
+    /// Operand of a unary expression
+    Operand,
+    /// Expression inside parentheses
+    Paren,
+    /// Inner expression of a subquery
+    Subquery,
+}
+
+impl fmt::Display for PathSegment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathSegment::Lhs => write!(f, "lhs"),
+            PathSegment::Rhs => write!(f, "rhs"),
+            PathSegment::Arg(i) => write!(f, "arg[{}]", i),
+            PathSegment::Param => write!(f, "param"),
+            PathSegment::Aggregated => write!(f, "aggregated"),
+            PathSegment::Operand => write!(f, "operand"),
+            PathSegment::Paren => write!(f, "paren"),
+            PathSegment::Subquery => write!(f, "subquery"),
+        }
+    }
+}
+
+/// A number literal.
+///
+/// Literals parsed from a query remember how they were written, such as
+/// `0x1F` or `1e3`, and [`Display`](fmt::Display) prints that spelling.
+/// Two literals are equal when their values are, however they were
+/// written.
+///
+/// With the `serde` feature, `NaN` and infinities serialize as the strings
+/// `"NaN"`, `"+Inf"` and `"-Inf"`, since formats like JSON have no numbers
+/// for them. A deserialized `raw` spelling that does not spell `value` is
+/// dropped.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "serde_number::NumberLiteralRepr"))]
+pub struct NumberLiteral {
+    #[cfg_attr(feature = "serde", serde(with = "serde_number"))]
+    value: f64,
+    /// The literal as written in the query; always spells `value`
+    raw: Option<String>,
+}
+
+impl NumberLiteral {
+    /// Create a number literal without a source spelling
+    pub fn new(value: f64) -> Self {
+        Self { value, raw: None }
+    }
+
+    /// Create a number literal as written in a query; `raw` must be the
+    /// spelling of `value`
+    pub(crate) fn with_raw(value: f64, raw: impl Into<String>) -> Self {
+        Self {
+            value,
+            raw: Some(raw.into()),
+        }
+    }
+
+    /// The value of the literal
+    pub fn value(&self) -> f64 {
+        self.value
+    }
+
+    /// The literal as written in the query, or `None` if it was not parsed
+    /// from a query or its value has been changed since
+    pub fn raw(&self) -> Option<&str> {
+        self.raw.as_deref()
+    }
+
+    /// Change the value of the literal, forgetting how it was written
+    pub fn set_value(&mut self, value: f64) {
+        self.value = value;
+        self.raw = None;
+    }
+}
+
+impl From<f64> for NumberLiteral {
+    fn from(value: f64) -> Self {
+        Self::new(value)
+    }
+}
+
+impl PartialEq for NumberLiteral {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl fmt::Display for NumberLiteral {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(raw) = &self.raw {
+            return write!(f, "{}", raw);
+        }
+        let n = self.value;
+        if n.is_nan() {
+            write!(f, "NaN")
+        } else if n.is_infinite() {
+            if n > 0.0 {
+                write!(f, "Inf")
+            } else {
+                write!(f, "-Inf")
+            }
+        } else if n != 0.0
+            && (-MAX_SAFE_INTEGER..=MAX_SAFE_INTEGER).contains(&n)
+            && (n as i64) as f64 == n
+        {
+            // Exactly representable integers, e.g. from hex or octal
+            // literals, print without a decimal point or exponent
+            write!(f, "{}", n as i64)
+        } else {
+            write!(f, "{}", n)
+        }
+    }
+}
+
+/// Function call expression
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Call {
+    /// Function name
+    pub name: String,
+    /// Function arguments
+    pub args: Vec<Expr>,
+}
+
+impl Call {
+    /// Create a new function call
+    pub fn new(name: impl Into<String>, args: Vec<Expr>) -> Self {
+        Self {
+            name: name.into(),
+            args,
+        }
+    }
+
+    /// Look up the builtin function being called, or `None` if the name is
+    /// not a known function.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::{Expr, expr};
+    /// use rusty_promql_parser::parser::function::ValueType;
+    ///
+    /// let (_, ast) = expr("rate(foo[5m])").unwrap();
+    /// let Expr::Call(call) = ast else { unreachable!() };
+    /// assert_eq!(
+    ///     call.known_function().map(|f| f.return_type),
+    ///     Some(ValueType::Vector)
+    /// );
+    /// ```
+    pub fn known_function(&self) -> Option<&'static Function> {
+        get_function(&self.name)
+    }
+
+    /// Replace the argument at `index` with `arg`, returning the old one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::{Expr, expr};
+    ///
+    /// let (_, ast) = expr("histogram_quantile(0.9, x)").unwrap();
+    /// let Expr::Call(mut call) = ast else { unreachable!() };
+    /// let old = call.replace_arg(0, Expr::Number(0.99.into()));
+    /// assert_eq!(old, Expr::Number(0.9.into()));
+    /// assert_eq!(call.to_string(), "histogram_quantile(0.99, x)");
+    /// ```
+    pub fn replace_arg(&mut self, index: usize, arg: Expr) -> Expr {
+        core::mem::replace(&mut self.args[index], arg)
+    }
+}
+
+impl fmt::Display for Call {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}(", self.name)?;
+        for (i, arg) in self.args.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", arg)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Aggregation expression
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Aggregation {
+    /// The aggregation operator name
+    pub op: String,
+    /// The expression to aggregate
+    pub expr: Expr,
+    /// Parameter for parametric aggregations (topk, quantile, etc.)
+    pub param: Option<Expr>,
+    /// Optional grouping clause (by/without).
+    ///
+    /// `None` (no clause) is kept apart from an empty clause so that the
+    /// query prints as written: `sum(x)` and `sum by () (x)` both aggregate
+    /// everything into one series, while `sum without () (x)` keeps all
+    /// labels and so aggregates nothing away.
+    pub grouping: Option<Grouping>,
+}
+
+impl Aggregation {
+    /// Create a new aggregation
+    pub fn new(op: impl Into<String>, expr: Expr) -> Self {
+        Self {
+            op: op.into(),
+            expr,
+            param: None,
+            grouping: None,
+        }
+    }
+
+    /// Create a new aggregation with a parameter
+    pub fn with_param(op: impl Into<String>, param: Expr, expr: Expr) -> Self {
+        Self {
+            op: op.into(),
+            expr,
+            param: Some(param),
+            grouping: None,
+        }
+    }
+
+    /// Set the grouping clause
+    pub fn with_grouping(mut self, grouping: Grouping) -> Self {
+        self.grouping = Some(grouping);
+        self
+    }
+
+    /// Add `label` to the grouping clause, keeping its `by` or `without`
+    /// action. Returns `false` if the label was already listed.
+    ///
+    /// Without a clause, a `by` clause is created: aggregating without one
+    /// is the same as `by ()`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::{Expr, expr};
+    ///
+    /// let (_, ast) = expr("sum by (job) (x)").unwrap();
+    /// let Expr::Aggregation(mut agg) = ast else { unreachable!() };
+    /// assert!(agg.add_group_label("instance"));
+    /// assert!(!agg.add_group_label("job"));
+    /// assert_eq!(agg.to_string(), "sum by (job, instance) (x)");
+    /// ```
+    pub fn add_group_label(&mut self, label: impl Into<String>) -> bool {
+        let label = label.into();
+        let grouping = self.grouping.get_or_insert_with(|| Grouping {
+            action: GroupingAction::By,
+            labels: Vec::new(),
+        });
+        if grouping.labels.contains(&label) {
+            return false;
+        }
+        grouping.labels.push(label);
+        true
+    }
+
+    /// Remove `label` from the grouping clause. Returns `false` if it was
+    /// not listed.
+    ///
+    /// The clause is kept even when it becomes empty, since `without ()`
+    /// and no clause aggregate differently.
+    pub fn remove_group_label(&mut self, label: &str) -> bool {
+        let Some(grouping) = &mut self.grouping else {
+            return false;
+        };
+        let len = grouping.labels.len();
+        grouping.labels.retain(|l| l != label);
+        grouping.labels.len() != len
+    }
+}
+
+impl fmt::Display for Aggregation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.op)?;
+        if let Some(ref grouping) = self.grouping {
+            write!(f, " {} ", grouping)?;
+        }
+        write!(f, "(")?;
+        if let Some(ref param) = self.param {
+            write!(f, "{}, ", param)?;
+        }
+        write!(f, "{})", self.expr)
+    }
+}
+
+/// Binary operators
+///
+/// This enum is `#[non_exhaustive]` so that new Prometheus operators can be
+/// added without a breaking release. Code that matched on every variant
+/// needs a wildcard arm; prefer the helper methods instead:
+///
+/// - [`BinaryOp::all`] to enumerate the operators
+/// - [`BinaryOp::as_str`] and [`str::parse`] to convert to and from PromQL
+/// - [`BinaryOp::is_comparison`], [`BinaryOp::is_set_operator`] and
+///   [`BinaryOp::is_arithmetic`] to classify an operator
+///
+/// ```rust
+/// use rusty_promql_parser::BinaryOp;
+///
+/// let op: BinaryOp = "unless".parse().unwrap();
+/// assert!(op.is_set_operator());
+/// assert_eq!(op.as_str(), "unless");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum BinaryOp {
+    // Arithmetic
+    Add,   // +
+    Sub,   // -
+    Mul,   // *
+    Div,   // /
+    Mod,   // %
+    Pow,   // ^
+    Atan2, // atan2
+
+    // Comparison
+    Eq, // ==
+    Ne, // !=
+    Lt, // <
+    Le, // <=
+    Gt, // >
+    Ge, // >=
+
+    // Set operations
+    And,    // and
+    Or,     // or
+    Unless, // unless
+}
+
+impl BinaryOp {
+    /// All binary operators, from lowest to highest precedence
+    pub fn all() -> &'static [BinaryOp] {
+        &[
+            BinaryOp::Or,
+            BinaryOp::And,
+            BinaryOp::Unless,
+            BinaryOp::Eq,
+            BinaryOp::Ne,
+            BinaryOp::Lt,
+            BinaryOp::Le,
+            BinaryOp::Gt,
+            BinaryOp::Ge,
+            BinaryOp::Add,
+            BinaryOp::Sub,
+            BinaryOp::Mul,
+            BinaryOp::Div,
+            BinaryOp::Mod,
+            BinaryOp::Atan2,
+            BinaryOp::Pow,
+        ]
+    }
+
+    /// Get the operator as a string
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Mod => "%",
+            BinaryOp::Pow => "^",
+            BinaryOp::Atan2 => "atan2",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::And => "and",
+            BinaryOp::Or => "or",
+            BinaryOp::Unless => "unless",
+        }
+    }
+
+    /// Get the precedence of this operator (higher = binds tighter)
+    pub fn precedence(&self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,                     // Lowest
+            BinaryOp::And | BinaryOp::Unless => 2, // Set intersection/difference
+            BinaryOp::Eq
+            | BinaryOp::Ne
+            | BinaryOp::Lt
+            | BinaryOp::Le
+            | BinaryOp::Gt
+            | BinaryOp::Ge => 3, // Comparison
+            BinaryOp::Add | BinaryOp::Sub => 4,    // Addition/subtraction
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod | BinaryOp::Atan2 => 5, // Multiplication/division
+            BinaryOp::Pow => 6,                                                   // Highest
+        }
+    }
+
+    /// Check if this operator is right-associative
+    pub fn is_right_associative(&self) -> bool {
+        matches!(self, BinaryOp::Pow)
+    }
+
+    /// Check if this is a comparison operator
+    pub fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge
+        )
+    }
+
+    /// Check if this is a set operator
+    pub fn is_set_operator(&self) -> bool {
+        matches!(self, BinaryOp::And | BinaryOp::Or | BinaryOp::Unless)
+    }
+
+    /// Check if swapping the operands leaves the set of matched series
+    /// unchanged: `+`, `*`, `and` and `or`.
+    ///
+    /// For `and` and `or`, sample values and labels still come from the
+    /// left-hand side where both sides match.
+    pub fn is_commutative(&self) -> bool {
+        matches!(
+            self,
+            BinaryOp::Add | BinaryOp::Mul | BinaryOp::And | BinaryOp::Or
+        )
+    }
+
+    /// Check if this is an arithmetic operator
+    pub fn is_arithmetic(&self) -> bool {
+        matches!(
+            self,
+            BinaryOp::Add
+                | BinaryOp::Sub
+                | BinaryOp::Mul
+                | BinaryOp::Div
+                | BinaryOp::Mod
+                | BinaryOp::Pow
+                | BinaryOp::Atan2
+        )
+    }
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for BinaryOp {
+    type Err = ParseOperatorError;
+
+    /// Parse an operator from its PromQL spelling.
+    ///
+    /// Keyword operators (`and`, `or`, `unless`, `atan2`) are matched
+    /// case-insensitively, like in queries.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        BinaryOp::all()
+            .iter()
+            .find(|op| op.as_str().eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or_else(|| ParseOperatorError {
+                input: s.to_string(),
+            })
+    }
+}
+
+/// Error returned when parsing an unknown operator with [`str::parse`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseOperatorError {
+    input: String,
+}
+
+impl fmt::Display for ParseOperatorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown operator {:?}", self.input)
+    }
+}
+
+impl core::error::Error for ParseOperatorError {}
+
+/// Error returned by [`Expr::with_offset`] and [`Expr::with_at`] for
+/// expressions that cannot take the modifier
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModifierError {
+    modifier: &'static str,
+    kind: ExprKind,
+}
+
+impl ModifierError {
+    fn new(modifier: &'static str, kind: ExprKind) -> Self {
+        Self { modifier, kind }
+    }
+
+    /// The kind of expression the modifier was applied to
+    pub fn kind(&self) -> ExprKind {
+        self.kind
+    }
+}
+
+impl fmt::Display for ModifierError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let article = match self.kind {
+            ExprKind::Aggregation => "an",
+            _ => "a",
+        };
+        write!(
+            f,
+            "the {} modifier cannot be applied to {} {}",
+            self.modifier, article, self.kind
+        )
+    }
+}
+
+impl core::error::Error for ModifierError {}
+
+/// Vector matching for binary operations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum VectorMatchingOp {
+    On,       // on (label1, label2)
+    Ignoring, // ignoring (label1, label2)
+}
+
+impl fmt::Display for VectorMatchingOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VectorMatchingOp::On => write!(f, "on"),
+            VectorMatchingOp::Ignoring => write!(f, "ignoring"),
+        }
+    }
+}
+
+/// Group modifier side
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum GroupSide {
+    Left,  // group_left
+    Right, // group_right
+}
+
+impl fmt::Display for GroupSide {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GroupSide::Left => write!(f, "group_left"),
+            GroupSide::Right => write!(f, "group_right"),
+        }
+    }
+}
+
+/// Group modifier for many-to-one/one-to-many matching
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GroupModifier {
+    /// Which side to group (left or right)
+    pub side: GroupSide,
+    /// Additional labels to include from the "one" side
+    pub labels: Vec<String>,
+}
+
+impl fmt::Display for GroupModifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.side)?;
+        if !self.labels.is_empty() {
+            write!(f, " ({})", LabelList(&self.labels))?;
+        }
+        Ok(())
+    }
+}
+
+/// Vector matching specification for binary operations
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VectorMatching {
     /// The matching operation (on or ignoring)
     pub op: VectorMatchingOp,
     /// Labels to match on or ignore
@@ -393,14 +2078,7 @@ pub struct VectorMatching {
 
 impl fmt::Display for VectorMatching {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{} (", self.op)?;
-        for (i, label) in self.labels.iter().enumerate() {
-            if i > 0 {
-                write!(f, ", ")?;
-            }
-            write!(f, "{}", label)?;
-        }
-        write!(f, ")")?;
+        write!(f, "{} ({})", self.op, LabelList(&self.labels))?;
         if let Some(ref group) = self.group {
             write!(f, " {}", group)?;
         }
@@ -410,6 +2088,7 @@ impl fmt::Display for VectorMatching {
 
 /// Modifier for binary operations
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BinaryModifier {
     /// Whether to return bool (0/1) instead of filtering for comparisons
     pub return_bool: bool,
@@ -457,6 +2136,7 @@ impl fmt::Display for BinaryModifier {
 
 /// Binary expression
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BinaryExpr {
     /// The binary operator
     pub op: BinaryOp,
@@ -488,6 +2168,50 @@ impl BinaryExpr {
             modifier: Some(modifier),
         }
     }
+
+    /// Create a new binary expression, wrapping operands in [`Expr::Paren`]
+    /// where precedence or associativity would otherwise regroup them when
+    /// printed and parsed again
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::ast::{BinaryExpr, BinaryOp, Expr};
+    ///
+    /// let sum = Expr::Binary(Box::new(BinaryExpr::new(
+    ///     BinaryOp::Add,
+    ///     Expr::Number(1.0.into()),
+    ///     Expr::Number(2.0.into()),
+    /// )));
+    /// let product = BinaryExpr::with_precedence_parens(BinaryOp::Mul, sum, Expr::Number(3.0.into()));
+    /// assert_eq!(product.to_string(), "(1 + 2) * 3");
+    /// ```
+    pub fn with_precedence_parens(op: BinaryOp, lhs: Expr, rhs: Expr) -> Self {
+        let wrap = |operand: Expr, is_lhs: bool| {
+            if needs_parens(&operand, op, is_lhs) {
+                Expr::Paren(Box::new(operand))
+            } else {
+                operand
+            }
+        };
+        Self::new(op, wrap(lhs, true), wrap(rhs, false))
+    }
+}
+
+/// Check if `operand` must be parenthesized to stay an operand of `op`
+fn needs_parens(operand: &Expr, op: BinaryOp, is_lhs: bool) -> bool {
+    match operand {
+        Expr::Binary(inner) => {
+            let (inner, outer) = (inner.op.precedence(), op.precedence());
+            // A left-associative operator groups with its left operand, a
+            // right-associative one with its right operand
+            inner < outer || (inner == outer && is_lhs == op.is_right_associative())
+        }
+        // This parser reads `-a ^ b` as `(-a) ^ b` but Prometheus reads it
+        // as `-(a ^ b)`, so spell out the grouping for both
+        Expr::Unary(_) => is_lhs && op == BinaryOp::Pow,
+        _ => false,
+    }
 }
 
 impl fmt::Display for BinaryExpr {
@@ -503,7 +2227,13 @@ impl fmt::Display for BinaryExpr {
 }
 
 /// Unary operators
+///
+/// Like [`BinaryOp`], this enum is `#[non_exhaustive]`; use
+/// [`UnaryOp::all`], [`UnaryOp::as_str`] and [`str::parse`] rather than
+/// exhaustive matches.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum UnaryOp {
     /// Unary plus (no-op)
     Plus,
@@ -512,6 +2242,11 @@ pub enum UnaryOp {
 }
 
 impl UnaryOp {
+    /// All unary operators
+    pub fn all() -> &'static [UnaryOp] {
+        &[UnaryOp::Plus, UnaryOp::Minus]
+    }
+
     /// Get the operator as a string
     pub fn as_str(&self) -> &'static str {
         match self {
@@ -527,8 +2262,23 @@ impl fmt::Display for UnaryOp {
     }
 }
 
+impl FromStr for UnaryOp {
+    type Err = ParseOperatorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        UnaryOp::all()
+            .iter()
+            .find(|op| op.as_str() == s)
+            .copied()
+            .ok_or_else(|| ParseOperatorError {
+                input: s.to_string(),
+            })
+    }
+}
+
 /// Unary expression
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct UnaryExpr {
     /// The unary operator
     pub op: UnaryOp,
@@ -551,6 +2301,7 @@ impl fmt::Display for UnaryExpr {
 
 /// Subquery expression
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SubqueryExpr {
     /// The inner expression to evaluate as a subquery
     pub expr: Expr,
@@ -564,50 +2315,239 @@ pub struct SubqueryExpr {
     pub at: Option<AtModifier>,
 }
 
-impl SubqueryExpr {
-    /// Create a new subquery expression
-    pub fn new(expr: Expr, range: Duration) -> Self {
-        Self {
-            expr,
-            range,
-            step: None,
-            offset: None,
-            at: None,
+impl SubqueryExpr {
+    /// Create a new subquery expression
+    pub fn new(expr: Expr, range: Duration) -> Self {
+        Self {
+            expr,
+            range,
+            step: None,
+            offset: None,
+            at: None,
+        }
+    }
+
+    /// Create a new subquery expression with step
+    pub fn with_step(expr: Expr, range: Duration, step: Duration) -> Self {
+        Self {
+            expr,
+            range,
+            step: Some(step),
+            offset: None,
+            at: None,
+        }
+    }
+
+    /// Set the step to `step` if none was given.
+    ///
+    /// See [`Expr::with_default_subquery_step`] to apply this to a whole
+    /// expression tree.
+    pub fn with_default_step(mut self, step: Duration) -> Self {
+        self.step.get_or_insert(step);
+        self
+    }
+}
+
+impl fmt::Display for SubqueryExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}[{}:", self.expr, self.range)?;
+        if let Some(ref step) = self.step {
+            write!(f, "{}", step)?;
+        }
+        write!(f, "]")?;
+        if let Some(ref at) = self.at {
+            write!(f, " {}", at)?;
+        }
+        if let Some(ref offset) = self.offset {
+            write!(f, " offset {}", offset)?;
+        }
+        Ok(())
+    }
+}
+
+/// Sort label matchers by name, then by their printed form
+fn sort_matchers(matchers: &mut [LabelMatcher]) {
+    matchers.sort_by_cached_key(|m| (m.name.clone(), m.to_string()));
+}
+
+/// Replace `e` with `(e)`
+fn wrap_in_parens(e: &mut Expr) {
+    let owned = core::mem::replace(e, Expr::Number(NumberLiteral::new(0.0)));
+    *e = Expr::Paren(Box::new(owned));
+}
+
+/// Check whether `selector` reads the metric `name`, by its metric name or
+/// a `__name__="name"` matcher
+fn selects_metric(selector: &VectorSelector, name: &str) -> bool {
+    selector.name.as_deref() == Some(name)
+        || selector
+            .matchers
+            .iter()
+            .any(|m| m.name == "__name__" && m.op == LabelMatchOp::Equal && m.value == name)
+}
+
+/// 64-bit FNV-1a hasher backing [`Expr::hash_stable`]
+struct StableHasher(u64);
+
+impl StableHasher {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    fn new() -> Self {
+        Self(Self::OFFSET_BASIS)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= u64::from(byte);
+            self.0 = self.0.wrapping_mul(Self::PRIME);
+        }
+    }
+
+    /// Feed the fields of `node` except its children, which the caller
+    /// feeds after it in pre-order
+    fn write_node(&mut self, node: &Expr) -> fmt::Result {
+        use fmt::Write;
+
+        match node {
+            Expr::Paren(_) => return Ok(()),
+            Expr::Number(n) => {
+                let bits = if n.value.is_nan() { f64::NAN } else { n.value }.to_bits();
+                write!(self, "n{}", bits)?;
+            }
+            Expr::String(s) => write!(self, "s{}", s)?,
+            Expr::VectorSelector(v) => write!(self, "v{}", v)?,
+            Expr::MatrixSelector(m) => write!(self, "m{}", m)?,
+            Expr::Call(c) => write!(self, "c{}/{}", c.name, c.args.len())?,
+            Expr::Aggregation(a) => {
+                write!(self, "a{}/{}", a.op, a.param.is_some())?;
+                if let Some(grouping) = &a.grouping {
+                    write!(self, "/{}", grouping)?;
+                }
+            }
+            Expr::Binary(b) => {
+                write!(self, "b{}", b.op)?;
+                if let Some(modifier) = &b.modifier
+                    && !modifier.is_empty()
+                {
+                    write!(self, "/{}", modifier)?;
+                }
+            }
+            Expr::Unary(u) => write!(self, "u{}", u.op)?,
+            Expr::Subquery(q) => {
+                write!(self, "q{}", q.range)?;
+                if let Some(step) = q.step {
+                    write!(self, "/{}", step)?;
+                }
+                if let Some(at) = &q.at {
+                    write!(self, "/{}", at)?;
+                }
+                if let Some(offset) = q.offset {
+                    write!(self, "/offset {}", offset)?;
+                }
+            }
+        }
+        // Terminate the node; 0xff never occurs in UTF-8 text
+        self.write(&[0xff]);
+        Ok(())
+    }
+}
+
+impl fmt::Write for StableHasher {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Serialization of [`Expr::Number`] values that keeps `NaN` and infinities
+#[cfg(feature = "serde")]
+mod serde_number {
+    use alloc::string::String;
+    use core::fmt;
+    use serde::de::{self, Visitor};
+    use serde::{Deserializer, Serializer};
+
+    use super::NumberLiteral;
+    use crate::lexer::number::number;
+
+    /// [`NumberLiteral`] as deserialized, before `raw` is checked
+    #[derive(serde::Deserialize)]
+    pub(super) struct NumberLiteralRepr {
+        #[serde(with = "self")]
+        value: f64,
+        #[serde(default)]
+        raw: Option<String>,
+    }
+
+    impl From<NumberLiteralRepr> for NumberLiteral {
+        fn from(repr: NumberLiteralRepr) -> Self {
+            let spells_value = |raw: &String| match number(raw) {
+                Ok(("", parsed)) => {
+                    parsed.to_bits() == repr.value.to_bits()
+                        || (parsed.is_nan() && repr.value.is_nan())
+                }
+                _ => false,
+            };
+            NumberLiteral {
+                value: repr.value,
+                raw: repr.raw.filter(spells_value),
+            }
         }
     }
 
-    /// Create a new subquery expression with step
-    pub fn with_step(expr: Expr, range: Duration, step: Duration) -> Self {
-        Self {
-            expr,
-            range,
-            step: Some(step),
-            offset: None,
-            at: None,
+    pub(super) fn serialize<S: Serializer>(value: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+        if value.is_nan() {
+            serializer.serialize_str("NaN")
+        } else if *value == f64::INFINITY {
+            serializer.serialize_str("+Inf")
+        } else if *value == f64::NEG_INFINITY {
+            serializer.serialize_str("-Inf")
+        } else {
+            serializer.serialize_f64(*value)
         }
     }
-}
 
-impl fmt::Display for SubqueryExpr {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}[{}:", self.expr, self.range)?;
-        if let Some(ref step) = self.step {
-            write!(f, "{}", step)?;
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+        deserializer.deserialize_any(NumberVisitor)
+    }
+
+    struct NumberVisitor;
+
+    impl Visitor<'_> for NumberVisitor {
+        type Value = f64;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("a number, \"NaN\", \"+Inf\" or \"-Inf\"")
         }
-        write!(f, "]")?;
-        if let Some(ref at) = self.at {
-            write!(f, " {}", at)?;
+
+        fn visit_f64<E: de::Error>(self, value: f64) -> Result<f64, E> {
+            Ok(value)
         }
-        if let Some(ref offset) = self.offset {
-            write!(f, " offset {}", offset)?;
+
+        fn visit_i64<E: de::Error>(self, value: i64) -> Result<f64, E> {
+            Ok(value as f64)
+        }
+
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<f64, E> {
+            Ok(value as f64)
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<f64, E> {
+            match value {
+                "NaN" => Ok(f64::NAN),
+                "+Inf" => Ok(f64::INFINITY),
+                "-Inf" => Ok(f64::NEG_INFINITY),
+                _ => Err(E::invalid_value(de::Unexpected::Str(value), &self)),
+            }
         }
-        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashMap;
 
     #[test]
     fn test_binary_op_precedence() {
@@ -619,6 +2559,27 @@ mod tests {
         assert!(BinaryOp::Mul.precedence() < BinaryOp::Pow.precedence());
     }
 
+    #[test]
+    fn test_binary_op_all_round_trip() {
+        for op in BinaryOp::all() {
+            assert_eq!(op.as_str().parse::<BinaryOp>(), Ok(*op));
+        }
+        assert_eq!(BinaryOp::all().len(), 16);
+        assert_eq!("AND".parse::<BinaryOp>(), Ok(BinaryOp::And));
+        assert!("=".parse::<BinaryOp>().is_err());
+    }
+
+    #[test]
+    fn test_unary_op_all_round_trip() {
+        for op in UnaryOp::all() {
+            assert_eq!(op.as_str().parse::<UnaryOp>(), Ok(*op));
+        }
+        assert_eq!(
+            "!".parse::<UnaryOp>().unwrap_err().to_string(),
+            "unknown operator \"!\""
+        );
+    }
+
     #[test]
     fn test_binary_op_associativity() {
         assert!(!BinaryOp::Add.is_right_associative());
@@ -626,6 +2587,46 @@ mod tests {
         assert!(BinaryOp::Pow.is_right_associative());
     }
 
+    #[test]
+    fn test_binary_expr_with_precedence_parens() {
+        let var = |name: &str| Expr::VectorSelector(VectorSelector::new(name));
+        let bin =
+            |op, lhs, rhs| Expr::Binary(Box::new(BinaryExpr::with_precedence_parens(op, lhs, rhs)));
+
+        let a_plus_b = bin(BinaryOp::Add, var("a"), var("b"));
+        let e = bin(BinaryOp::Mul, a_plus_b.clone(), var("c"));
+        assert_eq!(e.to_string(), "(a + b) * c");
+        let e = bin(BinaryOp::Mul, var("c"), a_plus_b.clone());
+        assert_eq!(e.to_string(), "c * (a + b)");
+
+        // Tighter operators and left-nested chains need no parentheses
+        let a_times_b = bin(BinaryOp::Mul, var("a"), var("b"));
+        assert_eq!(
+            bin(BinaryOp::Add, a_times_b, var("c")).to_string(),
+            "a * b + c"
+        );
+        let e = bin(BinaryOp::Sub, a_plus_b.clone(), var("c"));
+        assert_eq!(e.to_string(), "a + b - c");
+
+        // Associativity decides which side of an equal-precedence chain wraps
+        assert_eq!(
+            bin(BinaryOp::Sub, var("c"), a_plus_b).to_string(),
+            "c - (a + b)"
+        );
+        let a_pow_b = bin(BinaryOp::Pow, var("a"), var("b"));
+        assert_eq!(
+            bin(BinaryOp::Pow, a_pow_b.clone(), var("c")).to_string(),
+            "(a ^ b) ^ c"
+        );
+        assert_eq!(
+            bin(BinaryOp::Pow, var("c"), a_pow_b).to_string(),
+            "c ^ a ^ b"
+        );
+
+        let neg = Expr::Unary(Box::new(UnaryExpr::new(UnaryOp::Minus, var("a"))));
+        assert_eq!(bin(BinaryOp::Pow, neg, var("b")).to_string(), "(-a) ^ b");
+    }
+
     #[test]
     fn test_binary_op_categories() {
         assert!(BinaryOp::Add.is_arithmetic());
@@ -635,11 +2636,47 @@ mod tests {
 
     #[test]
     fn test_expr_display_number() {
-        assert_eq!(Expr::Number(42.0).to_string(), "42");
-        assert_eq!(Expr::Number(3.5).to_string(), "3.5");
-        assert_eq!(Expr::Number(f64::INFINITY).to_string(), "Inf");
-        assert_eq!(Expr::Number(f64::NEG_INFINITY).to_string(), "-Inf");
-        assert_eq!(Expr::Number(f64::NAN).to_string(), "NaN");
+        assert_eq!(Expr::Number(42.0.into()).to_string(), "42");
+        assert_eq!(Expr::Number(3.5.into()).to_string(), "3.5");
+        assert_eq!(Expr::Number(f64::INFINITY.into()).to_string(), "Inf");
+        assert_eq!(Expr::Number(f64::NEG_INFINITY.into()).to_string(), "-Inf");
+        assert_eq!(Expr::Number(f64::NAN.into()).to_string(), "NaN");
+        assert_eq!(Expr::Number(3735928559.0.into()).to_string(), "3735928559");
+        assert_eq!(
+            Expr::Number(MAX_SAFE_INTEGER.into()).to_string(),
+            "9007199254740992"
+        );
+        assert_eq!(Expr::Number((-493.0).into()).to_string(), "-493");
+    }
+
+    #[test]
+    fn test_number_literal_raw() {
+        let input = "0x1F + 1e10 * 0755 - 1E3 / .5 + inf";
+        let (_, mut e) = crate::parser::expr::expr(input).unwrap();
+        assert_eq!(e.to_string(), input);
+
+        // Equality and canonical form only look at the value
+        let (_, plain) =
+            crate::parser::expr::expr("31 + 10000000000 * 493 - 1000 / 0.5 + Inf").unwrap();
+        assert_eq!(e, plain);
+        assert_eq!(e.canonical_string(), plain.to_string());
+
+        // Changing the value drops the spelling
+        let _ = e.depth_first_mut(|node| {
+            if let Expr::Number(n) = node
+                && n.value() == 31.0
+            {
+                n.set_value(32.0);
+            }
+            ControlFlow::<()>::Continue(())
+        });
+        assert_eq!(e.to_string(), "32 + 1e10 * 0755 - 1E3 / .5 + inf");
+
+        let mut n = NumberLiteral::with_raw(31.0, "0x1F");
+        assert_eq!(n.raw(), Some("0x1F"));
+        n.set_value(-0.0);
+        assert_eq!(n.raw(), None);
+        assert_eq!(n.to_string(), "-0");
     }
 
     #[test]
@@ -649,16 +2686,20 @@ mod tests {
 
     #[test]
     fn test_unary_expr_display() {
-        let expr = UnaryExpr::new(UnaryOp::Minus, Expr::Number(42.0));
+        let expr = UnaryExpr::new(UnaryOp::Minus, Expr::Number(42.0.into()));
         assert_eq!(expr.to_string(), "-42");
 
-        let expr = UnaryExpr::new(UnaryOp::Plus, Expr::Number(42.0));
+        let expr = UnaryExpr::new(UnaryOp::Plus, Expr::Number(42.0.into()));
         assert_eq!(expr.to_string(), "+42");
     }
 
     #[test]
     fn test_binary_expr_display() {
-        let expr = BinaryExpr::new(BinaryOp::Add, Expr::Number(1.0), Expr::Number(2.0));
+        let expr = BinaryExpr::new(
+            BinaryOp::Add,
+            Expr::Number(1.0.into()),
+            Expr::Number(2.0.into()),
+        );
         assert_eq!(expr.to_string(), "1 + 2");
     }
 
@@ -679,16 +2720,893 @@ mod tests {
 
     #[test]
     fn test_expr_is_scalar() {
-        assert!(Expr::Number(42.0).is_scalar());
+        assert!(Expr::Number(42.0.into()).is_scalar());
         assert!(!Expr::String("test".to_string()).is_scalar());
     }
 
     #[test]
     fn test_expr_unwrap_parens() {
-        let inner = Expr::Number(42.0);
+        let inner = Expr::Number(42.0.into());
         let paren = Expr::Paren(Box::new(inner.clone()));
         let double_paren = Expr::Paren(Box::new(paren.clone()));
 
         assert_eq!(*double_paren.unwrap_parens(), inner);
     }
+
+    #[test]
+    fn test_expr_from_str() {
+        let e: Expr = "  rate(x[5m]) * 2 ".parse().unwrap();
+        assert_eq!(e, crate::parser::expr::expr("rate(x[5m]) * 2").unwrap().1);
+
+        assert_eq!(
+            "rate(x[5m]) )".parse::<Expr>(),
+            Err(ParseError::new(
+                12,
+                "unexpected closing delimiter ')' at column 13"
+            ))
+        );
+        assert!("".parse::<Expr>().is_err());
+    }
+
+    #[test]
+    fn test_expr_children() {
+        let cases = [
+            ("42", vec![]),
+            ("x[5m]", vec![]),
+            (
+                r#"label_replace(x, "a", "b", "c", "d")"#,
+                vec!["x", r#""a""#, r#""b""#, r#""c""#, r#""d""#],
+            ),
+            ("sum(x)", vec!["x"]),
+            ("topk(3, x)", vec!["3", "x"]),
+            ("a + on (job) b", vec!["a", "b"]),
+            ("-x", vec!["x"]),
+            ("(a + b)", vec!["a + b"]),
+            ("rate(x[5m])[1h:]", vec!["rate(x[5m])"]),
+        ];
+        for (input, expected) in cases {
+            let (_, e) = crate::parser::expr::expr(input).unwrap();
+            let children: Vec<String> = e.children().iter().map(|c| c.to_string()).collect();
+            assert_eq!(children, expected, "{}", input);
+        }
+
+        let (_, mut e) = crate::parser::expr::expr("topk(3, x)").unwrap();
+        for child in e.children_mut() {
+            if let Expr::Number(n) = child {
+                n.set_value(5.0);
+            }
+        }
+        assert_eq!(e.to_string(), "topk(5, x)");
+    }
+
+    #[test]
+    fn test_series_matchers_binary() {
+        let (_, e) = crate::parser::expr::expr(
+            r#"rate(http_requests{job="api"}[5m]) / on(job) up{job="api"}"#,
+        )
+        .unwrap();
+        let sets = e.series_matchers();
+        assert_eq!(sets.len(), 2);
+        assert_eq!(
+            sets[0],
+            vec![
+                LabelMatcher::new("__name__", LabelMatchOp::Equal, "http_requests"),
+                LabelMatcher::new("job", LabelMatchOp::Equal, "api"),
+            ]
+        );
+        assert_eq!(
+            sets[1],
+            vec![
+                LabelMatcher::new("__name__", LabelMatchOp::Equal, "up"),
+                LabelMatcher::new("job", LabelMatchOp::Equal, "api"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_series_matchers_dedup() {
+        let (_, e) =
+            crate::parser::expr::expr(r#"foo{a="1", b="2"} + foo{b="2", a="1"} + foo"#).unwrap();
+        let sets = e.series_matchers();
+        assert_eq!(sets.len(), 2);
+        assert_eq!(sets[0].len(), 3);
+        assert_eq!(sets[1].len(), 1);
+    }
+
+    #[test]
+    fn test_subquery_with_default_step() {
+        let sq = SubqueryExpr::new(Expr::Number(1.0.into()), Duration::from_secs(300))
+            .with_default_step(Duration::from_secs(60));
+        assert_eq!(sq.step, Some(Duration::from_secs(60)));
+        assert_eq!(sq.to_string(), "1[5m:1m]");
+
+        // An explicit step wins over the default
+        let sq = SubqueryExpr::with_step(
+            Expr::Number(1.0.into()),
+            Duration::from_secs(300),
+            Duration::from_secs(30),
+        )
+        .with_default_step(Duration::from_secs(60));
+        assert_eq!(sq.to_string(), "1[5m:30s]");
+    }
+
+    #[test]
+    fn test_expr_with_default_subquery_step() {
+        let (_, e) =
+            crate::parser::expr::expr("max_over_time(x[10m:]) + min_over_time(rate(y[1m])[1h:5m])")
+                .unwrap();
+        let e = e.with_default_subquery_step(Duration::from_secs(60));
+        assert_eq!(
+            e.to_string(),
+            "max_over_time(x[10m:1m]) + min_over_time(rate(y[1m])[1h:5m])"
+        );
+    }
+
+    #[test]
+    fn test_vector_selectors_mut_offsets() {
+        let (_, mut e) = crate::parser::expr::expr(
+            "sum(rate(a[5m])) / on(job) b + max_over_time(c[10m:1m] offset 1d)",
+        )
+        .unwrap();
+        let selectors = e.vector_selectors_mut();
+        assert_eq!(selectors.len(), 3);
+        for selector in selectors {
+            selector.offset = Some(Duration::from_secs(3600));
+        }
+        assert_eq!(
+            e.to_string(),
+            "sum(rate(a[5m] offset 1h)) / on (job) b offset 1h + max_over_time(c offset 1h[10m:1m] offset 1d)"
+        );
+        assert!(
+            e.vector_selectors()
+                .iter()
+                .all(|s| s.offset == Some(Duration::from_secs(3600)))
+        );
+    }
+
+    #[test]
+    fn test_matrix_selectors_mut_range() {
+        let (_, mut e) = crate::parser::expr::expr("rate(a[5m]) + increase(b[1h]) + c").unwrap();
+        for m in e.matrix_selectors_mut() {
+            m.range = Duration::from_secs(600);
+        }
+        assert_eq!(e.to_string(), "rate(a[10m]) + increase(b[10m]) + c");
+    }
+
+    #[test]
+    fn test_walk_selectors_with_path() {
+        let (_, e) = crate::parser::expr::expr(
+            r#"sum by (job) (topk(k, x)) / -(histogram_quantile(0.9, rate(y{tenant="a"}[5m])) + z[1h:])"#,
+        )
+        .unwrap();
+        let mut found = Vec::new();
+        e.walk_selectors_with_path(|path, selector| {
+            found.push((path.to_vec(), selector.name.clone().unwrap()));
+        });
+        use PathSegment::*;
+        assert_eq!(
+            found,
+            [
+                (vec![Lhs, Aggregated, Param], "k".to_string()),
+                (vec![Lhs, Aggregated, Aggregated], "x".to_string()),
+                (
+                    vec![Rhs, Operand, Paren, Lhs, Arg(1), Arg(0)],
+                    "y".to_string()
+                ),
+                (vec![Rhs, Operand, Paren, Rhs, Subquery], "z".to_string()),
+            ]
+        );
+
+        let (_, e) = crate::parser::expr::expr("up").unwrap();
+        let mut paths = Vec::new();
+        e.walk_selectors_with_path(|path, _| paths.push(path.len()));
+        assert_eq!(paths, [0]);
+    }
+
+    #[test]
+    fn test_remove_empty_ignoring() {
+        let cases = [
+            ("a + ignoring() b", "a + b"),
+            ("a + ignoring () b", "a + b"),
+            ("a + on() b", "a + on () b"),
+            ("a > bool ignoring() b", "a > bool b"),
+            ("a and ignoring() b", "a and b"),
+            ("a + ignoring(x) b", "a + ignoring (x) b"),
+            (
+                "a + ignoring() group_left b",
+                "a + ignoring () group_left b",
+            ),
+            ("sum(a / ignoring() (b - ignoring() c))", "sum(a / (b - c))"),
+        ];
+        for (input, expected) in cases {
+            let (_, e) = crate::parser::expr::expr(input).unwrap();
+            assert_eq!(e.remove_empty_ignoring().to_string(), expected, "{}", input);
+        }
+
+        let (_, e) = crate::parser::expr::expr("a + ignoring() b").unwrap();
+        let (_, plain) = crate::parser::expr::expr("a + b").unwrap();
+        assert_eq!(e.remove_empty_ignoring(), plain);
+    }
+
+    #[test]
+    fn test_call_known_function() {
+        let call = Call::new("label_replace", vec![]);
+        let function = call.known_function().unwrap();
+        assert_eq!(function.name, "label_replace");
+        assert_eq!(function.arg_types.len(), 5);
+        assert!(
+            Call::new("no_such_function", vec![])
+                .known_function()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_metric_names() {
+        let (_, e) = crate::parser::expr::expr(
+            r#"sum(rate(a[5m])) / ignoring(x) {__name__="b", job="j"} + {__name__=~"c.*"} + a"#,
+        )
+        .unwrap();
+        let names: Vec<_> = e.metric_names().into_iter().collect();
+        assert_eq!(names, ["a", "b"]);
+
+        let (_, e) = crate::parser::expr::expr("vector(1) + time()").unwrap();
+        assert!(e.metric_names().is_empty());
+    }
+
+    #[test]
+    fn test_selectors_for_metric() {
+        let (_, e) = crate::parser::expr::expr(
+            r#"sum(rate(http_requests_total{code="500"}[5m])) / sum(rate(http_requests_total[5m])) > on() group_left {__name__="http_requests_total", job="a"} + errors_total"#,
+        )
+        .unwrap();
+
+        let selectors = e.selectors_for_metric("http_requests_total");
+        let printed: Vec<String> = selectors.iter().map(|s| s.to_string()).collect();
+        assert_eq!(
+            printed,
+            [
+                r#"http_requests_total{code="500"}"#,
+                "http_requests_total",
+                r#"http_requests_total{job="a"}"#,
+            ]
+        );
+        assert!(e.contains_metric("http_requests_total"));
+        assert!(e.contains_metric("errors_total"));
+        assert_eq!(e.selectors_for_metric("errors_total").len(), 1);
+
+        // Partial names and regex matchers do not count
+        assert!(!e.contains_metric("http_requests"));
+        let (_, e) =
+            crate::parser::expr::expr(r#"{__name__=~"foo|bar"} + baz{__name__!="foo"}"#).unwrap();
+        assert!(!e.contains_metric("foo"));
+        assert!(e.selectors_for_metric("foo").is_empty());
+    }
+
+    #[test]
+    fn test_canonical_string() {
+        let equivalent = [
+            (
+                r#"SUM(rate(x{job="api",env='prod'}[5m])) BY (job)"#,
+                r#"sum by (job) (rate(((x{env="prod", job="api"}[5m]))))"#,
+            ),
+            ("((a)) + (b * c)", "a + b * c"),
+            ("(a - b) - c", "a - b - c"),
+            ("(-a) ^ b", "-a ^ b"),
+            ("0x10 * 1.6e1", "16 * 16.0"),
+            (
+                r#"a / on(job) group_left (team) b{b=~"x", a!=`y`}"#,
+                r#"(a) / on (job) group_left (team) (b{a!="y", b=~"x"})"#,
+            ),
+            (r#"up{a="2", a="1"}"#, r#"up{a="1", a="2"}"#),
+        ];
+        for (a, b) in equivalent {
+            let (_, a) = crate::parser::expr::expr(a).unwrap();
+            let (_, b) = crate::parser::expr::expr(b).unwrap();
+            assert_eq!(a.canonical_string(), b.canonical_string());
+        }
+
+        // Parentheses that change the structure are kept
+        for query in [
+            "(a + b) * c",
+            "a - (b - c)",
+            "(a ^ b) ^ c",
+            "-(a + b)",
+            "-(a ^ b)",
+            "(a + b)[5m:1m]",
+            "(-a)[5m:]",
+            "(a or b) and c",
+            "(a > bool b) + c",
+            "sum by (job) (a + b)",
+        ] {
+            let (_, e) = crate::parser::expr::expr(query).unwrap();
+            assert_eq!(e.canonical_string(), query);
+        }
+
+        // The canonical string parses back to the canonicalized tree
+        for query in [
+            r#"((sum without (instance) (rate({__name__="x", b="1", a=~"2"}[5m] offset 1m))))"#,
+            "-(((a * b))) / (c ^ -d)",
+            "(max_over_time((a - b)[1h:5m]))[1d:] @ start()",
+            r#"label_replace((up), "dst", "$1", "src", "(.*)")"#,
+            "topk(((5)), (x)) unless ignoring (job) (y)",
+        ] {
+            let (_, e) = crate::parser::expr::expr(query).unwrap();
+            let canonical = e.canonical_string();
+            let (rest, reparsed) = crate::parser::expr::expr(&canonical).unwrap();
+            assert!(rest.is_empty());
+            assert_eq!(reparsed, e.clone().canonicalized(), "{}", query);
+            assert_eq!(reparsed.canonical_string(), canonical);
+        }
+    }
+
+    #[test]
+    fn test_redacted_to_string() {
+        let cases = [
+            (r#"up{user="alice"}"#, r#"up{user="<redacted>"}"#),
+            ("up", "up"),
+            (
+                r#"sum by (user) (rate({__name__="logins_total", user!="bob"}[5m] offset 1h))"#,
+                r#"sum by (user) (rate(logins_total{user!="<redacted>"}[5m] offset 1h))"#,
+            ),
+            (
+                r#"{__name__=~"secret_.*"} / on (user) x{user="carol"}[5m:1m]"#,
+                r#"{__name__=~"secret_.*"} / on (user) x{user="<redacted>"}[5m:1m]"#,
+            ),
+            (
+                r#"label_replace(up{email="a@b.c"}, "dst", "$1", "src", "(.*)")"#,
+                r#"label_replace(up{email="<redacted>"}, "dst", "$1", "src", "(.*)")"#,
+            ),
+        ];
+        for (input, expected) in cases {
+            let (_, e) = crate::parser::expr::expr(input).unwrap();
+            assert_eq!(e.redacted_to_string(), expected, "{}", input);
+            // The expression itself is left untouched
+            assert!(!e.to_string().contains("<redacted>"));
+        }
+    }
+
+    #[test]
+    fn test_function_usage() {
+        let (_, e) = crate::parser::expr::expr("sum(rate(a[5m])) / rate(b[5m]) + abs(c)").unwrap();
+
+        let usage = e.function_usage(false);
+        assert_eq!(usage.len(), 2);
+        assert_eq!(usage["rate"], 2);
+        assert_eq!(usage["abs"], 1);
+
+        let usage = e.function_usage(true);
+        assert_eq!(usage.len(), 3);
+        assert_eq!(usage["sum"], 1);
+        assert_eq!(usage["rate"], 2);
+    }
+
+    #[test]
+    fn test_aggregation_group_label_editing() {
+        let parse = |q| match crate::parser::expr::expr(q).unwrap().1 {
+            Expr::Aggregation(a) => *a,
+            other => panic!("Expected Aggregation, got {:?}", other),
+        };
+
+        let mut agg = parse("sum by (job) (x)");
+        assert!(agg.add_group_label("instance"));
+        assert_eq!(agg.to_string(), "sum by (job, instance) (x)");
+        assert!(!agg.add_group_label("instance"));
+        assert!(agg.remove_group_label("job"));
+        assert!(!agg.remove_group_label("job"));
+        assert_eq!(agg.to_string(), "sum by (instance) (x)");
+        assert!(agg.remove_group_label("instance"));
+        assert_eq!(agg.to_string(), "sum by () (x)");
+
+        let mut agg = parse("topk without (job) (3, x)");
+        assert!(agg.add_group_label("pod"));
+        assert_eq!(agg.to_string(), "topk without (job, pod) (3, x)");
+        assert!(agg.remove_group_label("job"));
+        assert!(agg.remove_group_label("pod"));
+        assert_eq!(agg.to_string(), "topk without () (3, x)");
+
+        let mut agg = parse("sum(x)");
+        assert!(!agg.remove_group_label("job"));
+        assert_eq!(agg.grouping, None);
+        assert!(agg.add_group_label("job"));
+        assert_eq!(agg.to_string(), "sum by (job) (x)");
+
+        // Names that are not identifiers are quoted so the output parses back
+        assert!(agg.add_group_label("a.b"));
+        assert!(agg.add_group_label("on"));
+        assert_eq!(agg.to_string(), r#"sum by (job, "a.b", "on") (x)"#);
+        assert_eq!(
+            crate::parse(&agg.to_string()).unwrap(),
+            Expr::Aggregation(Box::new(agg))
+        );
+    }
+
+    #[test]
+    fn test_call_replace_arg() {
+        let (_, e) = crate::parser::expr::expr("clamp(x, 0, 1)").unwrap();
+        let Expr::Call(mut call) = e else {
+            panic!("Expected Call");
+        };
+        let old = call.replace_arg(2, Expr::Number(100.0.into()));
+        assert_eq!(old, Expr::Number(1.0.into()));
+        call.args[0] = Expr::VectorSelector(VectorSelector::new("y"));
+        assert_eq!(call.to_string(), "clamp(y, 0, 100)");
+    }
+
+    #[test]
+    fn test_operand_count_by_op() {
+        let (_, e) = crate::parser::expr::expr(
+            "sum(rate(a[5m])) / sum(rate(b[5m])) * 100 > 5 \
+             and on (job) (c - d + -(e - f)) == bool 1 \
+             or max_over_time((g ^ 2)[1h:]) unless h atan2 i",
+        )
+        .unwrap();
+
+        let counts = e.operand_count_by_op();
+        let expected = [
+            (BinaryOp::Add, 1),
+            (BinaryOp::Sub, 2),
+            (BinaryOp::Mul, 1),
+            (BinaryOp::Div, 1),
+            (BinaryOp::Pow, 1),
+            (BinaryOp::Atan2, 1),
+            (BinaryOp::Eq, 1),
+            (BinaryOp::Gt, 1),
+            (BinaryOp::And, 1),
+            (BinaryOp::Or, 1),
+            (BinaryOp::Unless, 1),
+        ];
+        assert_eq!(counts.into_iter().collect::<Vec<_>>(), expected);
+
+        let (_, e) = crate::parser::expr::expr("rate(x[5m])").unwrap();
+        assert!(e.operand_count_by_op().is_empty());
+    }
+
+    #[test]
+    fn test_hash_stable() {
+        let hash = |query: &str| crate::parser::expr::expr(query).unwrap().1.hash_stable();
+
+        assert_eq!(hash("(a + b)"), hash("a + b"));
+        assert_eq!(hash("((a + b)) * c"), hash("(a + b) * c"));
+        assert_eq!(hash("sum(x) by (job)"), hash("sum by (job) (x)"));
+        assert_eq!(hash("a / on (job) b"), hash("a / ON(job) b"));
+        assert_eq!(hash("NaN"), hash("nan"));
+
+        let distinct = [
+            "a + b",
+            "b + a",
+            "a - b",
+            "a + bool b",
+            "a + on (job) b",
+            "(a + b) * c",
+            "a + b * c",
+            "sum(x)",
+            "sum by () (x)",
+            "sum without () (x)",
+            "topk(5, x)",
+            "x[5m:]",
+            "x[5m:1m]",
+            "x[5m:] offset 1m",
+            "-x",
+            r#""a""#,
+            "1",
+            "0",
+            "x[5m]",
+            r#"x{job="a"}"#,
+            "rate(x[5m])",
+            "irate(x[5m])",
+        ];
+        let hashes: BTreeSet<u64> = distinct.iter().map(|q| hash(q)).collect();
+        assert_eq!(hashes.len(), distinct.len());
+
+        // Pinned so that an accidental change of the encoding is noticed
+        assert_eq!(hash("sum by (job) (rate(x[5m])) > 0"), 902785793452428900);
+    }
+
+    #[test]
+    fn test_write_to_matches_display() {
+        let mut buf = String::from("prefix: ");
+        let (_, e) = crate::parser::expr::expr(
+            r#"sum by (job) (rate(http_requests_total{code=~"5.."}[5m] offset 1h)) > 0.5"#,
+        )
+        .unwrap();
+        e.write_to(&mut buf);
+        assert_eq!(buf, format!("prefix: {}", e));
+    }
+
+    #[test]
+    fn test_trivial_conditions() {
+        let parse = |q| crate::parser::expr::expr(q).unwrap().1;
+
+        let e = parse("1 == bool 1");
+        assert!(e.is_trivially_true());
+        assert!(!e.is_trivially_false());
+
+        let e = parse("1 > bool 2");
+        assert!(e.is_trivially_false());
+        assert!(!e.is_trivially_true());
+
+        let e = parse("((2 * 3)) >= bool -(-6)");
+        assert!(e.is_trivially_true());
+
+        // Any vector operand makes the condition non-trivial
+        for q in ["up == 0", "0 < bool up", "vector(1) == bool 1", "1 + 1"] {
+            let e = parse(q);
+            assert!(!e.is_trivially_true(), "{q}");
+            assert!(!e.is_trivially_false(), "{q}");
+        }
+    }
+
+    #[test]
+    fn test_depth_first_mut_stops_early() {
+        let (_, mut e) =
+            crate::parser::expr::expr("sum(rate(a[5m])) / (b + c{job=\"x\"})").unwrap();
+
+        let mut visited = 0;
+        let flow = e.depth_first_mut(|node| {
+            visited += 1;
+            if let Expr::VectorSelector(s) = node {
+                s.name = Some("renamed".to_string());
+                return ControlFlow::Break(visited);
+            }
+            ControlFlow::Continue(())
+        });
+
+        // The matrix selector `a[5m]` is not a vector selector, so `b` is the
+        // first match: binary, aggregation, call, matrix, paren, binary, `b`
+        assert_eq!(flow, ControlFlow::Break(7));
+        assert_eq!(
+            e.to_string(),
+            r#"sum(rate(a[5m])) / (renamed + c{job="x"})"#
+        );
+    }
+
+    #[test]
+    fn test_depth_first_mut_deep_nesting() {
+        let mut e = Expr::Number(1.0.into());
+        for _ in 0..100_000 {
+            e = Expr::Paren(Box::new(e));
+        }
+        let mut count = 0;
+        let flow: ControlFlow<()> = e.depth_first_mut(|_| {
+            count += 1;
+            ControlFlow::Continue(())
+        });
+        assert_eq!(flow, ControlFlow::Continue(()));
+        assert_eq!(count, 100_001);
+        // Unwrap iteratively, as the recursive drop would overflow the stack
+        while let Expr::Paren(inner) = e {
+            e = *inner;
+        }
+    }
+
+    #[test]
+    fn test_binary_op_commutative() {
+        let commutative: Vec<_> = BinaryOp::all()
+            .iter()
+            .filter(|op| op.is_commutative())
+            .collect();
+        assert_eq!(
+            commutative,
+            [
+                &BinaryOp::Or,
+                &BinaryOp::And,
+                &BinaryOp::Add,
+                &BinaryOp::Mul
+            ]
+        );
+    }
+
+    #[test]
+    fn test_canonicalize_commutative() {
+        let canon = |q| {
+            crate::parser::expr::expr(q)
+                .unwrap()
+                .1
+                .canonicalize_commutative()
+        };
+
+        assert_eq!(canon("a + b"), canon("b + a"));
+        assert_eq!(canon("a * 2"), canon("2 * a"));
+        assert_eq!(canon("x and on(job) y"), canon("y and on(job) x"));
+        assert_eq!(canon("c + a + b"), canon("b + c + a"));
+        assert_ne!(canon("a - b"), canon("b - a"));
+        assert_ne!(canon("a / b"), canon("b / a"));
+        assert_ne!(canon("a > b"), canon("b > a"));
+        assert_ne!(canon("a unless b"), canon("b unless a"));
+
+        // Canonicalization recurses into other nodes
+        assert_eq!(
+            canon("sum(rate(b[5m]) + rate(a[5m]))").to_string(),
+            "sum(rate(a[5m]) + rate(b[5m]))"
+        );
+        // The modifier must match for a chain to be merged
+        assert_eq!(canon("c + on(x) b + a").to_string(), "(b + on (x) c) + a");
+        // group_left pins the sides
+        assert_eq!(
+            canon("b * on(x) group_left a").to_string(),
+            "b * on (x) group_left a"
+        );
+    }
+
+    #[test]
+    fn test_canonicalize_commutative_reparses() {
+        for q in ["a - b + c", "c + a - b", "x or y and z", "b / a * c"] {
+            let canon = crate::parser::expr::expr(q)
+                .unwrap()
+                .1
+                .canonicalize_commutative();
+            let printed = canon.to_string();
+            let (_, reparsed) = crate::parser::expr::expr(&printed).unwrap();
+            assert_eq!(reparsed, canon, "{q} -> {printed}");
+            assert_eq!(canon.clone().canonicalize_commutative(), canon, "{q}");
+        }
+    }
+
+    #[test]
+    fn test_expr_kind() {
+        let cases = [
+            ("1", ExprKind::Number),
+            (r#""a""#, ExprKind::String),
+            ("up", ExprKind::VectorSelector),
+            ("up[5m]", ExprKind::MatrixSelector),
+            ("rate(up[5m])", ExprKind::Call),
+            ("sum(up)", ExprKind::Aggregation),
+            ("a + b", ExprKind::Binary),
+            ("-a", ExprKind::Unary),
+            ("(a)", ExprKind::Paren),
+            ("up[5m:]", ExprKind::Subquery),
+        ];
+        let mut counts = HashMap::new();
+        for (input, kind) in cases {
+            let (_, e) = crate::parser::expr::expr(input).unwrap();
+            assert_eq!(e.kind(), kind, "{}", input);
+            *counts.entry(e.kind()).or_insert(0) += 1;
+        }
+        assert_eq!(counts.len(), cases.len());
+    }
+
+    #[test]
+    fn test_rewrite_offsets() {
+        let week = Duration::from_secs(7 * 86400);
+        let (_, mut e) =
+            crate::parser::expr::expr("sum by (job) (rate(a[5m])) / on(job) (b offset 1h + -c)")
+                .unwrap();
+        e.rewrite_offsets(week);
+        assert_eq!(
+            e.to_string(),
+            "sum by (job) (rate(a[5m] offset 1w)) / on (job) (b offset 1w1h + -c offset 1w)"
+        );
+
+        // Shifting back restores the original offsets and drops zero ones
+        e.rewrite_offsets(Duration::from_millis(-week.as_millis()));
+        assert_eq!(
+            e.to_string(),
+            "sum by (job) (rate(a[5m])) / on (job) (b offset 1h + -c)"
+        );
+    }
+
+    #[test]
+    fn test_estimate_series_cardinality_class() {
+        let class = |input: &str| {
+            let (_, e) = crate::parser::expr::expr(input).unwrap();
+            e.estimate_series_cardinality_class()
+        };
+        assert_eq!(class("1 + 2"), CardinalityClass::Low);
+        assert_eq!(
+            class(r#"rate(a{instance="x"}[5m]) / rate(b{instance="x"}[5m])"#),
+            CardinalityClass::Low
+        );
+        assert_eq!(
+            class(r#"sum by (job) (rate(a{job="x"}[5m])) > a{instance="x"}"#),
+            CardinalityClass::Medium
+        );
+        assert_eq!(
+            class(r#"a{instance="x"} + on(job) group_left b"#),
+            CardinalityClass::High
+        );
+    }
+
+    #[test]
+    fn test_is_selector_only() {
+        for (input, expected) in [
+            ("up", true),
+            (r#"up{j="x"} offset 5m"#, true),
+            (r#"{__name__="up"} @ start()"#, true),
+            ("((up))", true),
+            ("up[5m]", false),
+            ("up[5m:1m]", false),
+            ("rate(up[5m])", false),
+            ("-up", false),
+            ("up + 0", false),
+            ("sum(up)", false),
+            ("1", false),
+        ] {
+            let (_, e) = crate::parser::expr::expr(input).unwrap();
+            assert_eq!(e.is_selector_only(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_is_comparable_with() {
+        let comparable = |a: &str, b: &str| {
+            let (_, a) = crate::parser::expr::expr(a).unwrap();
+            let (_, b) = crate::parser::expr::expr(b).unwrap();
+            assert_eq!(a.is_comparable_with(&b), b.is_comparable_with(&a));
+            a.is_comparable_with(&b)
+        };
+
+        assert!(comparable("rate(a[5m])", "rate(a[1h] offset 1d)"));
+        assert!(comparable("1", "scalar(a) * 2"));
+        assert!(comparable(r#"a{job="x"}"#, "a"));
+        assert!(comparable(
+            "sum by (job, env) (rate(a[5m]))",
+            "sum by (env, job) (b)"
+        ));
+        assert!(comparable("topk(5, a)", "a"));
+        assert!(comparable("2 * sum(a)", "count(b)"));
+
+        // Different value types
+        assert!(!comparable("1", "a"));
+        assert!(!comparable("a", "a[5m]"));
+        // Disjoint series
+        assert!(!comparable("rate(a[5m])", "rate(b[5m])"));
+        assert!(!comparable(r#"a{job="x"}"#, r#"a{job="y"}"#));
+        // Different result labels
+        assert!(!comparable("sum by (job) (a)", "sum by (env) (a)"));
+        assert!(!comparable("sum(a)", "a"));
+    }
+
+    #[test]
+    fn test_simplify_negations() {
+        let (_, e) = crate::parser::expr::expr("-5").unwrap();
+        assert_eq!(e.simplify_negations(), Expr::Number((-5.0).into()));
+
+        let (_, e) = crate::parser::expr::expr("-x").unwrap();
+        assert!(matches!(e.simplify_negations(), Expr::Unary(_)));
+
+        let (_, e) = crate::parser::expr::expr("+x").unwrap();
+        assert_eq!(e.simplify_negations().to_string(), "x");
+
+        let (_, e) = crate::parser::expr::expr("- -5").unwrap();
+        assert_eq!(e.simplify_negations(), Expr::Number(5.0.into()));
+    }
+
+    #[test]
+    fn test_simplify_negations_nested() {
+        let (_, e) = crate::parser::expr::expr("rate(a[5m]) * -1 > +2 and topk(-3, -b)").unwrap();
+        let e = e.simplify_negations();
+        assert_eq!(e.to_string(), "rate(a[5m]) * -1 > 2 and topk(-3, -b)");
+
+        let Expr::Binary(and) = &e else {
+            panic!("Expected Binary, got {:?}", e);
+        };
+        let Expr::Aggregation(topk) = &and.rhs else {
+            panic!("Expected Aggregation, got {:?}", and.rhs);
+        };
+        assert_eq!(topk.param, Some(Expr::Number((-3.0).into())));
+        assert!(matches!(topk.expr, Expr::Unary(_)));
+    }
+
+    #[test]
+    fn test_value_type() {
+        for (input, expected) in [
+            ("1", ValueType::Scalar),
+            ("-(1 + 2) ^ 3", ValueType::Scalar),
+            ("time() > bool 0", ValueType::Scalar),
+            ("\"a\"", ValueType::String),
+            ("up", ValueType::Vector),
+            ("up[5m]", ValueType::Matrix),
+            ("rate(up[5m])[1h:]", ValueType::Matrix),
+            ("sum(up)", ValueType::Vector),
+            ("scalar(up)", ValueType::Scalar),
+            ("label_join(up, \"a\", \",\")", ValueType::Vector),
+            ("no_such_function(1)", ValueType::Vector),
+            ("1 + up", ValueType::Vector),
+            ("up and 1", ValueType::Vector),
+            ("(up[5m])", ValueType::Matrix),
+            ("1[5m:]", ValueType::Matrix),
+        ] {
+            let (_, e) = crate::parser::expr::expr(input).unwrap();
+            assert_eq!(e.value_type(), expected, "{}", input);
+            assert_eq!(crate::validate::type_of(&e), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_with_offset_and_at() {
+        let apply = |input: &str| {
+            let (_, e) = crate::parser::expr::expr(input).unwrap();
+            e.with_offset(Duration::from_secs(-300))
+                .and_then(|e| e.with_at(AtModifier::Timestamp(1_000)))
+                .map(|e| e.to_string())
+        };
+        assert_eq!(apply("up").unwrap(), "up @ 1.000 offset -5m");
+        assert_eq!(
+            apply("up[1m] @ start() offset 1h").unwrap(),
+            "up[1m] @ 1.000 offset -5m"
+        );
+        assert_eq!(apply("x[1h:1m]").unwrap(), "x[1h:1m] @ 1.000 offset -5m");
+
+        for (input, kind, message) in [
+            ("1", ExprKind::Number, "a number literal"),
+            ("\"a\"", ExprKind::String, "a string literal"),
+            ("rate(x[5m])", ExprKind::Call, "a function call"),
+            ("sum(x)", ExprKind::Aggregation, "an aggregation"),
+            ("a + b", ExprKind::Binary, "a binary expression"),
+            ("-a", ExprKind::Unary, "a unary expression"),
+            ("(a)", ExprKind::Paren, "a parenthesized expression"),
+        ] {
+            let err = apply(input).unwrap_err();
+            assert_eq!(err.kind(), kind, "{}", input);
+            assert_eq!(
+                err.to_string(),
+                alloc::format!("the offset modifier cannot be applied to {}", message)
+            );
+        }
+    }
+
+    #[test]
+    fn test_offset_and_at_getters() {
+        for input in ["up", "up[1m]", "x[1h:1m]"] {
+            let (_, e) = crate::parser::expr::expr(input).unwrap();
+            assert_eq!((e.offset(), e.at()), (None, None), "{}", input);
+            let e = e
+                .with_offset(Duration::from_secs(-300))
+                .and_then(|e| e.with_at(AtModifier::Start))
+                .unwrap();
+            assert_eq!(e.offset(), Some(Duration::from_secs(-300)), "{}", input);
+            assert_eq!(e.at(), Some(&AtModifier::Start), "{}", input);
+        }
+        for input in [
+            "1",
+            "rate(x[5m] offset 1m)",
+            "(up @ end())",
+            "-up offset 1m",
+        ] {
+            let (_, e) = crate::parser::expr::expr(input).unwrap();
+            assert_eq!((e.offset(), e.at()), (None, None), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_rewrite_offsets_negative_result() {
+        let (_, mut e) = crate::parser::expr::expr("a offset 1h").unwrap();
+        e.rewrite_offsets(Duration::from_secs(-7200));
+        assert_eq!(e.to_string(), "a offset -1h");
+    }
+
+    #[test]
+    fn test_rewrite_offsets_subquery() {
+        // Selectors inside subqueries move, the subqueries' own offsets stay
+        let (_, mut e) =
+            crate::parser::expr::expr("max_over_time(rate(a[1m] offset 5m)[1h:1m] offset 2h) + b")
+                .unwrap();
+        e.rewrite_offsets(Duration::from_secs(86400));
+        assert_eq!(
+            e.to_string(),
+            "max_over_time(rate(a[1m] offset 1d5m)[1h:1m] offset 2h) + b offset 1d"
+        );
+    }
+
+    #[test]
+    fn test_rewrite_offsets_across_binary_with_nested_subquery() {
+        let (_, mut e) = crate::parser::expr::expr(
+            "sum(rate(a[5m])) / max_over_time(min_over_time(rate(b[1m])[10m:])[1h:5m] offset 1h)",
+        )
+        .unwrap();
+        e.rewrite_offsets(Duration::from_secs(7 * 86400));
+        assert_eq!(
+            e.to_string(),
+            "sum(rate(a[5m] offset 1w)) / max_over_time(min_over_time(rate(b[1m] offset 1w)[10m:])[1h:5m] offset 1h)"
+        );
+
+        // Every selector moved by the same amount
+        let offsets: Vec<_> = e
+            .matrix_selectors()
+            .iter()
+            .map(|ms| ms.selector.offset)
+            .collect();
+        assert_eq!(offsets, [Some(Duration::from_secs(7 * 86400)); 2]);
+    }
 }
@@ -0,0 +1,493 @@
+//! Programmatic construction of PromQL expressions.
+//!
+//! Generating queries by string concatenation is error-prone: a missing
+//! parenthesis silently changes operator precedence. The functions in this
+//! module build [`Expr`] trees directly and insert parentheses where
+//! precedence requires them, so the Display output parses back to the same
+//! tree.
+//!
+//! Metric and label names are quoted where PromQL requires it, in
+//! selectors as well as in grouping clauses. Input that PromQL cannot
+//! express at all, such as a function name `my.func` or a zero range, makes
+//! the builders panic, as documented on each function.
+//!
+//! # Example
+//!
+//! ```rust
+//! use rusty_promql_parser::Duration;
+//! use rusty_promql_parser::ast::BinaryOp;
+//! use rusty_promql_parser::build::{binary, metric, number, rate, sum};
+//!
+//! let requests = metric("http_requests_total").label("code", "500");
+//! let query = binary(
+//!     BinaryOp::Gt,
+//!     sum(rate(requests, Duration::from_secs(300))).by(["job"]),
+//!     number(10.0),
+//! );
+//! assert_eq!(
+//!     query.to_string(),
+//!     r#"sum by (job) (rate(http_requests_total{code="500"}[5m])) > 10"#
+//! );
+//! ```
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::ast::{
+    Aggregation, BinaryExpr, BinaryModifier, BinaryOp, Call, Expr, NumberLiteral, UnaryExpr,
+    UnaryOp,
+};
+use crate::error::IResult;
+use crate::lexer::duration::Duration;
+use crate::lexer::identifier::{aggregation_op, metric_name};
+use crate::parser::aggregation::{Grouping, GroupingAction};
+use crate::parser::selector::{
+    AtModifier, LabelMatchOp, LabelMatcher, MatrixSelector, VectorSelector,
+};
+
+/// Start a selector for the metric `name`
+pub fn metric(name: impl Into<String>) -> Selector {
+    Selector(VectorSelector::new(name))
+}
+
+/// Start a selector without a metric name, e.g. `{job="api"}`
+///
+/// Prometheus requires at least one matcher that does not match the empty
+/// string, so add matchers before using the selector.
+pub fn selector() -> Selector {
+    Selector(VectorSelector::with_matchers(Vec::new()))
+}
+
+/// Vector selector under construction
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selector(VectorSelector);
+
+impl Selector {
+    /// Add an equality matcher `name="value"`
+    pub fn label(self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.matcher(name, LabelMatchOp::Equal, value)
+    }
+
+    /// Add a matcher with any operator
+    ///
+    /// Like the parser, an equality matcher on `__name__` becomes the metric
+    /// name if the selector does not have one yet.
+    pub fn matcher(
+        mut self,
+        name: impl Into<String>,
+        op: LabelMatchOp,
+        value: impl Into<String>,
+    ) -> Self {
+        let matcher = LabelMatcher::new(name, op, value);
+        if self.0.name.is_none() && matcher.name == "__name__" && op == LabelMatchOp::Equal {
+            self.0.name = Some(matcher.value);
+        } else {
+            self.0.add_matcher(matcher);
+        }
+        self
+    }
+
+    /// Set the `offset` modifier
+    pub fn offset(mut self, offset: Duration) -> Self {
+        self.0.offset = Some(offset);
+        self
+    }
+
+    /// Set the `@` modifier
+    pub fn at(mut self, at: AtModifier) -> Self {
+        self.0.at = Some(at);
+        self
+    }
+
+    /// Turn the selector into a range vector selector, e.g. `foo[5m]`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range` is not greater than zero.
+    pub fn range(self, range: Duration) -> Expr {
+        assert!(
+            range.as_millis() > 0,
+            "range must be greater than 0, got {}",
+            range
+        );
+        Expr::MatrixSelector(MatrixSelector::new(self.0, range))
+    }
+
+    /// Get the underlying selector
+    pub fn into_inner(self) -> VectorSelector {
+        self.0
+    }
+}
+
+impl From<Selector> for Expr {
+    fn from(selector: Selector) -> Self {
+        Expr::VectorSelector(selector.0)
+    }
+}
+
+/// A number literal
+///
+/// Negative numbers, including `-0`, are built as a negated literal,
+/// which is how the parser reads `-1`.
+pub fn number(value: f64) -> Expr {
+    if value.is_sign_negative() && !value.is_nan() {
+        neg(Expr::Number(NumberLiteral::new(-value)))
+    } else {
+        Expr::Number(NumberLiteral::new(value))
+    }
+}
+
+/// A string literal
+pub fn string(value: impl Into<String>) -> Expr {
+    Expr::String(value.into())
+}
+
+/// A call to the function `name`
+///
+/// # Panics
+///
+/// Panics if `name` cannot be written as a function name: it must be an
+/// identifier, and not an aggregation operator or a number like `Inf`.
+pub fn call(name: impl Into<String>, args: Vec<Expr>) -> Expr {
+    let name = name.into();
+    assert!(
+        is_whole(metric_name, &name)
+            && !is_whole(aggregation_op, &name)
+            && !is_whole(crate::lexer::number::number, &name),
+        "invalid function name {:?}",
+        name
+    );
+    Expr::Call(Call::new(name, args))
+}
+
+/// `rate(selector[range])`
+///
+/// # Panics
+///
+/// Panics if `range` is not greater than zero.
+pub fn rate(selector: Selector, range: Duration) -> Expr {
+    call("rate", vec![selector.range(range)])
+}
+
+/// `irate(selector[range])`
+///
+/// # Panics
+///
+/// Panics if `range` is not greater than zero.
+pub fn irate(selector: Selector, range: Duration) -> Expr {
+    call("irate", vec![selector.range(range)])
+}
+
+/// `increase(selector[range])`
+///
+/// # Panics
+///
+/// Panics if `range` is not greater than zero.
+pub fn increase(selector: Selector, range: Duration) -> Expr {
+    call("increase", vec![selector.range(range)])
+}
+
+/// Start an aggregation with the operator `op`
+///
+/// # Panics
+///
+/// Panics if `op` is not an aggregation operator without a parameter,
+/// written in lower case, such as `sum` or `stddev`. Use [`topk`] and
+/// [`bottomk`] for operators with a parameter.
+pub fn aggregate(op: impl Into<String>, expr: impl Into<Expr>) -> AggregationBuilder {
+    let op = op.into();
+    assert!(
+        matches!(
+            aggregation_op(&op),
+            Ok(("", kw)) if !kw.is_aggregation_with_param() && kw.as_str() == op
+        ),
+        "invalid aggregation operator {:?}",
+        op
+    );
+    AggregationBuilder(Aggregation::new(op, expr.into()))
+}
+
+/// `sum(expr)`
+pub fn sum(expr: impl Into<Expr>) -> AggregationBuilder {
+    aggregate("sum", expr)
+}
+
+/// `avg(expr)`
+pub fn avg(expr: impl Into<Expr>) -> AggregationBuilder {
+    aggregate("avg", expr)
+}
+
+/// `min(expr)`
+pub fn min(expr: impl Into<Expr>) -> AggregationBuilder {
+    aggregate("min", expr)
+}
+
+/// `max(expr)`
+pub fn max(expr: impl Into<Expr>) -> AggregationBuilder {
+    aggregate("max", expr)
+}
+
+/// `count(expr)`
+pub fn count(expr: impl Into<Expr>) -> AggregationBuilder {
+    aggregate("count", expr)
+}
+
+/// `topk(k, expr)`
+pub fn topk(k: u32, expr: impl Into<Expr>) -> AggregationBuilder {
+    AggregationBuilder(Aggregation::with_param(
+        "topk",
+        number(k.into()),
+        expr.into(),
+    ))
+}
+
+/// `bottomk(k, expr)`
+pub fn bottomk(k: u32, expr: impl Into<Expr>) -> AggregationBuilder {
+    AggregationBuilder(Aggregation::with_param(
+        "bottomk",
+        number(k.into()),
+        expr.into(),
+    ))
+}
+
+/// Aggregation under construction
+#[derive(Debug, Clone, PartialEq)]
+pub struct AggregationBuilder(Aggregation);
+
+impl AggregationBuilder {
+    /// Group by the given labels: `by (labels)`
+    pub fn by<I, S>(self, labels: I) -> Expr
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.grouped(GroupingAction::By, labels)
+    }
+
+    /// Group by all but the given labels: `without (labels)`
+    pub fn without<I, S>(self, labels: I) -> Expr
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.grouped(GroupingAction::Without, labels)
+    }
+
+    fn grouped<I, S>(self, action: GroupingAction, labels: I) -> Expr
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let labels = labels.into_iter().map(Into::into).collect();
+        let grouping = Grouping { action, labels };
+        Expr::Aggregation(Box::new(self.0.with_grouping(grouping)))
+    }
+}
+
+impl From<AggregationBuilder> for Expr {
+    fn from(agg: AggregationBuilder) -> Self {
+        Expr::Aggregation(Box::new(agg.0))
+    }
+}
+
+/// Combine two expressions with a binary operator
+///
+/// Operands are parenthesized when precedence or associativity requires
+/// it, e.g. `binary(Mul, a + b, c)` prints as `(a + b) * c`.
+pub fn binary(op: BinaryOp, lhs: impl Into<Expr>, rhs: impl Into<Expr>) -> Expr {
+    build_binary(op, lhs.into(), rhs.into(), None)
+}
+
+/// Combine two expressions with a comparison that returns 0 or 1 instead
+/// of filtering: `lhs op bool rhs`
+pub fn binary_bool(op: BinaryOp, lhs: impl Into<Expr>, rhs: impl Into<Expr>) -> Expr {
+    build_binary(
+        op,
+        lhs.into(),
+        rhs.into(),
+        Some(BinaryModifier::with_bool()),
+    )
+}
+
+/// Negate an expression: `-expr`
+pub fn neg(expr: impl Into<Expr>) -> Expr {
+    let expr = match expr.into() {
+        e @ Expr::Binary(_) => paren(e),
+        e => e,
+    };
+    Expr::Unary(Box::new(UnaryExpr::new(UnaryOp::Minus, expr)))
+}
+
+fn build_binary(op: BinaryOp, lhs: Expr, rhs: Expr, modifier: Option<BinaryModifier>) -> Expr {
+    Expr::Binary(Box::new(BinaryExpr {
+        modifier,
+        ..BinaryExpr::with_precedence_parens(op, lhs, rhs)
+    }))
+}
+
+fn paren(expr: Expr) -> Expr {
+    Expr::Paren(Box::new(expr))
+}
+
+/// Check if `parser` consumes all of `input`
+fn is_whole<'a, O>(mut parser: impl FnMut(&'a str) -> IResult<&'a str, O>, input: &'a str) -> bool {
+    parser(input).is_ok_and(|(rest, _)| rest.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::expr::expr;
+
+    fn assert_reparses(e: &Expr) {
+        let printed = e.to_string();
+        let (rest, parsed) = expr(&printed).unwrap();
+        assert!(rest.is_empty(), "unparsed input: {:?}", rest);
+        assert_eq!(&parsed, e, "{}", printed);
+    }
+
+    #[test]
+    fn test_sum_by_rate() {
+        let e = sum(rate(metric("http"), Duration::from_secs(300))).by(["job"]);
+        assert_eq!(e.to_string(), "sum by (job) (rate(http[5m]))");
+        assert_reparses(&e);
+    }
+
+    #[test]
+    fn test_selector_modifiers() {
+        let e: Expr = metric("up")
+            .label("job", "api")
+            .matcher("env", LabelMatchOp::RegexNotMatch, "dev|test")
+            .offset(Duration::from_secs(3600))
+            .into();
+        assert_eq!(e.to_string(), r#"up{job="api", env!~"dev|test"} offset 1h"#);
+        assert_reparses(&e);
+
+        let e = selector()
+            .label("__name__", "up")
+            .label("job", "api")
+            .range(Duration::from_secs(60));
+        assert_reparses(&e);
+    }
+
+    #[test]
+    fn test_aggregations() {
+        let e: Expr = topk(5, metric("x")).into();
+        assert_eq!(e.to_string(), "topk(5, x)");
+        assert_reparses(&e);
+
+        let e = count(metric("x")).without(["instance", "pod"]);
+        assert_eq!(e.to_string(), "count without (instance, pod) (x)");
+        assert_reparses(&e);
+    }
+
+    #[test]
+    fn test_binary_inserts_parens() {
+        let a = || Expr::from(metric("a"));
+        let b = || Expr::from(metric("b"));
+        let c = || Expr::from(metric("c"));
+
+        let cases = [
+            (
+                binary(BinaryOp::Mul, binary(BinaryOp::Add, a(), b()), c()),
+                "(a + b) * c",
+            ),
+            (
+                binary(BinaryOp::Add, a(), binary(BinaryOp::Mul, b(), c())),
+                "a + b * c",
+            ),
+            (
+                binary(BinaryOp::Sub, binary(BinaryOp::Sub, a(), b()), c()),
+                "a - b - c",
+            ),
+            (
+                binary(BinaryOp::Sub, a(), binary(BinaryOp::Sub, b(), c())),
+                "a - (b - c)",
+            ),
+            (
+                binary(BinaryOp::Pow, binary(BinaryOp::Pow, a(), b()), c()),
+                "(a ^ b) ^ c",
+            ),
+            (
+                binary(BinaryOp::Pow, a(), binary(BinaryOp::Pow, b(), c())),
+                "a ^ b ^ c",
+            ),
+            (binary(BinaryOp::Pow, neg(a()), b()), "(-a) ^ b"),
+            (binary(BinaryOp::Pow, number(-2.0), b()), "(-2) ^ b"),
+            (
+                binary(BinaryOp::And, a(), binary(BinaryOp::Or, b(), c())),
+                "a and (b or c)",
+            ),
+            (neg(binary(BinaryOp::Add, a(), b())), "-(a + b)"),
+        ];
+        for (e, expected) in cases {
+            assert_eq!(e.to_string(), expected);
+            assert_reparses(&e);
+        }
+    }
+
+    #[test]
+    fn test_binary_bool() {
+        let e = binary_bool(BinaryOp::Gt, metric("up"), number(0.0));
+        assert_eq!(e.to_string(), "up > bool 0");
+        assert_reparses(&e);
+    }
+
+    #[test]
+    fn test_every_builder_reparses() {
+        let five_minutes = Duration::from_secs(300);
+        let cases: Vec<Expr> = vec![
+            metric("up").into(),
+            metric("my.metric").label("http.method", "GET").into(),
+            metric("Inf").into(),
+            selector().into(),
+            selector().label("job", "api").into(),
+            metric("up").at(AtModifier::End).range(five_minutes),
+            number(1.5),
+            number(-2.0),
+            number(-0.0),
+            string("a\"b"),
+            call("time", Vec::new()),
+            call("sum_over_time", vec![metric("x").range(five_minutes)]),
+            rate(metric("x"), five_minutes),
+            irate(metric("x"), five_minutes),
+            increase(metric("x"), five_minutes),
+            aggregate("stddev", metric("x")).into(),
+            sum(metric("x")).by(["job"]),
+            avg(metric("x")).without(["pod"]),
+            min(metric("x")).into(),
+            max(metric("x")).by(Vec::<String>::new()),
+            count(metric("x")).into(),
+            topk(3, metric("x")).into(),
+            bottomk(3, metric("x")).by(["job"]),
+            sum(metric("x")).by(["my.label"]),
+            sum(metric("x")).without(["on", "job"]),
+            binary(BinaryOp::Div, metric("a"), metric("b")),
+            binary_bool(BinaryOp::Eq, metric("a"), number(1.0)),
+            neg(metric("a")),
+        ];
+        for e in &cases {
+            assert_reparses(e);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "range must be greater than 0")]
+    fn test_zero_range_panics() {
+        rate(metric("x"), Duration::from_millis(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid aggregation operator \"topk\"")]
+    fn test_aggregate_with_param_panics() {
+        aggregate("topk", metric("x"));
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid function name \"sum\"")]
+    fn test_call_to_aggregation_panics() {
+        call("sum", vec![metric("x").into()]);
+    }
+}
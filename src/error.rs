@@ -0,0 +1,464 @@
+//! Error types for the high-level parsing API.
+//!
+//! The nom parsers in [`parser`](crate::parser) report failures as
+//! [`SyntaxError`], which holds the remaining input and the cause.
+//! [`ParseError`] instead records where in the original query parsing
+//! stopped, so that editors and linters can map it back to a source position.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::fmt::Write;
+
+use nom::error::ErrorKind;
+
+use crate::ast::BinaryOp;
+use crate::parser::version::PrometheusVersion;
+
+/// Longest source line, in characters, that [`ParseError::render`] shows in
+/// full
+const MAX_SNIPPET_WIDTH: usize = 80;
+
+/// An error produced while parsing a PromQL query.
+///
+/// Displays as `parse error at offset <offset>: <message>`. The offset is
+/// in bytes from the start of the query.
+///
+/// # Example
+///
+/// ```rust
+/// use rusty_promql_parser::parse;
+///
+/// let err = parse("rate(x[1.5m])").unwrap_err();
+/// assert_eq!(err.offset, 7);
+/// assert_eq!(err.message, "expected duration");
+/// assert_eq!(err.expected, ["duration"]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// Byte offset in the input where parsing stopped
+    pub offset: usize,
+    /// Description of the problem
+    pub message: String,
+    /// What the parser would have accepted at `offset`, such as
+    /// `"duration"` or `"expression"`, for tools that offer completions.
+    /// Empty when the parser cannot tell.
+    pub expected: Vec<&'static str>,
+}
+
+impl ParseError {
+    /// Create a new parse error
+    pub fn new(offset: usize, message: impl Into<String>) -> Self {
+        Self {
+            offset,
+            message: message.into(),
+            expected: Vec::new(),
+        }
+    }
+
+    /// Set what the parser would have accepted at the error offset
+    pub fn with_expected(mut self, expected: impl IntoIterator<Item = &'static str>) -> Self {
+        self.expected = expected.into_iter().collect();
+        self
+    }
+
+    /// Convert the offset into a 1-based `(line, column)` pair within
+    /// `input`, the query that failed to parse.
+    ///
+    /// Lines are separated by `\n`. The column counts characters (Unicode
+    /// scalar values) rather than bytes, so `中文` advances it by two.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::parse;
+    ///
+    /// let input = "sum(\n  rate(x[1.5m])\n)";
+    /// let err = parse(input).unwrap_err();
+    /// assert_eq!(err.line_col(input), (2, 10));
+    /// ```
+    pub fn line_col(&self, input: &str) -> (usize, usize) {
+        let (mut line, mut col) = (1, 1);
+        for (_, c) in input.char_indices().take_while(|&(i, _)| i < self.offset) {
+            if c == '\n' {
+                line += 1;
+                col = 1;
+            } else {
+                col += 1;
+            }
+        }
+        (line, col)
+    }
+
+    /// Render the error for a terminal, with the offending line of `input`
+    /// and a caret under the error position:
+    ///
+    /// ```text
+    /// error: expected duration
+    ///  --> 2:10
+    ///   |
+    /// 2 |   rate(x[1.5m])
+    ///   |          ^
+    /// ```
+    ///
+    /// An error at the end of input gets a caret just past the last
+    /// character. Lines longer than 80 characters are cut down to the 80
+    /// around the error position, with `...` marking the cut ends.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::parse;
+    ///
+    /// let input = "sum(\n  rate(x[1.5m])\n)";
+    /// let err = parse(input).unwrap_err();
+    /// assert_eq!(
+    ///     err.render(input),
+    ///     "error: expected duration\n --> 2:10\n  |\n2 |   rate(x[1.5m])\n  |          ^"
+    /// );
+    /// ```
+    pub fn render(&self, input: &str) -> String {
+        let (line, col) = self.line_col(input);
+
+        // The error offset rounded up to a character boundary
+        let offset = input
+            .char_indices()
+            .map(|(i, _)| i)
+            .find(|&i| i >= self.offset)
+            .unwrap_or(input.len());
+        let start = input[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let end = input[offset..]
+            .find('\n')
+            .map_or(input.len(), |i| offset + i);
+        let text: Vec<char> = input[start..end].trim_end_matches('\r').chars().collect();
+
+        // Show a window of the line around the caret if it is too long
+        let caret = col - 1;
+        let (from, to) = if text.len() > MAX_SNIPPET_WIDTH {
+            let from = caret
+                .saturating_sub(MAX_SNIPPET_WIDTH / 2)
+                .min(text.len() - MAX_SNIPPET_WIDTH);
+            (from, from + MAX_SNIPPET_WIDTH)
+        } else {
+            (0, text.len())
+        };
+
+        let mut snippet = String::new();
+        let mut marker = String::new();
+        if from > 0 {
+            snippet.push_str("...");
+            marker.push_str("   ");
+        }
+        snippet.extend(&text[from..to]);
+        // Copy tabs so that the caret lines up with the source
+        marker.extend(
+            text[from..caret.min(to)]
+                .iter()
+                .map(|&c| if c == '\t' { '\t' } else { ' ' }),
+        );
+        marker.push('^');
+        if to < text.len() {
+            snippet.push_str("...");
+        }
+
+        let number = line.to_string();
+        let pad = " ".repeat(number.len());
+        let mut out = String::new();
+        // Writing to a String never fails
+        let _ = write!(
+            out,
+            "error: {}\n{}--> {}:{}\n{} |\n{} | {}\n{} | {}",
+            self.message, pad, line, col, pad, number, snippet, pad, marker
+        );
+        out
+    }
+
+    /// Create an error for the position of `rest`, a suffix of `input`.
+    ///
+    /// The message names the character found there, or reports the end of
+    /// input if only whitespace remains. A closing delimiter without a
+    /// matching opening one is reported as such, with its 1-based column so
+    /// that it can be told apart from the other delimiters on the line.
+    pub(crate) fn at(input: &str, rest: &str) -> Self {
+        let rest = rest.trim_start();
+        let offset = input.len() - rest.len();
+        match rest.chars().next() {
+            None => Self::new(input.len(), "unexpected end of input"),
+            Some(c @ (')' | ']' | '}')) => {
+                let (_, col) = Self::new(offset, "").line_col(input);
+                Self::new(
+                    offset,
+                    format!("unexpected closing delimiter {:?} at column {}", c, col),
+                )
+            }
+            Some(c) => Self::new(offset, format!("unexpected character {:?}", c)),
+        }
+    }
+
+    /// Create an error for `rest`, the non-empty input left over after a
+    /// complete expression in `input`.
+    ///
+    /// An unmatched closing delimiter is reported as such, like in
+    /// [`ParseError::at`]; anything else is "unexpected trailing input".
+    pub(crate) fn trailing(input: &str, rest: &str) -> Self {
+        let rest = rest.trim_start();
+        match rest.chars().next() {
+            Some(')' | ']' | '}') | None => Self::at(input, rest),
+            Some(_) => Self::new(input.len() - rest.len(), "unexpected trailing input"),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "parse error at offset {}: {}", self.offset, self.message)
+    }
+}
+
+impl core::error::Error for ParseError {}
+
+/// Result type of the nom parsers in [`lexer`](crate::lexer) and
+/// [`parser`](crate::parser)
+pub type IResult<I, O> = nom::IResult<I, O, SyntaxError<I>>;
+
+/// Error type of the nom parsers in [`lexer`](crate::lexer) and
+/// [`parser`](crate::parser).
+///
+/// Like [`nom::error::Error`], it holds the remaining input where parsing
+/// stopped. Problems found by the parser itself rather than by a nom
+/// combinator carry their details in [`SyntaxErrorKind`], from which
+/// [`ParseError`] messages are built.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError<I> {
+    /// The remaining input where the error occurred
+    pub input: I,
+    /// What went wrong
+    pub kind: SyntaxErrorKind,
+}
+
+impl<I> SyntaxError<I> {
+    /// Create a new syntax error at `input`
+    pub fn new(input: I, kind: SyntaxErrorKind) -> Self {
+        Self { input, kind }
+    }
+}
+
+impl<I> nom::error::ParseError<I> for SyntaxError<I> {
+    fn from_error_kind(input: I, kind: ErrorKind) -> Self {
+        Self::new(input, SyntaxErrorKind::Nom(kind))
+    }
+
+    fn append(_input: I, _kind: ErrorKind, other: Self) -> Self {
+        other
+    }
+}
+
+impl<I, E> nom::error::FromExternalError<I, E> for SyntaxError<I> {
+    fn from_external_error(input: I, kind: ErrorKind, _e: E) -> Self {
+        Self::new(input, SyntaxErrorKind::Nom(kind))
+    }
+}
+
+/// The cause of a [`SyntaxError`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SyntaxErrorKind {
+    /// A nom combinator did not match
+    Nom(ErrorKind),
+    /// A selector has more label matchers than
+    /// [`ParseOptions::max_matchers_per_selector`](crate::ParseOptions::max_matchers_per_selector)
+    /// allows
+    TooManyMatchers {
+        /// The configured limit
+        max: usize,
+    },
+    /// The expression is nested deeper than
+    /// [`ParseOptions::max_depth`](crate::ParseOptions::max_depth) allows
+    TooDeep,
+    /// The braces of a selector set the metric name again, as in
+    /// `foo{__name__="bar"}`
+    MetricNameSetTwice {
+        /// The metric name in front of the braces
+        name: String,
+        /// The metric name in the braces
+        value: String,
+    },
+    /// A number with a fraction or exponent where a duration was expected,
+    /// as in `1.5m`
+    FloatDuration,
+    /// A zero range or subquery step, like `[0s]`
+    ZeroDuration,
+    /// A modifier (`"offset"` or `"@"`) set a second time
+    RepeatedModifier(&'static str),
+    /// A modifier (`"offset"` or `"@"`) after an expression that cannot
+    /// take it, as in `1 offset 1d`
+    MisplacedModifier(&'static str),
+    /// An `@` timestamp, as written, that does not fit in `i64`
+    /// milliseconds
+    TimestampOutOfBounds(String),
+    /// An aggregation with the wrong number of arguments, as in
+    /// `topk(x)`
+    AggregationArguments {
+        /// Arguments the operator takes, including its parameter
+        expected: usize,
+        /// Arguments given
+        got: usize,
+    },
+    /// A binary operator where an operand was expected, as in `a * / b`
+    UnexpectedOperator(BinaryOp),
+    /// A function or aggregation operator that the targeted Prometheus
+    /// version lacks
+    Unavailable {
+        /// Whether `name` is a `"function"` or an `"aggregation"`
+        feature: &'static str,
+        /// The function or aggregation operator
+        name: String,
+        /// The first Prometheus version that has it
+        since: PrometheusVersion,
+        /// The targeted Prometheus version
+        target: PrometheusVersion,
+    },
+    /// A quoted metric or label name, which the targeted Prometheus version
+    /// lacks
+    QuotedNamesUnavailable {
+        /// The targeted Prometheus version
+        target: PrometheusVersion,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_at_character() {
+        let input = "foo ;";
+        let err = ParseError::at(input, &input[3..]);
+        assert_eq!(err, ParseError::new(4, "unexpected character ';'"));
+        assert_eq!(
+            err.to_string(),
+            "parse error at offset 4: unexpected character ';'"
+        );
+    }
+
+    #[test]
+    fn test_error_trailing() {
+        let input = "foo bar";
+        let err = ParseError::trailing(input, &input[3..]);
+        assert_eq!(err, ParseError::new(4, "unexpected trailing input"));
+
+        let input = "sum(foo))";
+        let err = ParseError::trailing(input, &input[8..]);
+        assert_eq!(
+            err,
+            ParseError::new(8, "unexpected closing delimiter ')' at column 9")
+        );
+    }
+
+    #[test]
+    fn test_line_col() {
+        let input = "a +\nb *\r\n  )";
+        assert_eq!(ParseError::new(0, "").line_col(input), (1, 1));
+        assert_eq!(ParseError::new(2, "").line_col(input), (1, 3));
+        assert_eq!(ParseError::new(4, "").line_col(input), (2, 1));
+        assert_eq!(ParseError::new(11, "").line_col(input), (3, 3));
+        // The end of input is one past the last character
+        assert_eq!(ParseError::new(input.len(), "").line_col(input), (3, 4));
+
+        // Columns count characters, not bytes
+        let input = "up{名前=\"中文\"} )";
+        let offset = input.find(')').unwrap();
+        assert_eq!(offset, 20);
+        assert_eq!(ParseError::new(offset, "").line_col(input), (1, 13));
+    }
+
+    #[test]
+    fn test_render() {
+        let input = "up{job=\"a\"} )";
+        assert_eq!(
+            ParseError::new(12, "unexpected closing delimiter ')'").render(input),
+            concat!(
+                "error: unexpected closing delimiter ')'\n",
+                " --> 1:13\n",
+                "  |\n",
+                "1 | up{job=\"a\"} )\n",
+                "  |             ^",
+            )
+        );
+    }
+
+    #[test]
+    fn test_render_end_of_input() {
+        let input = "sum(\r\n  rate(x[5m]\r\n";
+        let rendered = ParseError::new(input.len(), "unexpected end of input").render(input);
+        assert_eq!(
+            rendered,
+            "error: unexpected end of input\n --> 3:1\n  |\n3 | \n  | ^"
+        );
+
+        let input = "sum(";
+        let rendered = ParseError::new(4, "unexpected end of input").render(input);
+        assert!(rendered.ends_with("1 | sum(\n  |     ^"), "{}", rendered);
+    }
+
+    #[test]
+    fn test_render_tabs_and_wide_line_numbers() {
+        let input = format!("{}\tfoo )", "\n".repeat(9));
+        let rendered = ParseError::new(input.len() - 1, "oops").render(&input);
+        assert_eq!(
+            rendered,
+            "error: oops\n  --> 10:6\n   |\n10 | \tfoo )\n   | \t    ^"
+        );
+    }
+
+    #[test]
+    fn test_render_long_line() {
+        let input = format!("{} + )", "a".repeat(200));
+        let rendered = ParseError::new(203, "oops").render(&input);
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines[1], " --> 1:204");
+        let snippet = lines[3].strip_prefix("1 | ").unwrap();
+        let marker = lines[4].strip_prefix("  | ").unwrap();
+        assert!(snippet.starts_with("...aaa"));
+        assert!(snippet.ends_with("a + )"));
+        assert_eq!(snippet.len(), 3 + MAX_SNIPPET_WIDTH);
+        assert_eq!(&snippet[marker.len() - 1..marker.len()], ")");
+
+        // Errors near the start keep the beginning of the line
+        let rendered = ParseError::new(1, "oops").render(&input);
+        let snippet = rendered.lines().nth(3).unwrap();
+        assert!(snippet.starts_with("1 | aaa"));
+        assert!(snippet.ends_with("a..."));
+        assert!(rendered.ends_with("\n  |  ^"));
+    }
+
+    #[test]
+    fn test_error_at_closing_delimiter() {
+        let input = "foo )";
+        let err = ParseError::at(input, &input[4..]);
+        assert_eq!(
+            err,
+            ParseError::new(4, "unexpected closing delimiter ')' at column 5")
+        );
+        assert_eq!(
+            err.to_string(),
+            "parse error at offset 4: unexpected closing delimiter ')' at column 5"
+        );
+
+        // The column counts from the start of the delimiter's line
+        let input = "sum(\n  foo\n))";
+        let err = ParseError::at(input, &input[12..]);
+        assert_eq!(
+            err,
+            ParseError::new(12, "unexpected closing delimiter ')' at column 2")
+        );
+    }
+
+    #[test]
+    fn test_error_at_end_of_input() {
+        let input = "sum(  ";
+        let err = ParseError::at(input, &input[4..]);
+        assert_eq!(err, ParseError::new(6, "unexpected end of input"));
+    }
+}
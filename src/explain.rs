@@ -0,0 +1,389 @@
+//! Plain-English summaries of PromQL queries.
+//!
+//! Requires the `explain` feature. [`explain`] walks an expression and
+//! describes it in words, for teaching and for a quick sanity check of what
+//! a complex query computes. The output is a readable summary, not a precise
+//! specification: functions without a dedicated phrase are described by
+//! name, and some modifiers are only mentioned briefly.
+//!
+//! # Example
+//!
+//! ```rust
+//! use rusty_promql_parser::explain::explain;
+//! use rusty_promql_parser::expr;
+//!
+//! let (_, ast) = expr("sum by (job) (rate(http_requests_total[5m])) > 100").unwrap();
+//! assert_eq!(
+//!     explain(&ast),
+//!     "sum of the per-second rate of http_requests_total over 5m, grouped by job, \
+//!      compared greater-than 100"
+//! );
+//! ```
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::ast::{Aggregation, BinaryExpr, BinaryModifier, BinaryOp, Call, Expr, GroupSide};
+use crate::ast::{NumberLiteral, SubqueryExpr, UnaryOp, VectorMatchingOp};
+use crate::lexer::duration::Duration;
+use crate::parser::aggregation::GroupingAction;
+use crate::parser::selector::{AtModifier, LabelMatchOp, VectorSelector};
+
+/// Describe an expression in plain English.
+///
+/// The summary is a sentence fragment without a trailing period. It is not
+/// capitalized, since it may start with a metric name.
+pub fn explain(expr: &Expr) -> String {
+    describe(expr)
+}
+
+fn describe(expr: &Expr) -> String {
+    match expr {
+        Expr::Number(_) => expr.to_string(),
+        Expr::String(s) => format!("the string {:?}", s),
+        Expr::VectorSelector(vs) => describe_selector(vs),
+        Expr::MatrixSelector(ms) => format!(
+            "the last {} of {}",
+            ms.range,
+            describe_selector(&ms.selector)
+        ),
+        Expr::Call(c) => describe_call(c),
+        Expr::Aggregation(a) => describe_aggregation(a),
+        Expr::Binary(b) => describe_binary(b),
+        Expr::Unary(u) => match u.op {
+            UnaryOp::Minus => format!("the negation of {}", describe(&u.expr)),
+            UnaryOp::Plus => describe(&u.expr),
+        },
+        Expr::Paren(e) => describe(e),
+        Expr::Subquery(sq) => describe_subquery(sq),
+    }
+}
+
+fn describe_selector(vs: &VectorSelector) -> String {
+    let mut text = match &vs.name {
+        Some(name) => name.clone(),
+        None => "series".to_string(),
+    };
+    let conditions: Vec<String> = vs
+        .matchers
+        .iter()
+        .map(|m| {
+            let verb = match m.op {
+                LabelMatchOp::Equal => "is",
+                LabelMatchOp::NotEqual => "is not",
+                LabelMatchOp::RegexMatch => "matches",
+                LabelMatchOp::RegexNotMatch => "does not match",
+            };
+            format!("{} {} {:?}", m.name, verb, m.value)
+        })
+        .collect();
+    if !conditions.is_empty() {
+        text.push_str(" where ");
+        text.push_str(&conditions.join(" and "));
+    }
+    push_time_modifiers(&mut text, vs.offset.as_ref(), vs.at.as_ref());
+    text
+}
+
+fn push_time_modifiers(text: &mut String, offset: Option<&Duration>, at: Option<&AtModifier>) {
+    if let Some(offset) = offset {
+        if offset.is_negative() {
+            let ahead = Duration::from_millis(offset.as_millis().saturating_neg());
+            text.push_str(&format!(", {} ahead", ahead));
+        } else {
+            text.push_str(&format!(", {} ago", offset));
+        }
+    }
+    match at {
+        None => {}
+        Some(AtModifier::Timestamp(ms)) => text.push_str(&format!(
+            ", at time {}",
+            NumberLiteral::new(*ms as f64 / 1000.0)
+        )),
+        Some(AtModifier::Start) => text.push_str(", at the start of the query range"),
+        Some(AtModifier::End) => text.push_str(", at the end of the query range"),
+    }
+}
+
+/// Phrases for functions over a range of samples, described as
+/// "the <phrase> of <series> over <range>"
+fn range_function_phrase(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "rate" => "per-second rate",
+        "irate" => "instant per-second rate",
+        "increase" => "increase",
+        "delta" => "change",
+        "idelta" => "last change",
+        "deriv" => "per-second derivative",
+        "changes" => "number of changes",
+        "resets" => "number of counter resets",
+        "avg_over_time" => "average",
+        "min_over_time" => "minimum",
+        "max_over_time" => "maximum",
+        "sum_over_time" => "sum",
+        "count_over_time" => "number of samples",
+        "last_over_time" => "latest sample",
+        "present_over_time" => "presence",
+        "stddev_over_time" => "standard deviation",
+        "stdvar_over_time" => "variance",
+        _ => return None,
+    })
+}
+
+/// Phrases for functions applied to each sample, described as
+/// "the <phrase> of <argument>"
+fn function_phrase(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "abs" => "absolute value",
+        "ceil" => "rounded-up value",
+        "floor" => "rounded-down value",
+        "round" => "rounded value",
+        "sqrt" => "square root",
+        "exp" => "exponential",
+        "ln" => "natural logarithm",
+        "log2" => "binary logarithm",
+        "log10" => "decimal logarithm",
+        "sgn" => "sign",
+        "scalar" => "scalar value",
+        "vector" => "vector",
+        "absent" => "absence",
+        "timestamp" => "sample timestamps",
+        "sort" => "ascending sort",
+        "sort_desc" => "descending sort",
+        _ => return None,
+    })
+}
+
+fn describe_call(call: &Call) -> String {
+    let name = call.name.as_str();
+    match (name, call.args.as_slice()) {
+        ("time", []) => "the evaluation time".to_string(),
+        ("histogram_quantile", [q, hist]) => {
+            format!(
+                "the {} quantile of histogram {}",
+                describe(q),
+                describe(hist)
+            )
+        }
+        ("quantile_over_time", [q, range]) => match range_parts(range) {
+            Some((inner, window)) => {
+                format!("the {} quantile of {} over {}", describe(q), inner, window)
+            }
+            None => generic_call(call),
+        },
+        ("predict_linear", [range, t]) => match range_parts(range) {
+            Some((inner, window)) => format!(
+                "the value of {} predicted {} seconds ahead from the last {}",
+                inner,
+                describe(t),
+                window
+            ),
+            None => generic_call(call),
+        },
+        (_, [arg]) => {
+            if let (Some(phrase), Some((inner, window))) =
+                (range_function_phrase(name), range_parts(arg))
+            {
+                format!("the {} of {} over {}", phrase, inner, window)
+            } else if let Some(phrase) = function_phrase(name) {
+                format!("the {} of {}", phrase, describe(arg))
+            } else {
+                generic_call(call)
+            }
+        }
+        _ => generic_call(call),
+    }
+}
+
+/// Split a range argument into the description of its series and its window
+fn range_parts(expr: &Expr) -> Option<(String, String)> {
+    match expr {
+        Expr::MatrixSelector(ms) => Some((describe_selector(&ms.selector), ms.range.to_string())),
+        Expr::Subquery(sq) => {
+            let mut window = sq.range.to_string();
+            if let Some(step) = sq.step {
+                window.push_str(&format!(" at {} resolution", step));
+            }
+            push_time_modifiers(&mut window, sq.offset.as_ref(), sq.at.as_ref());
+            Some((describe(&sq.expr), window))
+        }
+        _ => None,
+    }
+}
+
+fn generic_call(call: &Call) -> String {
+    if call.args.is_empty() {
+        return format!("{}()", call.name);
+    }
+    let args: Vec<String> = call.args.iter().map(describe).collect();
+    format!("{} of {}", call.name, args.join(", "))
+}
+
+fn describe_aggregation(agg: &Aggregation) -> String {
+    let param = agg.param.as_ref().map(describe).unwrap_or_default();
+    let head = match agg.op.as_str() {
+        "sum" => "sum".to_string(),
+        "avg" => "average".to_string(),
+        "count" => "count".to_string(),
+        "min" => "minimum".to_string(),
+        "max" => "maximum".to_string(),
+        "group" => "grouping".to_string(),
+        "stddev" => "standard deviation".to_string(),
+        "stdvar" => "variance".to_string(),
+        "topk" => format!("the top {} series", param),
+        "bottomk" => format!("the bottom {} series", param),
+        "quantile" => format!("the {} quantile", param),
+        "count_values" => match &agg.param {
+            Some(Expr::String(label)) => format!("the count of each value (as label {:?})", label),
+            _ => format!("the count of each value (as label {})", param),
+        },
+        "limitk" => format!("a sample of {} series", param),
+        "limit_ratio" => format!("a sample of {} of the series", param),
+        other => other.to_string(),
+    };
+    let mut text = format!("{} of {}", head, describe(&agg.expr));
+    if let Some(grouping) = &agg.grouping {
+        let labels = grouping.labels.join(", ");
+        match (&grouping.action, labels.is_empty()) {
+            (GroupingAction::By, true) => {}
+            (GroupingAction::By, false) => text.push_str(&format!(", grouped by {}", labels)),
+            (GroupingAction::Without, true) => text.push_str(", keeping all labels"),
+            (GroupingAction::Without, false) => {
+                text.push_str(&format!(", grouped by all labels except {}", labels))
+            }
+        }
+    }
+    text
+}
+
+fn describe_binary(binary: &BinaryExpr) -> String {
+    let phrase = match binary.op {
+        BinaryOp::Add => "plus",
+        BinaryOp::Sub => "minus",
+        BinaryOp::Mul => "times",
+        BinaryOp::Div => "divided by",
+        BinaryOp::Mod => "modulo",
+        BinaryOp::Pow => "to the power of",
+        BinaryOp::Atan2 => "atan2 with",
+        BinaryOp::Eq => "compared equal to",
+        BinaryOp::Ne => "compared not-equal to",
+        BinaryOp::Lt => "compared less-than",
+        BinaryOp::Le => "compared less-than-or-equal",
+        BinaryOp::Gt => "compared greater-than",
+        BinaryOp::Ge => "compared greater-than-or-equal",
+        BinaryOp::And => "where also present in",
+        BinaryOp::Or => "or else",
+        BinaryOp::Unless => "unless present in",
+    };
+    let mut text = format!(
+        "{}, {} {}",
+        describe(&binary.lhs),
+        phrase,
+        describe(&binary.rhs)
+    );
+    if let Some(modifier) = &binary.modifier {
+        push_modifier(&mut text, modifier);
+    }
+    text
+}
+
+fn push_modifier(text: &mut String, modifier: &BinaryModifier) {
+    if modifier.return_bool {
+        text.push_str(", as 0 or 1");
+    }
+    let Some(matching) = &modifier.matching else {
+        return;
+    };
+    let labels = matching.labels.join(", ");
+    match (&matching.op, labels.is_empty()) {
+        (VectorMatchingOp::On, true) => text.push_str(", matching all series"),
+        (VectorMatchingOp::On, false) => text.push_str(&format!(", matching on {}", labels)),
+        (VectorMatchingOp::Ignoring, true) => {}
+        (VectorMatchingOp::Ignoring, false) => {
+            text.push_str(&format!(", matching ignoring {}", labels))
+        }
+    }
+    if let Some(group) = &matching.group {
+        let side = match group.side {
+            GroupSide::Left => "many-to-one",
+            GroupSide::Right => "one-to-many",
+        };
+        text.push_str(&format!(", {}", side));
+        if !group.labels.is_empty() {
+            text.push_str(&format!(" copying {}", group.labels.join(", ")));
+        }
+    }
+}
+
+fn describe_subquery(sq: &SubqueryExpr) -> String {
+    let mut text = format!(
+        "{}, evaluated over the last {}",
+        describe(&sq.expr),
+        sq.range
+    );
+    if let Some(step) = sq.step {
+        text.push_str(&format!(" every {}", step));
+    }
+    push_time_modifiers(&mut text, sq.offset.as_ref(), sq.at.as_ref());
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::expr::expr;
+
+    fn explain_query(query: &str) -> String {
+        let (rest, e) = expr(query).unwrap();
+        assert!(rest.is_empty(), "unparsed input: {:?}", rest);
+        explain(&e)
+    }
+
+    #[test]
+    fn test_explain_aggregated_rate() {
+        let text = explain_query(
+            r#"sum by (job) (rate(http_requests_total{code=~"5.."}[5m])) / ignoring(code) sum by (job) (rate(http_requests_total[5m]))"#,
+        );
+        assert!(text.starts_with("sum of the per-second rate of http_requests_total"));
+        assert!(text.contains(r#"where code matches "5..""#));
+        assert!(text.contains("over 5m, grouped by job"));
+        assert!(text.contains("divided by sum"));
+        assert!(text.contains("matching ignoring code"));
+    }
+
+    #[test]
+    fn test_explain_phrases() {
+        let cases = [
+            ("42", "42"),
+            ("-foo", "the negation of foo"),
+            ("topk(3, foo offset 1h)", "the top 3 series of foo, 1h ago"),
+            (
+                "histogram_quantile(0.99, sum by (le) (rate(x_bucket[5m])))",
+                "the 0.99 quantile of histogram sum of the per-second rate of x_bucket over 5m, grouped by le",
+            ),
+            (
+                "max_over_time(rate(x[1m])[1h:5m])",
+                "the maximum of the per-second rate of x over 1m over 1h at 5m resolution",
+            ),
+            (
+                "a > bool on (job) group_left (team) b",
+                "a, compared greater-than b, as 0 or 1, matching on job, many-to-one copying team",
+            ),
+            ("up @ start()", "up, at the start of the query range"),
+            (
+                r#"count_values("version", build_info)"#,
+                r#"the count of each value (as label "version") of build_info"#,
+            ),
+            ("up @ 100.5 offset -1m", "up, 1m ahead, at time 100.5"),
+            ("foo[5m:1m]", "foo, evaluated over the last 5m every 1m"),
+            (
+                r#"label_replace(up, "a", "$1", "b", "(.*)")"#,
+                r#"label_replace of up, the string "a", the string "$1", the string "b", the string "(.*)""#,
+            ),
+        ];
+        for (query, expected) in cases {
+            assert_eq!(explain_query(query), expected, "{}", query);
+        }
+    }
+}
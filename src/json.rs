@@ -0,0 +1,932 @@
+//! Versioned JSON representation of the AST, for durable storage.
+//!
+//! Requires the `json` feature. [`Expr::to_json_value`] produces a document
+//! in a schema owned by this crate, and [`Expr::from_json_value`] reads it
+//! back. The schema does not follow the Rust type layout, so it stays stable
+//! when the AST types change; a change to the schema itself bumps
+//! [`JSON_SCHEMA_VERSION`]. Stored documents carry their version, so readers
+//! can detect and migrate old ones.
+//!
+//! # Schema (version 1)
+//!
+//! The document is `{"version": 1, "expr": <node>}`. Every node is an object
+//! with a `"type"` field and type-specific fields:
+//!
+//! | `type`            | Fields |
+//! |-------------------|--------|
+//! | `number`          | `value`: number, or `"NaN"`, `"+Inf"`, `"-Inf"` |
+//! | `string`          | `value`: string |
+//! | `vector_selector` | `name`: string or null, `matchers`, `offset_ms`, `at` |
+//! | `matrix_selector` | the `vector_selector` fields, `range_ms`: integer |
+//! | `call`            | `name`: string, `args`: array of nodes |
+//! | `aggregation`     | `op`: string, `param`: node or null, `expr`: node, `grouping` |
+//! | `binary`          | `op`: operator as written in PromQL, `lhs`, `rhs`: nodes, `modifier` |
+//! | `unary`           | `op`: `"-"` or `"+"`, `expr`: node |
+//! | `paren`           | `expr`: node |
+//! | `subquery`        | `expr`: node, `range_ms`: integer, `step_ms`: integer or null, `offset_ms`, `at` |
+//!
+//! The shared fields are:
+//!
+//! - `matchers`: array of `{"name", "op", "value"}` objects, where `op` is
+//!   one of `=`, `!=`, `=~` and `!~`
+//! - `offset_ms`: offset in milliseconds, or null
+//! - `at`: `@` timestamp in milliseconds, `"start"`, `"end"`, or null
+//! - `grouping`: `{"action": "by" | "without", "labels": [...]}` or null
+//! - `modifier`: null, or `{"bool": boolean, "matching": matching}` where
+//!   `matching` is null or `{"op": "on" | "ignoring", "labels": [...],
+//!   "group": group}` and `group` is null or
+//!   `{"side": "left" | "right", "labels": [...]}`
+//!
+//! Unknown fields are ignored when reading.
+//!
+//! # Prometheus format
+//!
+//! [`to_prometheus_json`] instead writes the format of the Prometheus
+//! `/api/v1/parse_query` endpoint, for interchange with Go tooling. It is
+//! write-only and follows the Go AST: selectors list their metric name as a
+//! `__name__` matcher, and a minus sign in front of a number literal is
+//! folded into the number.
+//!
+//! # Example
+//!
+//! ```rust
+//! use rusty_promql_parser::{Expr, expr};
+//!
+//! let (_, ast) = expr("sum by (job) (rate(up[5m]))").unwrap();
+//! let stored = ast.to_json_value().to_string();
+//!
+//! let value = serde_json::from_str(&stored).unwrap();
+//! assert_eq!(Expr::from_json_value(&value).unwrap(), ast);
+//! ```
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+use serde_json::{Map, Value, json};
+
+use crate::ast::{
+    Aggregation, BinaryExpr, BinaryModifier, BinaryOp, Call, Expr, GroupModifier, GroupSide,
+    NumberLiteral, SubqueryExpr, UnaryExpr, UnaryOp, VectorMatching, VectorMatchingOp,
+};
+use crate::lexer::duration::Duration;
+use crate::parser::aggregation::{Grouping, GroupingAction};
+use crate::parser::function::{ValueType, Variadic, get_function};
+use crate::parser::selector::{
+    AtModifier, LabelMatchOp, LabelMatcher, MatrixSelector, VectorSelector,
+};
+use crate::validate::type_of;
+
+/// Version of the JSON schema written by [`Expr::to_json_value`]
+pub const JSON_SCHEMA_VERSION: u64 = 1;
+
+/// Error returned by [`Expr::from_json_value`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonError {
+    /// Description of the problem
+    pub message: String,
+}
+
+impl JsonError {
+    fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid AST JSON: {}", self.message)
+    }
+}
+
+impl core::error::Error for JsonError {}
+
+impl Expr {
+    /// Convert the expression to a JSON document in this crate's versioned
+    /// schema. See the [module documentation](crate::json) for the schema.
+    pub fn to_json_value(&self) -> Value {
+        json!({
+            "version": JSON_SCHEMA_VERSION,
+            "expr": encode_expr(self),
+        })
+    }
+
+    /// Read an expression from a document written by
+    /// [`Expr::to_json_value`].
+    ///
+    /// Fails if the document has a different schema version, or if a node
+    /// is malformed; the error names the offending node and field.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::Expr;
+    /// use serde_json::json;
+    ///
+    /// let err = Expr::from_json_value(&json!({"version": 2, "expr": {}})).unwrap_err();
+    /// assert_eq!(
+    ///     err.message,
+    ///     "unsupported schema version 2, this version of the crate reads version 1"
+    /// );
+    /// ```
+    pub fn from_json_value(value: &Value) -> Result<Expr, JsonError> {
+        let document = Node::new(value, "document")?;
+        let version = document.field("version")?;
+        match version.as_u64() {
+            Some(JSON_SCHEMA_VERSION) => {}
+            Some(v) => {
+                return Err(JsonError::new(format!(
+                    "unsupported schema version {}, this version of the crate reads version {}",
+                    v, JSON_SCHEMA_VERSION
+                )));
+            }
+            None => return Err(document.invalid("version", "a non-negative integer")),
+        }
+        decode_expr(document.field("expr")?)
+    }
+}
+
+fn encode_expr(expr: &Expr) -> Value {
+    match expr {
+        Expr::Number(n) => json!({"type": "number", "value": encode_number(n.value())}),
+        Expr::String(s) => json!({"type": "string", "value": s}),
+        Expr::VectorSelector(vs) => {
+            let mut node = encode_selector(vs);
+            node.insert("type".into(), "vector_selector".into());
+            Value::Object(node)
+        }
+        Expr::MatrixSelector(ms) => {
+            let mut node = encode_selector(&ms.selector);
+            node.insert("type".into(), "matrix_selector".into());
+            node.insert("range_ms".into(), ms.range.as_millis().into());
+            Value::Object(node)
+        }
+        Expr::Call(c) => json!({
+            "type": "call",
+            "name": c.name,
+            "args": c.args.iter().map(encode_expr).collect::<Vec<_>>(),
+        }),
+        Expr::Aggregation(a) => json!({
+            "type": "aggregation",
+            "op": a.op,
+            "param": a.param.as_ref().map(encode_expr),
+            "expr": encode_expr(&a.expr),
+            "grouping": a.grouping.as_ref().map(|g| json!({
+                "action": match g.action {
+                    GroupingAction::By => "by",
+                    GroupingAction::Without => "without",
+                },
+                "labels": g.labels,
+            })),
+        }),
+        Expr::Binary(b) => json!({
+            "type": "binary",
+            "op": b.op.as_str(),
+            "lhs": encode_expr(&b.lhs),
+            "rhs": encode_expr(&b.rhs),
+            "modifier": b.modifier.as_ref().map(encode_modifier),
+        }),
+        Expr::Unary(u) => json!({
+            "type": "unary",
+            "op": u.op.as_str(),
+            "expr": encode_expr(&u.expr),
+        }),
+        Expr::Paren(e) => json!({"type": "paren", "expr": encode_expr(e)}),
+        Expr::Subquery(sq) => json!({
+            "type": "subquery",
+            "expr": encode_expr(&sq.expr),
+            "range_ms": sq.range.as_millis(),
+            "step_ms": sq.step.map(|d| d.as_millis()),
+            "offset_ms": sq.offset.map(|d| d.as_millis()),
+            "at": encode_at(sq.at.as_ref()),
+        }),
+    }
+}
+
+/// JSON has no representation for non-finite numbers
+fn encode_number(n: f64) -> Value {
+    if n.is_nan() {
+        "NaN".into()
+    } else if n == f64::INFINITY {
+        "+Inf".into()
+    } else if n == f64::NEG_INFINITY {
+        "-Inf".into()
+    } else {
+        n.into()
+    }
+}
+
+fn encode_selector(vs: &VectorSelector) -> Map<String, Value> {
+    let matchers: Vec<Value> = vs
+        .matchers
+        .iter()
+        .map(|m| json!({"name": m.name, "op": m.op.as_str(), "value": m.value}))
+        .collect();
+    let mut node = Map::new();
+    node.insert("name".into(), vs.name.clone().into());
+    node.insert("matchers".into(), matchers.into());
+    node.insert("offset_ms".into(), vs.offset.map(|d| d.as_millis()).into());
+    node.insert("at".into(), encode_at(vs.at.as_ref()));
+    node
+}
+
+fn encode_at(at: Option<&AtModifier>) -> Value {
+    match at {
+        None => Value::Null,
+        Some(AtModifier::Timestamp(ms)) => (*ms).into(),
+        Some(AtModifier::Start) => "start".into(),
+        Some(AtModifier::End) => "end".into(),
+    }
+}
+
+fn encode_modifier(modifier: &BinaryModifier) -> Value {
+    json!({
+        "bool": modifier.return_bool,
+        "matching": modifier.matching.as_ref().map(|m| json!({
+            "op": m.op.to_string(),
+            "labels": m.labels,
+            "group": m.group.as_ref().map(|g| json!({
+                "side": match g.side {
+                    GroupSide::Left => "left",
+                    GroupSide::Right => "right",
+                },
+                "labels": g.labels,
+            })),
+        })),
+    })
+}
+
+/// A JSON object being decoded, with a name for error messages
+struct Node<'a> {
+    map: &'a Map<String, Value>,
+    name: &'a str,
+}
+
+impl<'a> Node<'a> {
+    fn new(value: &'a Value, name: &'a str) -> Result<Self, JsonError> {
+        match value {
+            Value::Object(map) => Ok(Self { map, name }),
+            _ => Err(JsonError::new(format!("{} must be an object", name))),
+        }
+    }
+
+    fn invalid(&self, field: &str, expected: &str) -> JsonError {
+        JsonError::new(format!(
+            "field \"{}\" of {} must be {}",
+            field, self.name, expected
+        ))
+    }
+
+    fn field(&self, field: &str) -> Result<&'a Value, JsonError> {
+        self.map
+            .get(field)
+            .ok_or_else(|| JsonError::new(format!("{} has no field \"{}\"", self.name, field)))
+    }
+
+    /// A field that may be absent or null
+    fn optional(&self, field: &str) -> Option<&'a Value> {
+        self.map.get(field).filter(|v| !v.is_null())
+    }
+
+    fn str(&self, field: &str) -> Result<&'a str, JsonError> {
+        self.field(field)?
+            .as_str()
+            .ok_or_else(|| self.invalid(field, "a string"))
+    }
+
+    fn millis(&self, field: &str) -> Result<Duration, JsonError> {
+        self.field(field)?
+            .as_i64()
+            .map(Duration::from_millis)
+            .ok_or_else(|| self.invalid(field, "an integer"))
+    }
+
+    fn optional_millis(&self, field: &str) -> Result<Option<Duration>, JsonError> {
+        match self.optional(field) {
+            None => Ok(None),
+            Some(_) => self.millis(field).map(Some),
+        }
+    }
+
+    fn strings(&self, field: &str) -> Result<Vec<String>, JsonError> {
+        self.field(field)?
+            .as_array()
+            .and_then(|items| {
+                items
+                    .iter()
+                    .map(|item| item.as_str().map(String::from))
+                    .collect()
+            })
+            .ok_or_else(|| self.invalid(field, "an array of strings"))
+    }
+
+    fn expr(&self, field: &str) -> Result<Expr, JsonError> {
+        decode_expr(self.field(field)?)
+    }
+
+    fn optional_expr(&self, field: &str) -> Result<Option<Expr>, JsonError> {
+        self.optional(field).map(decode_expr).transpose()
+    }
+
+    fn at(&self) -> Result<Option<AtModifier>, JsonError> {
+        match self.optional("at") {
+            None => Ok(None),
+            Some(Value::String(s)) if s == "start" => Ok(Some(AtModifier::Start)),
+            Some(Value::String(s)) if s == "end" => Ok(Some(AtModifier::End)),
+            Some(v) => v
+                .as_i64()
+                .map(|ms| Some(AtModifier::Timestamp(ms)))
+                .ok_or_else(|| self.invalid("at", "an integer, \"start\" or \"end\"")),
+        }
+    }
+}
+
+fn decode_expr(value: &Value) -> Result<Expr, JsonError> {
+    let node = Node::new(value, "node")?;
+    let kind = node.str("type")?;
+    let node = Node { name: kind, ..node };
+    let expr = match kind {
+        "number" => Expr::Number(NumberLiteral::new(decode_number(&node)?)),
+        "string" => Expr::String(node.str("value")?.to_string()),
+        "vector_selector" => Expr::VectorSelector(decode_selector(&node)?),
+        "matrix_selector" => Expr::MatrixSelector(MatrixSelector {
+            selector: decode_selector(&node)?,
+            range: node.millis("range_ms")?,
+        }),
+        "call" => {
+            let args = node
+                .field("args")?
+                .as_array()
+                .ok_or_else(|| node.invalid("args", "an array"))?
+                .iter()
+                .map(decode_expr)
+                .collect::<Result<_, _>>()?;
+            Expr::Call(Call::new(node.str("name")?, args))
+        }
+        "aggregation" => {
+            let grouping = match node.optional("grouping") {
+                None => None,
+                Some(value) => {
+                    let grouping = Node::new(value, "grouping")?;
+                    let action = match grouping.str("action")? {
+                        "by" => GroupingAction::By,
+                        "without" => GroupingAction::Without,
+                        _ => return Err(grouping.invalid("action", "\"by\" or \"without\"")),
+                    };
+                    Some(Grouping {
+                        action,
+                        labels: grouping.strings("labels")?,
+                    })
+                }
+            };
+            Expr::Aggregation(Box::new(Aggregation {
+                op: node.str("op")?.to_string(),
+                expr: node.expr("expr")?,
+                param: node.optional_expr("param")?,
+                grouping,
+            }))
+        }
+        "binary" => {
+            let op: BinaryOp = node
+                .str("op")?
+                .parse()
+                .map_err(|_| node.invalid("op", "a binary operator"))?;
+            let modifier = node.optional("modifier").map(decode_modifier).transpose()?;
+            Expr::Binary(Box::new(BinaryExpr {
+                op,
+                lhs: node.expr("lhs")?,
+                rhs: node.expr("rhs")?,
+                modifier,
+            }))
+        }
+        "unary" => {
+            let op: UnaryOp = node
+                .str("op")?
+                .parse()
+                .map_err(|_| node.invalid("op", "\"-\" or \"+\""))?;
+            Expr::Unary(Box::new(UnaryExpr::new(op, node.expr("expr")?)))
+        }
+        "paren" => Expr::Paren(Box::new(node.expr("expr")?)),
+        "subquery" => Expr::Subquery(Box::new(SubqueryExpr {
+            expr: node.expr("expr")?,
+            range: node.millis("range_ms")?,
+            step: node.optional_millis("step_ms")?,
+            offset: node.optional_millis("offset_ms")?,
+            at: node.at()?,
+        })),
+        other => return Err(JsonError::new(format!("unknown node type \"{}\"", other))),
+    };
+    Ok(expr)
+}
+
+fn decode_number(node: &Node<'_>) -> Result<f64, JsonError> {
+    match node.field("value")? {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) if s == "NaN" => Some(f64::NAN),
+        Value::String(s) if s == "+Inf" => Some(f64::INFINITY),
+        Value::String(s) if s == "-Inf" => Some(f64::NEG_INFINITY),
+        _ => None,
+    }
+    .ok_or_else(|| node.invalid("value", "a number, \"NaN\", \"+Inf\" or \"-Inf\""))
+}
+
+fn decode_selector(node: &Node<'_>) -> Result<VectorSelector, JsonError> {
+    let name = match node.optional("name") {
+        None => None,
+        Some(_) => Some(node.str("name")?.to_string()),
+    };
+    let matchers = node
+        .field("matchers")?
+        .as_array()
+        .ok_or_else(|| node.invalid("matchers", "an array"))?
+        .iter()
+        .map(|value| {
+            let matcher = Node::new(value, "matcher")?;
+            let op = match matcher.str("op")? {
+                "=" => LabelMatchOp::Equal,
+                "!=" => LabelMatchOp::NotEqual,
+                "=~" => LabelMatchOp::RegexMatch,
+                "!~" => LabelMatchOp::RegexNotMatch,
+                _ => return Err(matcher.invalid("op", "one of =, !=, =~ and !~")),
+            };
+            Ok(LabelMatcher::new(
+                matcher.str("name")?,
+                op,
+                matcher.str("value")?,
+            ))
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(VectorSelector {
+        name,
+        matchers,
+        offset: node.optional_millis("offset_ms")?,
+        at: node.at()?,
+    })
+}
+
+fn decode_modifier(value: &Value) -> Result<BinaryModifier, JsonError> {
+    let modifier = Node::new(value, "modifier")?;
+    let return_bool = modifier
+        .field("bool")?
+        .as_bool()
+        .ok_or_else(|| modifier.invalid("bool", "a boolean"))?;
+    let matching = match modifier.optional("matching") {
+        None => None,
+        Some(value) => {
+            let matching = Node::new(value, "matching")?;
+            let op = match matching.str("op")? {
+                "on" => VectorMatchingOp::On,
+                "ignoring" => VectorMatchingOp::Ignoring,
+                _ => return Err(matching.invalid("op", "\"on\" or \"ignoring\"")),
+            };
+            let group = match matching.optional("group") {
+                None => None,
+                Some(value) => {
+                    let group = Node::new(value, "group")?;
+                    let side = match group.str("side")? {
+                        "left" => GroupSide::Left,
+                        "right" => GroupSide::Right,
+                        _ => return Err(group.invalid("side", "\"left\" or \"right\"")),
+                    };
+                    Some(GroupModifier {
+                        side,
+                        labels: group.strings("labels")?,
+                    })
+                }
+            };
+            Some(VectorMatching {
+                op,
+                labels: matching.strings("labels")?,
+                group,
+            })
+        }
+    };
+    Ok(BinaryModifier {
+        return_bool,
+        matching,
+    })
+}
+
+/// Convert the expression to the JSON AST of the Prometheus
+/// `/api/v1/parse_query` endpoint.
+///
+/// Nodes carry a `"type"` field such as `"binaryExpr"` or
+/// `"vectorSelector"`, with the field names Prometheus uses. See the
+/// [module documentation](crate::json#prometheus-format) for where the
+/// output differs from this crate's AST.
+///
+/// # Example
+///
+/// ```rust
+/// use rusty_promql_parser::expr;
+/// use rusty_promql_parser::json::to_prometheus_json;
+/// use serde_json::json;
+///
+/// let (_, ast) = expr("up offset 5m").unwrap();
+/// assert_eq!(
+///     to_prometheus_json(&ast),
+///     json!({
+///         "type": "vectorSelector",
+///         "name": "up",
+///         "offset": 300000,
+///         "matchers": [{"name": "__name__", "type": "=", "value": "up"}],
+///         "timestamp": null,
+///         "startOrEnd": null,
+///     })
+/// );
+/// ```
+pub fn to_prometheus_json(expr: &Expr) -> Value {
+    match expr {
+        Expr::Number(n) => json!({"type": "numberLiteral", "val": prometheus_number(n.value())}),
+        Expr::String(s) => json!({"type": "stringLiteral", "val": s}),
+        Expr::VectorSelector(vs) => {
+            let mut node = prometheus_selector(vs);
+            node.insert("type".into(), "vectorSelector".into());
+            Value::Object(node)
+        }
+        Expr::MatrixSelector(ms) => {
+            let mut node = prometheus_selector(&ms.selector);
+            node.insert("type".into(), "matrixSelector".into());
+            node.insert("range".into(), ms.range.as_millis().into());
+            Value::Object(node)
+        }
+        Expr::Call(c) => {
+            let func = get_function(&c.name);
+            json!({
+                "type": "call",
+                "func": {
+                    "name": c.name,
+                    "argTypes": func.map_or(&[][..], |f| f.arg_types)
+                        .iter()
+                        .map(|&t| prometheus_value_type(t))
+                        .collect::<Vec<_>>(),
+                    "variadic": func.map_or(0, |f| match f.variadic {
+                        Variadic::None => 0,
+                        Variadic::Repeat => -1,
+                        Variadic::Optional(n) => i64::from(n),
+                    }),
+                    "returnType": func.map(|f| prometheus_value_type(f.return_type)),
+                },
+                "args": c.args.iter().map(to_prometheus_json).collect::<Vec<_>>(),
+            })
+        }
+        Expr::Aggregation(a) => json!({
+            "type": "aggregation",
+            "op": a.op.to_lowercase(),
+            "expr": to_prometheus_json(&a.expr),
+            "param": a.param.as_ref().map(to_prometheus_json),
+            "grouping": a.grouping.as_ref().map_or(&[][..], |g| &g.labels),
+            "without": a.grouping.as_ref().is_some_and(|g| g.action == GroupingAction::Without),
+        }),
+        Expr::Binary(b) => json!({
+            "type": "binaryExpr",
+            "op": b.op.as_str(),
+            "lhs": to_prometheus_json(&b.lhs),
+            "rhs": to_prometheus_json(&b.rhs),
+            "matching": prometheus_matching(b),
+            "bool": b.modifier.as_ref().is_some_and(|m| m.return_bool),
+        }),
+        // Prometheus folds the sign into a number literal, innermost first
+        Expr::Unary(u) => match signed_number(expr) {
+            Some(n) => to_prometheus_json(&Expr::Number(NumberLiteral::new(n))),
+            None => json!({
+                "type": "unaryExpr",
+                "op": u.op.as_str(),
+                "expr": to_prometheus_json(&u.expr),
+            }),
+        },
+        Expr::Paren(e) => json!({"type": "parenExpr", "expr": to_prometheus_json(e)}),
+        Expr::Subquery(sq) => {
+            let mut node = Map::new();
+            node.insert("type".into(), "subquery".into());
+            node.insert("expr".into(), to_prometheus_json(&sq.expr));
+            node.insert("range".into(), sq.range.as_millis().into());
+            node.insert(
+                "offset".into(),
+                sq.offset.map_or(0, |d| d.as_millis()).into(),
+            );
+            node.insert("step".into(), sq.step.map_or(0, |d| d.as_millis()).into());
+            insert_prometheus_at(&mut node, sq.at.as_ref());
+            Value::Object(node)
+        }
+    }
+}
+
+/// The value of a number literal under any number of unary signs, as
+/// Prometheus folds them
+fn signed_number(expr: &Expr) -> Option<f64> {
+    match expr {
+        Expr::Number(n) => Some(n.value()),
+        Expr::Unary(u) => signed_number(&u.expr).map(|n| match u.op {
+            UnaryOp::Minus => -n,
+            UnaryOp::Plus => n,
+        }),
+        _ => None,
+    }
+}
+
+/// Go formats numbers without an exponent, and non-finite ones like
+/// `NaN`, `+Inf` and `-Inf`
+fn prometheus_number(n: f64) -> String {
+    if n.is_nan() {
+        "NaN".into()
+    } else if n == f64::INFINITY {
+        "+Inf".into()
+    } else if n == f64::NEG_INFINITY {
+        "-Inf".into()
+    } else {
+        n.to_string()
+    }
+}
+
+fn prometheus_value_type(t: ValueType) -> &'static str {
+    match t {
+        ValueType::Scalar => "scalar",
+        ValueType::Vector => "vector",
+        ValueType::Matrix => "matrix",
+        ValueType::String => "string",
+    }
+}
+
+fn prometheus_selector(vs: &VectorSelector) -> Map<String, Value> {
+    // Prometheus appends the metric name to the matchers
+    let name_matcher = vs
+        .name
+        .as_ref()
+        .map(|name| LabelMatcher::new("__name__", LabelMatchOp::Equal, name.as_str()));
+    let matchers: Vec<Value> = vs
+        .matchers
+        .iter()
+        .chain(&name_matcher)
+        .map(|m| json!({"name": m.name, "type": m.op.as_str(), "value": m.value}))
+        .collect();
+    let mut node = Map::new();
+    node.insert("name".into(), vs.name.as_deref().unwrap_or("").into());
+    node.insert(
+        "offset".into(),
+        vs.offset.map_or(0, |d| d.as_millis()).into(),
+    );
+    node.insert("matchers".into(), matchers.into());
+    insert_prometheus_at(&mut node, vs.at.as_ref());
+    node
+}
+
+fn insert_prometheus_at(node: &mut Map<String, Value>, at: Option<&AtModifier>) {
+    let (timestamp, start_or_end) = match at {
+        None => (Value::Null, Value::Null),
+        Some(AtModifier::Timestamp(ms)) => ((*ms).into(), Value::Null),
+        Some(AtModifier::Start) => (Value::Null, "start".into()),
+        Some(AtModifier::End) => (Value::Null, "end".into()),
+    };
+    node.insert("timestamp".into(), timestamp);
+    node.insert("startOrEnd".into(), start_or_end);
+}
+
+/// Prometheus only keeps vector matching between two instant vectors, and
+/// then always fills it in
+fn prometheus_matching(b: &BinaryExpr) -> Value {
+    if type_of(&b.lhs) != ValueType::Vector || type_of(&b.rhs) != ValueType::Vector {
+        return Value::Null;
+    }
+    let matching = b.modifier.as_ref().and_then(|m| m.matching.as_ref());
+    let card = match matching.and_then(|m| m.group.as_ref()) {
+        _ if b.op.is_set_operator() => "many-to-many",
+        Some(GroupModifier {
+            side: GroupSide::Left,
+            ..
+        }) => "many-to-one",
+        Some(GroupModifier {
+            side: GroupSide::Right,
+            ..
+        }) => "one-to-many",
+        None => "one-to-one",
+    };
+    json!({
+        "card": card,
+        "labels": matching.map_or(&[][..], |m| &m.labels),
+        "on": matching.is_some_and(|m| m.op == VectorMatchingOp::On),
+        "include": matching
+            .and_then(|m| m.group.as_ref())
+            .map_or(&[][..], |g| &g.labels),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::expr::expr;
+
+    #[test]
+    fn test_round_trip() {
+        let queries = [
+            "1",
+            "-2.5",
+            "NaN",
+            "Inf - -Inf",
+            "0.1 + 0.2",
+            r#""a\nb""#,
+            r#"http_requests_total{job="api", code!~"5.."}"#,
+            r#"{__name__=~"a.*", env!=""} offset -5m"#,
+            "up @ 1609459200.123",
+            "up @ start() offset 1h",
+            "rate(up[5m] @ end())",
+            "sum by (job) (rate(http_requests_total[5m]))",
+            "topk without (instance) (3, up)",
+            r#"count_values("v", up)"#,
+            "a / on (job) group_left (team) b",
+            "a > bool ignoring (instance) b",
+            "a + b * c ^ -d",
+            "(a or b) unless c",
+            "max_over_time(rate(x[1m])[1h:5m] offset 1d)",
+            "min_over_time(x[30m:] @ 100)",
+            "label_replace(up, \"dst\", \"$1\", \"src\", \"(.*)\")",
+        ];
+        for query in queries {
+            let (rest, e) = expr(query).unwrap();
+            assert!(rest.is_empty(), "unparsed input: {:?}", rest);
+            let value = e.to_json_value();
+            // Through text, as when stored
+            let value: Value = serde_json::from_str(&value.to_string()).unwrap();
+            let decoded = Expr::from_json_value(&value).unwrap();
+            assert_eq!(decoded.to_string(), e.to_string(), "{}", query);
+            if !query.contains("NaN") {
+                assert_eq!(decoded, e, "{}", query);
+            }
+        }
+    }
+
+    #[test]
+    fn test_empty_modifier_round_trip() {
+        // `Some` empty modifier and `None` are distinct values
+        let e = Expr::Binary(Box::new(BinaryExpr {
+            op: BinaryOp::Add,
+            lhs: Expr::Number(1.0.into()),
+            rhs: Expr::Number(2.0.into()),
+            modifier: Some(BinaryModifier::default()),
+        }));
+        assert_eq!(Expr::from_json_value(&e.to_json_value()).unwrap(), e);
+    }
+
+    #[test]
+    fn test_schema() {
+        let (_, e) = expr(r#"sum by (job) (rate(x{a="b"}[5m] offset 1m)) > bool 1"#).unwrap();
+        let expected = json!({
+            "version": 1,
+            "expr": {
+                "type": "binary",
+                "op": ">",
+                "lhs": {
+                    "type": "aggregation",
+                    "op": "sum",
+                    "param": null,
+                    "expr": {
+                        "type": "call",
+                        "name": "rate",
+                        "args": [{
+                            "type": "matrix_selector",
+                            "name": "x",
+                            "matchers": [{"name": "a", "op": "=", "value": "b"}],
+                            "offset_ms": 60000,
+                            "at": null,
+                            "range_ms": 300000,
+                        }],
+                    },
+                    "grouping": {"action": "by", "labels": ["job"]},
+                },
+                "rhs": {"type": "number", "value": 1.0},
+                "modifier": {"bool": true, "matching": null},
+            },
+        });
+        assert_eq!(e.to_json_value(), expected);
+    }
+
+    #[test]
+    fn test_version_mismatch() {
+        let (_, e) = expr("up").unwrap();
+        let mut value = e.to_json_value();
+        value["version"] = json!(2);
+        let err = Expr::from_json_value(&value).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid AST JSON: unsupported schema version 2, this version of the crate reads version 1"
+        );
+
+        let err = Expr::from_json_value(&json!({"expr": {}})).unwrap_err();
+        assert_eq!(err.message, "document has no field \"version\"");
+        let err = Expr::from_json_value(&json!({"version": "1"})).unwrap_err();
+        assert_eq!(
+            err.message,
+            "field \"version\" of document must be a non-negative integer"
+        );
+    }
+
+    #[test]
+    fn test_malformed_nodes() {
+        let cases = [
+            (json!([]), "document must be an object"),
+            (json!({"version": 1}), "document has no field \"expr\""),
+            (
+                json!({"version": 1, "expr": {"type": "lambda"}}),
+                "unknown node type \"lambda\"",
+            ),
+            (
+                json!({"version": 1, "expr": {"type": "number", "value": "1"}}),
+                "field \"value\" of number must be a number, \"NaN\", \"+Inf\" or \"-Inf\"",
+            ),
+            (
+                json!({"version": 1, "expr": {"type": "binary", "op": "<>"}}),
+                "field \"op\" of binary must be a binary operator",
+            ),
+            (
+                json!({"version": 1, "expr": {"type": "paren"}}),
+                "paren has no field \"expr\"",
+            ),
+            (
+                json!({"version": 1, "expr": {
+                    "type": "vector_selector",
+                    "name": "up",
+                    "matchers": [{"name": "a", "op": "==", "value": "b"}],
+                }}),
+                "field \"op\" of matcher must be one of =, !=, =~ and !~",
+            ),
+        ];
+        for (value, message) in cases {
+            let err = Expr::from_json_value(&value).unwrap_err();
+            assert_eq!(err.message, message, "{}", value);
+        }
+    }
+
+    #[test]
+    fn test_prometheus_json() {
+        let convert = |query: &str| to_prometheus_json(&expr(query).unwrap().1);
+
+        assert_eq!(
+            convert("-1.5e3"),
+            json!({"type": "numberLiteral", "val": "-1500"})
+        );
+        assert_eq!(
+            convert("-Inf"),
+            json!({"type": "numberLiteral", "val": "-Inf"})
+        );
+        assert_eq!(
+            convert("- -1"),
+            json!({"type": "numberLiteral", "val": "1"})
+        );
+        assert_eq!(
+            convert("-+-2"),
+            json!({"type": "numberLiteral", "val": "2"})
+        );
+        assert_eq!(
+            convert("-(1)"),
+            json!({
+                "type": "unaryExpr",
+                "op": "-",
+                "expr": {"type": "parenExpr", "expr": {"type": "numberLiteral", "val": "1"}},
+            })
+        );
+
+        let node = convert(r#"topk without (a) (3, x{job!="b"} @ start())"#);
+        assert_eq!(node["op"], "topk");
+        assert_eq!(node["param"], json!({"type": "numberLiteral", "val": "3"}));
+        assert_eq!(node["grouping"], json!(["a"]));
+        assert_eq!(node["without"], true);
+        assert_eq!(node["expr"]["name"], "x");
+        assert_eq!(
+            node["expr"]["matchers"],
+            json!([
+                {"name": "job", "type": "!=", "value": "b"},
+                {"name": "__name__", "type": "=", "value": "x"},
+            ])
+        );
+        assert_eq!(node["expr"]["startOrEnd"], "start");
+        assert_eq!(convert("sum(x)")["grouping"], json!([]));
+
+        // Vector matching is only kept between instant vectors
+        assert_eq!(convert("x > bool 1")["matching"], Value::Null);
+        assert_eq!(convert("x > bool 1")["bool"], true);
+        assert_eq!(
+            convert("x and ignoring (a) y")["matching"],
+            json!({"card": "many-to-many", "labels": ["a"], "on": false, "include": []})
+        );
+        assert_eq!(
+            convert("x + y")["matching"],
+            json!({"card": "one-to-one", "labels": [], "on": false, "include": []})
+        );
+
+        let node = convert(r#"round(x, 2) + label_join(x, "a", ",", "b", "c")"#);
+        assert_eq!(
+            node["lhs"]["func"],
+            json!({
+                "name": "round",
+                "argTypes": ["vector", "scalar"],
+                "variadic": 1,
+                "returnType": "vector",
+            })
+        );
+        assert_eq!(node["rhs"]["func"]["variadic"], -1);
+    }
+}
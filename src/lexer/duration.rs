@@ -22,7 +22,7 @@
 //! # Examples
 //!
 //! ```rust
-//! use rusty_promql_parser::lexer::duration::duration;
+//! use rusty_promql_parser::lexer::duration::{Duration, duration};
 //!
 //! // Simple durations
 //! let (_, dur) = duration("5m").unwrap();
@@ -35,18 +35,29 @@
 //! // Milliseconds
 //! let (_, dur) = duration("100ms").unwrap();
 //! assert_eq!(dur.as_millis(), 100);
+//!
+//! // Standalone strings, e.g. from a configuration file
+//! let dur: Duration = "1h30m".parse().unwrap();
+//! assert_eq!(dur.as_secs(), 5400);
 //! ```
 
+use alloc::vec::Vec;
+use core::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+use core::str::FromStr;
+
 use nom::{
-    IResult, Parser,
+    Parser,
     branch::alt,
     bytes::complete::tag,
     character::complete::digit1,
     combinator::{map, map_res, opt},
+    error::ErrorKind,
     multi::many1,
     sequence::pair,
 };
 
+use crate::error::{IResult, ParseError, SyntaxError, SyntaxErrorKind};
+
 /// A duration value representing a time span in milliseconds.
 ///
 /// Durations are used throughout PromQL for specifying time ranges,
@@ -61,7 +72,20 @@ use nom::{
 /// assert_eq!(dur.as_millis(), 300_000);
 /// assert_eq!(dur.to_string(), "5m");
 /// ```
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+///
+/// Durations can be added, subtracted and compared. Only the total is
+/// stored, so the result always prints in canonical form:
+///
+/// ```rust
+/// use rusty_promql_parser::lexer::duration::Duration;
+///
+/// let dur: Duration = "90m".parse().unwrap();
+/// assert_eq!(dur.to_string(), "1h30m");
+/// assert_eq!((dur - Duration::from_secs(5400)).to_string(), "0s");
+/// assert!(dur > Duration::from_secs(3600));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Duration {
     /// Duration in milliseconds (can be negative for negative offsets)
     pub milliseconds: i64,
@@ -89,71 +113,174 @@ impl Duration {
     pub const fn as_secs(&self) -> i64 {
         self.milliseconds / 1000
     }
-}
 
-impl std::fmt::Display for Duration {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut ms = self.milliseconds;
-        if ms == 0 {
-            return write!(f, "0s");
-        }
-
-        let mut result = String::new();
+    /// Check if the duration is negative.
+    ///
+    /// Negative offsets such as `offset -5m` look forward in time.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::lexer::duration::signed_duration;
+    ///
+    /// let (_, dur) = signed_duration("-5m").unwrap();
+    /// assert!(dur.is_negative());
+    /// assert_eq!(dur.to_string(), "-5m");
+    /// ```
+    pub const fn is_negative(&self) -> bool {
+        self.milliseconds < 0
+    }
 
-        // Handle negative durations
-        if ms < 0 {
-            result.push('-');
-            ms = -ms;
+    /// Multiply by `n`, or `None` on overflow.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::lexer::duration::Duration;
+    ///
+    /// assert_eq!(Duration::from_secs(30).checked_mul(4), Some(Duration::from_secs(120)));
+    /// assert_eq!(Duration::from_millis(i64::MAX).checked_mul(2), None);
+    /// ```
+    pub const fn checked_mul(self, n: i64) -> Option<Duration> {
+        match self.milliseconds.checked_mul(n) {
+            Some(ms) => Some(Duration::from_millis(ms)),
+            None => None,
         }
+    }
 
-        // Handle years
-        let years = ms / 31_536_000_000;
-        if years > 0 {
-            result.push_str(&format!("{}y", years));
-            ms %= 31_536_000_000;
+    /// Add `other`, or `None` on overflow.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::lexer::duration::Duration;
+    ///
+    /// let hour = Duration::from_secs(3600);
+    /// assert_eq!(hour.checked_add(Duration::from_secs(-60)), Some(Duration::from_secs(3540)));
+    /// assert_eq!(Duration::from_millis(i64::MAX).checked_add(hour), None);
+    /// ```
+    pub const fn checked_add(self, other: Duration) -> Option<Duration> {
+        match self.milliseconds.checked_add(other.milliseconds) {
+            Some(ms) => Some(Duration::from_millis(ms)),
+            None => None,
         }
+    }
 
-        // Handle weeks
-        let weeks = ms / 604_800_000;
-        if weeks > 0 {
-            result.push_str(&format!("{}w", weeks));
-            ms %= 604_800_000;
+    /// Subtract `other`, or `None` on overflow.
+    ///
+    /// The result may be negative, like the duration of `offset -5m`.
+    pub const fn checked_sub(self, other: Duration) -> Option<Duration> {
+        match self.milliseconds.checked_sub(other.milliseconds) {
+            Some(ms) => Some(Duration::from_millis(ms)),
+            None => None,
         }
+    }
 
-        // Handle days
-        let days = ms / 86_400_000;
-        if days > 0 {
-            result.push_str(&format!("{}d", days));
-            ms %= 86_400_000;
+    /// Divide by `n`, rounding toward zero to whole milliseconds, or `None`
+    /// if `n` is zero or the result overflows.
+    ///
+    /// Useful to derive a subquery step from its range, e.g. 60 points:
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::lexer::duration::Duration;
+    ///
+    /// let range = Duration::from_secs(3600);
+    /// assert_eq!(range.checked_div(60), Some(Duration::from_secs(60)));
+    /// assert_eq!(range.checked_div(0), None);
+    /// ```
+    pub const fn checked_div(self, n: i64) -> Option<Duration> {
+        match self.milliseconds.checked_div(n) {
+            Some(ms) => Some(Duration::from_millis(ms)),
+            None => None,
         }
+    }
+}
 
-        // Handle hours
-        let hours = ms / 3_600_000;
-        if hours > 0 {
-            result.push_str(&format!("{}h", hours));
-            ms %= 3_600_000;
-        }
+impl Add for Duration {
+    type Output = Duration;
 
-        // Handle minutes
-        let minutes = ms / 60_000;
-        if minutes > 0 {
-            result.push_str(&format!("{}m", minutes));
-            ms %= 60_000;
-        }
+    /// # Panics
+    ///
+    /// Panics on overflow, see [`Duration::checked_add`].
+    fn add(self, other: Duration) -> Duration {
+        self.checked_add(other)
+            .expect("overflow when adding durations")
+    }
+}
+
+impl Sub for Duration {
+    type Output = Duration;
+
+    /// # Panics
+    ///
+    /// Panics on overflow, see [`Duration::checked_sub`].
+    fn sub(self, other: Duration) -> Duration {
+        self.checked_sub(other)
+            .expect("overflow when subtracting durations")
+    }
+}
 
-        // Handle seconds
-        let seconds = ms / 1000;
-        if seconds > 0 {
-            result.push_str(&format!("{}s", seconds));
-            ms %= 1000;
+impl AddAssign for Duration {
+    fn add_assign(&mut self, other: Duration) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Duration {
+    fn sub_assign(&mut self, other: Duration) {
+        *self = *self - other;
+    }
+}
+
+impl Neg for Duration {
+    type Output = Duration;
+
+    /// Flip the sign, turning `offset 5m` into `offset -5m`.
+    ///
+    /// # Panics
+    ///
+    /// Panics for the most negative duration, whose negation overflows.
+    fn neg(self) -> Duration {
+        Duration::from_millis(
+            self.milliseconds
+                .checked_neg()
+                .expect("overflow when negating duration"),
+        )
+    }
+}
+
+/// Writes the duration in canonical form: each non-zero unit once, largest
+/// first, so `90m` prints as `1h30m`.
+impl core::fmt::Display for Duration {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if self.milliseconds == 0 {
+            return write!(f, "0s");
         }
 
-        // Handle milliseconds
-        if ms > 0 {
-            result.push_str(&format!("{}ms", ms));
+        // Handle negative durations
+        if self.is_negative() {
+            write!(f, "-")?;
         }
 
-        write!(f, "{}", result)
+        // Write each non-zero component, largest unit first, directly to
+        // the formatter
+        let mut ms = self.milliseconds.unsigned_abs();
+        for (unit, unit_ms) in [
+            ("y", 31_536_000_000),
+            ("w", 604_800_000),
+            ("d", 86_400_000),
+            ("h", 3_600_000),
+            ("m", 60_000),
+            ("s", 1000),
+            ("ms", 1),
+        ] {
+            let count = ms / unit_ms;
+            if count > 0 {
+                write!(f, "{}{}", count, unit)?;
+                ms %= unit_ms;
+            }
+        }
+        Ok(())
     }
 }
 
@@ -203,11 +330,67 @@ pub fn duration(input: &str) -> IResult<&str, Duration> {
     map_res(many1(duration_component), compute_duration_millis).parse(input)
 }
 
+/// Parse a duration that must be greater than zero, as required for
+/// ranges and subquery steps.
+///
+/// A zero duration like `0s` fails without backtracking, with error kind
+/// [`SyntaxErrorKind::ZeroDuration`] at the start of the duration.
+///
+/// # Example
+///
+/// ```rust
+/// use rusty_promql_parser::lexer::duration::positive_duration;
+///
+/// assert!(positive_duration("5m").is_ok());
+/// assert!(matches!(positive_duration("0m0s"), Err(nom::Err::Failure(_))));
+/// ```
+pub fn positive_duration(input: &str) -> IResult<&str, Duration> {
+    let (rest, dur) = duration(input)?;
+    if dur.as_millis() == 0 {
+        return Err(nom::Err::Failure(SyntaxError::new(
+            input,
+            SyntaxErrorKind::ZeroDuration,
+        )));
+    }
+    Ok((rest, dur))
+}
+
 /// Parse a single duration component: <number><unit>
 fn duration_component(input: &str) -> IResult<&str, (i64, DurationUnit)> {
+    reject_float(input)?;
     pair(map_res(digit1, |s: &str| s.parse::<i64>()), duration_unit).parse(input)
 }
 
+/// Fail without backtracking on a number with a fraction or exponent, such
+/// as `1.5m` or `1e3s`, which are not durations.
+///
+/// Without this, `digit1` would take the integer part and the error would
+/// point at the `.` or `e` that follows. The error kind is
+/// [`SyntaxErrorKind::FloatDuration`].
+fn reject_float(input: &str) -> IResult<&str, ()> {
+    let rest = input.trim_start_matches(|c: char| c.is_ascii_digit());
+    if rest.len() == input.len() {
+        return Ok((input, ()));
+    }
+    let mut chars = rest.chars();
+    let is_float = match chars.next() {
+        Some('.') => chars.next().is_some_and(|c| c.is_ascii_digit()),
+        Some('e' | 'E') => match chars.next() {
+            Some('+' | '-') => chars.next().is_some_and(|c| c.is_ascii_digit()),
+            c => c.is_some_and(|c| c.is_ascii_digit()),
+        },
+        _ => false,
+    };
+    if is_float {
+        Err(nom::Err::Failure(SyntaxError::new(
+            input,
+            SyntaxErrorKind::FloatDuration,
+        )))
+    } else {
+        Ok((input, ()))
+    }
+}
+
 /// Parse a duration unit
 fn duration_unit(input: &str) -> IResult<&str, DurationUnit> {
     alt((
@@ -239,6 +422,56 @@ pub fn signed_duration(input: &str) -> IResult<&str, Duration> {
     .parse(input)
 }
 
+/// Parse a standalone duration string such as `5m` or `1h30m`.
+///
+/// Unlike [`duration`], the whole input must be consumed. A leading `-`
+/// is accepted, so any [`Duration`] round-trips through its `Display`
+/// output.
+///
+/// # Example
+///
+/// ```rust
+/// use rusty_promql_parser::lexer::duration::parse_duration;
+///
+/// assert_eq!(parse_duration("1h30m").unwrap().as_secs(), 5400);
+/// assert!(parse_duration("5min").is_err());
+/// ```
+pub fn parse_duration(s: &str) -> Result<Duration, ParseError> {
+    match signed_duration(s) {
+        Ok(("", dur)) => Ok(dur),
+        Ok((rest, _)) if rest.trim_start().is_empty() => Err(ParseError::new(
+            s.len() - rest.len(),
+            "unexpected trailing input",
+        )),
+        Ok((rest, _)) => Err(ParseError::at(s, rest)),
+        Err(nom::Err::Error(e) | nom::Err::Failure(e))
+            if e.kind == SyntaxErrorKind::Nom(ErrorKind::MapRes) =>
+        {
+            Err(ParseError::new(
+                s.len() - e.input.len(),
+                "duration out of range",
+            ))
+        }
+        Err(nom::Err::Failure(SyntaxError {
+            input,
+            kind: SyntaxErrorKind::FloatDuration,
+        })) => {
+            Err(ParseError::new(s.len() - input.len(), "expected duration")
+                .with_expected(["duration"]))
+        }
+        Err(nom::Err::Error(e) | nom::Err::Failure(e)) => Err(ParseError::at(s, e.input)),
+        Err(nom::Err::Incomplete(_)) => Err(ParseError::at(s, "")),
+    }
+}
+
+impl FromStr for Duration {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_duration(s)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -379,7 +612,92 @@ mod tests {
         assert_signed_duration("5m", 300_000);
     }
 
+    #[test]
+    fn test_is_negative() {
+        assert!(Duration::from_millis(-1).is_negative());
+        assert!(!Duration::from_millis(0).is_negative());
+        assert!(!Duration::from_secs(300).is_negative());
+    }
+
     // Display formatting
+    #[test]
+    fn test_checked_arithmetic() {
+        let five_minutes = Duration::from_secs(300);
+        assert_eq!(five_minutes.checked_div(5), Some(Duration::from_secs(60)));
+        assert_eq!(
+            five_minutes.checked_div(7),
+            Some(Duration::from_millis(42_857))
+        );
+        assert_eq!(five_minutes.checked_div(-5), Some(Duration::from_secs(-60)));
+        assert_eq!(five_minutes.checked_div(0), None);
+        assert_eq!(Duration::from_millis(i64::MIN).checked_div(-1), None);
+
+        assert_eq!(
+            five_minutes.checked_mul(12),
+            Some(Duration::from_secs(3600))
+        );
+        assert_eq!(five_minutes.checked_mul(0), Some(Duration::from_millis(0)));
+        assert_eq!(
+            five_minutes.checked_mul(-1),
+            Some(Duration::from_secs(-300))
+        );
+        assert_eq!(Duration::from_millis(i64::MAX / 2 + 1).checked_mul(2), None);
+    }
+
+    #[test]
+    fn test_add_sub_neg() {
+        let hour = Duration::from_secs(3600);
+        let half_hour = Duration::from_secs(1800);
+        assert_eq!((hour + half_hour).to_string(), "1h30m");
+        assert_eq!((half_hour - hour).to_string(), "-30m");
+        assert_eq!((-hour).to_string(), "-1h");
+        assert_eq!(-(-hour), hour);
+
+        let mut dur = Duration::from_millis(0);
+        dur += hour;
+        dur -= Duration::from_millis(1);
+        assert_eq!(dur.to_string(), "59m59s999ms");
+
+        assert_eq!(Duration::from_millis(i64::MIN).checked_sub(hour), None);
+        assert_eq!(Duration::from_millis(i64::MAX).checked_add(hour), None);
+        assert_eq!(
+            Duration::from_millis(i64::MIN).checked_add(hour),
+            Some(Duration::from_millis(i64::MIN + 3_600_000))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "overflow when adding durations")]
+    fn test_add_overflow_panics() {
+        let _ = Duration::from_millis(i64::MAX) + Duration::from_millis(1);
+    }
+
+    #[test]
+    fn test_ordering() {
+        let mut durations: Vec<Duration> = ["1h", "-5m", "90m", "0s", "59m60s"]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+        durations.sort();
+        let sorted: Vec<_> = durations.iter().map(ToString::to_string).collect();
+        assert_eq!(sorted, ["-5m", "0s", "1h", "1h", "1h30m"]);
+        assert!(Duration::from_secs(-60) < Duration::from_millis(0));
+    }
+
+    #[test]
+    fn test_display_normalizes() {
+        for (input, expected) in [
+            ("90m", "1h30m"),
+            ("3600s", "1h"),
+            ("1500ms", "1s500ms"),
+            ("7d", "1w"),
+            ("-90s", "-1m30s"),
+        ] {
+            let dur: Duration = input.parse().unwrap();
+            assert_eq!(dur.to_string(), expected, "{}", input);
+        }
+    }
+
     #[test]
     fn test_duration_display() {
         assert_eq!(Duration::from_millis(0).to_string(), "0s");
@@ -394,6 +712,26 @@ mod tests {
     }
 
     // Edge cases
+    #[test]
+    fn test_positive_duration() {
+        assert_eq!(
+            positive_duration("1ms]"),
+            Ok(("]", Duration::from_millis(1)))
+        );
+        for input in ["0s", "0ms", "0h0m", "00m"] {
+            assert_eq!(
+                positive_duration(input),
+                Err(nom::Err::Failure(SyntaxError::new(
+                    input,
+                    SyntaxErrorKind::ZeroDuration
+                ))),
+                "{}",
+                input
+            );
+        }
+        assert!(matches!(positive_duration("m"), Err(nom::Err::Error(_))));
+    }
+
     #[test]
     fn test_partial_parse() {
         // Duration followed by other content
@@ -413,4 +751,81 @@ mod tests {
     fn test_fail_found_with_fuzzing() {
         assert!(duration("5555555555555555555m").is_err());
     }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(5400)));
+        assert_eq!(
+            parse_duration("1d2h3m4s5ms").unwrap().as_millis(),
+            93_784_005
+        );
+        assert_eq!(parse_duration("-5m"), Ok(Duration::from_secs(-300)));
+        assert_eq!("2w".parse::<Duration>(), Ok(Duration::from_secs(1_209_600)));
+    }
+
+    #[test]
+    fn test_parse_duration_display_round_trip() {
+        for ms in [1, 1_500, 5_400_000, -300_000, 31_536_000_001] {
+            let dur = Duration::from_millis(ms);
+            assert_eq!(dur.to_string().parse::<Duration>(), Ok(dur));
+        }
+    }
+
+    #[test]
+    fn test_parse_duration_errors() {
+        assert_eq!(
+            parse_duration("5min"),
+            Err(ParseError::new(2, "unexpected character 'i'"))
+        );
+        assert_eq!(
+            parse_duration(""),
+            Err(ParseError::new(0, "unexpected end of input"))
+        );
+        assert_eq!(
+            parse_duration("5m "),
+            Err(ParseError::new(2, "unexpected trailing input"))
+        );
+        assert!(parse_duration("m").is_err());
+        assert_eq!(
+            parse_duration("9999999999999999y"),
+            Err(ParseError::new(0, "duration out of range"))
+        );
+        assert_eq!(
+            parse_duration("1h1.5m"),
+            Err(ParseError::new(2, "expected duration").with_expected(["duration"]))
+        );
+    }
+
+    #[test]
+    fn test_duration_rejects_float() {
+        for input in ["1e3s", "1.5e2m", "1.5m", "1E3s", "1e-3s", "2e+1h"] {
+            assert_eq!(
+                duration(input),
+                Err(nom::Err::Failure(SyntaxError::new(
+                    input,
+                    SyntaxErrorKind::FloatDuration
+                ))),
+                "{}",
+                input
+            );
+        }
+        // Only digits after the `.` or `e` make a float
+        assert!(matches!(duration("5e"), Err(nom::Err::Error(_))));
+        assert!(matches!(duration("5."), Err(nom::Err::Error(_))));
+        assert_eq!(duration("5m.5"), Ok((".5", Duration::from_secs(300))));
+    }
+
+    #[test]
+    fn test_duration_display_extremes() {
+        assert_eq!(
+            Duration::from_millis(i64::MAX).to_string(),
+            "292471208y35w2d7h12m55s807ms"
+        );
+        assert!(
+            Duration::from_millis(i64::MIN)
+                .to_string()
+                .starts_with("-292471208y")
+        );
+    }
 }
@@ -7,13 +7,17 @@
 //! Keywords in PromQL are context-sensitive - they can be used as metric names
 //! or label names when not in a keyword position.
 
+use alloc::string::{String, ToString};
+
 use nom::{
-    IResult, Parser,
+    Parser,
     bytes::complete::{take_while, take_while1},
     combinator::{recognize, verify},
     sequence::pair,
 };
 
+use crate::error::IResult;
+
 /// Result of parsing an identifier - distinguishes between regular identifiers
 /// and metric identifiers (which contain colons)
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -47,8 +51,8 @@ impl Identifier {
     }
 }
 
-impl std::fmt::Display for Identifier {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Display for Identifier {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "{}", self.as_str())
     }
 }
@@ -108,9 +112,16 @@ pub fn label_name(input: &str) -> IResult<&str, &str> {
 ///
 /// Grouping clauses and vector matching label lists reserve PromQL keywords,
 /// so names like `on` or `group_left` must be rejected there even though they
-/// remain valid label names in selectors.
+/// remain valid label names in selectors. The `@` preprocessors `start`,
+/// `end` and `step` are only keywords after `@`, so they are allowed.
 pub(crate) fn clause_label_name(input: &str) -> IResult<&str, &str> {
-    verify(label_name, |name: &&str| lookup_keyword(name).is_none()).parse(input)
+    verify(label_name, |name: &&str| {
+        matches!(
+            lookup_keyword(name),
+            None | Some(Keyword::Start | Keyword::End | Keyword::Step)
+        )
+    })
+    .parse(input)
 }
 
 /// Parse a metric name: `[a-zA-Z_:][a-zA-Z0-9_:]*`
@@ -142,6 +153,49 @@ pub fn metric_name(input: &str) -> IResult<&str, &str> {
     .parse(input)
 }
 
+/// Check if a character can continue a bare metric name when UTF-8 metric
+/// names are enabled: anything but whitespace and ASCII punctuation, except
+/// `_`, `:` and `.`
+#[inline]
+pub(crate) fn is_utf8_metric_char(c: char) -> bool {
+    matches!(c, '_' | ':' | '.') || !(c.is_whitespace() || c.is_ascii_punctuation())
+}
+
+/// Parse a bare metric name with an extended character set.
+///
+/// Accepts everything [`metric_name`] does, plus dots and non-ASCII
+/// characters, as some setups allow in bare metric names. Whitespace and
+/// ASCII punctuation such as `{`, `[` or operators still end the name. The
+/// name cannot start with a digit or a dot, so numbers stay unambiguous.
+///
+/// Standard PromQL requires such names to be quoted; the expression parser
+/// only uses this when
+/// [`ParseOptions::allow_utf8_metric_names`](crate::ParseOptions::allow_utf8_metric_names)
+/// is set.
+///
+/// # Examples
+///
+/// ```
+/// use rusty_promql_parser::lexer::identifier::utf8_metric_name;
+///
+/// let (rest, name) = utf8_metric_name("app.http.requests{job=\"a\"}").unwrap();
+/// assert_eq!(name, "app.http.requests");
+/// assert_eq!(rest, "{job=\"a\"}");
+///
+/// assert!(utf8_metric_name(".5").is_err());
+/// ```
+pub fn utf8_metric_name(input: &str) -> IResult<&str, &str> {
+    recognize(pair(
+        verify(take_while1(is_utf8_metric_char), |s: &str| {
+            s.chars()
+                .next()
+                .is_some_and(|c| c != '.' && !c.is_ascii_digit())
+        }),
+        take_while(is_utf8_metric_char),
+    ))
+    .parse(input)
+}
+
 /// Parse an identifier (either label name or metric identifier)
 ///
 /// Returns `Identifier::Plain` for identifiers without colons,
@@ -292,14 +346,14 @@ impl Keyword {
     }
 }
 
-impl std::fmt::Display for Keyword {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Display for Keyword {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "{}", self.as_str())
     }
 }
 
 /// Try to look up a keyword from a string (case-insensitive)
-fn lookup_keyword(s: &str) -> Option<Keyword> {
+pub(crate) fn lookup_keyword(s: &str) -> Option<Keyword> {
     match s.to_ascii_lowercase().as_str() {
         // Aggregation operators
         "sum" => Some(Keyword::Sum),
@@ -369,7 +423,7 @@ pub fn keyword(input: &str) -> IResult<&str, Keyword> {
     if let Some(kw) = lookup_keyword(word) {
         Ok((rest, kw))
     } else {
-        Err(nom::Err::Error(nom::error::Error::new(
+        Err(nom::Err::Error(nom::error::make_error(
             input,
             nom::error::ErrorKind::Tag,
         )))
@@ -420,7 +474,7 @@ pub fn aggregation_op(input: &str) -> IResult<&str, Keyword> {
     if kw.is_aggregation() {
         Ok((rest, kw))
     } else {
-        Err(nom::Err::Error(nom::error::Error::new(
+        Err(nom::Err::Error(nom::error::make_error(
             input,
             nom::error::ErrorKind::Tag,
         )))
@@ -433,7 +487,7 @@ pub fn set_operator(input: &str) -> IResult<&str, Keyword> {
     if kw.is_set_operator() {
         Ok((rest, kw))
     } else {
-        Err(nom::Err::Error(nom::error::Error::new(
+        Err(nom::Err::Error(nom::error::make_error(
             input,
             nom::error::ErrorKind::Tag,
         )))
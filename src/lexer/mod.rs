@@ -10,7 +10,8 @@
 //! - [`identifier`] - Metric names, label names, and keywords
 //! - [`mod@number`] - Numeric literals (integers, floats, hex, scientific notation)
 //! - [`string`] - String literals (double-quoted, single-quoted, backtick)
-//! - [`whitespace`] - Whitespace and comment handling
+//! - [`whitespace`] - Whitespace and comment handling, reusable when
+//!   embedding PromQL in a larger nom grammar
 //!
 //! # Example
 //!
@@ -36,8 +36,10 @@
 //! assert!(n.is_infinite());
 //! ```
 
+use alloc::format;
+
 use nom::{
-    IResult, Parser,
+    Parser,
     branch::alt,
     bytes::complete::{tag, tag_no_case, take_while1},
     character::complete::{char, one_of},
@@ -45,6 +47,8 @@ use nom::{
     sequence::{pair, preceded},
 };
 
+use crate::error::IResult;
+
 /// Parse a PromQL number literal and return its f64 value.
 ///
 /// This parser handles all PromQL number formats:
@@ -74,7 +78,7 @@ fn special_float(input: &str) -> IResult<&str, f64> {
 
     // Ensure not followed by alphanumeric/underscore (would make it an identifier like "info")
     if rest.chars().next().is_some_and(is_ident_char) {
-        return Err(nom::Err::Error(nom::error::Error::new(
+        return Err(nom::Err::Error(nom::error::make_error(
             input,
             nom::error::ErrorKind::Tag,
         )));
@@ -144,7 +148,7 @@ fn octal_legacy(input: &str) -> IResult<&str, f64> {
     // Check if there's at least one more octal digit
     if remaining.is_empty() {
         // Just "0" - let decimal handle it
-        return Err(nom::Err::Error(nom::error::Error::new(
+        return Err(nom::Err::Error(nom::error::make_error(
             input,
             nom::error::ErrorKind::Tag,
         )));
@@ -154,7 +158,7 @@ fn octal_legacy(input: &str) -> IResult<&str, f64> {
 
     // If next char is not an octal digit, let decimal handle it
     if !matches!(next_char, '0'..='7') {
-        return Err(nom::Err::Error(nom::error::Error::new(
+        return Err(nom::Err::Error(nom::error::make_error(
             input,
             nom::error::ErrorKind::Tag,
         )));
@@ -168,7 +172,7 @@ fn octal_legacy(input: &str) -> IResult<&str, f64> {
         && (c == '.' || c == 'e' || c == 'E')
     {
         // This is a decimal number, not octal
-        return Err(nom::Err::Error(nom::error::Error::new(
+        return Err(nom::Err::Error(nom::error::make_error(
             input,
             nom::error::ErrorKind::Tag,
         )));
@@ -178,7 +182,7 @@ fn octal_legacy(input: &str) -> IResult<&str, f64> {
     let full_octal = format!("0{}", octal_digits);
     match i64::from_str_radix(&full_octal, 8) {
         Ok(v) => Ok((remaining, v as f64)),
-        Err(_) => Err(nom::Err::Error(nom::error::Error::new(
+        Err(_) => Err(nom::Err::Error(nom::error::make_error(
             input,
             nom::error::ErrorKind::MapRes,
         ))),
@@ -29,6 +29,20 @@
 //!
 //! Raw strings (backtick) have no escape processing.
 //!
+//! # Printing
+//!
+//! The AST keeps only the string value, not the quote style it was written
+//! in. When printed, every string is normalized to a double-quoted literal
+//! with the same value, escaping backslashes, double quotes and control
+//! characters:
+//!
+//! ```rust
+//! use rusty_promql_parser::expr;
+//!
+//! let (_, ast) = expr(r"`a\nb`").unwrap();
+//! assert_eq!(ast.to_string(), r#""a\\nb""#);
+//! ```
+//!
 //! # Examples
 //!
 //! ```rust
@@ -47,8 +61,11 @@
 //! assert_eq!(s, r"\n is literal");
 //! ```
 
+use alloc::string::String;
+use core::fmt;
+
 use nom::{
-    IResult, Parser,
+    Parser,
     branch::alt,
     bytes::complete::take_while_m_n,
     character::complete::{anychar, char, none_of},
@@ -57,6 +74,8 @@ use nom::{
     sequence::{delimited, preceded},
 };
 
+use crate::error::IResult;
+
 /// Parse a PromQL string literal and return the unescaped string value.
 ///
 /// Accepts double-quoted, single-quoted, or backtick-quoted strings.
@@ -64,6 +83,30 @@ pub fn string_literal(input: &str) -> IResult<&str, String> {
     alt((double_quoted_string, single_quoted_string, raw_string)).parse(input)
 }
 
+/// Displays a string value as a double-quoted PromQL literal
+pub(crate) struct DoubleQuoted<'a>(pub &'a str);
+
+impl fmt::Display for DoubleQuoted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use fmt::Write;
+
+        f.write_char('"')?;
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                '\r' => f.write_str("\\r")?,
+                '\t' => f.write_str("\\t")?,
+                c if c.is_ascii_control() => write!(f, "\\x{:02x}", c as u32)?,
+                c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+                c => f.write_char(c)?,
+            }
+        }
+        f.write_char('"')
+    }
+}
+
 /// Parse a double-quoted string: "hello \"world\""
 pub fn double_quoted_string(input: &str) -> IResult<&str, String> {
     delimited(
@@ -17,9 +17,34 @@
 //! let (rest, _) = ws_opt("# comment\nfoo").unwrap();
 //! assert_eq!(rest, "foo");
 //! ```
+//!
+//! # Embedding in a custom grammar
+//!
+//! These parsers are part of the stable API, so tools that extend PromQL
+//! can skip whitespace and comments exactly like the PromQL parser does.
+//! Like all nom parsers of this crate, they fail with [`SyntaxError`]
+//! rather than [`nom::error::Error`] since 0.3.
+//!
+//! For example, a template language that puts a query between `{{` and
+//! `}}`:
+//!
+//! ```rust
+//! use nom::{Parser, bytes::complete::tag, sequence::delimited};
+//! use rusty_promql_parser::error::IResult;
+//! use rusty_promql_parser::{Expr, expr};
+//! use rusty_promql_parser::lexer::whitespace::ws_opt;
+//!
+//! fn template(input: &str) -> IResult<&str, Expr> {
+//!     delimited((tag("{{"), ws_opt), expr, (ws_opt, tag("}}"))).parse(input)
+//! }
+//!
+//! let (rest, query) = template("{{ rate(x[5m]) # per second\n }} rest").unwrap();
+//! assert_eq!(query.to_string(), "rate(x[5m])");
+//! assert_eq!(rest, " rest");
+//! ```
 
 use nom::{
-    IResult, Parser,
+    Parser,
     branch::alt,
     bytes::complete::{take_while, take_while1},
     character::complete::{char, not_line_ending},
@@ -28,6 +53,8 @@ use nom::{
     sequence::preceded,
 };
 
+use crate::error::{IResult, SyntaxError};
+
 /// Check if a character is whitespace (space, tab, newline, or carriage return).
 #[inline]
 pub fn is_whitespace(c: char) -> bool {
@@ -49,6 +76,18 @@ pub fn whitespace1(input: &str) -> IResult<&str, &str> {
 /// Parse a line comment starting with '#'.
 /// Consumes the '#' and all characters until (but not including) the end of line.
 /// Returns the comment content (without the '#' prefix).
+///
+/// The line ends at `\n` or `\r\n`, or at the end of the input. A lone
+/// `\r` does not end the line, so the comment fails to parse.
+///
+/// # Example
+///
+/// ```rust
+/// use rusty_promql_parser::lexer::whitespace::line_comment;
+///
+/// assert_eq!(line_comment("# note\nup"), Ok(("\nup", " note")));
+/// assert!(line_comment("up # note").is_err());
+/// ```
 pub fn line_comment(input: &str) -> IResult<&str, &str> {
     preceded(char('#'), not_line_ending).parse(input)
 }
@@ -62,7 +101,28 @@ fn ws_element(input: &str) -> IResult<&str, ()> {
 /// This is the most commonly used whitespace parser - it consumes any combination
 /// of whitespace and comments, returning the empty unit.
 ///
-/// Use this between tokens where whitespace is optional.
+/// Use this between tokens where whitespace is optional. It never fails:
+/// on input that starts with neither whitespace nor `#`, it consumes
+/// nothing. Only the characters accepted by [`is_whitespace`] count as
+/// whitespace; other Unicode spaces do not.
+///
+/// # Example
+///
+/// ```rust
+/// use nom::{Parser, character::complete::char, sequence::delimited};
+/// use rusty_promql_parser::error::IResult;
+/// use rusty_promql_parser::lexer::whitespace::ws_opt;
+/// use rusty_promql_parser::lexer::number::number;
+///
+/// // A number between angle brackets, with optional spacing: `< 42 >`
+/// fn bracketed(input: &str) -> IResult<&str, f64> {
+///     delimited((char('<'), ws_opt), number, (ws_opt, char('>'))).parse(input)
+/// }
+///
+/// assert_eq!(bracketed("<42>"), Ok(("", 42.0)));
+/// assert_eq!(bracketed("< 42 # answer\n>"), Ok(("", 42.0)));
+/// assert_eq!(ws_opt("up"), Ok(("up", ())));
+/// ```
 pub fn ws_opt(input: &str) -> IResult<&str, ()> {
     value((), many0(ws_element)).parse(input)
 }
@@ -71,6 +131,15 @@ pub fn ws_opt(input: &str) -> IResult<&str, ()> {
 /// At least one whitespace character or comment must be present.
 ///
 /// Use this where whitespace is required (e.g., between `offset` and the duration).
+///
+/// # Example
+///
+/// ```rust
+/// use rusty_promql_parser::lexer::whitespace::ws_req;
+///
+/// assert_eq!(ws_req(" #c\n 5m"), Ok(("5m", ())));
+/// assert!(ws_req("5m").is_err());
+/// ```
 pub fn ws_req(input: &str) -> IResult<&str, ()> {
     let (input, _) = ws_element(input)?;
     ws_opt(input)
@@ -88,7 +157,7 @@ pub fn ws_req(input: &str) -> IResult<&str, ()> {
 /// ```
 pub fn ws<'a, O, F>(mut parser: F) -> impl FnMut(&'a str) -> IResult<&'a str, O>
 where
-    F: Parser<&'a str, Output = O, Error = nom::error::Error<&'a str>>,
+    F: Parser<&'a str, Output = O, Error = SyntaxError<&'a str>>,
 {
     move |input: &'a str| {
         let (input, _) = ws_opt(input)?;
@@ -139,6 +208,8 @@ mod tests {
         assert_eq!(line_comment("# comment\nfoo"), Ok(("\nfoo", " comment")));
         assert_eq!(line_comment("#"), Ok(("", "")));
         assert!(line_comment("not a comment").is_err());
+        assert_eq!(line_comment("# crlf\r\nfoo"), Ok(("\r\nfoo", " crlf")));
+        assert!(line_comment("# lone\rfoo").is_err());
     }
 
     #[test]
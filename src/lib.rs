@@ -21,6 +21,20 @@
 //! println!("{:#?}", ast);
 //! ```
 //!
+//! To parse a complete query, use [`parse()`] or [`str::parse`] instead.
+//! They reject trailing input and report failures as a [`ParseError`] with
+//! the byte offset of the problem:
+//!
+//! ```rust
+//! use rusty_promql_parser::{Expr, parse};
+//!
+//! assert!(parse(r#"http_requests_total{job="api"}"#).is_ok());
+//! assert!(r#"http_requests_total{job="api"}"#.parse::<Expr>().is_ok());
+//!
+//! let err = parse("sum(up))").unwrap_err();
+//! assert_eq!(err.offset, 7);
+//! ```
+//!
 //! ## Examples
 //!
 //! ### Parsing a metric with label filtering
@@ -73,8 +87,37 @@
 //! ## Modules
 //!
 //! - [`ast`] - Abstract Syntax Tree type definitions
+//! - [`build`] - Programmatic construction of expressions
+//! - [`error`] - Error types for the high-level parsing API
+//! - `explain` - Plain-English summaries of queries (requires the `explain`
+//!   feature)
+//! - `json` - Versioned JSON representation of the AST, and the Prometheus
+//!   JSON AST (requires the `json` feature)
 //! - [`lexer`] - Low-level token parsers (numbers, strings, durations, identifiers)
+//! - [`mod@lint`] - Best-practice checks for parsed expressions
 //! - [`parser`] - Expression and statement parsers
+//! - `rules` - Prometheus rule file parsing (requires the `rules` feature)
+//! - [`mod@validate`] - Semantic validation of parsed expressions
+//! - [`visit`] - Traversal and in-place rewriting of the AST with visitors
+//!
+//! ## `no_std` support
+//!
+//! The crate is `no_std` compatible and only needs an allocator. Disable the
+//! default `std` feature to build it without the standard library:
+//!
+//! ```toml
+//! rusty-promql-parser = { version = "0.3", default-features = false }
+//! ```
+//!
+//! Without `std`, constant folding (used by [`Expr::is_trivially_true`]) does
+//! not evaluate `^` and `atan2`, and the `rules` feature is unavailable.
+//!
+//! ## Serialization
+//!
+//! The `serde` feature implements `Serialize` and `Deserialize` for [`Expr`]
+//! and the types it is built from, to store parsed queries or send them
+//! between services. For a documented, versioned JSON format, use the `json`
+//! feature instead.
 //!
 //! ## Display
 //!
@@ -87,17 +130,45 @@
 //! let (_, ast) = expr("1 + 2 * 3").unwrap();
 //! assert_eq!(ast.to_string(), "1 + 2 * 3");
 //! ```
+//!
+//! For long queries, [`Expr::pretty`] spreads the output over indented lines.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
 
 pub mod ast;
+pub mod build;
+pub mod error;
+#[cfg(feature = "explain")]
+pub mod explain;
+#[cfg(feature = "json")]
+pub mod json;
 pub mod lexer;
+pub mod lint;
 pub mod parser;
+mod pretty;
+#[cfg(feature = "rules")]
+pub mod rules;
+pub mod validate;
+pub mod visit;
 
 // Re-export commonly used types and parsers
 pub use ast::{
-    Aggregation, BinaryExpr, BinaryModifier, BinaryOp, Call, Expr, GroupModifier, GroupSide,
-    SubqueryExpr, UnaryExpr, UnaryOp, VectorMatching, VectorMatchingOp,
+    Aggregation, BinaryExpr, BinaryModifier, BinaryOp, Call, Expr, ExprKind, GroupModifier,
+    GroupSide, ModifierError, NumberLiteral, PathSegment, SubqueryExpr, UnaryExpr, UnaryOp,
+    VectorMatching, VectorMatchingOp,
 };
+pub use error::ParseError;
+pub use lexer::duration::{Duration, parse_duration};
 pub use lexer::number;
+pub use lint::{Lint, LintOptions, Severity, lint, lint_with_options};
 pub use parser::aggregation::{Grouping, GroupingAction};
 pub use parser::expr;
-pub use parser::selector::{LabelMatchOp, LabelMatcher, MatrixSelector, VectorSelector};
+pub use parser::options::{ParseOptions, parse, parse_prefix, parse_with_options};
+pub use parser::partial::{PartialParse, parse_partial};
+pub use parser::selector::{
+    CardinalityClass, LabelMatchOp, LabelMatcher, MatrixSelector, VectorSelector,
+};
+pub use parser::version::PrometheusVersion;
+pub use validate::{ValidationError, type_of, validate};
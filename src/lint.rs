@@ -0,0 +1,609 @@
+//! Best-practice checks for PromQL queries.
+//!
+//! Unlike [`validate()`](crate::validate()), which reports queries that
+//! Prometheus would reject, the linter reports queries that are valid but
+//! probably not what the author meant, or needlessly expensive.
+//!
+//! Each [`Lint`] has a stable [`code`](Lint::code) that tools can use to
+//! filter or suppress specific checks:
+//!
+//! | Code | Severity | Description |
+//! |------|----------|-------------|
+//! | `missing-le-grouping` | Warning | `histogram_quantile` over an aggregation that drops the `le` label |
+//! | `broad-regex` | Warning | Regex matcher that matches every value, like `=~".*"`, or any non-empty value, like `=~".+"` |
+//! | `subquery-step-exceeds-range` | Warning | Subquery step larger than its range |
+//! | `scalar-comparison` | Error | Comparison between two scalars without `bool` |
+//! | `experimental-function` | Info | Function behind the `promql-experimental-functions` feature flag |
+//! | `keyword-metric-name` | Warning | Metric name that is also a PromQL keyword |
+//! | `duplicate-label` | Warning | Label listed twice in `by`, `without`, `on`, `ignoring` or `group_left`/`group_right` |
+//! | `histogram-quantile-non-bucket` | Info | `histogram_quantile` over metrics without a `_bucket` suffix, which only works for native histograms |
+//! | `counter-without-rate` | Warning | Counter such as `http_requests_total` used as a raw instant vector instead of through `rate()` |
+//! | `mixed-grouping` | Info | Aggregation with `by` directly over one with `without`, or the other way around |
+//!
+//! Some checks are heuristics that can be tuned with [`LintOptions`] and
+//! [`lint_with_options`].
+//!
+//! # Example
+//!
+//! ```rust
+//! use rusty_promql_parser::{expr, lint};
+//!
+//! let (_, ast) = expr(r#"up{job=~".*"}"#).unwrap();
+//! let lints = lint(&ast);
+//! assert_eq!(lints.len(), 1);
+//! assert_eq!(lints[0].code, "broad-regex");
+//! ```
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::Range;
+
+use crate::ast::{Aggregation, BinaryExpr, Call, Expr, SubqueryExpr};
+use crate::lexer::identifier::lookup_keyword;
+use crate::parser::aggregation::GroupingAction;
+use crate::parser::function::{ValueType, get_function};
+use crate::parser::selector::{LabelMatchOp, VectorSelector};
+use crate::validate::type_of;
+
+/// How serious a lint is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Worth knowing, but usually fine
+    Info,
+    /// Likely a mistake or a performance problem
+    Warning,
+    /// Prometheus will reject or misevaluate the query
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Info => write!(f, "info"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A single lint finding
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lint {
+    /// Stable identifier of the check, e.g. `broad-regex`
+    pub code: &'static str,
+    /// How serious the finding is
+    pub severity: Severity,
+    /// Human-readable description
+    pub message: String,
+    /// Byte range in the source query, when known.
+    ///
+    /// The AST does not record source positions, so this is currently
+    /// always `None`.
+    pub span: Option<Range<usize>>,
+}
+
+impl Lint {
+    fn new(code: &'static str, severity: Severity, message: String) -> Self {
+        Self {
+            code,
+            severity,
+            message,
+            span: None,
+        }
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} [{}]: {}", self.severity, self.code, self.message)
+    }
+}
+
+/// Settings for the heuristic lints
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintOptions {
+    /// Metric name suffixes that mark a counter for `counter-without-rate`.
+    ///
+    /// Defaults to `_total`, `_count` and `_sum`. An empty list disables
+    /// the check.
+    pub counter_suffixes: Vec<String>,
+}
+
+impl Default for LintOptions {
+    fn default() -> Self {
+        Self {
+            counter_suffixes: ["_total", "_count", "_sum"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        }
+    }
+}
+
+/// Run all lints on an expression with the default [`LintOptions`].
+///
+/// Findings are returned in the order the offending nodes appear in the
+/// query.
+pub fn lint(expr: &Expr) -> Vec<Lint> {
+    lint_with_options(expr, &LintOptions::default())
+}
+
+/// Run all lints on an expression.
+///
+/// # Example
+///
+/// ```rust
+/// use rusty_promql_parser::expr;
+/// use rusty_promql_parser::lint::{LintOptions, lint_with_options};
+///
+/// let (_, ast) = expr("jobs_processed").unwrap();
+/// let options = LintOptions {
+///     counter_suffixes: vec!["_processed".to_string()],
+/// };
+/// let lints = lint_with_options(&ast, &options);
+/// assert_eq!(lints[0].code, "counter-without-rate");
+/// ```
+pub fn lint_with_options(expr: &Expr, options: &LintOptions) -> Vec<Lint> {
+    let mut lints = Vec::new();
+    lint_expr(expr, options, false, &mut lints);
+    lints
+}
+
+/// `in_rate` is set below calls that turn counters into rates
+fn lint_expr(expr: &Expr, options: &LintOptions, in_rate: bool, lints: &mut Vec<Lint>) {
+    let mut in_rate = in_rate;
+    match expr {
+        Expr::VectorSelector(vs) => {
+            lint_selector(vs, lints);
+            if !in_rate {
+                lint_counter(vs, options, lints);
+            }
+        }
+        Expr::MatrixSelector(ms) => lint_selector(&ms.selector, lints),
+        Expr::Call(call) => {
+            lint_call(call, lints);
+            in_rate |= matches!(call.name.as_str(), "rate" | "irate" | "increase");
+        }
+        Expr::Aggregation(agg) => lint_aggregation(agg, lints),
+        Expr::Binary(bin) => lint_binary(bin, lints),
+        Expr::Subquery(sq) => lint_subquery(sq, lints),
+        _ => {}
+    }
+    for child in expr.children() {
+        lint_expr(child, options, in_rate, lints);
+    }
+}
+
+fn lint_counter(vs: &VectorSelector, options: &LintOptions, lints: &mut Vec<Lint>) {
+    if let Some(name) = &vs.name
+        && options
+            .counter_suffixes
+            .iter()
+            .any(|suffix| name.ends_with(suffix.as_str()))
+    {
+        lints.push(Lint::new(
+            "counter-without-rate",
+            Severity::Warning,
+            format!(
+                "counter \"{}\" is used without rate(); its raw value only ever grows, use rate({}[...]) or increase({}[...])",
+                name, name, name
+            ),
+        ));
+    }
+}
+
+fn lint_selector(vs: &VectorSelector, lints: &mut Vec<Lint>) {
+    if let Some(name) = &vs.name
+        && lookup_keyword(name).is_some()
+    {
+        lints.push(Lint::new(
+            "keyword-metric-name",
+            Severity::Warning,
+            format!(
+                "metric name \"{}\" is also a PromQL keyword; use {{__name__=\"{}\"}} to avoid ambiguity",
+                name, name
+            ),
+        ));
+    }
+
+    for m in vs
+        .matchers
+        .iter()
+        .filter(|m| m.op == LabelMatchOp::RegexMatch)
+    {
+        let message = match m.value.as_str() {
+            ".*" | "^.*$" => format!(
+                "matcher {}=~\"{}\" matches every value; remove it or use a more specific pattern",
+                m.name, m.value
+            ),
+            // Unlike `.*`, this drops series without the label
+            ".+" | "^.+$" => format!(
+                "matcher {}=~\"{}\" only requires the label to be present; use {}!=\"\" instead",
+                m.name, m.value, m.name
+            ),
+            _ => continue,
+        };
+        lints.push(Lint::new("broad-regex", Severity::Warning, message));
+    }
+}
+
+fn lint_call(call: &Call, lints: &mut Vec<Lint>) {
+    if get_function(&call.name).is_some_and(|f| f.experimental) {
+        lints.push(Lint::new(
+            "experimental-function",
+            Severity::Info,
+            format!(
+                "function \"{}\" is experimental and requires --enable-feature=promql-experimental-functions",
+                call.name
+            ),
+        ));
+    }
+
+    if call.name == "histogram_quantile"
+        && let Some(arg) = call.args.get(1)
+        && let names = selector_names(arg)
+        && !names.is_empty()
+        && !names.iter().any(|n| n.ends_with("_bucket"))
+    {
+        lints.push(Lint::new(
+            "histogram-quantile-non-bucket",
+            Severity::Info,
+            format!(
+                "histogram_quantile over \"{}\", which is not a _bucket series; this only works if it is a native histogram",
+                names[0]
+            ),
+        ));
+    }
+
+    if call.name == "histogram_quantile"
+        && let Some(Expr::Aggregation(agg)) = call.args.get(1).map(Expr::unwrap_parens)
+        && !preserves_le(agg)
+        && selects_buckets(&agg.expr)
+    {
+        lints.push(Lint::new(
+            "missing-le-grouping",
+            Severity::Warning,
+            format!(
+                "{} drops the \"le\" label required by histogram_quantile; add \"le\" to the grouping",
+                agg.op
+            ),
+        ));
+    }
+}
+
+fn lint_aggregation(agg: &Aggregation, lints: &mut Vec<Lint>) {
+    let Some(g) = &agg.grouping else {
+        return;
+    };
+    lint_label_list(&g.action.to_string(), &g.labels, lints);
+
+    for inner in nearest_aggregations(&agg.expr) {
+        if let Some(inner_g) = &inner.grouping
+            && inner_g.action != g.action
+        {
+            lints.push(Lint::new(
+                "mixed-grouping",
+                Severity::Info,
+                format!(
+                    "{} {} aggregates the result of {} {}; mixing by and without in one aggregation chain is easy to misread",
+                    agg.op, g, inner.op, inner_g
+                ),
+            ));
+        }
+    }
+}
+
+fn lint_binary(bin: &BinaryExpr, lints: &mut Vec<Lint>) {
+    if let Some(matching) = bin.modifier.as_ref().and_then(|m| m.matching.as_ref()) {
+        lint_label_list(&matching.op.to_string(), &matching.labels, lints);
+        if let Some(group) = &matching.group {
+            lint_label_list(&group.side.to_string(), &group.labels, lints);
+        }
+    }
+
+    let return_bool = bin.modifier.as_ref().is_some_and(|m| m.return_bool);
+    if bin.op.is_comparison()
+        && !return_bool
+        && type_of(&bin.lhs) == ValueType::Scalar
+        && type_of(&bin.rhs) == ValueType::Scalar
+    {
+        lints.push(Lint::new(
+            "scalar-comparison",
+            Severity::Error,
+            format!(
+                "comparison between scalars must use the bool modifier: {} {} bool {}",
+                bin.lhs, bin.op, bin.rhs
+            ),
+        ));
+    }
+}
+
+fn lint_label_list(clause: &str, labels: &[String], lints: &mut Vec<Lint>) {
+    for (i, label) in labels.iter().enumerate() {
+        // Report each duplicated label once, at its second occurrence
+        if labels[..i].iter().filter(|l| *l == label).count() == 1 {
+            lints.push(Lint::new(
+                "duplicate-label",
+                Severity::Warning,
+                format!("duplicate label \"{}\" in {} clause", label, clause),
+            ));
+        }
+    }
+}
+
+fn lint_subquery(sq: &SubqueryExpr, lints: &mut Vec<Lint>) {
+    if let Some(step) = sq.step
+        && step.as_millis() > sq.range.as_millis()
+    {
+        lints.push(Lint::new(
+            "subquery-step-exceeds-range",
+            Severity::Warning,
+            format!(
+                "subquery step {} is larger than its range {}; each window holds at most one point",
+                step, sq.range
+            ),
+        ));
+    }
+}
+
+/// Check whether an aggregation keeps the `le` label of its input
+fn preserves_le(agg: &Aggregation) -> bool {
+    // These select series rather than combine them, so all labels survive
+    if matches!(
+        agg.op.to_ascii_lowercase().as_str(),
+        "topk" | "bottomk" | "limitk" | "limit_ratio"
+    ) {
+        return true;
+    }
+    match &agg.grouping {
+        None => false,
+        Some(g) => {
+            let has_le = g.labels.iter().any(|l| l == "le");
+            match g.action {
+                GroupingAction::By => has_le,
+                GroupingAction::Without => !has_le,
+            }
+        }
+    }
+}
+
+/// The outermost aggregations within an expression, in source order
+fn nearest_aggregations(expr: &Expr) -> Vec<&Aggregation> {
+    match expr {
+        Expr::Aggregation(agg) => vec![agg.as_ref()],
+        _ => expr
+            .children()
+            .into_iter()
+            .flat_map(nearest_aggregations)
+            .collect(),
+    }
+}
+
+/// Check whether an expression reads classic histogram bucket series
+fn selects_buckets(expr: &Expr) -> bool {
+    selector_names(expr).iter().any(|n| n.ends_with("_bucket"))
+}
+
+/// Metric names of the selectors in an expression, in source order
+fn selector_names(expr: &Expr) -> Vec<&str> {
+    expr.vector_selectors()
+        .into_iter()
+        .filter_map(|vs| vs.name.as_deref())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::expr::expr;
+
+    fn codes(input: &str) -> Vec<&'static str> {
+        let (rest, e) = expr(input).unwrap();
+        assert!(rest.is_empty(), "unparsed input: {:?}", rest);
+        lint(&e).into_iter().map(|l| l.code).collect()
+    }
+
+    #[test]
+    fn test_clean_query() {
+        assert!(codes("sum by (job) (rate(http_requests_total[5m]))").is_empty());
+    }
+
+    #[test]
+    fn test_missing_le_grouping() {
+        assert_eq!(
+            codes("histogram_quantile(0.9, sum(rate(req_duration_bucket[5m])))"),
+            vec!["missing-le-grouping"]
+        );
+        assert_eq!(
+            codes("histogram_quantile(0.9, sum by (job) (rate(req_duration_bucket[5m])))"),
+            vec!["missing-le-grouping"]
+        );
+        assert_eq!(
+            codes("histogram_quantile(0.9, sum without (le) (rate(req_duration_bucket[5m])))"),
+            vec!["missing-le-grouping"]
+        );
+        assert!(
+            codes("histogram_quantile(0.9, sum by (job, le) (rate(req_duration_bucket[5m])))")
+                .is_empty()
+        );
+        // Native histograms have no le label to keep
+        assert!(
+            !codes("histogram_quantile(0.9, sum(rate(req_duration[5m])))")
+                .contains(&"missing-le-grouping")
+        );
+    }
+
+    #[test]
+    fn test_broad_regex() {
+        assert_eq!(codes(r#"up{job=~".*"}"#), vec!["broad-regex"]);
+        assert_eq!(codes(r#"up{job=~"^.*$"}"#), vec!["broad-regex"]);
+        assert_eq!(codes(r#"rate(up{job=~".+"}[5m])"#), vec!["broad-regex"]);
+        assert!(codes(r#"up{job=~"api.*"}"#).is_empty());
+
+        // `.+` is not a no-op: it drops series without the label
+        let (_, e) = expr(r#"up{job=~".+"}"#).unwrap();
+        assert_eq!(
+            lint(&e)[0].message,
+            "matcher job=~\".+\" only requires the label to be present; use job!=\"\" instead"
+        );
+    }
+
+    #[test]
+    fn test_subquery_step_exceeds_range() {
+        assert_eq!(
+            codes("max_over_time(rate(x[1m])[5m:10m])"),
+            vec!["subquery-step-exceeds-range"]
+        );
+        assert!(codes("max_over_time(rate(x[1m])[5m:1m])").is_empty());
+    }
+
+    #[test]
+    fn test_scalar_comparison() {
+        assert_eq!(codes("1 > 2"), vec!["scalar-comparison"]);
+        assert!(codes("1 > bool 2").is_empty());
+        assert!(codes("up > 2").is_empty());
+    }
+
+    #[test]
+    fn test_experimental_function() {
+        let (_, e) = expr("mad_over_time(x[5m])").unwrap();
+        let lints = lint(&e);
+        assert_eq!(lints.len(), 1);
+        assert_eq!(lints[0].code, "experimental-function");
+        assert_eq!(lints[0].severity, Severity::Info);
+        assert_eq!(lints[0].span, None);
+    }
+
+    #[test]
+    fn test_experimental_double_exponential_smoothing() {
+        assert_eq!(
+            codes("double_exponential_smoothing(x[5m], 0.5, 0.1)"),
+            vec!["experimental-function"]
+        );
+    }
+
+    #[test]
+    fn test_keyword_metric_name() {
+        assert_eq!(codes("start"), vec!["keyword-metric-name"]);
+        assert_eq!(codes("rate(bool[5m])"), vec!["keyword-metric-name"]);
+        assert!(codes("starts").is_empty());
+    }
+
+    #[test]
+    fn test_lint_display() {
+        let (_, e) = expr(r#"up{job=~".*"}"#).unwrap();
+        assert_eq!(
+            lint(&e)[0].to_string(),
+            "warning [broad-regex]: matcher job=~\".*\" matches every value; remove it or use a more specific pattern"
+        );
+    }
+
+    #[test]
+    fn test_duplicate_label() {
+        let (_, e) = expr("sum by (job, job) (x)").unwrap();
+        let lints = lint(&e);
+        assert_eq!(lints.len(), 1);
+        assert_eq!(lints[0].code, "duplicate-label");
+        assert_eq!(lints[0].message, "duplicate label \"job\" in by clause");
+
+        assert!(codes("sum by (job, instance) (x)").is_empty());
+        assert_eq!(
+            codes("sum without (a, b, a, a) (x)"),
+            vec!["duplicate-label"]
+        );
+        assert_eq!(codes("a / on(job, job) b"), vec!["duplicate-label"]);
+        assert_eq!(
+            codes("a / ignoring(x) group_left(y, y) b"),
+            vec!["duplicate-label"]
+        );
+        assert!(codes("a / on(job) group_left(job) b").is_empty());
+    }
+
+    #[test]
+    fn test_counter_without_rate() {
+        assert_eq!(codes("http_requests_total"), vec!["counter-without-rate"]);
+        assert_eq!(
+            codes("sum by (job) (http_requests_total)"),
+            vec!["counter-without-rate"]
+        );
+        assert_eq!(
+            codes("req_duration_sum / req_duration_count"),
+            vec!["counter-without-rate", "counter-without-rate"]
+        );
+        assert!(codes("rate(http_requests_total[5m])").is_empty());
+        assert!(codes("increase(http_requests_total[1h])").is_empty());
+        // A subquery over the raw counter is fine inside rate()
+        assert!(codes("rate(http_requests_total[5m:1m])").is_empty());
+        assert!(codes("http_requests").is_empty());
+    }
+
+    #[test]
+    fn test_mixed_grouping() {
+        let (_, e) = expr("sum by (job) (avg without (instance) (x))").unwrap();
+        let lints = lint(&e);
+        assert_eq!(lints.len(), 1);
+        assert_eq!(lints[0].code, "mixed-grouping");
+        assert_eq!(lints[0].severity, Severity::Info);
+        assert_eq!(
+            lints[0].message,
+            "sum by (job) aggregates the result of avg without (instance); mixing by and without in one aggregation chain is easy to misread"
+        );
+
+        assert_eq!(
+            codes("max without (pod) (rate(x[5m]) / on (job) sum by (job) (y))"),
+            vec!["mixed-grouping"]
+        );
+        assert_eq!(
+            codes("sum by (a) (max by (a, b) (min without (c) (x)))"),
+            vec!["mixed-grouping"]
+        );
+        assert!(codes("sum by (job) (avg by (job, instance) (x))").is_empty());
+        assert!(codes("sum without (a) (avg without (a, b) (x))").is_empty());
+        // Aggregations without a clause have no grouping kind to mix
+        assert!(codes("sum by (job) (max(x))").is_empty());
+        assert!(codes("sum(avg without (instance) (x))").is_empty());
+        // Side by side is not nested
+        assert!(codes("sum by (job) (x) / sum without (instance) (y)").is_empty());
+    }
+
+    #[test]
+    fn test_counter_suffixes_configurable() {
+        let (_, e) = expr("jobs_processed + http_requests_total").unwrap();
+        let options = LintOptions {
+            counter_suffixes: vec!["_processed".to_string()],
+        };
+        let lints = lint_with_options(&e, &options);
+        assert_eq!(lints.len(), 1);
+        assert!(lints[0].message.contains("jobs_processed"));
+
+        let options = LintOptions {
+            counter_suffixes: Vec::new(),
+        };
+        assert!(lint_with_options(&e, &options).is_empty());
+    }
+
+    #[test]
+    fn test_histogram_quantile_non_bucket() {
+        let (_, e) = expr("histogram_quantile(0.9, rate(req_duration_seconds[5m]))").unwrap();
+        let lints = lint(&e);
+        assert_eq!(lints.len(), 1);
+        assert_eq!(lints[0].code, "histogram-quantile-non-bucket");
+        assert_eq!(lints[0].severity, Severity::Info);
+        assert!(lints[0].message.contains("req_duration_seconds"));
+
+        assert_eq!(
+            codes("histogram_quantile(0.9, sum(rate(req_duration_seconds[5m])))"),
+            vec!["histogram-quantile-non-bucket"]
+        );
+        assert!(codes("histogram_quantile(0.9, rate(req_duration_seconds_bucket[5m]))").is_empty());
+        assert!(
+            codes("histogram_quantile(0.9, sum by (le) (rate(req_duration_seconds_bucket[5m])))")
+                .is_empty()
+        );
+        // Nothing to go on without a metric name
+        assert!(codes(r#"histogram_quantile(0.9, {__name__=~"req_.*"})"#).is_empty());
+    }
+}
@@ -5,6 +5,9 @@
 //! - `by (label1, label2)` - Group by specific labels, dropping all others
 //! - `without (label1, label2)` - Drop specific labels, keeping all others
 //!
+//! Label names that are not valid identifiers are quoted, as in
+//! `by ("http.method")`.
+//!
 //! # Supported Aggregation Operators
 //!
 //! These operators support grouping clauses:
@@ -26,13 +29,17 @@
 //! assert_eq!(g.action, GroupingAction::Without);
 //! ```
 
-use std::fmt;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
 
 use nom::{
-    IResult, Parser, branch::alt, bytes::complete::tag_no_case, character::complete::char,
+    Parser, branch::alt, bytes::complete::tag_no_case, character::complete::char,
     multi::separated_list0, sequence::delimited,
 };
 
+use crate::error::IResult;
+use crate::lexer::string::{DoubleQuoted, string_literal};
 use crate::lexer::{identifier::clause_label_name, whitespace::ws_opt};
 
 /// The action for aggregation grouping: `by` or `without`.
@@ -40,6 +47,7 @@ use crate::lexer::{identifier::clause_label_name, whitespace::ws_opt};
 /// - [`GroupingAction::By`]: Group results by the specified labels only
 /// - [`GroupingAction::Without`]: Group results by all labels except those specified
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum GroupingAction {
     /// Group by specific labels, dropping all others.
     ///
@@ -76,6 +84,7 @@ impl fmt::Display for GroupingAction {
 /// assert_eq!(g.to_string(), "by (job, instance)");
 /// ```
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Grouping {
     /// The grouping action (by or without).
     pub action: GroupingAction,
@@ -85,17 +94,37 @@ pub struct Grouping {
 
 impl fmt::Display for Grouping {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{} (", self.action)?;
-        for (i, label) in self.labels.iter().enumerate() {
+        write!(f, "{} ({})", self.action, LabelList(&self.labels))
+    }
+}
+
+/// Displays the labels of a grouping or vector matching clause, separated
+/// by commas. Labels that are not valid there as identifiers, like
+/// `http.method` or the keyword `on`, are quoted.
+pub(crate) struct LabelList<'a>(pub &'a [String]);
+
+impl fmt::Display for LabelList<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, label) in self.0.iter().enumerate() {
             if i > 0 {
                 write!(f, ", ")?;
             }
-            write!(f, "{}", label)?;
+            if clause_label_name(label).is_ok_and(|(rest, _)| rest.is_empty()) {
+                write!(f, "{}", label)?;
+            } else {
+                write!(f, "{}", DoubleQuoted(label))?;
+            }
         }
-        write!(f, ")")
+        Ok(())
     }
 }
 
+/// Parse a label name in a grouping or vector matching clause, either as an
+/// identifier or quoted like `"http.method"`
+pub(crate) fn clause_label(input: &str) -> IResult<&str, String> {
+    alt((clause_label_name.map(String::from), string_literal)).parse(input)
+}
+
 /// Parse a grouping clause: `by (label1, label2)` or `without (label1, label2)`
 ///
 /// # Examples
@@ -113,6 +142,14 @@ impl fmt::Display for Grouping {
 /// assert_eq!(g.action, GroupingAction::Without);
 /// ```
 pub fn grouping(input: &str) -> IResult<&str, Grouping> {
+    grouping_with(input, clause_label)
+}
+
+/// Parse a grouping clause whose label names are parsed by `label`
+pub(crate) fn grouping_with<'a>(
+    input: &'a str,
+    label: impl Fn(&'a str) -> IResult<&'a str, String>,
+) -> IResult<&'a str, Grouping> {
     (
         // Parse the action (by or without)
         alt((
@@ -122,10 +159,7 @@ pub fn grouping(input: &str) -> IResult<&str, Grouping> {
         // Parse: ws "(" ws labels ws ")"
         delimited(
             (ws_opt, char('('), ws_opt),
-            separated_list0(
-                (ws_opt, char(','), ws_opt),
-                clause_label_name.map(String::from),
-            ),
+            separated_list0((ws_opt, char(','), ws_opt), label),
             (ws_opt, char(')')),
         ),
     )
@@ -178,6 +212,14 @@ mod tests {
         assert_eq!(g.action, GroupingAction::Without);
     }
 
+    #[test]
+    fn test_grouping_quoted_labels() {
+        let (rest, g) = grouping(r#"by ("http.method", job, 'on')"#).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(g.labels, vec!["http.method", "job", "on"]);
+        assert_eq!(g.to_string(), r#"by ("http.method", job, "on")"#);
+    }
+
     #[test]
     fn test_grouping_display() {
         let g = Grouping {
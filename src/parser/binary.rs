@@ -38,12 +38,15 @@
 //! assert_eq!(op, BinaryOp::And);
 //! ```
 
+use alloc::string::String;
+use alloc::vec::Vec;
+
 use nom::{
-    IResult, Parser,
+    Parser,
     branch::alt,
     bytes::complete::{tag, tag_no_case},
     character::complete::{char, satisfy},
-    combinator::{map, not, opt, peek, value},
+    combinator::{not, opt, peek, value},
     multi::separated_list0,
     sequence::delimited,
 };
@@ -51,7 +54,8 @@ use nom::{
 use crate::ast::{
     BinaryModifier, BinaryOp, GroupModifier, GroupSide, VectorMatching, VectorMatchingOp,
 };
-use crate::lexer::{identifier::clause_label_name, whitespace::ws_opt};
+use crate::error::IResult;
+use crate::lexer::whitespace::ws_opt;
 
 /// Parser that succeeds only at a word boundary (not followed by alphanumeric or underscore)
 fn word_boundary(input: &str) -> IResult<&str, ()> {
@@ -120,21 +124,25 @@ fn vector_matching_op(input: &str) -> IResult<&str, VectorMatchingOp> {
         .parse(input)
 }
 
-/// Parse a label list in parentheses: `(label1, label2)`
-fn label_list(input: &str) -> IResult<&str, Vec<String>> {
+/// Parse a label list in parentheses, `(label1, label2)`, with the label
+/// names parsed by `label`
+fn label_list<'a>(
+    input: &'a str,
+    label: impl Fn(&'a str) -> IResult<&'a str, String>,
+) -> IResult<&'a str, Vec<String>> {
     delimited(
         (char('('), ws_opt),
-        separated_list0(
-            delimited(ws_opt, char(','), ws_opt),
-            map(clause_label_name, |s| s.to_string()),
-        ),
+        separated_list0(delimited(ws_opt, char(','), ws_opt), label),
         (ws_opt, char(')')),
     )
     .parse(input)
 }
 
 /// Parse the group modifier (group_left/group_right)
-fn group_modifier(input: &str) -> IResult<&str, GroupModifier> {
+fn group_modifier<'a>(
+    input: &'a str,
+    label: impl Fn(&'a str) -> IResult<&'a str, String> + Copy,
+) -> IResult<&'a str, GroupModifier> {
     (
         alt((
             value(GroupSide::Left, tag_no_case("group_left")),
@@ -142,7 +150,7 @@ fn group_modifier(input: &str) -> IResult<&str, GroupModifier> {
         )),
         word_boundary,
         ws_opt,
-        opt(label_list),
+        opt(|i| label_list(i, label)),
     )
         .map(|(side, _, _, labels)| GroupModifier {
             side,
@@ -152,13 +160,16 @@ fn group_modifier(input: &str) -> IResult<&str, GroupModifier> {
 }
 
 /// Parse vector matching specification: `on(labels) group_left(labels)`
-fn vector_matching(input: &str) -> IResult<&str, VectorMatching> {
+fn vector_matching<'a>(
+    input: &'a str,
+    label: impl Fn(&'a str) -> IResult<&'a str, String> + Copy,
+) -> IResult<&'a str, VectorMatching> {
     (
         vector_matching_op,
         ws_opt,
-        label_list,
+        |i| label_list(i, label),
         ws_opt,
-        opt(group_modifier),
+        opt(|i| group_modifier(i, label)),
     )
         .map(|(op, _, labels, _, group)| VectorMatching { op, labels, group })
         .parse(input)
@@ -167,14 +178,22 @@ fn vector_matching(input: &str) -> IResult<&str, VectorMatching> {
 /// Parse binary expression modifier: `bool on(labels) group_left(labels)`
 ///
 /// This parses the optional modifiers that can appear between the operator
-/// and the right-hand side operand.
-pub(crate) fn binary_modifier(input: &str) -> IResult<&str, BinaryModifier> {
-    let (rest, (_, return_bool, _, matching)) =
-        (ws_opt, opt(bool_modifier), ws_opt, opt(vector_matching)).parse(input)?;
+/// and the right-hand side operand. Label names are parsed by `label`.
+pub(crate) fn binary_modifier<'a>(
+    input: &'a str,
+    label: impl Fn(&'a str) -> IResult<&'a str, String> + Copy,
+) -> IResult<&'a str, BinaryModifier> {
+    let (rest, (_, return_bool, _, matching)) = (
+        ws_opt,
+        opt(bool_modifier),
+        ws_opt,
+        opt(|i| vector_matching(i, label)),
+    )
+        .parse(input)?;
 
     // If neither bool nor matching, fail
     if return_bool.is_none() && matching.is_none() {
-        return Err(nom::Err::Error(nom::error::Error::new(
+        return Err(nom::Err::Error(nom::error::make_error(
             input,
             nom::error::ErrorKind::Tag,
         )));
@@ -192,6 +211,7 @@ pub(crate) fn binary_modifier(input: &str) -> IResult<&str, BinaryModifier> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::parser::aggregation::clause_label;
 
     // Binary operator tests
     #[test]
@@ -250,7 +270,7 @@ mod tests {
     // Vector matching tests
     #[test]
     fn test_vector_matching_on() {
-        let (rest, vm) = vector_matching("on(job, instance)").unwrap();
+        let (rest, vm) = vector_matching("on(job, instance)", clause_label).unwrap();
         assert!(rest.is_empty());
         assert_eq!(vm.op, VectorMatchingOp::On);
         assert_eq!(vm.labels, vec!["job", "instance"]);
@@ -259,7 +279,7 @@ mod tests {
 
     #[test]
     fn test_vector_matching_ignoring() {
-        let (rest, vm) = vector_matching("ignoring(instance)").unwrap();
+        let (rest, vm) = vector_matching("ignoring(instance)", clause_label).unwrap();
         assert!(rest.is_empty());
         assert_eq!(vm.op, VectorMatchingOp::Ignoring);
         assert_eq!(vm.labels, vec!["instance"]);
@@ -267,7 +287,7 @@ mod tests {
 
     #[test]
     fn test_vector_matching_empty() {
-        let (rest, vm) = vector_matching("on()").unwrap();
+        let (rest, vm) = vector_matching("on()", clause_label).unwrap();
         assert!(rest.is_empty());
         assert_eq!(vm.op, VectorMatchingOp::On);
         assert!(vm.labels.is_empty());
@@ -275,7 +295,7 @@ mod tests {
 
     #[test]
     fn test_vector_matching_with_group_left() {
-        let (rest, vm) = vector_matching("on(job) group_left").unwrap();
+        let (rest, vm) = vector_matching("on(job) group_left", clause_label).unwrap();
         assert!(rest.is_empty());
         assert_eq!(vm.op, VectorMatchingOp::On);
         let group = vm.group.unwrap();
@@ -285,7 +305,8 @@ mod tests {
 
     #[test]
     fn test_vector_matching_with_group_right_labels() {
-        let (rest, vm) = vector_matching("ignoring(instance) group_right(job)").unwrap();
+        let (rest, vm) =
+            vector_matching("ignoring(instance) group_right(job)", clause_label).unwrap();
         assert!(rest.is_empty());
         assert_eq!(vm.op, VectorMatchingOp::Ignoring);
         let group = vm.group.unwrap();
@@ -295,20 +316,20 @@ mod tests {
 
     #[test]
     fn test_vector_matching_case_insensitive() {
-        let (_, vm) = vector_matching("ON(job)").unwrap();
+        let (_, vm) = vector_matching("ON(job)", clause_label).unwrap();
         assert_eq!(vm.op, VectorMatchingOp::On);
 
-        let (_, vm) = vector_matching("IGNORING(job)").unwrap();
+        let (_, vm) = vector_matching("IGNORING(job)", clause_label).unwrap();
         assert_eq!(vm.op, VectorMatchingOp::Ignoring);
 
-        let (_, vm) = vector_matching("on(job) GROUP_LEFT").unwrap();
+        let (_, vm) = vector_matching("on(job) GROUP_LEFT", clause_label).unwrap();
         assert!(vm.group.is_some());
     }
 
     // Binary modifier tests
     #[test]
     fn test_binary_modifier_bool_only() {
-        let (rest, m) = binary_modifier(" bool").unwrap();
+        let (rest, m) = binary_modifier(" bool", clause_label).unwrap();
         assert!(rest.is_empty() || rest.chars().all(|c| c.is_whitespace()));
         assert!(m.return_bool);
         assert!(m.matching.is_none());
@@ -316,7 +337,7 @@ mod tests {
 
     #[test]
     fn test_binary_modifier_matching_only() {
-        let (rest, m) = binary_modifier(" on(job)").unwrap();
+        let (rest, m) = binary_modifier(" on(job)", clause_label).unwrap();
         assert!(rest.is_empty());
         assert!(!m.return_bool);
         assert!(m.matching.is_some());
@@ -324,7 +345,7 @@ mod tests {
 
     #[test]
     fn test_binary_modifier_bool_and_matching() {
-        let (rest, m) = binary_modifier(" bool on(job)").unwrap();
+        let (rest, m) = binary_modifier(" bool on(job)", clause_label).unwrap();
         assert!(rest.is_empty());
         assert!(m.return_bool);
         assert!(m.matching.is_some());
@@ -332,7 +353,7 @@ mod tests {
 
     #[test]
     fn test_binary_modifier_fails_on_empty() {
-        assert!(binary_modifier("foo").is_err());
+        assert!(binary_modifier("foo", clause_label).is_err());
     }
 
     // Display tests
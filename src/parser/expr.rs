@@ -43,35 +43,53 @@
 //! assert!(rest.is_empty());
 //! ```
 
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
 use nom::{
-    IResult, Parser,
+    Parser,
     branch::alt,
     character::complete::char,
-    combinator::{opt, peek},
+    combinator::{consumed, opt, peek},
     multi::separated_list0,
     sequence::{delimited, preceded, terminated},
 };
 
-use crate::ast::{Aggregation, BinaryExpr, Call, Expr, SubqueryExpr, UnaryExpr};
+use crate::ast::{
+    Aggregation, BinaryExpr, BinaryOp, Call, Expr, NumberLiteral, SubqueryExpr, UnaryExpr,
+};
+use crate::error::{IResult, SyntaxError, SyntaxErrorKind};
 use crate::lexer::{
-    duration::duration,
-    identifier::{Keyword, aggregation_op, metric_name},
+    duration::positive_duration,
+    identifier::{Keyword, aggregation_op, is_utf8_metric_char, metric_name, utf8_metric_name},
     number::number,
     string::string_literal,
     whitespace::ws_opt,
 };
 use crate::parser::{
-    aggregation::grouping,
+    aggregation::{clause_label, grouping, grouping_with},
     binary::{binary_modifier, binary_op},
-    selector::{label_matchers, parse_modifiers},
+    options::ParseOptions,
+    selector::{
+        LabelMatcher, at_modifier, check_name_not_repeated, label_matchers, offset_modifier,
+        parse_modifiers, unquoted_label_matchers,
+    },
     subquery::{looks_like_subquery, subquery_range},
     unary::unary_op,
+    version::PrometheusVersion,
 };
 
 /// Parse a PromQL expression
 ///
 /// This is the main entry point for parsing PromQL expressions.
 ///
+/// The nesting depth is not limited, so deeply nested input can overflow
+/// the stack. Use [`parse_with_options`](crate::parse_with_options), whose
+/// default options set [`max_depth`](ParseOptions::max_depth), for
+/// untrusted input.
+///
 /// # Examples
 ///
 /// ```
@@ -90,22 +108,103 @@ use crate::parser::{
 /// assert!(rest.is_empty());
 /// ```
 pub fn expr(input: &str) -> IResult<&str, Expr> {
+    let unlimited = ParseOptions {
+        max_depth: None,
+        ..ParseOptions::default()
+    };
+    expr_with_options(input, &unlimited)
+}
+
+/// Parse a PromQL expression, enforcing the limits set in `options`
+///
+/// Violating a limit is reported as a [`nom::Err::Failure`] so that no
+/// alternative parse is attempted.
+///
+/// # Examples
+///
+/// ```
+/// use rusty_promql_parser::ParseOptions;
+/// use rusty_promql_parser::parser::expr::expr_with_options;
+///
+/// let options = ParseOptions {
+///     max_matchers_per_selector: Some(1),
+///     ..ParseOptions::default()
+/// };
+/// assert!(expr_with_options(r#"up{job="a"}"#, &options).is_ok());
+/// assert!(expr_with_options(r#"up{job="a", env="b"}"#, &options).is_err());
+/// ```
+pub fn expr_with_options<'a>(input: &'a str, opts: &ParseOptions) -> IResult<&'a str, Expr> {
+    expr_at_depth(input, opts, 0)
+}
+
+/// Parse an expression nested `depth` levels deep
+fn expr_at_depth<'a>(input: &'a str, opts: &ParseOptions, depth: usize) -> IResult<&'a str, Expr> {
     // Skip leading whitespace, then parse binary expression with minimum precedence 0
-    preceded(ws_opt, |i| parse_binary_expr(i, 0)).parse(input)
+    preceded(ws_opt, |i| parse_binary_expr(i, 0, opts, depth)).parse(input)
+}
+
+/// Parse a complete query that is a lone instant vector selector, like `up`
+/// or `http_requests_total{job="api"}`, without the general expression
+/// parser.
+///
+/// Most real-world queries have this shape. Returns `None` for anything
+/// else, including surrounding comments, invalid input and selectors the
+/// options reject, so that the general parser handles (and reports) them.
+/// Whenever this returns `Some`, the general parser gives the same AST.
+pub(crate) fn parse_simple_selector(input: &str, opts: &ParseOptions) -> Option<Expr> {
+    use crate::lexer::whitespace::is_whitespace;
+    use crate::parser::selector::VectorSelector;
+
+    let query = input.trim_matches(is_whitespace);
+    let name_end = query
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == ':'))
+        .unwrap_or(query.len());
+    let (name, rest) = query.split_at(name_end);
+    // Numbers like `Inf` and aggregations need the general parser
+    if name.is_empty()
+        || name.starts_with(|c: char| c.is_ascii_digit())
+        || number(name).is_ok()
+        || aggregation_op(name).is_ok()
+    {
+        return None;
+    }
+
+    let matchers = if rest.is_empty() {
+        Vec::new()
+    } else if rest.starts_with('{') {
+        match limited_label_matchers(rest, opts) {
+            Ok(("", matchers)) if check_name_not_repeated(rest, name, &matchers).is_ok() => {
+                matchers
+            }
+            _ => return None,
+        }
+    } else {
+        return None;
+    };
+
+    Some(Expr::VectorSelector(VectorSelector {
+        name: Some(name.to_string()),
+        matchers,
+        offset: None,
+        at: None,
+    }))
 }
 
 /// Parse a binary expression using Pratt parser (precedence climbing)
 ///
 /// The `min_precedence` parameter ensures we only parse operators at or above
 /// the given precedence level, which handles precedence correctly.
-fn parse_binary_expr(input: &str, min_precedence: u8) -> IResult<&str, Expr> {
-    let (mut input, mut lhs) = parse_unary_expr(input)?;
+fn parse_binary_expr<'a>(
+    input: &'a str,
+    min_precedence: u8,
+    opts: &ParseOptions,
+    depth: usize,
+) -> IResult<&'a str, Expr> {
+    let (mut input, mut lhs) = parse_unary_expr(input, opts, depth)?;
 
     loop {
         // Try to parse: ws binary_op ws modifier? ws rhs
-        let Ok((after_ws, _)) = ws_opt(input) else {
-            break;
-        };
+        let (after_ws, _) = ws_opt(input)?;
         let Ok((after_op, op)) = binary_op(after_ws) else {
             break;
         };
@@ -125,10 +224,21 @@ fn parse_binary_expr(input: &str, min_precedence: u8) -> IResult<&str, Expr> {
         };
 
         // Parse: ws modifier? ws rhs
-        let (remaining, (_, modifier, _, rhs)) = (ws_opt, opt(binary_modifier), ws_opt, |i| {
-            parse_binary_expr(i, next_min_precedence)
-        })
-            .parse(after_op)?;
+        let (after_modifier, (_, modifier, _)) =
+            (ws_opt, opt(|i| binary_modifier(i, label_for(opts))), ws_opt).parse(after_op)?;
+        // Chains of right-associative operators recurse here
+        let rhs_depth = deeper(after_modifier, opts, depth)?;
+        let rhs_result = parse_binary_expr(after_modifier, next_min_precedence, opts, rhs_depth);
+        if matches!(rhs_result, Err(nom::Err::Error(_)))
+            && let Some(op) = leading_operator(after_modifier)
+        {
+            // Consecutive operators like `a * / b`; `a + -b` parsed above
+            return Err(nom::Err::Failure(SyntaxError::new(
+                after_modifier,
+                SyntaxErrorKind::UnexpectedOperator(op),
+            )));
+        }
+        let (remaining, rhs) = rhs_result?;
 
         lhs = Expr::Binary(Box::new(BinaryExpr {
             op,
@@ -142,16 +252,54 @@ fn parse_binary_expr(input: &str, min_precedence: u8) -> IResult<&str, Expr> {
     Ok((input, lhs))
 }
 
+/// The symbolic binary operator that `input` starts with, unless it can
+/// also be a unary operator
+fn leading_operator(input: &str) -> Option<BinaryOp> {
+    match binary_op(input) {
+        Ok((_, op))
+            if !matches!(op, BinaryOp::Add | BinaryOp::Sub | BinaryOp::Atan2)
+                && !op.is_set_operator() =>
+        {
+            Some(op)
+        }
+        _ => None,
+    }
+}
+
+/// The depth of a sub-expression at `input`, one level deeper than `depth`.
+///
+/// Fails with [`SyntaxErrorKind::TooDeep`] once [`ParseOptions::max_depth`]
+/// is used up.
+fn deeper<'a>(
+    input: &'a str,
+    opts: &ParseOptions,
+    depth: usize,
+) -> Result<usize, nom::Err<SyntaxError<&'a str>>> {
+    if opts.max_depth.is_some_and(|max| depth >= max) {
+        return Err(nom::Err::Failure(SyntaxError::new(
+            input,
+            SyntaxErrorKind::TooDeep,
+        )));
+    }
+    Ok(depth + 1)
+}
+
 /// Parse a unary expression: `unary_op? postfix_expr`
-fn parse_unary_expr(input: &str) -> IResult<&str, Expr> {
+fn parse_unary_expr<'a>(
+    input: &'a str,
+    opts: &ParseOptions,
+    depth: usize,
+) -> IResult<&'a str, Expr> {
+    // Every other sub-expression is parsed through here
+    let depth = deeper(input, opts, depth)?;
     alt((
         // Unary operator followed by another unary expression (recursive)
         // This handles chained unary operators like `--foo` or `-+foo`
         // Note: -2^3 = -(2^3), not (-2)^3, because unary binds looser than ^
-        (unary_op, ws_opt, parse_unary_expr)
+        (unary_op, ws_opt, |i| parse_unary_expr(i, opts, depth))
             .map(|(op, _, operand)| Expr::Unary(Box::new(UnaryExpr { op, expr: operand }))),
         // No unary operator, fall through to postfix
-        parse_postfix_expr,
+        |i| parse_postfix_expr(i, opts, depth),
     ))
     .parse(input)
 }
@@ -161,8 +309,15 @@ fn parse_unary_expr(input: &str) -> IResult<&str, Expr> {
 /// Postfix operations include:
 /// - Subquery: `[5m:1m]`
 /// - Modifiers: `offset 5m`, `@ start()`
-fn parse_postfix_expr(input: &str) -> IResult<&str, Expr> {
-    let (mut rest, mut expr) = parse_primary_expr(input)?;
+///
+/// Modifiers after anything but a selector or subquery fail with
+/// [`SyntaxErrorKind::MisplacedModifier`].
+fn parse_postfix_expr<'a>(
+    input: &'a str,
+    opts: &ParseOptions,
+    depth: usize,
+) -> IResult<&'a str, Expr> {
+    let (mut rest, mut expr) = parse_primary_expr(input, opts, depth)?;
 
     // Try to parse subquery postfix operations
     // Use peek to check for subquery pattern without consuming input
@@ -180,6 +335,28 @@ fn parse_postfix_expr(input: &str) -> IResult<&str, Expr> {
         rest = remaining;
     }
 
+    // Selectors and subqueries parse their own modifiers, so a modifier
+    // here follows an expression that cannot take one, like `1 offset 1d`
+    if !matches!(
+        expr,
+        Expr::VectorSelector(_) | Expr::MatrixSelector(_) | Expr::Subquery(_)
+    ) {
+        let (modifier, _) = ws_opt(rest)?;
+        let name = if offset_modifier(modifier).is_ok() {
+            Some("offset")
+        } else if at_modifier(modifier).is_ok() {
+            Some("@")
+        } else {
+            None
+        };
+        if let Some(name) = name {
+            return Err(nom::Err::Failure(SyntaxError::new(
+                modifier,
+                SyntaxErrorKind::MisplacedModifier(name),
+            )));
+        }
+    }
+
     Ok((rest, expr))
 }
 
@@ -189,7 +366,7 @@ fn peek_subquery_start(input: &str) -> IResult<&str, ()> {
     if looks_like_subquery(input) {
         Ok((input, ()))
     } else {
-        Err(nom::Err::Error(nom::error::Error::new(
+        Err(nom::Err::Error(nom::error::make_error(
             input,
             nom::error::ErrorKind::Tag,
         )))
@@ -197,33 +374,47 @@ fn peek_subquery_start(input: &str) -> IResult<&str, ()> {
 }
 
 /// Parse a primary expression (atoms)
-fn parse_primary_expr(input: &str) -> IResult<&str, Expr> {
+fn parse_primary_expr<'a>(
+    input: &'a str,
+    opts: &ParseOptions,
+    depth: usize,
+) -> IResult<&'a str, Expr> {
     alt((
         // Parenthesized expression
-        parse_paren_expr,
+        |i| parse_paren_expr(i, opts, depth),
         // Number literal (must come before identifier to handle negative numbers correctly)
         parse_number_literal,
         // String literal
         parse_string_literal,
         // Selector starting with { (labels only, no metric name prefix)
-        parse_labels_only_selector,
+        |i| parse_labels_only_selector(i, opts),
         // Aggregation, function call, or vector selector
         // (these all start with an identifier, so we handle them together)
-        parse_identifier_expr,
+        |i| parse_identifier_expr(i, opts, depth),
     ))
     .parse(input)
 }
 
 /// Parse a parenthesized expression: `( expr )`
-fn parse_paren_expr(input: &str) -> IResult<&str, Expr> {
-    delimited((char('('), ws_opt), expr, (ws_opt, char(')')))
-        .map(|inner| Expr::Paren(Box::new(inner)))
-        .parse(input)
+fn parse_paren_expr<'a>(
+    input: &'a str,
+    opts: &ParseOptions,
+    depth: usize,
+) -> IResult<&'a str, Expr> {
+    delimited(
+        (char('('), ws_opt),
+        |i| expr_at_depth(i, opts, depth),
+        (ws_opt, char(')')),
+    )
+    .map(|inner| Expr::Paren(Box::new(inner)))
+    .parse(input)
 }
 
 /// Parse a number literal
 fn parse_number_literal(input: &str) -> IResult<&str, Expr> {
-    number.map(Expr::Number).parse(input)
+    consumed(number)
+        .map(|(raw, value)| Expr::Number(NumberLiteral::with_raw(value, raw)))
+        .parse(input)
 }
 
 /// Parse a string literal
@@ -237,23 +428,65 @@ fn parse_string_literal(input: &str) -> IResult<&str, Expr> {
 /// - Aggregation operators: `sum(...)`, `avg by (...) (...)`
 /// - Function calls: `rate(...)`, `abs(...)`
 /// - Vector selectors: `metric`, `metric{labels}`
-fn parse_identifier_expr(input: &str) -> IResult<&str, Expr> {
-    // First, check if this is an aggregation operator
-    if let Ok((rest, op)) = aggregation_op(input) {
-        return parse_aggregation_expr(rest, op);
+fn parse_identifier_expr<'a>(
+    input: &'a str,
+    opts: &ParseOptions,
+    depth: usize,
+) -> IResult<&'a str, Expr> {
+    // First, check if this is an aggregation operator. Like other keywords,
+    // aggregation operators not followed by arguments or a grouping clause
+    // are metric names, as in `rate(avg[5m])`. With UTF-8 metric names,
+    // `sum.total` is a name rather than `sum` followed by junk.
+    if let Ok((rest, op)) = aggregation_op(input)
+        && !(opts.allow_utf8_metric_names && rest.starts_with(is_utf8_metric_char))
+        && ws_opt(rest).is_ok_and(|(after, _)| after.starts_with('(') || grouping(after).is_ok())
+    {
+        check_available(input, "aggregation", op.as_str(), opts)?;
+        return parse_aggregation_expr(rest, op, opts, depth);
     }
 
     // Parse metric name followed by optional whitespace, then dispatch
-    let (rest, (name, _)) = (metric_name, ws_opt).parse(input)?;
+    let name_parser = if opts.allow_utf8_metric_names {
+        utf8_metric_name
+    } else {
+        metric_name
+    };
+    let (rest, (name, _)) = (name_parser, ws_opt).parse(input)?;
 
     // Use peek to check for '(' without consuming
     if peek_open_paren(rest).is_ok() {
-        parse_function_call(rest, name)
+        check_available(input, "function", name, opts)?;
+        parse_function_call(rest, name, opts, depth)
     } else {
-        parse_vector_selector_with_name(rest, name)
+        parse_vector_selector_with_name(rest, name, opts)
     }
 }
 
+/// Fail if the targeted Prometheus version lacks the function or
+/// aggregation operator `name`, with the error located at `input`
+fn check_available<'a>(
+    input: &'a str,
+    feature: &'static str,
+    name: &str,
+    opts: &ParseOptions,
+) -> Result<(), nom::Err<SyntaxError<&'a str>>> {
+    if let Some(target) = opts.prometheus_version
+        && let Some(since) = PrometheusVersion::introduced(name)
+        && since > target
+    {
+        return Err(nom::Err::Failure(SyntaxError::new(
+            input,
+            SyntaxErrorKind::Unavailable {
+                feature,
+                name: name.to_string(),
+                since,
+                target,
+            },
+        )));
+    }
+    Ok(())
+}
+
 /// Peek for opening parenthesis
 /// Helper with explicit return type for type inference
 fn peek_open_paren(input: &str) -> IResult<&str, char> {
@@ -267,28 +500,42 @@ fn peek_open_brace(input: &str) -> IResult<&str, char> {
 }
 
 /// Parse an aggregation expression
-fn parse_aggregation_expr(input: &str, op: Keyword) -> IResult<&str, Expr> {
+fn parse_aggregation_expr<'a>(
+    input: &'a str,
+    op: Keyword,
+    opts: &ParseOptions,
+    depth: usize,
+) -> IResult<&'a str, Expr> {
+    let expr = |i| expr_at_depth(i, opts, depth);
+    let grouping = |i| grouping_with(i, label_for(opts));
+
     // Try to parse grouping before the expression
     let (rest, grouping_before) =
         preceded(ws_opt, opt(terminated(grouping, ws_opt))).parse(input)?;
 
-    // Parse the arguments in parentheses
-    let (rest, (param, inner_expr)) = delimited(
-        (char('('), ws_opt),
-        |i| {
-            if op.is_aggregation_with_param() {
-                // Parametric: parse parameter, comma, then inner expression
-                let (rest, (param, _, _, _, inner)) =
-                    (expr, ws_opt, char(','), ws_opt, expr).parse(i)?;
-                Ok((rest, (Some(param), inner)))
-            } else {
-                // Non-parametric: just parse inner expression
-                expr.map(|inner| (None, inner)).parse(i)
-            }
-        },
-        (ws_opt, char(')')),
-    )
-    .parse(rest)?;
+    // Parse the arguments in parentheses: the parameter, if the operator
+    // takes one, and the inner expression
+    let (mut after_args, first) = preceded((char('('), ws_opt), expr).parse(rest)?;
+    let mut args = vec![first];
+    while let Ok((next, _)) = (ws_opt, char(','), ws_opt).parse(after_args) {
+        let (next, arg) = expr(next)?;
+        args.push(arg);
+        after_args = next;
+    }
+    let (after_args, _) = (ws_opt, char(')')).parse(after_args)?;
+    let expected = if op.is_aggregation_with_param() { 2 } else { 1 };
+    if args.len() != expected {
+        return Err(nom::Err::Failure(SyntaxError::new(
+            rest,
+            SyntaxErrorKind::AggregationArguments {
+                expected,
+                got: args.len(),
+            },
+        )));
+    }
+    let inner_expr = args.pop().expect("argument count checked above");
+    let param = args.pop();
+    let rest = after_args;
 
     // Try to parse grouping after the expression (if not already parsed)
     let (rest, grouping_after) = if grouping_before.is_none() {
@@ -308,10 +555,17 @@ fn parse_aggregation_expr(input: &str, op: Keyword) -> IResult<&str, Expr> {
 }
 
 /// Parse a function call
-fn parse_function_call<'a>(input: &'a str, name: &str) -> IResult<&'a str, Expr> {
+fn parse_function_call<'a>(
+    input: &'a str,
+    name: &str,
+    opts: &ParseOptions,
+    depth: usize,
+) -> IResult<&'a str, Expr> {
     delimited(
         (char('('), ws_opt),
-        separated_list0((ws_opt, char(','), ws_opt), expr),
+        separated_list0((ws_opt, char(','), ws_opt), |i| {
+            expr_at_depth(i, opts, depth)
+        }),
         (ws_opt, opt((char(','), ws_opt)), char(')')),
     )
     .map(|args| Expr::Call(Call::new(name, args)))
@@ -319,21 +573,32 @@ fn parse_function_call<'a>(input: &'a str, name: &str) -> IResult<&'a str, Expr>
 }
 
 /// Parse a vector selector starting with a known metric name
-fn parse_vector_selector_with_name<'a>(input: &'a str, name: &str) -> IResult<&'a str, Expr> {
+fn parse_vector_selector_with_name<'a>(
+    input: &'a str,
+    name: &str,
+    opts: &ParseOptions,
+) -> IResult<&'a str, Expr> {
     use crate::parser::selector::{MatrixSelector, VectorSelector};
 
     // Parse optional label matchers (only if input starts with '{')
     // Using peek to check without copying/trimming
     let (rest, matchers) = if peek_open_brace(input).is_ok() {
-        label_matchers(input)?
+        limited_label_matchers(input, opts)?
     } else {
         (input, Vec::new())
     };
+    check_name_not_repeated(input, name, &matchers)?;
 
     // Check if this is a matrix selector: ws + '[' but NOT subquery pattern
     if (ws_opt, peek_matrix_bracket).parse(rest).is_ok() {
         // Matrix selector: ws [duration] modifiers
-        return (ws_opt, char('['), duration, char(']'), parse_modifiers)
+        return (
+            ws_opt,
+            char('['),
+            positive_duration,
+            char(']'),
+            parse_modifiers,
+        )
             .map(|(_, _, range, _, (at, offset))| {
                 let selector = VectorSelector {
                     name: Some(name.to_string()),
@@ -366,7 +631,7 @@ fn peek_matrix_bracket(input: &str) -> IResult<&str, char> {
     let (rest, c) = peek(char('[')).parse(input)?;
     // Make sure it's NOT a subquery
     if looks_like_subquery(input) {
-        return Err(nom::Err::Error(nom::error::Error::new(
+        return Err(nom::Err::Error(nom::error::make_error(
             input,
             nom::error::ErrorKind::Tag,
         )));
@@ -375,30 +640,22 @@ fn peek_matrix_bracket(input: &str) -> IResult<&str, char> {
 }
 
 /// Parse a vector selector starting with just labels (no metric name)
-fn parse_labels_only_selector(input: &str) -> IResult<&str, Expr> {
-    use crate::parser::selector::{LabelMatchOp, MatrixSelector, VectorSelector};
+fn parse_labels_only_selector<'a>(input: &'a str, opts: &ParseOptions) -> IResult<&'a str, Expr> {
+    use crate::parser::selector::{MatrixSelector, VectorSelector, split_metric_name};
 
-    let (rest, matchers) = label_matchers(input)?;
+    let (rest, matchers) = limited_label_matchers(input, opts)?;
 
-    // Extract __name__ matcher if present
-    let name = matchers
-        .iter()
-        .find(|m| m.name == "__name__" && m.op == LabelMatchOp::Equal)
-        .map(|m| m.value.clone());
-
-    // Filter out the __name__= matcher that we're using as the name
-    let other_matchers: Vec<_> = if name.is_some() {
-        matchers
-            .into_iter()
-            .filter(|m| !(m.name == "__name__" && m.op == LabelMatchOp::Equal))
-            .collect()
-    } else {
-        matchers
-    };
+    let (name, other_matchers) = split_metric_name(matchers);
 
     // Check if this is a matrix selector: ws + '[' but NOT subquery pattern
     if (ws_opt, peek_matrix_bracket).parse(rest).is_ok() {
-        return (ws_opt, char('['), duration, char(']'), parse_modifiers)
+        return (
+            ws_opt,
+            char('['),
+            positive_duration,
+            char(']'),
+            parse_modifiers,
+        )
             .map(|(_, _, range, _, (at, offset))| {
                 let selector = VectorSelector {
                     name: name.clone(),
@@ -425,16 +682,59 @@ fn parse_labels_only_selector(input: &str) -> IResult<&str, Expr> {
         .parse(rest)
 }
 
+/// Parser for label names in grouping and vector matching clauses, which
+/// rejects quoted names if the targeted Prometheus version predates them
+fn label_for<'a>(opts: &ParseOptions) -> impl Fn(&'a str) -> IResult<&'a str, String> + Copy {
+    let target = opts
+        .prometheus_version
+        .filter(|target| !target.supports_quoted_names());
+    move |input| match target {
+        Some(target) if string_literal(input).is_ok() => Err(nom::Err::Failure(SyntaxError::new(
+            input,
+            SyntaxErrorKind::QuotedNamesUnavailable { target },
+        ))),
+        _ => clause_label(input),
+    }
+}
+
+/// Parse label matchers in braces, enforcing `max_matchers_per_selector`
+/// and the syntax of the targeted Prometheus version
+fn limited_label_matchers<'a>(
+    input: &'a str,
+    opts: &ParseOptions,
+) -> IResult<&'a str, Vec<LabelMatcher>> {
+    let (rest, matchers) = label_matchers(input)?;
+    if let Some(target) = opts.prometheus_version
+        && !target.supports_quoted_names()
+        && let Err(nom::Err::Error(e)) = unquoted_label_matchers(input)
+    {
+        return Err(nom::Err::Failure(SyntaxError::new(
+            e.input,
+            SyntaxErrorKind::QuotedNamesUnavailable { target },
+        )));
+    }
+    if let Some(max) = opts.max_matchers_per_selector
+        && matchers.len() > max
+    {
+        return Err(nom::Err::Failure(SyntaxError::new(
+            input,
+            SyntaxErrorKind::TooManyMatchers { max },
+        )));
+    }
+    Ok((rest, matchers))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::ast::{BinaryOp, UnaryOp};
+    use crate::ast::UnaryOp;
+    use crate::parser::selector::AtModifier;
 
     #[test]
     fn test_parse_number() {
         let (rest, e) = expr("42").unwrap();
         assert!(rest.is_empty());
-        assert_eq!(e, Expr::Number(42.0));
+        assert_eq!(e, Expr::Number(42.0.into()));
     }
 
     #[test]
@@ -561,12 +861,12 @@ mod tests {
         match e {
             Expr::Binary(b) => {
                 assert_eq!(b.op, BinaryOp::Pow);
-                assert_eq!(b.lhs, Expr::Number(2.0));
+                assert_eq!(b.lhs, Expr::Number(2.0.into()));
                 match b.rhs {
                     Expr::Binary(inner) => {
                         assert_eq!(inner.op, BinaryOp::Pow);
-                        assert_eq!(inner.lhs, Expr::Number(3.0));
-                        assert_eq!(inner.rhs, Expr::Number(2.0));
+                        assert_eq!(inner.lhs, Expr::Number(3.0.into()));
+                        assert_eq!(inner.rhs, Expr::Number(2.0.into()));
                     }
                     _ => panic!("Expected inner Binary"),
                 }
@@ -582,12 +882,67 @@ mod tests {
         match e {
             Expr::Unary(u) => {
                 assert_eq!(u.op, UnaryOp::Minus);
-                assert_eq!(u.expr, Expr::Number(42.0));
+                assert_eq!(u.expr, Expr::Number(42.0.into()));
             }
             _ => panic!("Expected Unary"),
         }
     }
 
+    #[test]
+    fn test_parse_special_floats() {
+        fn number(e: &Expr) -> f64 {
+            match e {
+                Expr::Number(n) => n.value(),
+                other => panic!("Expected Number, got {:?}", other),
+            }
+        }
+
+        for input in ["Inf", "inf", "INF", "iNf"] {
+            let (rest, e) = expr(input).unwrap();
+            assert!(rest.is_empty());
+            assert_eq!(number(&e), f64::INFINITY, "{}", input);
+        }
+        for input in ["NaN", "nan", "NAN", "nAn"] {
+            let (rest, e) = expr(input).unwrap();
+            assert!(rest.is_empty());
+            assert!(number(&e).is_nan(), "{}", input);
+        }
+
+        // Signs are unary operators, like for `-42`
+        for (input, op) in [("-Inf", UnaryOp::Minus), ("+inf", UnaryOp::Plus)] {
+            let (rest, e) = expr(input).unwrap();
+            assert!(rest.is_empty());
+            match e {
+                Expr::Unary(u) => {
+                    assert_eq!(u.op, op);
+                    assert_eq!(number(&u.expr), f64::INFINITY);
+                }
+                other => panic!("Expected Unary for '{}', got {:?}", input, other),
+            }
+        }
+
+        let (_, e) = expr("x < Inf").unwrap();
+        match e {
+            Expr::Binary(b) => assert_eq!(number(&b.rhs), f64::INFINITY),
+            other => panic!("Expected Binary, got {:?}", other),
+        }
+        let (_, e) = expr("clamp_max(x, +Inf)").unwrap();
+        assert_eq!(e.to_string(), "clamp_max(x, +Inf)");
+        match e {
+            Expr::Call(c) => match &c.args[1] {
+                Expr::Unary(u) => assert_eq!(number(&u.expr), f64::INFINITY),
+                other => panic!("Expected Unary, got {:?}", other),
+            },
+            other => panic!("Expected Call, got {:?}", other),
+        }
+
+        // Longer names starting with inf or nan are metric names
+        for input in ["Infx", "inf_total", "nanos"] {
+            let (_, e) = expr(input).unwrap();
+            assert!(matches!(e, Expr::VectorSelector(_)), "{}", input);
+        }
+    }
+
     #[test]
     fn test_parse_unary_with_binary() {
         // -1 + 2 should parse as (-1) + 2
@@ -622,6 +977,23 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_fully_parenthesized_query() {
+        use crate::parser::options::{ParseOptions, parse_with_options};
+
+        let e =
+            parse_with_options("(sum(rate(x[5m])) by (job))", &ParseOptions::default()).unwrap();
+        assert!(matches!(e, Expr::Paren(_)));
+        match e.unwrap_parens() {
+            Expr::Aggregation(agg) => assert_eq!(agg.op, "sum"),
+            other => panic!("Expected Aggregation, got {:?}", other),
+        }
+
+        let e = parse_with_options("(((((some_metric)))))", &ParseOptions::default()).unwrap();
+        assert!(matches!(e.unwrap_parens(), Expr::VectorSelector(_)));
+        assert_eq!(e.to_string(), "(((((some_metric)))))");
+    }
+
     #[test]
     fn test_parse_paren_affects_precedence() {
         // (1 + 2) * 3 should parse differently than 1 + 2 * 3
@@ -756,7 +1128,7 @@ mod tests {
             Expr::Aggregation(a) => {
                 assert_eq!(a.op, "topk");
                 assert!(a.param.is_some());
-                assert_eq!(*a.param.as_ref().unwrap(), Expr::Number(5.0));
+                assert_eq!(*a.param.as_ref().unwrap(), Expr::Number(5.0.into()));
             }
             _ => panic!("Expected Aggregation"),
         }
@@ -774,6 +1146,39 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_mixed_indentation() {
+        let single_line = r#"sum by (job) (rate(http_requests_total{status=~"5..", job!="test"}[5m] offset 1m)) / on (job) group_left (team) sum by (job) (rate(http_requests_total[5m])) > bool 0.05 and on (job) max_over_time(up{env="prod"}[10m:1m]) == 1"#;
+        let formatted = concat!(
+            "sum by (\tjob )\t(\n",
+            "\t  rate(\n",
+            "\t\t    http_requests_total{\n",
+            "\t\t\t status=~\"5..\",\r\n",
+            "  \t\t  job!=\"test\"\n",
+            "\t\t    }[5m]\n",
+            "\t\t    offset 1m\n",
+            "\t  )\n",
+            ")\n",
+            "  /\ton (job)\n",
+            "\tgroup_left (\n",
+            "\t  team\n",
+            "\t)\n",
+            "sum by (job) (\r\n",
+            " \trate(http_requests_total[5m])\r\n",
+            ")\n",
+            "\t> bool\t0.05\n",
+            "and  on\t(job)\n",
+            "    max_over_time(\n",
+            "\t\tup{env=\"prod\"}[10m:1m]\n",
+            "    ) == 1\n",
+        );
+        let (rest, expected) = expr(single_line).unwrap();
+        assert!(rest.is_empty());
+        let (rest, e) = expr(formatted).unwrap();
+        assert_eq!(rest.trim(), "");
+        assert_eq!(e, expected);
+    }
+
     #[test]
     fn test_parse_subquery_with_both_modifiers() {
         // Test @ before offset
@@ -802,4 +1207,76 @@ mod tests {
             _ => panic!("Expected Subquery"),
         }
     }
+
+    #[test]
+    fn test_parse_subquery_and_inner_selector_at_modifiers() {
+        let (rest, e) = expr("rate(http[5m] @ 100)[30m:1m] @ 200").unwrap();
+        assert!(rest.is_empty());
+        let Expr::Subquery(s) = &e else {
+            panic!("Expected Subquery");
+        };
+        assert_eq!(s.at, Some(AtModifier::Timestamp(200_000)));
+        let Expr::Call(call) = &s.expr else {
+            panic!("Expected Call");
+        };
+        match &call.args[0] {
+            Expr::MatrixSelector(m) => {
+                assert_eq!(m.selector.at, Some(AtModifier::Timestamp(100_000)));
+            }
+            _ => panic!("Expected MatrixSelector"),
+        }
+    }
+
+    #[test]
+    fn test_parse_multiple_name_matchers() {
+        for (input, name, count) in [
+            (r#"{__name__="bar"}"#, Some("bar"), 0),
+            (r#"{__name__=~"bar", __name__!~"baz"}"#, None, 2),
+            (r#"{__name__="bar", __name__="baz"}"#, None, 2),
+            (r#"{"bar", __name__="baz"}"#, None, 2),
+        ] {
+            let (rest, e) = expr(input).unwrap();
+            assert!(rest.is_empty());
+            match e {
+                Expr::VectorSelector(v) => {
+                    assert_eq!(v.name.as_deref(), name, "name of {}", input);
+                    assert_eq!(v.matchers.len(), count, "matchers of {}", input);
+                }
+                _ => panic!("Expected VectorSelector for {}", input),
+            }
+        }
+    }
+
+    #[test]
+    fn test_error_kinds_carry_details() {
+        let input = "a * / b";
+        assert_eq!(
+            expr(input),
+            Err(nom::Err::Failure(SyntaxError::new(
+                &input[4..],
+                SyntaxErrorKind::UnexpectedOperator(BinaryOp::Div)
+            )))
+        );
+
+        let input = r#"foo{__name__="bar"}"#;
+        assert_eq!(
+            expr(input),
+            Err(nom::Err::Failure(SyntaxError::new(
+                &input[3..],
+                SyntaxErrorKind::MetricNameSetTwice {
+                    name: "foo".to_string(),
+                    value: "bar".to_string(),
+                }
+            )))
+        );
+
+        let input = "x @ 1e300";
+        assert_eq!(
+            expr(input),
+            Err(nom::Err::Failure(SyntaxError::new(
+                &input[4..],
+                SyntaxErrorKind::TimestampOutOfBounds("1e300".to_string())
+            )))
+        );
+    }
 }
@@ -28,6 +28,8 @@
 //! assert_eq!(func.min_args(), 1);
 //! ```
 
+use alloc::vec::Vec;
+
 /// Value types for function arguments and return values.
 ///
 /// PromQL has four fundamental value types that functions operate on.
@@ -43,8 +45,8 @@ pub enum ValueType {
     String,
 }
 
-impl std::fmt::Display for ValueType {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Display for ValueType {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             ValueType::Scalar => write!(f, "scalar"),
             ValueType::Vector => write!(f, "instant vector"),
@@ -727,6 +729,73 @@ pub fn is_function(name: &str) -> bool {
     get_function(name).is_some()
 }
 
+/// Aggregation operators, which are called like functions
+const AGGREGATION_OPERATORS: &[&str] = &[
+    "sum",
+    "avg",
+    "count",
+    "min",
+    "max",
+    "group",
+    "stddev",
+    "stdvar",
+    "topk",
+    "bottomk",
+    "count_values",
+    "quantile",
+    "limitk",
+    "limit_ratio",
+];
+
+/// Suggest the known function or aggregation operator closest to `name`.
+///
+/// Intended for "did you mean" hints on misspelled calls. Names are
+/// compared case-insensitively by edit distance, allowing one edit per three
+/// characters (at least one, at most three). Returns `None` when nothing is
+/// close enough or `name` is already a known function.
+///
+/// # Example
+///
+/// ```rust
+/// use rusty_promql_parser::parser::function::suggest_function;
+///
+/// assert_eq!(suggest_function("ratee"), Some("rate"));
+/// assert_eq!(suggest_function("hisogram_quantile"), Some("histogram_quantile"));
+/// assert_eq!(suggest_function("completely_unrelated"), None);
+/// ```
+pub fn suggest_function(name: &str) -> Option<&'static str> {
+    if is_function(name) {
+        return None;
+    }
+    let name = name.to_ascii_lowercase();
+    let max_distance = (name.chars().count() / 3).clamp(1, 3);
+    FUNCTIONS
+        .iter()
+        .map(|f| f.name)
+        .chain(AGGREGATION_OPERATORS.iter().copied())
+        .map(|candidate| (edit_distance(&name, candidate), candidate))
+        .filter(|&(distance, _)| distance <= max_distance)
+        // The first of equally close candidates wins
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein distance between two strings, counted in characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -784,4 +853,32 @@ mod tests {
             assert!(get_function(name).is_some(), "Missing function: {}", name);
         }
     }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("rate", ""), 4);
+        assert_eq!(edit_distance("rate", "rate"), 0);
+        assert_eq!(edit_distance("ratee", "rate"), 1);
+        assert_eq!(edit_distance("rtae", "rate"), 2);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn test_suggest_function() {
+        assert_eq!(suggest_function("ratee"), Some("rate"));
+        assert_eq!(suggest_function("RATE"), Some("rate"));
+        assert_eq!(
+            suggest_function("hisogram_quantile"),
+            Some("histogram_quantile")
+        );
+        assert_eq!(suggest_function("sum_ovr_time"), Some("sum_over_time"));
+        assert_eq!(suggest_function("topkk"), Some("topk"));
+        // Known functions need no suggestion
+        assert_eq!(suggest_function("rate"), None);
+        // Nothing close enough
+        assert_eq!(suggest_function("non_existent_function_far_bar"), None);
+        assert_eq!(suggest_function("xyz"), None);
+        assert_eq!(suggest_function("b"), None);
+    }
 }
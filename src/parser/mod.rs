@@ -9,9 +9,12 @@
 //! - [`binary`] - Binary operators and modifiers
 //! - [`mod@expr`] - Main expression parser
 //! - [`function`] - Built-in function definitions
+//! - [`options`] - Parser configuration
+//! - [`partial`] - Best-effort parsing of incomplete queries
 //! - [`selector`] - Vector and matrix selectors
 //! - [`subquery`] - Subquery expression parsing
 //! - [`unary`] - Unary operators
+//! - [`version`] - Prometheus versions and the features they introduced
 //!
 //! # Example
 //!
@@ -26,9 +29,12 @@ pub mod aggregation;
 pub mod binary;
 pub mod expr;
 pub mod function;
+pub mod options;
+pub mod partial;
 pub mod selector;
 pub mod subquery;
 pub mod unary;
+pub mod version;
 
 // Re-export the main expression parser
 pub use expr::expr;
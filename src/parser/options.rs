@@ -0,0 +1,789 @@
+//! Configuration and entry points for the high-level parsing API.
+//!
+//! [`ParseOptions`] lets callers such as query gateways put limits on the
+//! queries they accept or enable syntax extensions. The default options
+//! only limit the nesting depth (see [`ParseOptions::max_depth`]);
+//! [`expr()`](crate::parser::expr::expr) imposes no limits at all.
+//!
+//! [`parse`] and [`parse_with_options`] parse a complete query, while
+//! [`parse_prefix`] parses a query embedded at the start of a larger input.
+//!
+//! # Example
+//!
+//! ```rust
+//! use rusty_promql_parser::{ParseOptions, parse_with_options};
+//!
+//! let options = ParseOptions {
+//!     max_matchers_per_selector: Some(2),
+//!     ..ParseOptions::default()
+//! };
+//! assert!(parse_with_options(r#"up{a="1", b="2"}"#, &options).is_ok());
+//!
+//! let err = parse_with_options(r#"up{a="1", b="2", c="3"}"#, &options).unwrap_err();
+//! assert_eq!(err.message, "selector has more than 2 label matchers");
+//! ```
+
+use alloc::format;
+
+use alloc::string::ToString;
+
+use crate::ast::Expr;
+use crate::error::{ParseError, SyntaxError, SyntaxErrorKind};
+use crate::lexer::whitespace::ws_opt;
+use crate::parser::expr::{expr_with_options, parse_simple_selector};
+use crate::parser::version::PrometheusVersion;
+
+/// Nesting depth allowed by the default [`ParseOptions`]
+pub const DEFAULT_MAX_DEPTH: usize = 48;
+
+/// Options controlling what the parser accepts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Maximum number of label matchers inside the braces of a single
+    /// selector. The metric name in front of the braces is not counted.
+    /// `None` (the default) means unlimited.
+    pub max_matchers_per_selector: Option<usize>,
+    /// Accept dots and non-ASCII characters in bare metric names, e.g.
+    /// `app.requests_total`, as parsed by
+    /// [`utf8_metric_name`](crate::lexer::identifier::utf8_metric_name).
+    ///
+    /// Off by default: standard PromQL only allows such names quoted.
+    pub allow_utf8_metric_names: bool,
+    /// Accept a single `;` after the query in [`parse_with_options`], as
+    /// left behind by tools that paste queries as statements.
+    ///
+    /// Off by default: standard PromQL has no statement terminator.
+    pub allow_trailing_semicolon: bool,
+    /// Reject functions and syntax that this Prometheus version does not
+    /// support, see [`version`](crate::parser::version).
+    ///
+    /// `None` (the default) accepts everything this crate knows about.
+    pub prometheus_version: Option<PrometheusVersion>,
+    /// Maximum nesting depth of sub-expressions. Each unary operator,
+    /// binary operand, and expression inside parentheses, a function call
+    /// or an aggregation is one level deeper than its parent.
+    ///
+    /// The parser recurses once per level, so this bounds its stack usage
+    /// on hostile input like `((((...))))` or `----...---foo`. Defaults to
+    /// [`DEFAULT_MAX_DEPTH`], which fits a 2 MiB thread stack even in
+    /// unoptimized builds, where a nested function call costs about 32 KiB
+    /// of stack. `None` means unlimited, as in
+    /// [`expr()`](crate::parser::expr::expr).
+    pub max_depth: Option<usize>,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            max_matchers_per_selector: None,
+            allow_utf8_metric_names: false,
+            allow_trailing_semicolon: false,
+            prometheus_version: None,
+            max_depth: Some(DEFAULT_MAX_DEPTH),
+        }
+    }
+}
+
+/// Parse a complete PromQL query.
+///
+/// Unlike [`expr()`](crate::parser::expr::expr), which returns the input it
+/// did not consume, the whole query must be a single expression. Trailing
+/// whitespace and comments are allowed; anything else is an error at the
+/// offset where the expression ended.
+///
+/// This is [`parse_with_options`] with the default options.
+///
+/// # Example
+///
+/// ```rust
+/// use rusty_promql_parser::{ParseError, parse};
+///
+/// let ast = parse("sum(rate(http_requests_total[5m])) by (job)\n").unwrap();
+/// assert_eq!(ast.to_string(), "sum by (job) (rate(http_requests_total[5m]))");
+///
+/// let err = parse("up down").unwrap_err();
+/// assert_eq!(err, ParseError::new(3, "unexpected trailing input"));
+/// ```
+pub fn parse(input: &str) -> Result<Expr, ParseError> {
+    parse_with_options(input, &ParseOptions::default())
+}
+
+/// Parse a complete PromQL query, enforcing the limits set in `options`
+///
+/// Unlike [`expr_with_options`], the whole input (apart from trailing
+/// whitespace and comments, and a `;` if
+/// [`allow_trailing_semicolon`](ParseOptions::allow_trailing_semicolon) is
+/// set) must be consumed.
+pub fn parse_with_options(input: &str, options: &ParseOptions) -> Result<Expr, ParseError> {
+    if let Some(expr) = parse_simple_selector(input, options) {
+        return Ok(expr);
+    }
+    parse_general(input, options)
+}
+
+/// [`parse_with_options`] without the fast path for lone selectors
+fn parse_general(input: &str, options: &ParseOptions) -> Result<Expr, ParseError> {
+    let result = expr_with_options(input, options)
+        .and_then(|(rest, expr)| ws_opt(rest).map(|(rest, _)| (rest, expr)));
+    let result = result.and_then(|(rest, expr)| match rest.strip_prefix(';') {
+        Some(rest) if options.allow_trailing_semicolon => {
+            ws_opt(rest).map(|(rest, _)| (rest, expr))
+        }
+        _ => Ok((rest, expr)),
+    });
+    match result {
+        Ok(("", expr)) => Ok(expr),
+        Ok((rest, _)) => Err(ParseError::trailing(input, rest)),
+        Err(e) => Err(convert_error(input, e, options)),
+    }
+}
+
+/// Parse a PromQL expression at the start of `input`, returning it along
+/// with the number of bytes it spans.
+///
+/// Parsing stops at the first input that cannot continue the expression,
+/// so callers embedding PromQL in a larger grammar can resume at
+/// `&input[consumed..]`. Whitespace after the expression is not counted as
+/// consumed.
+///
+/// # Example
+///
+/// ```rust
+/// use rusty_promql_parser::parse_prefix;
+///
+/// let input = "sum(up) by (job); next statement";
+/// let (ast, consumed) = parse_prefix(input).unwrap();
+/// assert_eq!(ast.to_string(), "sum by (job) (up)");
+/// assert_eq!(&input[consumed..], "; next statement");
+/// ```
+pub fn parse_prefix(input: &str) -> Result<(Expr, usize), ParseError> {
+    let options = ParseOptions::default();
+    let (rest, expr) =
+        expr_with_options(input, &options).map_err(|e| convert_error(input, e, &options))?;
+    let consumed = input.len() - rest.len();
+    // Leave trailing whitespace to the caller
+    let consumed = input[..consumed].trim_end().len();
+    Ok((expr, consumed))
+}
+
+/// Convert a nom error into a [`ParseError`] located in `input`
+pub(crate) fn convert_error(
+    input: &str,
+    err: nom::Err<SyntaxError<&str>>,
+    options: &ParseOptions,
+) -> ParseError {
+    let e = match err {
+        nom::Err::Error(e) | nom::Err::Failure(e) => e,
+        nom::Err::Incomplete(_) => return ParseError::new(input.len(), "unexpected end of input"),
+    };
+    let offset = input.len() - e.input.len();
+    let message = match e.kind {
+        SyntaxErrorKind::Nom(_) => return ParseError::at(input, e.input),
+        SyntaxErrorKind::TooManyMatchers { max } => {
+            format!("selector has more than {} label matchers", max)
+        }
+        SyntaxErrorKind::TooDeep => format!(
+            "expression is nested more than {} levels deep",
+            options.max_depth.unwrap_or_default()
+        ),
+        SyntaxErrorKind::MetricNameSetTwice { name, value } => format!(
+            "metric name must not be set twice: \"{}\" or \"{}\"",
+            name, value
+        ),
+        SyntaxErrorKind::FloatDuration => {
+            return ParseError::new(offset, "expected duration").with_expected(["duration"]);
+        }
+        SyntaxErrorKind::ZeroDuration => "duration must be greater than 0".to_string(),
+        SyntaxErrorKind::RepeatedModifier(modifier) => {
+            format!("{} may not be set multiple times", modifier)
+        }
+        SyntaxErrorKind::MisplacedModifier(modifier) => format!(
+            "{} modifier must be preceded by an instant vector selector or range vector selector or a subquery",
+            modifier
+        ),
+        SyntaxErrorKind::TimestampOutOfBounds(timestamp) => {
+            format!("timestamp out of bounds for @ modifier: {}", timestamp)
+        }
+        SyntaxErrorKind::AggregationArguments { expected, got } => format!(
+            "wrong number of arguments for aggregate expression provided, expected {}, got {}",
+            expected, got
+        ),
+        SyntaxErrorKind::UnexpectedOperator(op) => {
+            return ParseError::new(
+                offset,
+                format!("expected operand, found operator '{}'", op.as_str()),
+            )
+            .with_expected(["expression"]);
+        }
+        SyntaxErrorKind::Unavailable {
+            feature,
+            name,
+            since,
+            target,
+        } => format!(
+            "{} \"{}\" is not available in Prometheus {}, it was added in {}",
+            feature, name, target, since
+        ),
+        SyntaxErrorKind::QuotedNamesUnavailable { target } => format!(
+            "quoted metric and label names are not available in Prometheus {}, they were added in {}",
+            target,
+            PrometheusVersion::V3_0
+        ),
+    };
+    ParseError::new(offset, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limited(max: usize) -> ParseOptions {
+        ParseOptions {
+            max_matchers_per_selector: Some(max),
+            ..ParseOptions::default()
+        }
+    }
+
+    #[test]
+    fn test_default_options_unlimited() {
+        let query = r#"up{a="1",b="2",c="3",d="4",e="5",f="6",g="7",h="8",i="9",j="10"}"#;
+        assert!(parse_with_options(query, &ParseOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn test_max_matchers_boundary() {
+        let options = limited(3);
+        assert!(parse_with_options(r#"up{a="1", b="2", c="3"}"#, &options).is_ok());
+
+        let err = parse_with_options(r#"up{a="1", b="2", c="3", d="4"}"#, &options).unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::new(2, "selector has more than 3 label matchers")
+        );
+    }
+
+    #[test]
+    fn test_max_matchers_metric_name_not_counted() {
+        let options = limited(1);
+        assert!(parse_with_options(r#"up{job="a"}"#, &options).is_ok());
+        // A `__name__` matcher inside the braces still counts
+        assert!(parse_with_options(r#"{__name__="up", job="a"}"#, &options).is_err());
+    }
+
+    #[test]
+    fn test_max_matchers_nested() {
+        let options = limited(1);
+        assert!(parse_with_options(r#"sum(rate(up{a="1"}[5m])) by (job)"#, &options).is_ok());
+
+        let err = parse_with_options(r#"sum(rate(up{a="1", b="2"}[5m])) by (job)"#, &options)
+            .unwrap_err();
+        assert_eq!(err.offset, 11);
+        assert!(parse_with_options(r#"1 + {a="1", b="2"}[5m:1m]"#, &options).is_err());
+    }
+
+    #[test]
+    fn test_parse_with_options_trailing_input() {
+        let err = parse_with_options("up )", &ParseOptions::default()).unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::new(3, "unexpected closing delimiter ')' at column 4")
+        );
+        assert!(parse_with_options("up # comment\n", &ParseOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn test_trailing_semicolon() {
+        let options = ParseOptions {
+            allow_trailing_semicolon: true,
+            ..ParseOptions::default()
+        };
+        let up = parse_with_options("up", &ParseOptions::default()).unwrap();
+        for query in ["up;", "up ;", "up;  \n", "  up ; # done\n"] {
+            assert_eq!(
+                parse_with_options(query, &options).unwrap(),
+                up,
+                "{}",
+                query
+            );
+        }
+        assert_eq!(
+            parse_with_options("sum(rate(x[5m]));", &options)
+                .unwrap()
+                .to_string(),
+            "sum(rate(x[5m]))"
+        );
+
+        let err = parse_with_options("up;", &ParseOptions::default()).unwrap_err();
+        assert_eq!(err.offset, 2);
+        for query in ["up;;", "up; down", ";", "up{;}"] {
+            assert!(parse_with_options(query, &options).is_err(), "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_prometheus_version() {
+        let target = |version| ParseOptions {
+            prometheus_version: Some(version),
+            ..ParseOptions::default()
+        };
+        let (v2_40, v2_54, v3_0) = (
+            target(PrometheusVersion::V2_40),
+            target(PrometheusVersion::V2_54),
+            target(PrometheusVersion::V3_0),
+        );
+
+        let query = r#"sort_by_label(sum by (job) (rate(x[5m])), "job")"#;
+        assert!(parse_with_options(query, &v2_54).is_ok());
+        let err = parse_with_options(query, &v2_40).unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::new(
+                0,
+                "function \"sort_by_label\" is not available in Prometheus 2.40, it was added in 2.47"
+            )
+        );
+
+        let query = "sum(x) / limitk(5, info(y))";
+        assert!(parse_with_options(query, &v3_0).is_ok());
+        let err = parse_with_options(query, &v2_54).unwrap_err();
+        assert_eq!(err.offset, 19);
+        assert_eq!(
+            parse_with_options(query, &v2_40).unwrap_err().message,
+            "aggregation \"limitk\" is not available in Prometheus 2.40, it was added in 2.54"
+        );
+
+        // A metric named like a newer function is not a call
+        assert!(parse_with_options("info + sort_by_label", &v2_40).is_ok());
+
+        for (query, offset) in [
+            (r#"{"app.requests"}"#, 1),
+            (r#"rate({"app.requests", job="a"}[5m])"#, 6),
+            (r#"{job="a", "app.requests"}"#, 10),
+            (r#"rate(x[5m]) + {"app.requests"}"#, 15),
+            (r#"up{"http.method"="GET"}"#, 3),
+            (r#"sum by ("http.method") (x)"#, 8),
+            (r#"sum(x) without (job, 'a.b')"#, 21),
+            (r#"a / on("a.b") b"#, 7),
+            (r#"a / ignoring(job) group_left(`a.b`) b"#, 29),
+        ] {
+            assert!(parse_with_options(query, &v3_0).is_ok(), "{}", query);
+            let err = parse_with_options(query, &v2_54).unwrap_err();
+            assert_eq!(
+                err,
+                ParseError::new(
+                    offset,
+                    "quoted metric and label names are not available in Prometheus 2.54, they were added in 3.0"
+                ),
+                "{}",
+                query
+            );
+        }
+        // Label values and __name__ matchers may hold any characters
+        assert!(parse_with_options(r#"{__name__="app.requests"}"#, &v2_40).is_ok());
+        assert!(parse_with_options(r#"up{job="a.b"}"#, &v2_40).is_ok());
+    }
+
+    #[test]
+    fn test_stray_closing_delimiters() {
+        let cases = [
+            ("}", 0, '}', 1),
+            (")", 0, ')', 1),
+            ("]", 0, ']', 1),
+            ("some}", 4, '}', 5),
+            ("sum(x))", 6, ')', 7),
+            ("x[5m]]", 5, ']', 6),
+            ("x{a=\"b\"}  }", 10, '}', 11),
+            ("sum(\n  x\n) )", 11, ')', 3),
+        ];
+        for (input, offset, delimiter, column) in cases {
+            let err = parse_with_options(input, &ParseOptions::default()).unwrap_err();
+            assert_eq!(
+                err,
+                ParseError::new(
+                    offset,
+                    format!(
+                        "unexpected closing delimiter {:?} at column {}",
+                        delimiter, column
+                    )
+                ),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let depth = |max| ParseOptions {
+            max_depth: Some(max),
+            ..ParseOptions::default()
+        };
+        // A lone operand is one level, each operator or parenthesis adds one
+        assert!(parse_with_options("up", &depth(1)).is_ok());
+        assert!(parse_with_options("-up", &depth(1)).is_err());
+        assert!(parse_with_options("-up", &depth(2)).is_ok());
+        assert!(parse_with_options("(up)", &depth(2)).is_ok());
+        assert!(parse_with_options("a + b + c + d", &depth(2)).is_ok());
+        assert!(parse_with_options("a ^ b ^ c", &depth(2)).is_err());
+        assert!(parse_with_options("sum(rate(x[5m]))", &depth(3)).is_ok());
+        assert!(parse_with_options("sum(rate(x[5m]))", &depth(2)).is_err());
+
+        let err = parse_with_options("((up))", &depth(2)).unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::new(2, "expression is nested more than 2 levels deep")
+        );
+
+        let parens = format!("{}up{}", "(".repeat(20), ")".repeat(20));
+        assert!(parse_with_options(&parens, &depth(21)).is_ok());
+        let err = parse_with_options(&parens, &depth(20)).unwrap_err();
+        assert_eq!(err.offset, 20);
+
+        // Unlimited depth, like `expr()`
+        let unlimited = ParseOptions {
+            max_depth: None,
+            ..ParseOptions::default()
+        };
+        assert!(parse_with_options(&parens, &unlimited).is_ok());
+        assert!(crate::parser::expr::expr(&parens).is_ok());
+    }
+
+    #[test]
+    fn test_metric_name_set_twice() {
+        let cases = [
+            (r#"foo{__name__="bar"}"#, 3, "foo", "bar"),
+            (r#"foo{"bar"}"#, 3, "foo", "bar"),
+            (
+                r#"rate(foo {job="a", __name__="bar"}[5m])"#,
+                9,
+                "foo",
+                "bar",
+            ),
+            (
+                r#"up + foo:bar{__name__="baz"} offset 5m"#,
+                12,
+                "foo:bar",
+                "baz",
+            ),
+        ];
+        for (query, offset, name, value) in cases {
+            let err = parse_with_options(query, &ParseOptions::default()).unwrap_err();
+            assert_eq!(
+                err,
+                ParseError::new(
+                    offset,
+                    format!(
+                        "metric name must not be set twice: \"{}\" or \"{}\"",
+                        name, value
+                    )
+                ),
+                "{}",
+                query
+            );
+        }
+
+        for query in [
+            r#"{__name__=~"bar", __name__!~"baz"}"#,
+            r#"{__name__="bar", __name__="baz"}"#,
+            r#"{"bar", __name__="baz"}"#,
+            r#"foo{__name__!="bar"}"#,
+            r#"foo{__name__=~"foo|bar"}"#,
+        ] {
+            assert!(
+                parse_with_options(query, &ParseOptions::default()).is_ok(),
+                "{}",
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn test_utf8_metric_names() {
+        let utf8 = ParseOptions {
+            allow_utf8_metric_names: true,
+            ..ParseOptions::default()
+        };
+        let cases = [
+            "app.requests_total",
+            r#"app.requests_total{job="a"}"#,
+            "rate(app.requests_total[5m])",
+            "sum by (job) (app.requests_total) / app.limit",
+            "métrique_température",
+            "a.b+c.d",
+        ];
+        for query in cases {
+            assert!(
+                parse_with_options(query, &ParseOptions::default()).is_err(),
+                "{}",
+                query
+            );
+            let e = parse_with_options(query, &utf8).unwrap();
+            assert_eq!(parse_with_options(&e.to_string(), &utf8).unwrap(), e);
+        }
+
+        let e = parse_with_options("app.x{job=\"a\"}[5m] offset 1m", &utf8).unwrap();
+        match e {
+            Expr::MatrixSelector(m) => assert_eq!(m.name(), Some("app.x")),
+            other => panic!("Expected MatrixSelector, got {:?}", other),
+        }
+
+        // A keyword followed by name characters is a metric name
+        let e = parse_with_options("sum.total", &utf8).unwrap();
+        assert!(matches!(e, Expr::VectorSelector(_)));
+        assert!(parse_with_options("sum(x)", &utf8).is_ok());
+
+        // Structural characters still end the name
+        assert!(parse_with_options("a.b c", &utf8).is_err());
+        assert!(parse_with_options(".5", &utf8).is_ok());
+    }
+
+    #[test]
+    fn test_float_durations_rejected() {
+        let cases = [
+            ("some_metric[1e3s]", 12),
+            ("some_metric[1.5e2m]", 12),
+            ("x[1.5m]", 2),
+            ("x[1h1.5m]", 4),
+            ("x[5m:1e3s]", 5),
+            ("x[1e3s:]", 2),
+            ("x offset 1e3s", 9),
+            ("rate(x[1e3s])", 7),
+        ];
+        for (input, offset) in cases {
+            let err = parse_with_options(input, &ParseOptions::default()).unwrap_err();
+            assert_eq!(
+                err,
+                ParseError::new(offset, "expected duration").with_expected(["duration"]),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_at_timestamp_out_of_bounds() {
+        let cases = [
+            ("foo @ +Inf", 6, "+Inf"),
+            ("foo @ -inf", 6, "-inf"),
+            ("foo @NaN offset 5m", 5, "NaN"),
+            ("foo offset 5m @ 1e300", 16, "1e300"),
+            ("foo[5m] @ -9.3e15", 10, "-9.3e15"),
+            ("x[1h:5m] @ Inf", 11, "Inf"),
+            ("rate(x[5m:] @ nan)", 14, "nan"),
+        ];
+        for (input, offset, timestamp) in cases {
+            let err = parse_with_options(input, &ParseOptions::default()).unwrap_err();
+            assert_eq!(
+                err,
+                ParseError::new(
+                    offset,
+                    format!("timestamp out of bounds for @ modifier: {}", timestamp)
+                ),
+                "{}",
+                input
+            );
+        }
+        // The largest timestamps that fit in i64 milliseconds
+        assert!(parse_with_options("foo @ 9.2e15", &ParseOptions::default()).is_ok());
+        assert!(parse_with_options("foo @ -9.2e15", &ParseOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn test_repeated_modifiers() {
+        let cases = [
+            ("foo offset 1s offset 2s", 14, "offset"),
+            ("foo @ 1 @ 2", 8, "@"),
+            ("foo @ 1 offset 1m @ end()", 18, "@"),
+            ("foo[5m] offset 1m @ 1 OFFSET 1m", 22, "offset"),
+            ("x[1h:] @ start() @ start()", 17, "@"),
+            ("foo offset 1s # comment\n offset 2s", 25, "offset"),
+        ];
+        for (input, offset, modifier) in cases {
+            let err = parse_with_options(input, &ParseOptions::default()).unwrap_err();
+            assert_eq!(
+                err,
+                ParseError::new(
+                    offset,
+                    format!("{} may not be set multiple times", modifier)
+                ),
+                "{}",
+                input
+            );
+        }
+        // One of each, in either order
+        for input in [
+            "foo @ 1 offset 1m",
+            "foo offset 1m @ 1",
+            "x[1h:] offset 1m @ end()",
+        ] {
+            assert!(
+                parse_with_options(input, &ParseOptions::default()).is_ok(),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_modifier_after_non_selector() {
+        let cases = [
+            ("1 offset 1d", 2, "offset"),
+            ("-1 offset 1d", 3, "offset"),
+            (r#""a" @ 5"#, 4, "@"),
+            ("rate(x[5m]) @ 1234", 12, "@"),
+            ("sum(x) offset 1m", 7, "offset"),
+            ("(foo) offset 5m", 6, "offset"),
+            ("foo + 2 @ start()", 8, "@"),
+        ];
+        for (input, offset, modifier) in cases {
+            let err = parse_with_options(input, &ParseOptions::default()).unwrap_err();
+            assert_eq!(
+                err,
+                ParseError::new(
+                    offset,
+                    format!(
+                        "{} modifier must be preceded by an instant vector selector or range vector selector or a subquery",
+                        modifier
+                    )
+                ),
+                "{}",
+                input
+            );
+        }
+        // Subqueries take modifiers, and a metric named offset is not one
+        assert!(parse_with_options("sum(x)[1h:] offset 1m", &ParseOptions::default()).is_ok());
+        assert!(parse_with_options("1 + offset", &ParseOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn test_consecutive_operators() {
+        // A binary operator followed by a unary one is valid
+        for (input, expected) in [
+            ("a + -b", "a + -b"),
+            ("a + +b", "a + +b"),
+            ("a * - -b", "a * --b"),
+        ] {
+            let e = parse_with_options(input, &ParseOptions::default()).unwrap();
+            assert_eq!(e.to_string(), expected);
+        }
+
+        for (input, offset, op) in [
+            ("a * / b", 4, "/"),
+            ("a + * b", 4, "*"),
+            ("a == != b", 5, "!="),
+            ("a / ^ b", 4, "^"),
+            ("a > bool >= b", 9, ">="),
+            ("a * on (job) % b", 13, "%"),
+            ("sum(a - (b * < c))", 13, "<"),
+        ] {
+            let err = parse_with_options(input, &ParseOptions::default()).unwrap_err();
+            assert_eq!(
+                err,
+                ParseError::new(offset, format!("expected operand, found operator '{}'", op))
+                    .with_expected(["expression"]),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_prefix() {
+        let input = "1 + 2 ; rest";
+        let (e, consumed) = parse_prefix(input).unwrap();
+        assert_eq!(e.to_string(), "1 + 2");
+        assert_eq!(consumed, 5);
+        assert_eq!(&input[consumed..], " ; rest");
+
+        let (_, consumed) = parse_prefix("rate(x[5m])").unwrap();
+        assert_eq!(consumed, 11);
+
+        // Stops before input that cannot continue the expression
+        let input = r#"up{job="a"} == 0 then"#;
+        let (_, consumed) = parse_prefix(input).unwrap();
+        assert_eq!(&input[..consumed], r#"up{job="a"} == 0"#);
+
+        let err = parse_prefix("; rest").unwrap_err();
+        assert_eq!(err, ParseError::new(0, "unexpected character ';'"));
+    }
+
+    #[test]
+    fn test_simple_selector_fast_path_matches_general_parser() {
+        let utf8 = ParseOptions {
+            allow_utf8_metric_names: true,
+            ..ParseOptions::default()
+        };
+        let queries = [
+            // Taken by the fast path
+            "up",
+            "  up \n",
+            "http_requests_total",
+            ":job:rate5m",
+            "a_b:c_1",
+            r#"up{job="api"}"#,
+            r#"up{job="api", code=~"5..", env!="", path!~'/x.*'}"#,
+            r#"up{job="a",}"#,
+            "up{}",
+            "up { job = `x` }",
+            "offset",
+            "by",
+            "bool",
+            "on",
+            "and",
+            "time",
+            "rate",
+            "start",
+            "infinity",
+            "summary",
+            // Left to the general parser
+            "",
+            " ",
+            "Inf",
+            "nan",
+            "sum",
+            "SUM",
+            "count_values",
+            "0x1f",
+            "1e5",
+            "up # comment",
+            "# comment\nup",
+            "up offset 5m",
+            "up @ 100",
+            "up[5m]",
+            "up[5m:]",
+            "up + 1",
+            "up{job=\"a\"} or down",
+            "up{job=\"a\"}[5m]",
+            "up{",
+            "up{job}",
+            "up{job=\"a\"",
+            "up}",
+            "up)",
+            "up;",
+            "rate()",
+            "app.requests_total",
+            "a\u{a0}",
+            "{job=\"a\"}",
+            r#"{"up"}"#,
+        ];
+        for options in [ParseOptions::default(), limited(1), utf8] {
+            for query in queries {
+                let general = parse_general(query, &options);
+                if let Some(fast) = parse_simple_selector(query, &options) {
+                    assert_eq!(Ok(fast), general, "{:?}", query);
+                }
+                // Compared as text since NaN != NaN
+                assert_eq!(
+                    format!("{:?}", parse_with_options(query, &options)),
+                    format!("{:?}", general),
+                    "{:?}",
+                    query
+                );
+            }
+        }
+        assert!(parse_simple_selector("up", &ParseOptions::default()).is_some());
+        assert!(parse_simple_selector(r#"up{job="a"}"#, &limited(1)).is_some());
+        assert!(parse_simple_selector(r#"up{a="1", b="2"}"#, &limited(1)).is_none());
+        assert!(parse_simple_selector("Inf", &ParseOptions::default()).is_none());
+    }
+}
@@ -0,0 +1,666 @@
+//! Best-effort parsing of incomplete queries.
+//!
+//! Editors offering completions need to understand queries the user is still
+//! typing, such as `sum(rate(http_`. [`parse_partial()`] never fails: it
+//! returns the AST of the largest prefix it could make sense of, the error at
+//! the point where parsing stopped, and a [`CompletionContext`] describing
+//! what is being typed at the end of the input.
+//!
+//! The partial AST is built by closing any unclosed strings and delimiters,
+//! filling in an empty label value where one is expected, and dropping
+//! trailing tokens until the result parses.
+//!
+//! # Example
+//!
+//! ```rust
+//! use rusty_promql_parser::parser::partial::{CursorPosition, parse_partial};
+//!
+//! let partial = parse_partial("sum(rate(http_");
+//! assert_eq!(partial.expr.unwrap().to_string(), "sum(rate(http_))");
+//! assert_eq!(partial.error.unwrap().offset, 14);
+//!
+//! let calls: Vec<_> = partial.context.calls.iter().map(|c| c.name.as_str()).collect();
+//! assert_eq!(calls, ["sum", "rate"]);
+//! assert_eq!(
+//!     partial.context.position,
+//!     CursorPosition::Expression { prefix: "http_".to_string() }
+//! );
+//! ```
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::ast::Expr;
+use crate::error::{ParseError, SyntaxError, SyntaxErrorKind};
+use crate::lexer::identifier::lookup_keyword;
+use crate::lexer::whitespace::ws_opt;
+use crate::parser::expr::expr_with_options;
+use crate::parser::options::{ParseOptions, convert_error};
+
+/// Result of [`parse_partial()`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartialParse {
+    /// AST of the largest prefix that could be parsed, if any
+    pub expr: Option<Expr>,
+    /// Where and why parsing stopped; `None` if the whole input parsed
+    pub error: Option<ParseError>,
+    /// What is being typed at the end of the input
+    pub context: CompletionContext,
+}
+
+/// Syntactic context at the end of the input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompletionContext {
+    /// Unclosed function calls and aggregations, outermost first
+    pub calls: Vec<CallContext>,
+    /// What kind of token is expected at the end of the input
+    pub position: CursorPosition,
+}
+
+/// An unclosed function call or aggregation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallContext {
+    /// Function or aggregation name as written
+    pub name: String,
+    /// Zero-based index of the argument being typed
+    pub arg_index: usize,
+}
+
+/// Kind of token expected at the end of the input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CursorPosition {
+    /// Start of an operand: a metric name, function, aggregation or literal.
+    /// `prefix` holds the part of the identifier typed so far.
+    Expression { prefix: String },
+    /// After a complete operand, where an operator or modifier may follow
+    AfterExpression,
+    /// Label name inside a selector's braces
+    LabelName {
+        metric: Option<String>,
+        prefix: String,
+    },
+    /// Label value inside a selector's braces, after the matching operator
+    LabelValue {
+        metric: Option<String>,
+        label: String,
+        prefix: String,
+    },
+    /// Label name inside a `by`, `without`, `on`, `ignoring`, `group_left`
+    /// or `group_right` clause
+    GroupingLabel { prefix: String },
+    /// Range or subquery duration inside brackets
+    Duration,
+    /// Anywhere else, e.g. inside a string argument
+    Other,
+}
+
+/// Parse a possibly incomplete query.
+///
+/// See the [module documentation](self) for how the partial AST is built.
+/// Nesting is limited as with [`ParseOptions::default()`]; deeper queries
+/// report that as the error.
+pub fn parse_partial(input: &str) -> PartialParse {
+    let context = completion_context(input);
+
+    let options = ParseOptions::default();
+    let (failed_at, too_deep) = match expr_with_options(input, &options) {
+        Ok((rest, e)) => {
+            let rest = ws_opt(rest).map_or(rest, |(rest, _)| rest);
+            if rest.is_empty() {
+                return PartialParse {
+                    expr: Some(e),
+                    error: None,
+                    context,
+                };
+            }
+            (input.len() - rest.len(), None)
+        }
+        Err(
+            err @ nom::Err::Failure(SyntaxError {
+                input: rest,
+                kind: SyntaxErrorKind::TooDeep,
+            }),
+        ) => (
+            input.len() - rest.len(),
+            Some(convert_error(input, err, &options)),
+        ),
+        Err(nom::Err::Error(e) | nom::Err::Failure(e)) => (input.len() - e.input.len(), None),
+        Err(nom::Err::Incomplete(_)) => (input.len(), None),
+    };
+
+    // Closing delimiters cannot make a query shallower, so only the part
+    // before too deep nesting is worth repairing
+    let repaired = match too_deep {
+        Some(_) => repair(&input[..failed_at]),
+        None => repair(input),
+    };
+    let cut = repaired.as_ref().map_or(0, |(_, cut)| *cut);
+    let error = too_deep.unwrap_or_else(|| ParseError::at(input, &input[failed_at.max(cut)..]));
+    PartialParse {
+        expr: repaired.map(|(e, _)| e),
+        error: Some(error),
+        context,
+    }
+}
+
+/// Kind of an unclosed delimiter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FrameKind {
+    Paren,
+    Grouping,
+    Braces,
+    Brackets,
+}
+
+impl FrameKind {
+    fn closer(self) -> char {
+        match self {
+            FrameKind::Paren | FrameKind::Grouping => ')',
+            FrameKind::Braces => '}',
+            FrameKind::Brackets => ']',
+        }
+    }
+}
+
+/// An unclosed delimiter found by [`scan`]
+#[derive(Debug)]
+struct Frame {
+    kind: FrameKind,
+    /// Function or aggregation for parens, metric name for braces
+    name: Option<String>,
+    /// Number of top-level commas seen so far
+    commas: usize,
+    /// Start of the current argument or matcher
+    segment_start: usize,
+}
+
+/// Delimiter and string state at the end of the input
+#[derive(Debug, Default)]
+struct Scan {
+    frames: Vec<Frame>,
+    /// Quote character and content start of an unclosed string
+    string: Option<(char, usize)>,
+    /// Whether the input ends with a backslash inside a string
+    escaped: bool,
+    /// Whether the input ends inside a `#` comment
+    in_comment: bool,
+}
+
+/// Track unclosed strings and delimiters without parsing
+fn scan(input: &str) -> Scan {
+    let mut scan = Scan::default();
+    // Aggregation whose `by`/`without` clause was just closed, with the
+    // offset right after the closing paren
+    let mut grouped_agg: Option<(String, usize)> = None;
+
+    for (i, c) in input.char_indices() {
+        if scan.in_comment {
+            scan.in_comment = c != '\n';
+            continue;
+        }
+        if let Some((quote, _)) = scan.string {
+            if scan.escaped {
+                scan.escaped = false;
+            } else if c == '\\' && quote != '`' {
+                scan.escaped = true;
+            } else if c == quote {
+                scan.string = None;
+            }
+            continue;
+        }
+
+        let before = &input[..i];
+        let (kind, name) = match c {
+            '"' | '\'' | '`' => {
+                scan.string = Some((c, i + 1));
+                continue;
+            }
+            '#' => {
+                scan.in_comment = true;
+                continue;
+            }
+            '(' => match trailing_ident(before) {
+                Some(kw) if is_grouping_keyword(kw) => {
+                    // `sum by (` - remember `sum` for the paren that follows
+                    let head = before.trim_end();
+                    let agg = trailing_ident(&head[..head.len() - kw.len()]);
+                    (FrameKind::Grouping, agg.map(str::to_string))
+                }
+                Some(name) if lookup_keyword(name).is_none_or(|k| k.is_aggregation()) => {
+                    (FrameKind::Paren, Some(name.to_string()))
+                }
+                Some(_) => (FrameKind::Paren, None),
+                None => match &grouped_agg {
+                    Some((agg, end)) if before.trim_end().len() == *end => {
+                        (FrameKind::Paren, Some(agg.clone()))
+                    }
+                    _ => (FrameKind::Paren, None),
+                },
+            },
+            '{' => (
+                FrameKind::Braces,
+                trailing_ident(before).map(str::to_string),
+            ),
+            '[' => (FrameKind::Brackets, None),
+            ')' | '}' | ']' => {
+                if let Some(frame) = scan.frames.pop()
+                    && frame.kind == FrameKind::Grouping
+                {
+                    grouped_agg = frame.name.map(|agg| (agg, i + 1));
+                }
+                continue;
+            }
+            ',' => {
+                if let Some(frame) = scan.frames.last_mut() {
+                    frame.commas += 1;
+                    frame.segment_start = i + 1;
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        scan.frames.push(Frame {
+            kind,
+            name,
+            commas: 0,
+            segment_start: i + 1,
+        });
+    }
+    scan
+}
+
+fn completion_context(input: &str) -> CompletionContext {
+    let scan = scan(input);
+    let calls = scan
+        .frames
+        .iter()
+        .filter(|f| f.kind == FrameKind::Paren)
+        .filter_map(|f| {
+            f.name.as_ref().map(|name| CallContext {
+                name: name.clone(),
+                arg_index: f.commas,
+            })
+        })
+        .collect();
+    CompletionContext {
+        calls,
+        position: cursor_position(input, &scan),
+    }
+}
+
+fn cursor_position(input: &str, scan: &Scan) -> CursorPosition {
+    if scan.in_comment {
+        return CursorPosition::Other;
+    }
+    let frame = scan.frames.last();
+
+    if let Some((_, content_start)) = scan.string {
+        return match frame {
+            Some(f) if f.kind == FrameKind::Braces => {
+                match split_matcher(&input[f.segment_start..content_start - 1]) {
+                    Some((label, "")) => CursorPosition::LabelValue {
+                        metric: f.name.clone(),
+                        label: label.to_string(),
+                        prefix: input[content_start..].to_string(),
+                    },
+                    _ => CursorPosition::Other,
+                }
+            }
+            _ => CursorPosition::Other,
+        };
+    }
+
+    match frame {
+        Some(f) if f.kind == FrameKind::Braces => {
+            let segment = &input[f.segment_start..];
+            match split_matcher(segment) {
+                Some((label, "")) => CursorPosition::LabelValue {
+                    metric: f.name.clone(),
+                    label: label.to_string(),
+                    prefix: String::new(),
+                },
+                Some(_) => CursorPosition::Other,
+                None => CursorPosition::LabelName {
+                    metric: f.name.clone(),
+                    prefix: segment.trim().to_string(),
+                },
+            }
+        }
+        Some(f) if f.kind == FrameKind::Grouping => CursorPosition::GroupingLabel {
+            prefix: input[f.segment_start..].trim().to_string(),
+        },
+        Some(f) if f.kind == FrameKind::Brackets => CursorPosition::Duration,
+        _ => expression_position(input),
+    }
+}
+
+fn expression_position(input: &str) -> CursorPosition {
+    let trimmed = input.trim_end();
+    let ident = trailing_ident(trimmed);
+
+    // Still typing an identifier
+    if trimmed.len() == input.len()
+        && let Some(prefix) = ident
+        && !prefix.starts_with(|c: char| c.is_ascii_digit())
+    {
+        return CursorPosition::Expression {
+            prefix: prefix.to_string(),
+        };
+    }
+
+    let after_operand = match ident {
+        // Binary operator keywords expect another operand
+        Some(word) => lookup_keyword(word).is_none_or(|k| k.is_aggregation()),
+        None => trimmed.ends_with([')', ']', '}', '"', '\'', '`']),
+    };
+    if after_operand {
+        CursorPosition::AfterExpression
+    } else {
+        CursorPosition::Expression {
+            prefix: String::new(),
+        }
+    }
+}
+
+/// Split a matcher like `job=~"a` into the label name and the text after the
+/// operator, or `None` if there is no operator yet
+fn split_matcher(segment: &str) -> Option<(&str, &str)> {
+    let op_start = segment.find(['=', '!'])?;
+    let label = segment[..op_start].trim();
+    let after_op = segment[op_start..].trim_start_matches(['=', '!', '~']);
+    Some((label, after_op.trim()))
+}
+
+/// Close unclosed strings and delimiters and fill in a missing label value
+fn complete(prefix: &str) -> String {
+    let scan = scan(prefix);
+    let mut completed = prefix.to_string();
+
+    if scan.in_comment {
+        completed.push('\n');
+    }
+    match cursor_position(prefix, &scan) {
+        CursorPosition::LabelName { prefix, .. } if !prefix.is_empty() => {
+            completed.push_str("=\"\"")
+        }
+        CursorPosition::LabelValue { .. } if scan.string.is_none() => completed.push_str("\"\""),
+        _ => {}
+    }
+    if let Some((quote, _)) = scan.string {
+        if scan.escaped {
+            completed.push('\\');
+        }
+        completed.push(quote);
+    }
+    for frame in scan.frames.iter().rev() {
+        completed.push(frame.kind.closer());
+    }
+    completed
+}
+
+/// Find the longest prefix of `input` that parses once completed.
+///
+/// Returns the AST and the length of the prefix without trailing whitespace.
+fn repair(input: &str) -> Option<(Expr, usize)> {
+    let mut end = input.len();
+    loop {
+        let prefix = &input[..end];
+        if let Ok((rest, e)) = expr_with_options(&complete(prefix), &ParseOptions::default())
+            && ws_opt(rest).is_ok_and(|(rest, _)| rest.is_empty())
+        {
+            return Some((e, prefix.trim_end().len()));
+        }
+        end = strip_last_token(prefix)?;
+        // An aggregation cut off before its arguments is not a metric name
+        if let Some(word) = trailing_ident(&input[..end])
+            && lookup_keyword(word).is_some_and(|k| k.is_aggregation())
+            && opens_aggregation(&input[end..])
+        {
+            end = input[..end].trim_end().len() - word.len();
+        }
+    }
+}
+
+/// Whether `rest` continues an aggregation: with its arguments or a
+/// `by`/`without` clause
+fn opens_aggregation(rest: &str) -> bool {
+    let rest = rest.trim_start();
+    let word = &rest[..rest.len() - rest.trim_start_matches(is_ident_char).len()];
+    rest.starts_with('(') || word.eq_ignore_ascii_case("by") || word.eq_ignore_ascii_case("without")
+}
+
+/// Length of `input` without its last token, or `None` if it has none
+fn strip_last_token(input: &str) -> Option<usize> {
+    let trimmed = input.trim_end();
+    let last = trimmed.chars().last()?;
+    let kept = if is_ident_char(last) || last == '.' {
+        trimmed.trim_end_matches(|c| is_ident_char(c) || c == '.')
+    } else if is_operator_char(last) {
+        trimmed.trim_end_matches(is_operator_char)
+    } else {
+        &trimmed[..trimmed.len() - last.len_utf8()]
+    };
+    Some(kept.len())
+}
+
+/// The identifier at the end of `input`, ignoring trailing whitespace
+fn trailing_ident(input: &str) -> Option<&str> {
+    let trimmed = input.trim_end();
+    let start = trimmed
+        .rfind(|c: char| !is_ident_char(c))
+        .map_or(0, |i| i + 1);
+    let ident = &trimmed[start..];
+    (!ident.is_empty()).then_some(ident)
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == ':'
+}
+
+fn is_operator_char(c: char) -> bool {
+    matches!(
+        c,
+        '+' | '-' | '*' | '/' | '%' | '^' | '=' | '!' | '<' | '>' | '~' | ',' | '@' | ':'
+    )
+}
+
+fn is_grouping_keyword(word: &str) -> bool {
+    [
+        "by",
+        "without",
+        "on",
+        "ignoring",
+        "group_left",
+        "group_right",
+    ]
+    .iter()
+    .any(|kw| word.eq_ignore_ascii_case(kw))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn partial_ast(input: &str) -> Option<String> {
+        parse_partial(input).expr.map(|e| e.to_string())
+    }
+
+    fn call_names(input: &str) -> Vec<(String, usize)> {
+        parse_partial(input)
+            .context
+            .calls
+            .into_iter()
+            .map(|c| (c.name, c.arg_index))
+            .collect()
+    }
+
+    #[test]
+    fn test_complete_query() {
+        let partial = parse_partial("sum(rate(x[5m]))");
+        assert_eq!(partial.expr.unwrap().to_string(), "sum(rate(x[5m]))");
+        assert_eq!(partial.error, None);
+        assert_eq!(partial.context.calls, vec![]);
+        assert_eq!(partial.context.position, CursorPosition::AfterExpression);
+    }
+
+    #[test]
+    fn test_metric_name_prefix_in_function() {
+        let partial = parse_partial("sum(rate(http_");
+        assert_eq!(
+            partial.expr.map(|e| e.to_string()).as_deref(),
+            Some("sum(rate(http_))")
+        );
+        assert_eq!(
+            partial.error,
+            Some(ParseError::new(14, "unexpected end of input"))
+        );
+        assert_eq!(
+            call_names("sum(rate(http_"),
+            vec![("sum".to_string(), 0), ("rate".to_string(), 0)]
+        );
+        assert_eq!(
+            partial.context.position,
+            CursorPosition::Expression {
+                prefix: "http_".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_after_binary_operator() {
+        let partial = parse_partial("foo + ");
+        assert_eq!(partial.expr.unwrap().to_string(), "foo");
+        assert_eq!(partial.error.unwrap().offset, 6);
+        assert_eq!(
+            partial.context.position,
+            CursorPosition::Expression {
+                prefix: String::new()
+            }
+        );
+        assert_eq!(
+            parse_partial("foo and ").context.position,
+            CursorPosition::Expression {
+                prefix: String::new()
+            }
+        );
+    }
+
+    #[test]
+    fn test_label_name() {
+        let partial = parse_partial("up{jo");
+        assert_eq!(partial.expr.unwrap().to_string(), r#"up{jo=""}"#);
+        assert_eq!(
+            partial.context.position,
+            CursorPosition::LabelName {
+                metric: Some("up".to_string()),
+                prefix: "jo".to_string()
+            }
+        );
+        assert_eq!(
+            parse_partial(r#"up{job="a", "#).context.position,
+            CursorPosition::LabelName {
+                metric: Some("up".to_string()),
+                prefix: String::new()
+            }
+        );
+    }
+
+    #[test]
+    fn test_label_value() {
+        assert_eq!(partial_ast("up{job="), Some(r#"up{job=""}"#.to_string()));
+        assert_eq!(
+            parse_partial("up{job=~").context.position,
+            CursorPosition::LabelValue {
+                metric: Some("up".to_string()),
+                label: "job".to_string(),
+                prefix: String::new()
+            }
+        );
+
+        let partial = parse_partial(r#"rate(up{job="ap"#);
+        assert_eq!(partial.expr.unwrap().to_string(), r#"rate(up{job="ap"})"#);
+        assert_eq!(
+            partial.context.position,
+            CursorPosition::LabelValue {
+                metric: Some("up".to_string()),
+                label: "job".to_string(),
+                prefix: "ap".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_grouping_labels() {
+        let partial = parse_partial("sum by (jo");
+        assert_eq!(
+            partial.context.position,
+            CursorPosition::GroupingLabel {
+                prefix: "jo".to_string()
+            }
+        );
+        assert_eq!(
+            call_names("sum by (job) (rate("),
+            vec![("sum".to_string(), 0), ("rate".to_string(), 0)]
+        );
+        assert_eq!(
+            parse_partial("a / on(").context.position,
+            CursorPosition::GroupingLabel {
+                prefix: String::new()
+            }
+        );
+    }
+
+    #[test]
+    fn test_argument_index() {
+        assert_eq!(call_names("topk(3, "), vec![("topk".to_string(), 1)]);
+        assert_eq!(
+            call_names(r#"label_replace(up, "dst", "#),
+            vec![("label_replace".to_string(), 2)]
+        );
+        assert_eq!(
+            parse_partial(r#"label_replace(up, "ds"#).context.position,
+            CursorPosition::Other
+        );
+    }
+
+    #[test]
+    fn test_duration() {
+        let partial = parse_partial("rate(x[5");
+        assert_eq!(partial.context.position, CursorPosition::Duration);
+        assert_eq!(partial.expr.unwrap().to_string(), "rate(x)");
+    }
+
+    #[test]
+    fn test_trailing_garbage() {
+        let partial = parse_partial("foo bar");
+        assert_eq!(partial.expr.unwrap().to_string(), "foo");
+        assert_eq!(
+            partial.error,
+            Some(ParseError::new(4, "unexpected character 'b'"))
+        );
+    }
+
+    #[test]
+    fn test_nothing_parses() {
+        let partial = parse_partial("");
+        assert_eq!(partial.expr, None);
+        assert_eq!(
+            partial.context.position,
+            CursorPosition::Expression {
+                prefix: String::new()
+            }
+        );
+        assert_eq!(partial_ast(")"), None);
+    }
+
+    #[test]
+    fn test_unclosed_aggregation_is_not_a_metric() {
+        assert_eq!(partial_ast("sum("), None);
+        assert_eq!(partial_ast("topk ("), None);
+        assert_eq!(partial_ast("sum by (job) ("), None);
+        assert_eq!(partial_ast("sum(sum(sum("), None);
+        assert_eq!(partial_ast("up + avg("), Some("up".to_string()));
+        // Without arguments it is a metric name
+        assert_eq!(partial_ast("up + avg"), Some("up + avg".to_string()));
+    }
+}
@@ -32,6 +32,9 @@
 //! | `=~`     | Regex match       | `path=~"/api/.*"`     |
 //! | `!~`     | Regex not match   | `status!~"5.."`       |
 //!
+//! Label names that are not valid identifiers are quoted, like metric names
+//! inside braces: `{"my.metric", "http.method"="GET"}`.
+//!
 //! # Modifiers
 //!
 //! Selectors can have optional modifiers:
@@ -57,21 +60,25 @@
 //! assert_eq!(sel.range_millis(), 5 * 60 * 1000);
 //! ```
 
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
 use nom::{
-    IResult, Parser,
+    Parser,
     branch::alt,
-    bytes::complete::tag,
+    bytes::complete::{tag, tag_no_case},
     character::complete::char,
     combinator::{map, opt, success},
     multi::separated_list1,
     sequence::{delimited, terminated},
 };
 
+use crate::error::{IResult, SyntaxError, SyntaxErrorKind};
 use crate::lexer::{
-    duration::{Duration, duration, signed_duration},
+    duration::{Duration, positive_duration, signed_duration},
     identifier::{label_name, metric_name},
     number::number,
-    string::string_literal,
+    string::{DoubleQuoted, string_literal},
     whitespace::ws_opt,
 };
 
@@ -86,6 +93,7 @@ use crate::lexer::{
 /// - `metric @ start()` - Pin to evaluation start
 /// - `metric @ end()` - Pin to evaluation end
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AtModifier {
     /// Pin to a specific Unix timestamp (in milliseconds).
     Timestamp(i64),
@@ -95,8 +103,8 @@ pub enum AtModifier {
     End,
 }
 
-impl std::fmt::Display for AtModifier {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Display for AtModifier {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             AtModifier::Timestamp(ts) => {
                 // Convert milliseconds to seconds with 3 decimal places
@@ -113,6 +121,7 @@ impl std::fmt::Display for AtModifier {
 ///
 /// Used in label matchers to specify how to compare label values.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LabelMatchOp {
     /// `=` - Exact string equality.
     Equal,
@@ -146,8 +155,8 @@ impl LabelMatchOp {
     }
 }
 
-impl std::fmt::Display for LabelMatchOp {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Display for LabelMatchOp {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "{}", self.as_str())
     }
 }
@@ -165,6 +174,7 @@ impl std::fmt::Display for LabelMatchOp {
 /// assert_eq!(matcher.to_string(), r#"job="prometheus""#);
 /// ```
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LabelMatcher {
     /// Label name (e.g., "job", "__name__").
     pub name: String,
@@ -185,6 +195,11 @@ impl LabelMatcher {
     }
 
     /// Check if this matcher matches the empty string
+    ///
+    /// Regexes are not compiled, so only common patterns are recognized:
+    /// `""`, `.*`, `^$` and `^.*$` match the empty string, while `.+` and
+    /// plain strings without regex syntax, like `foo`, do not. With any
+    /// other pattern, the matcher is assumed not to match the empty string.
     pub fn matches_empty(&self) -> bool {
         match self.op {
             LabelMatchOp::Equal => self.value.is_empty(),
@@ -199,25 +214,67 @@ impl LabelMatcher {
             }
             LabelMatchOp::RegexNotMatch => {
                 // !~ matches empty if the regex doesn't match ""
-                // ".+" requires at least one character, so it doesn't match ""
+                // ".+" and non-empty literals need at least one character
                 self.value == ".+"
+                    || (!self.value.is_empty()
+                        && !self.value.contains(|c| r"\.+*?()|[]{}^$".contains(c)))
             }
         }
     }
 }
 
-impl std::fmt::Display for LabelMatcher {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}{}\"{}\"",
-            self.name,
-            self.op,
-            self.value.escape_default()
-        )
+impl core::fmt::Display for LabelMatcher {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if is_bare(label_name, &self.name) {
+            write!(f, "{}", self.name)?;
+        } else {
+            write!(f, "{}", DoubleQuoted(&self.name))?;
+        }
+        write!(f, "{}{}", self.op, DoubleQuoted(&self.value))
     }
 }
 
+/// Check if `name` is entirely matched by the identifier parser `bare`, so
+/// it can be written without quotes
+fn is_bare(bare: fn(&str) -> IResult<&str, &str>, name: &str) -> bool {
+    bare(name).is_ok_and(|(rest, _)| rest.is_empty())
+}
+
+/// Write the metric name and label matchers of a selector. Metric names
+/// that are not valid identifiers, or that read as numbers like `Inf`, go
+/// quoted inside the braces, as in `{"my.metric", job="a"}`. A selector
+/// with neither is written as `{}`.
+fn write_name_and_matchers(
+    f: &mut core::fmt::Formatter<'_>,
+    name: Option<&str>,
+    matchers: &[LabelMatcher],
+) -> core::fmt::Result {
+    let quoted_name = match name {
+        Some(name)
+            if is_bare(metric_name, name)
+                && !number(name).is_ok_and(|(rest, _)| rest.is_empty()) =>
+        {
+            write!(f, "{}", name)?;
+            None
+        }
+        name => name,
+    };
+    if name.is_some() && quoted_name.is_none() && matchers.is_empty() {
+        return Ok(());
+    }
+    write!(f, "{{")?;
+    if let Some(name) = quoted_name {
+        write!(f, "{}", DoubleQuoted(name))?;
+    }
+    for (i, m) in matchers.iter().enumerate() {
+        if i > 0 || quoted_name.is_some() {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", m)?;
+    }
+    write!(f, "}}")
+}
+
 /// A vector selector expression (instant vector).
 ///
 /// Selects a set of time series with a single sample value for each
@@ -233,6 +290,7 @@ impl std::fmt::Display for LabelMatcher {
 /// assert_eq!(sel.to_string(), r#"http_requests_total{job="api"}"#);
 /// ```
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct VectorSelector {
     /// Metric name (optional if label matchers include `__name__`).
     pub name: Option<String>,
@@ -294,23 +352,82 @@ impl VectorSelector {
         // Check if any label matcher doesn't match empty
         self.matchers.iter().any(|m| !m.matches_empty())
     }
-}
 
-impl std::fmt::Display for VectorSelector {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if let Some(ref name) = self.name {
-            write!(f, "{}", name)?;
+    /// Roughly classify how many series this selector may select.
+    ///
+    /// This is a heuristic for query gateways, based on the matchers alone
+    /// and without knowledge of the actual data:
+    ///
+    /// - [`Low`](CardinalityClass::Low): the metric name and one of the
+    ///   per-target labels `instance` or `pod` are pinned with `=`, which
+    ///   usually leaves a handful of series.
+    /// - [`Medium`](CardinalityClass::Medium): the metric name is pinned and
+    ///   another label is narrowed with `=` or a regex other than `.*` and
+    ///   `.+`.
+    /// - [`High`](CardinalityClass::High): anything else, i.e. a metric name
+    ///   alone, a metric name with only negative or catch-all matchers, or
+    ///   no pinned metric name at all.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::parser::selector::{CardinalityClass, vector_selector};
+    ///
+    /// let (_, sel) = vector_selector(r#"up{instance="host:9100"}"#).unwrap();
+    /// assert_eq!(sel.cardinality_class(), CardinalityClass::Low);
+    ///
+    /// let (_, sel) = vector_selector("up").unwrap();
+    /// assert_eq!(sel.cardinality_class(), CardinalityClass::High);
+    /// ```
+    pub fn cardinality_class(&self) -> CardinalityClass {
+        let matchers = self.all_matchers();
+        let pins = |label: &str| {
+            matchers
+                .iter()
+                .any(|m| m.name == label && m.op == LabelMatchOp::Equal && !m.value.is_empty())
+        };
+        if !pins("__name__") {
+            return CardinalityClass::High;
         }
-        if !self.matchers.is_empty() {
-            write!(f, "{{")?;
-            for (i, m) in self.matchers.iter().enumerate() {
-                if i > 0 {
-                    write!(f, ", ")?;
+        if IDENTITY_LABELS.iter().any(|label| pins(label)) {
+            return CardinalityClass::Low;
+        }
+        let narrows = matchers.iter().any(|m| {
+            m.name != "__name__"
+                && match m.op {
+                    LabelMatchOp::Equal => !m.value.is_empty(),
+                    LabelMatchOp::RegexMatch => !m.matches_empty() && m.value != ".+",
+                    LabelMatchOp::NotEqual | LabelMatchOp::RegexNotMatch => false,
                 }
-                write!(f, "{}", m)?;
-            }
-            write!(f, "}}")?;
+        });
+        if narrows {
+            CardinalityClass::Medium
+        } else {
+            CardinalityClass::High
         }
+    }
+}
+
+/// Labels that identify a single scrape target
+const IDENTITY_LABELS: &[&str] = &["instance", "pod"];
+
+/// Coarse estimate of how many series a selector or query touches
+///
+/// See [`VectorSelector::cardinality_class`] for the rules. Classes are
+/// ordered from `Low` to `High`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CardinalityClass {
+    /// Likely a few series
+    Low,
+    /// Likely a subset of a metric's series
+    Medium,
+    /// Likely all series of a metric, or of several metrics
+    High,
+}
+
+impl core::fmt::Display for VectorSelector {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write_name_and_matchers(f, self.name.as_deref(), &self.matchers)?;
         // @ modifier comes before offset in PromQL
         if let Some(ref at) = self.at {
             write!(f, " {}", at)?;
@@ -337,6 +454,7 @@ impl std::fmt::Display for VectorSelector {
 /// assert_eq!(sel.to_string(), "http_requests[5m]");
 /// ```
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MatrixSelector {
     /// The underlying vector selector.
     pub selector: VectorSelector,
@@ -389,22 +507,10 @@ impl MatrixSelector {
     }
 }
 
-impl std::fmt::Display for MatrixSelector {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Display for MatrixSelector {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         // Write name and matchers without offset/at
-        if let Some(ref name) = self.selector.name {
-            write!(f, "{}", name)?;
-        }
-        if !self.selector.matchers.is_empty() {
-            write!(f, "{{")?;
-            for (i, m) in self.selector.matchers.iter().enumerate() {
-                if i > 0 {
-                    write!(f, ", ")?;
-                }
-                write!(f, "{}", m)?;
-            }
-            write!(f, "}}")?;
-        }
+        write_name_and_matchers(f, self.selector.name.as_deref(), &self.selector.matchers)?;
         // Write range
         write!(f, "[{}]", self.range)?;
         // Write @ modifier (if any) - comes before offset
@@ -421,12 +527,12 @@ impl std::fmt::Display for MatrixSelector {
 
 /// Parse a range duration in square brackets: `[5m]`, `[1h30m]`
 fn range_duration(input: &str) -> IResult<&str, Duration> {
-    delimited(char('['), duration, char(']')).parse(input)
+    delimited(char('['), positive_duration, char(']')).parse(input)
 }
 
 /// Parse the offset modifier keyword (case-insensitive)
 fn offset_keyword(input: &str) -> IResult<&str, &str> {
-    alt((tag("offset"), tag("OFFSET"), tag("Offset"))).parse(input)
+    tag_no_case("offset").parse(input)
 }
 
 /// Parse an offset modifier: `offset 5m`, `offset -1h`
@@ -463,6 +569,16 @@ pub fn offset_modifier(input: &str) -> IResult<&str, Duration> {
 /// The @ modifier allows pinning a query to a specific timestamp,
 /// or to the start/end of the evaluation range.
 ///
+/// Timestamps are given in seconds and stored in milliseconds. Like
+/// Prometheus (`math.Round(ts * 1000)`), extra precision is rounded to the
+/// nearest millisecond, with ties rounded away from zero: `3.3335` becomes
+/// 3334 ms and `-3.3335` becomes -3334 ms.
+///
+/// Timestamps that are infinite, NaN or out of range fail with a
+/// [`nom::Err::Failure`] of kind [`SyntaxErrorKind::TimestampOutOfBounds`]
+/// pointing at the timestamp, reported by [`parse`](crate::parse) as
+/// "timestamp out of bounds".
+///
 /// # Examples
 ///
 /// ```
@@ -485,28 +601,45 @@ pub fn at_modifier(input: &str) -> IResult<&str, AtModifier> {
     let (rest, _) = char('@')(rest)?;
     let (rest, _) = ws_opt(rest)?;
 
-    // Try start() or end() first
-    if let Ok((rest, _)) = tag::<&str, &str, nom::error::Error<&str>>("start()")(rest) {
+    // Try start() or end() first (keywords are case-insensitive)
+    if let Ok((rest, _)) = tag_no_case::<&str, &str, SyntaxError<&str>>("start()")(rest) {
         return Ok((rest, AtModifier::Start));
     }
-    if let Ok((rest, _)) = tag::<&str, &str, nom::error::Error<&str>>("end()")(rest) {
+    if let Ok((rest, _)) = tag_no_case::<&str, &str, SyntaxError<&str>>("end()")(rest) {
         return Ok((rest, AtModifier::End));
     }
 
     // Otherwise parse a number (timestamp in seconds)
+    let timestamp = rest;
     let (rest, ts) = number(rest)?;
 
-    // Check for invalid timestamps (Inf, NaN)
-    if ts.is_infinite() || ts.is_nan() {
-        return Err(nom::Err::Error(nom::error::Error::new(
-            input,
-            nom::error::ErrorKind::Verify,
+    // Like Prometheus, reject timestamps that do not fit in i64
+    // milliseconds, including Inf and NaN
+    let ts_ms = ts * 1000.0;
+    if !(ts_ms > i64::MIN as f64 && ts_ms < i64::MAX as f64) {
+        let written = &timestamp[..timestamp.len() - rest.len()];
+        return Err(nom::Err::Failure(SyntaxError::new(
+            timestamp,
+            SyntaxErrorKind::TimestampOutOfBounds(written.to_string()),
         )));
     }
 
     // Convert seconds to milliseconds, rounding to nearest
-    let ts_ms = (ts * 1000.0).round() as i64;
-    Ok((rest, AtModifier::Timestamp(ts_ms)))
+    Ok((rest, AtModifier::Timestamp(round_to_i64(ts_ms))))
+}
+
+/// Round to the nearest integer, away from zero on ties, like `f64::round`
+/// (which is not available without `std`). Out of range values saturate.
+fn round_to_i64(x: f64) -> i64 {
+    let truncated = x as i64;
+    let fract = x - truncated as f64;
+    if fract >= 0.5 {
+        truncated.saturating_add(1)
+    } else if fract <= -0.5 {
+        truncated.saturating_sub(1)
+    } else {
+        truncated
+    }
 }
 
 /// Parse a matrix selector (range vector)
@@ -544,6 +677,9 @@ pub fn matrix_selector(input: &str) -> IResult<&str, MatrixSelector> {
 
 /// Parse @ and offset modifiers in any order.
 /// Returns (at_modifier, offset_modifier)
+///
+/// Each modifier may appear once; a second one fails with
+/// [`SyntaxErrorKind::RepeatedModifier`] at that modifier.
 pub(crate) fn parse_modifiers(
     input: &str,
 ) -> IResult<&str, (Option<AtModifier>, Option<Duration>)> {
@@ -552,28 +688,32 @@ pub(crate) fn parse_modifiers(
     let mut offset = None;
 
     loop {
-        if let Ok((next, parsed_at)) = at_modifier(rest) {
-            if at.is_some() {
-                return Err(nom::Err::Error(nom::error::Error::new(
-                    rest,
-                    nom::error::ErrorKind::Verify,
-                )));
+        match at_modifier(rest) {
+            Ok((next, parsed_at)) => {
+                if at.is_some() {
+                    return Err(repeated_modifier(rest, "@"));
+                }
+                at = Some(parsed_at);
+                rest = next;
+                continue;
             }
-            at = Some(parsed_at);
-            rest = next;
-            continue;
+            // An out of bounds timestamp cannot be anything else
+            Err(e @ nom::Err::Failure(_)) => return Err(e),
+            Err(_) => {}
         }
 
-        if let Ok((next, parsed_offset)) = offset_modifier(rest) {
-            if offset.is_some() {
-                return Err(nom::Err::Error(nom::error::Error::new(
-                    rest,
-                    nom::error::ErrorKind::Verify,
-                )));
+        match offset_modifier(rest) {
+            Ok((next, parsed_offset)) => {
+                if offset.is_some() {
+                    return Err(repeated_modifier(rest, "offset"));
+                }
+                offset = Some(parsed_offset);
+                rest = next;
+                continue;
             }
-            offset = Some(parsed_offset);
-            rest = next;
-            continue;
+            // An invalid duration after `offset` cannot be anything else
+            Err(e @ nom::Err::Failure(_)) => return Err(e),
+            Err(_) => {}
         }
 
         break;
@@ -582,7 +722,20 @@ pub(crate) fn parse_modifiers(
     Ok((rest, (at, offset)))
 }
 
+/// The error for `modifier`, which was already set, located at the modifier
+/// after any whitespace and comments in `input`
+fn repeated_modifier<'a>(input: &'a str, modifier: &'static str) -> nom::Err<SyntaxError<&'a str>> {
+    let rest = ws_opt(input).map_or(input, |(rest, _)| rest);
+    nom::Err::Failure(SyntaxError::new(
+        rest,
+        SyntaxErrorKind::RepeatedModifier(modifier),
+    ))
+}
+
 /// Parse a label match operator
+///
+/// Two-character operators must be tried before `=`, or `=~` would be read
+/// as `=` followed by a stray `~`.
 fn label_match_op(input: &str) -> IResult<&str, LabelMatchOp> {
     alt((
         map(tag("!="), |_| LabelMatchOp::NotEqual),
@@ -593,20 +746,26 @@ fn label_match_op(input: &str) -> IResult<&str, LabelMatchOp> {
     .parse(input)
 }
 
-/// Parse a single label matcher: `label_name op "value"`
+/// Parse a single label matcher: `label_name op "value"`, where the label
+/// name may also be quoted, as in `"http.method"="GET"`
 fn label_matcher(input: &str) -> IResult<&str, LabelMatcher> {
+    matcher_with_name(alt((label_name.map(ToString::to_string), string_literal))).parse(input)
+}
+
+/// Parse a single label matcher with a bare label name, as before
+/// Prometheus 3.0
+fn bare_label_matcher(input: &str) -> IResult<&str, LabelMatcher> {
+    matcher_with_name(label_name.map(ToString::to_string)).parse(input)
+}
+
+/// Parse a label matcher whose label name is parsed by `name`
+fn matcher_with_name<'a>(
+    name: impl Parser<&'a str, Output = String, Error = SyntaxError<&'a str>>,
+) -> impl Parser<&'a str, Output = LabelMatcher, Error = SyntaxError<&'a str>> {
     map(
-        (
-            ws_opt,
-            label_name,
-            ws_opt,
-            label_match_op,
-            ws_opt,
-            string_literal,
-        ),
-        |(_, name, _, op, _, value)| LabelMatcher::new(name.to_string(), op, value),
+        (ws_opt, name, ws_opt, label_match_op, ws_opt, string_literal),
+        |(_, name, _, op, _, value)| LabelMatcher::new(name, op, value),
     )
-    .parse(input)
 }
 
 /// Parse a quoted metric name as a matcher: `"metric_name"` inside braces
@@ -624,18 +783,32 @@ fn matcher_item(input: &str) -> IResult<&str, LabelMatcher> {
 
 /// Parse label matchers inside braces: `{label="value", ...}`
 pub fn label_matchers(input: &str) -> IResult<&str, Vec<LabelMatcher>> {
+    braced_matchers(matcher_item).parse(input)
+}
+
+/// Parse label matchers inside braces without quoted metric or label
+/// names, as before Prometheus 3.0.
+///
+/// Fails at the first quoted name.
+pub(crate) fn unquoted_label_matchers(input: &str) -> IResult<&str, Vec<LabelMatcher>> {
+    braced_matchers(bare_label_matcher).parse(input)
+}
+
+/// Parse a braced, comma-separated list of matchers parsed by `item`
+fn braced_matchers<'a>(
+    item: fn(&'a str) -> IResult<&'a str, LabelMatcher>,
+) -> impl Parser<&'a str, Output = Vec<LabelMatcher>, Error = SyntaxError<&'a str>> {
     delimited(
         (char('{'), ws_opt),
         alt((
             terminated(
-                separated_list1(delimited(ws_opt, char(','), ws_opt), matcher_item),
+                separated_list1(delimited(ws_opt, char(','), ws_opt), item),
                 opt((ws_opt, char(','))),
             ),
             success(Vec::new()),
         )),
         (ws_opt, char('}')),
     )
-    .parse(input)
 }
 
 /// Parse a vector selector
@@ -645,6 +818,8 @@ pub fn label_matchers(input: &str) -> IResult<&str, Vec<LabelMatcher>> {
 /// - `metric_name{label="value"}` - Metric with label matchers
 /// - `{label="value"}` - Label matchers only
 /// - `{"metric_name"}` - Quoted metric name in braces
+/// - `{"label.name"="value"}` - Quoted label name, for names that are not
+///   valid identifiers
 ///
 /// # Examples
 ///
@@ -670,6 +845,50 @@ pub fn vector_selector(input: &str) -> IResult<&str, VectorSelector> {
     .parse(input)
 }
 
+/// Promote a `__name__="..."` matcher of a braces-only selector to the
+/// selector's metric name.
+///
+/// Only a single `__name__` equality matcher is promoted. Selectors like
+/// `{__name__="a", __name__="b"}` are valid (and match nothing), so all of
+/// their matchers are kept as-is.
+pub(crate) fn split_metric_name(
+    matchers: Vec<LabelMatcher>,
+) -> (Option<String>, Vec<LabelMatcher>) {
+    let is_name_eq = |m: &LabelMatcher| m.name == "__name__" && m.op == LabelMatchOp::Equal;
+    if matchers.iter().filter(|m| is_name_eq(m)).count() != 1 {
+        return (None, matchers);
+    }
+    let (names, others): (Vec<_>, Vec<_>) = matchers.into_iter().partition(is_name_eq);
+    (names.into_iter().next().map(|m| m.value), others)
+}
+
+/// Fail if `matchers`, the label matchers of a selector with the metric
+/// name `name` in front of the braces, set the metric name again, as in
+/// `foo{__name__="bar"}` or `foo{"bar"}`. `input` is where the braces
+/// start.
+///
+/// Other `__name__` matchers, like `foo{__name__!="bar"}`, are allowed, as
+/// are any number of `__name__` matchers in braces-only selectors.
+pub(crate) fn check_name_not_repeated<'a>(
+    input: &'a str,
+    name: &str,
+    matchers: &[LabelMatcher],
+) -> Result<(), nom::Err<SyntaxError<&'a str>>> {
+    if let Some(m) = matchers
+        .iter()
+        .find(|m| m.name == "__name__" && m.op == LabelMatchOp::Equal)
+    {
+        return Err(nom::Err::Failure(SyntaxError::new(
+            input,
+            SyntaxErrorKind::MetricNameSetTwice {
+                name: name.to_string(),
+                value: m.value.clone(),
+            },
+        )));
+    }
+    Ok(())
+}
+
 /// Parse a vector selector without offset modifier.
 /// This is used internally by matrix_selector which handles offset after the range.
 pub fn base_vector_selector(input: &str) -> IResult<&str, VectorSelector> {
@@ -679,12 +898,14 @@ pub fn base_vector_selector(input: &str) -> IResult<&str, VectorSelector> {
     match name_result {
         Ok((rest, name)) => {
             // Check for label matchers
-            let (rest, matchers) = opt(label_matchers).parse(rest)?;
+            let (after, matchers) = opt(label_matchers).parse(rest)?;
+            let matchers = matchers.unwrap_or_default();
+            check_name_not_repeated(rest, name, &matchers)?;
             Ok((
-                rest,
+                after,
                 VectorSelector {
                     name: Some(name.to_string()),
-                    matchers: matchers.unwrap_or_default(),
+                    matchers,
                     offset: None,
                     at: None,
                 },
@@ -694,21 +915,7 @@ pub fn base_vector_selector(input: &str) -> IResult<&str, VectorSelector> {
             // No metric name, try label matchers only
             let (rest, matchers) = label_matchers(input)?;
 
-            // Check if any matcher is a __name__ matcher (quoted metric name)
-            let name = matchers
-                .iter()
-                .find(|m| m.name == "__name__" && m.op == LabelMatchOp::Equal)
-                .map(|m| m.value.clone());
-
-            // Filter out the __name__= matcher that we're using as the name
-            let other_matchers: Vec<_> = if name.is_some() {
-                matchers
-                    .into_iter()
-                    .filter(|m| !(m.name == "__name__" && m.op == LabelMatchOp::Equal))
-                    .collect()
-            } else {
-                matchers
-            };
+            let (name, other_matchers) = split_metric_name(matchers);
 
             Ok((
                 rest,
@@ -736,6 +943,56 @@ mod tests {
         assert_eq!(label_match_op("!~").unwrap().1, LabelMatchOp::RegexNotMatch);
     }
 
+    #[test]
+    fn test_label_match_op_longest_match() {
+        // `=~` must not be read as `=` followed by `~`
+        assert_eq!(
+            label_match_op(r#"=~"b""#),
+            Ok((r#""b""#, LabelMatchOp::RegexMatch))
+        );
+        assert_eq!(
+            label_match_op(r#"!~"b""#),
+            Ok((r#""b""#, LabelMatchOp::RegexNotMatch))
+        );
+        assert_eq!(
+            label_match_op(r#"=="b""#),
+            Ok((r#"="b""#, LabelMatchOp::Equal))
+        );
+    }
+
+    #[test]
+    fn test_adjacent_operator_selectors() {
+        for (input, op, value) in [
+            (r#"{a=~"b"}"#, LabelMatchOp::RegexMatch, "b"),
+            (r#"{a!~"b"}"#, LabelMatchOp::RegexNotMatch, "b"),
+            (r#"{a!="b"}"#, LabelMatchOp::NotEqual, "b"),
+            (r#"{a="~b"}"#, LabelMatchOp::Equal, "~b"),
+            (r#"{a=~"~"}"#, LabelMatchOp::RegexMatch, "~"),
+            (r#"{a=~"=~"}"#, LabelMatchOp::RegexMatch, "=~"),
+        ] {
+            let (rest, sel) = vector_selector(input).unwrap();
+            assert!(rest.is_empty(), "{}", input);
+            assert_eq!(
+                sel.matchers,
+                [LabelMatcher::new("a", op, value)],
+                "{}",
+                input
+            );
+        }
+
+        for input in [
+            "{a==}",
+            r#"{a=="b"}"#,
+            r#"{a= ~"b"}"#,
+            r#"{a=~~"b"}"#,
+            r#"{a~="b"}"#,
+            r#"{a=!"b"}"#,
+            r#"{a!"b"}"#,
+        ] {
+            assert!(vector_selector(input).is_err(), "{}", input);
+        }
+    }
+
     #[test]
     fn test_label_match_op_display() {
         assert_eq!(LabelMatchOp::Equal.to_string(), "=");
@@ -810,6 +1067,10 @@ mod tests {
         assert!(!LabelMatcher::new("a", LabelMatchOp::RegexMatch, ".+").matches_empty());
         // Not regex .+ matches empty
         assert!(LabelMatcher::new("a", LabelMatchOp::RegexNotMatch, ".+").matches_empty());
+        // Not a literal matches empty, not a pattern that may match "" doesn't
+        assert!(LabelMatcher::new("a", LabelMatchOp::RegexNotMatch, "foo").matches_empty());
+        assert!(!LabelMatcher::new("a", LabelMatchOp::RegexNotMatch, ".*").matches_empty());
+        assert!(!LabelMatcher::new("a", LabelMatchOp::RegexNotMatch, "foo|").matches_empty());
     }
 
     // VectorSelector tests
@@ -1170,6 +1431,56 @@ mod tests {
         assert_eq!(at, AtModifier::Timestamp(3_330));
     }
 
+    #[test]
+    fn test_at_modifier_rounding() {
+        // Must match Prometheus' `math.Round(ts * 1000)`
+        for (input, expected) in [
+            (" @ 3.3335", 3_334),
+            (" @ 3.3334", 3_333),
+            (" @ 3.0005", 3_001),
+            (" @ 3.0004999", 3_000),
+            (" @ -3.3335", -3_334),
+            (" @ -3.0005", -3_001),
+            (" @ 0.0005", 1),
+            (" @ 0.0004", 0),
+            (" @ 1603774568.1235", 1_603_774_568_124),
+        ] {
+            let (_, at) = at_modifier(input).unwrap();
+            assert_eq!(at, AtModifier::Timestamp(expected), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_cardinality_class() {
+        let cases = [
+            (r#"up{instance="host:9100"}"#, CardinalityClass::Low),
+            (r#"up{job="node", pod="api-0"}"#, CardinalityClass::Low),
+            (r#"{__name__="up", instance="a"}"#, CardinalityClass::Low),
+            (r#"up{job="node"}"#, CardinalityClass::Medium),
+            (r#"up{job=~"node|api"}"#, CardinalityClass::Medium),
+            (r#"up{instance=~"host-.*"}"#, CardinalityClass::Medium),
+            ("up", CardinalityClass::High),
+            (r#"up{instance=""}"#, CardinalityClass::High),
+            (
+                r#"up{job!="node", env=~".*", pod=~".+"}"#,
+                CardinalityClass::High,
+            ),
+            (r#"{instance="host:9100"}"#, CardinalityClass::High),
+            (r#"{__name__=~"http_.*", pod="a"}"#, CardinalityClass::High),
+        ];
+        for (input, expected) in cases {
+            let (_, sel) = vector_selector(input).unwrap();
+            assert_eq!(sel.cardinality_class(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_round_to_i64_matches_f64_round() {
+        for x in [0.0, 0.4, 0.5, 1.5, 2.5, -0.5, -1.5, -2.4, 1e300, -1e300] {
+            assert_eq!(round_to_i64(x), x.round() as i64, "{}", x);
+        }
+    }
+
     #[test]
     fn test_at_modifier_start() {
         let (rest, at) = at_modifier(" @ start()").unwrap();
@@ -1287,4 +1598,78 @@ mod tests {
         let sel = MatrixSelector::new(vs, Duration::from_secs(300));
         assert_eq!(sel.to_string(), "foo[5m] @ start() offset 1m");
     }
+
+    #[test]
+    fn test_display_quotes_names() {
+        let matcher = LabelMatcher::new("http.method", LabelMatchOp::Equal, "GET");
+        assert_eq!(matcher.to_string(), r#""http.method"="GET""#);
+        let matcher = LabelMatcher::new("a\"b", LabelMatchOp::RegexMatch, "x");
+        assert_eq!(matcher.to_string(), r#""a\"b"=~"x""#);
+        let matcher = LabelMatcher::new("", LabelMatchOp::Equal, "x");
+        assert_eq!(matcher.to_string(), r#"""="x""#);
+
+        let mut sel = VectorSelector::new("my.metric");
+        assert_eq!(sel.to_string(), r#"{"my.metric"}"#);
+        sel.add_matcher(LabelMatcher::new("job", LabelMatchOp::Equal, "a"));
+        sel.offset = Some(Duration::from_secs(60));
+        assert_eq!(sel.to_string(), r#"{"my.metric", job="a"} offset 1m"#);
+        let matrix = MatrixSelector {
+            selector: sel,
+            range: Duration::from_secs(300),
+        };
+        assert_eq!(
+            matrix.to_string(),
+            r#"{"my.metric", job="a"}[5m] offset 1m"#
+        );
+
+        // Every output parses back to the same selector
+        for input in [
+            r#"{"my.metric"}"#,
+            r#"{"a b", "c\nd"!~"x"}"#,
+            r#"foo:bar{"0label"="x", NaN="y"}"#,
+            r#"{"über"="x"}[5m]"#,
+            r#"{"Inf"}"#,
+            r#"{"nan", job="a"}"#,
+            "{}",
+        ] {
+            let (rest, e) = crate::parser::expr::expr(input).unwrap();
+            assert!(rest.is_empty());
+            assert_eq!(e.to_string(), input);
+        }
+    }
+
+    #[test]
+    fn test_multiple_name_matchers() {
+        // A single __name__ equality becomes the metric name
+        let (_, sel) = vector_selector(r#"{__name__="bar", job="a"}"#).unwrap();
+        assert_eq!(sel.name, Some("bar".to_string()));
+        assert_eq!(sel.matchers.len(), 1);
+
+        // Regex __name__ matchers stay regular matchers
+        let (_, sel) = vector_selector(r#"{__name__=~"bar", __name__!~"baz"}"#).unwrap();
+        assert_eq!(sel.name, None);
+        assert_eq!(sel.matchers.len(), 2);
+
+        // Two equalities are kept, not collapsed into one name
+        let (_, sel) = vector_selector(r#"{__name__="bar", __name__="baz"}"#).unwrap();
+        assert_eq!(sel.name, None);
+        assert_eq!(sel.matchers.len(), 2);
+        assert_eq!(sel.to_string(), r#"{__name__="bar", __name__="baz"}"#);
+
+        let (_, sel) = vector_selector(r#"{"bar", __name__="baz"}"#).unwrap();
+        assert_eq!(sel.name, None);
+        assert_eq!(
+            sel.matchers,
+            vec![
+                LabelMatcher::new("__name__", LabelMatchOp::Equal, "bar"),
+                LabelMatcher::new("__name__", LabelMatchOp::Equal, "baz"),
+            ]
+        );
+
+        // A name in front of the braces cannot be set again inside them
+        let err = vector_selector(r#"foo{__name__="bar"}"#).unwrap_err();
+        assert!(matches!(err, nom::Err::Failure(e) if e.input == r#"{__name__="bar"}"#));
+        let (_, sel) = vector_selector(r#"foo{__name__!="bar"}"#).unwrap();
+        assert_eq!(sel.name, Some("foo".to_string()));
+    }
 }
@@ -34,19 +34,21 @@
 //! ```
 
 use nom::{
-    IResult, Parser,
+    Parser,
     character::complete::char,
     combinator::{map, opt, peek, recognize},
     sequence::delimited,
 };
 
 use crate::ast::{Expr, SubqueryExpr};
-use crate::lexer::duration::{Duration, duration};
+use crate::error::IResult;
+use crate::lexer::duration::{Duration, duration, positive_duration};
 use crate::parser::selector::parse_modifiers;
 
 /// Parse a subquery range: `[range:step]` or `[range:]`
 ///
-/// Returns (range, optional_step)
+/// Returns (range, optional_step). Both must be greater than zero, see
+/// [`positive_duration`].
 ///
 /// # Examples
 ///
@@ -66,9 +68,10 @@ use crate::parser::selector::parse_modifiers;
 pub fn subquery_range(input: &str) -> IResult<&str, (Duration, Option<Duration>)> {
     delimited(
         char('['),
-        map((duration, char(':'), opt(duration)), |(range, _, step)| {
-            (range, step)
-        }),
+        map(
+            (positive_duration, char(':'), opt(positive_duration)),
+            |(range, _, step)| (range, step),
+        ),
         char(']'),
     )
     .parse(input)
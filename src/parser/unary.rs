@@ -30,7 +30,7 @@
 //! ```
 
 use nom::{
-    IResult, Parser,
+    Parser,
     branch::alt,
     character::complete::{char, multispace0},
     combinator::{peek, value},
@@ -38,6 +38,7 @@ use nom::{
 };
 
 use crate::ast::UnaryOp;
+use crate::error::IResult;
 
 /// Parse a unary operator
 ///
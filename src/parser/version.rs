@@ -0,0 +1,161 @@
+//! Prometheus versions and the PromQL features they introduced.
+//!
+//! Prometheus keeps adding functions and syntax, so a query written for a
+//! recent server may be rejected by an older one. Setting
+//! [`ParseOptions::prometheus_version`](crate::ParseOptions::prometheus_version)
+//! makes [`parse_with_options`](crate::parse_with_options) reject features
+//! that the targeted version does not have.
+//!
+//! The feature matrix starts at [`PrometheusVersion::V2_40`], the first
+//! release with native histogram functions. Everything older than that,
+//! such as the `@` modifier and negative offsets (enabled by default since
+//! 2.33), is available in every version.
+//!
+//! | Version | Features |
+//! |---------|----------|
+//! | 2.47 | `sort_by_label`, `sort_by_label_desc`, `histogram_stddev`, `histogram_stdvar` |
+//! | 2.53 | `histogram_avg`, `mad_over_time` |
+//! | 2.54 | `limitk`, `limit_ratio` |
+//! | 3.0 | `double_exponential_smoothing`, `info`, quoted metric and label names like `{"app.requests", "http.method"="GET"}` |
+//! | 3.5 | `ts_of_min_over_time`, `ts_of_max_over_time`, `ts_of_last_over_time` |
+//! | 3.7 | `first_over_time`, `ts_of_first_over_time` |
+//!
+//! Experimental functions additionally need the
+//! `promql-experimental-functions` feature flag, which is reported by the
+//! `experimental-function` [lint](mod@crate::lint).
+//!
+//! # Example
+//!
+//! ```rust
+//! use rusty_promql_parser::{ParseOptions, PrometheusVersion, parse_with_options};
+//!
+//! let options = ParseOptions {
+//!     prometheus_version: Some(PrometheusVersion::V2_40),
+//!     ..ParseOptions::default()
+//! };
+//! let err = parse_with_options(r#"sort_by_label(up, "job")"#, &options).unwrap_err();
+//! assert_eq!(
+//!     err.message,
+//!     "function \"sort_by_label\" is not available in Prometheus 2.40, it was added in 2.47"
+//! );
+//! ```
+
+use core::fmt;
+
+/// A Prometheus release that changed which PromQL features are available
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum PrometheusVersion {
+    /// 2.40, the oldest version with a distinct feature set
+    V2_40,
+    /// 2.47
+    V2_47,
+    /// 2.53
+    V2_53,
+    /// 2.54
+    V2_54,
+    /// 3.0
+    V3_0,
+    /// 3.5
+    V3_5,
+    /// 3.7
+    V3_7,
+}
+
+impl PrometheusVersion {
+    /// The newest version known to this crate
+    pub const LATEST: PrometheusVersion = PrometheusVersion::V3_7;
+
+    /// Check whether metric and label names may be quoted, as in
+    /// `{"app.requests", "http.method"="GET"}`
+    pub fn supports_quoted_names(self) -> bool {
+        self >= PrometheusVersion::V3_0
+    }
+
+    /// The version that introduced the function or aggregation operator
+    /// `name`, or `None` if it is available in every version (or unknown).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::PrometheusVersion;
+    ///
+    /// assert_eq!(
+    ///     PrometheusVersion::introduced("limitk"),
+    ///     Some(PrometheusVersion::V2_54)
+    /// );
+    /// assert_eq!(PrometheusVersion::introduced("rate"), None);
+    /// ```
+    pub fn introduced(name: &str) -> Option<PrometheusVersion> {
+        use PrometheusVersion::*;
+
+        let version = match name {
+            "sort_by_label" | "sort_by_label_desc" | "histogram_stddev" | "histogram_stdvar" => {
+                V2_47
+            }
+            "histogram_avg" | "mad_over_time" => V2_53,
+            "limitk" | "limit_ratio" => V2_54,
+            "double_exponential_smoothing" | "info" => V3_0,
+            "ts_of_min_over_time" | "ts_of_max_over_time" | "ts_of_last_over_time" => V3_5,
+            "first_over_time" | "ts_of_first_over_time" => V3_7,
+            _ => return None,
+        };
+        Some(version)
+    }
+
+    /// Check whether the function or aggregation operator `name` is
+    /// available in this version
+    pub fn supports(self, name: &str) -> bool {
+        PrometheusVersion::introduced(name).is_none_or(|since| since <= self)
+    }
+}
+
+impl fmt::Display for PrometheusVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let version = match self {
+            PrometheusVersion::V2_40 => "2.40",
+            PrometheusVersion::V2_47 => "2.47",
+            PrometheusVersion::V2_53 => "2.53",
+            PrometheusVersion::V2_54 => "2.54",
+            PrometheusVersion::V3_0 => "3.0",
+            PrometheusVersion::V3_5 => "3.5",
+            PrometheusVersion::V3_7 => "3.7",
+        };
+        write!(f, "{}", version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::function::FUNCTIONS;
+
+    #[test]
+    fn test_versions_ordered() {
+        assert!(PrometheusVersion::V2_40 < PrometheusVersion::V2_47);
+        assert!(PrometheusVersion::V2_54 < PrometheusVersion::V3_0);
+        assert_eq!(PrometheusVersion::LATEST.to_string(), "3.7");
+        assert!(!PrometheusVersion::V2_54.supports_quoted_names());
+        assert!(PrometheusVersion::V3_0.supports_quoted_names());
+    }
+
+    #[test]
+    fn test_supports() {
+        assert!(PrometheusVersion::V2_40.supports("rate"));
+        assert!(!PrometheusVersion::V2_40.supports("sort_by_label"));
+        assert!(PrometheusVersion::V2_47.supports("sort_by_label"));
+        assert!(!PrometheusVersion::V2_54.supports("info"));
+        assert!(PrometheusVersion::V3_0.supports("info"));
+    }
+
+    #[test]
+    fn test_latest_supports_every_function() {
+        for function in FUNCTIONS {
+            assert!(
+                PrometheusVersion::LATEST.supports(function.name),
+                "{}",
+                function.name
+            );
+        }
+    }
+}
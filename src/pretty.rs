@@ -0,0 +1,236 @@
+//! Multi-line formatting of expressions, see [`Expr::pretty`].
+
+use alloc::string::{String, ToString};
+
+use crate::ast::Expr;
+
+/// Indentation added for each nesting level
+const INDENT: &str = "  ";
+
+impl Expr {
+    /// Format the expression across several indented lines, so that no line
+    /// is longer than `max_width` characters where possible.
+    ///
+    /// Expressions that fit are printed on one line, like [`Display`]. Others
+    /// are split: each function argument and the parameter and body of an
+    /// aggregation go on their own line, one level deeper, and the operator
+    /// of a binary expression starts a new line. Only whitespace differs
+    /// from the [`Display`] output, so the result parses back to the same
+    /// expression.
+    ///
+    /// [`Display`]: core::fmt::Display
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rusty_promql_parser::expr;
+    ///
+    /// let (_, ast) = expr(
+    ///     "histogram_quantile(0.99, sum by (le) (rate(http_request_duration_seconds_bucket[5m])))",
+    /// )
+    /// .unwrap();
+    /// assert_eq!(
+    ///     ast.pretty(60),
+    ///     "\
+    /// histogram_quantile(
+    ///   0.99,
+    ///   sum by (le) (
+    ///     rate(http_request_duration_seconds_bucket[5m])
+    ///   )
+    /// )"
+    /// );
+    /// ```
+    pub fn pretty(&self, max_width: usize) -> String {
+        Printer { max_width }.format(self, 0, 0)
+    }
+}
+
+struct Printer {
+    max_width: usize,
+}
+
+impl Printer {
+    /// Format `expr` with its first line starting at `column`, and the lines
+    /// after it indented by `level`. The first line is not indented.
+    fn format(&self, expr: &Expr, level: usize, column: usize) -> String {
+        let line = expr.to_string();
+        if column + line.chars().count() <= self.max_width {
+            return line;
+        }
+
+        let indent = INDENT.repeat(level);
+        match expr {
+            Expr::Call(c) if !c.args.is_empty() => {
+                let mut out = c.name.clone();
+                out.push('(');
+                self.push_lines(&mut out, c.args.iter(), level + 1);
+                out.push('\n');
+                out.push_str(&indent);
+                out.push(')');
+                out
+            }
+            Expr::Aggregation(a) => {
+                let mut out = a.op.clone();
+                if let Some(grouping) = &a.grouping {
+                    out.push(' ');
+                    out.push_str(&grouping.to_string());
+                    out.push(' ');
+                }
+                out.push('(');
+                self.push_lines(&mut out, a.param.iter().chain([&a.expr]), level + 1);
+                out.push('\n');
+                out.push_str(&indent);
+                out.push(')');
+                out
+            }
+            Expr::Binary(b) => {
+                let mut operator = b.op.to_string();
+                if let Some(modifier) = &b.modifier
+                    && !modifier.is_empty()
+                {
+                    operator.push(' ');
+                    operator.push_str(&modifier.to_string());
+                }
+                operator.push(' ');
+
+                let mut out = self.format(&b.lhs, level, column);
+                out.push('\n');
+                out.push_str(&indent);
+                out.push_str(&operator);
+                let rhs_column = indent.len() + operator.chars().count();
+                out.push_str(&self.format(&b.rhs, level, rhs_column));
+                out
+            }
+            Expr::Unary(u) => {
+                let mut out = u.op.to_string();
+                out.push_str(&self.format(&u.expr, level, column + out.len()));
+                out
+            }
+            Expr::Paren(inner) => {
+                let mut out = String::from("(");
+                self.push_lines(&mut out, [inner.as_ref()], level + 1);
+                out.push('\n');
+                out.push_str(&indent);
+                out.push(')');
+                out
+            }
+            Expr::Subquery(sq) => {
+                // Display writes the inner expression first, then the range
+                // and modifiers
+                let suffix = &line[sq.expr.to_string().len()..];
+                let mut out = self.format(&sq.expr, level, column);
+                out.push_str(suffix);
+                out
+            }
+            _ => line,
+        }
+    }
+
+    /// Append each expression on its own line at `level`, separated by
+    /// commas
+    fn push_lines<'a>(
+        &self,
+        out: &mut String,
+        exprs: impl IntoIterator<Item = &'a Expr>,
+        level: usize,
+    ) {
+        let indent = INDENT.repeat(level);
+        for (i, expr) in exprs.into_iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            out.push('\n');
+            out.push_str(&indent);
+            out.push_str(&self.format(expr, level, indent.len()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::expr::expr;
+    use alloc::string::String;
+
+    fn pretty(input: &str, max_width: usize) -> String {
+        let (rest, e) = expr(input).unwrap();
+        assert!(rest.is_empty());
+        let output = e.pretty(max_width);
+
+        // Only whitespace may differ from the single-line output
+        let (rest, reparsed) = expr(&output).unwrap();
+        assert!(rest.is_empty(), "{}", output);
+        assert_eq!(reparsed.to_string(), e.to_string());
+        output
+    }
+
+    #[test]
+    fn test_short_expressions_stay_on_one_line() {
+        assert_eq!(
+            pretty("sum(rate(x[5m])) by (job)", 80),
+            "sum by (job) (rate(x[5m]))"
+        );
+        assert_eq!(pretty("a + b", 5), "a + b");
+        // Leaves are never split
+        assert_eq!(pretty("some_long_metric_name", 4), "some_long_metric_name");
+    }
+
+    #[test]
+    fn test_recording_rule() {
+        let input = r#"histogram_quantile(0.99, sum(rate(http_request_duration_seconds_bucket[5m])) by (le, job))"#;
+        assert_eq!(
+            pretty(input, 40),
+            concat!(
+                "histogram_quantile(\n",
+                "  0.99,\n",
+                "  sum by (le, job) (\n",
+                "    rate(\n",
+                "      http_request_duration_seconds_bucket[5m]\n",
+                "    )\n",
+                "  )\n",
+                ")",
+            )
+        );
+    }
+
+    #[test]
+    fn test_binary_operators_start_lines() {
+        let input = "sum by (app, proc) (instance_memory_limit_bytes - instance_memory_usage_bytes) / 1024 / 1024";
+        assert_eq!(
+            pretty(input, 60),
+            concat!(
+                "sum by (app, proc) (\n",
+                "  instance_memory_limit_bytes - instance_memory_usage_bytes\n",
+                ")\n",
+                "/ 1024\n",
+                "/ 1024",
+            )
+        );
+
+        let input = "rate(errors_total[5m]) / on (job) group_left (team) sum by (job) (rate(requests_total[5m]))";
+        assert_eq!(
+            pretty(input, 50),
+            concat!(
+                "rate(errors_total[5m])\n",
+                "/ on (job) group_left (team) sum by (job) (\n",
+                "  rate(requests_total[5m])\n",
+                ")",
+            )
+        );
+    }
+
+    #[test]
+    fn test_paren_unary_and_subquery() {
+        let input = "-(max_over_time(rate(http_requests_total[5m])[30m:1m] offset 1h) > bool 100)";
+        assert_eq!(
+            pretty(input, 50),
+            concat!(
+                "-(\n",
+                "  max_over_time(\n",
+                "    rate(http_requests_total[5m])[30m:1m] offset 1h\n",
+                "  )\n",
+                "  > bool 100\n",
+                ")",
+            )
+        );
+    }
+}
@@ -0,0 +1,472 @@
+//! Prometheus rule file parsing.
+//!
+//! Requires the `rules` feature. [`parse_rule_file`] reads a rule file in
+//! the format used by Prometheus' `rule_files` setting and parses the `expr`
+//! of every recording and alerting rule. A query that fails to parse does
+//! not stop the others, so the result can drive a rule-file linter.
+//!
+//! JSON is a subset of YAML, so JSON rule files are accepted as well.
+//!
+//! [`order_recording_rules`] sorts recording rules so that every rule runs
+//! after the rules producing the metrics it reads.
+//!
+//! # Example
+//!
+//! ```rust
+//! use rusty_promql_parser::rules::{RuleKind, parse_rule_file};
+//!
+//! let yaml = r#"
+//! groups:
+//!   - name: example
+//!     rules:
+//!       - record: job:up:sum
+//!         expr: sum by (job) (up)
+//!       - alert: InstanceDown
+//!         expr: up == 0 )
+//! "#;
+//!
+//! let rules = parse_rule_file(yaml).unwrap();
+//! assert_eq!(rules.len(), 2);
+//! assert_eq!(rules[0].kind, RuleKind::Record);
+//! assert!(rules[0].expr.is_ok());
+//!
+//! let err = rules[1].expr.as_ref().unwrap_err();
+//! assert_eq!(err.line, 8);
+//! assert_eq!(err.column, Some(23));
+//! ```
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use yaml_rust2::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust2::scanner::{Marker, TScalarStyle};
+
+use crate::ast::Expr;
+use crate::parser::options::{ParseOptions, parse_with_options};
+
+/// Whether a rule records a new series or fires an alert
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleKind {
+    /// Recording rule (`record:`)
+    Record,
+    /// Alerting rule (`alert:`)
+    Alert,
+}
+
+impl fmt::Display for RuleKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleKind::Record => write!(f, "record"),
+            RuleKind::Alert => write!(f, "alert"),
+        }
+    }
+}
+
+/// A rule from a rule file, with its parsed expression
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedRule {
+    /// Name of the enclosing rule group
+    pub group: String,
+    /// Recorded series name or alert name.
+    ///
+    /// Names are not unique: several alerting rules commonly share a name
+    /// with different thresholds.
+    pub name: String,
+    /// Kind of rule
+    pub kind: RuleKind,
+    /// Line (1-based) where the `expr` value starts
+    pub line: usize,
+    /// The parsed expression, or why it failed to parse
+    pub expr: Result<Expr, RuleError>,
+}
+
+/// An error in a rule file, located by line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleError {
+    /// Line (1-based) in the rule file
+    pub line: usize,
+    /// Column (1-based) in the rule file, for errors inside an `expr`.
+    ///
+    /// Only known when the query text maps back to the file: for `|` block
+    /// scalars, and for single-line plain or quoted scalars without escape
+    /// sequences. Otherwise this is `None` and [`line`](RuleError::line) is
+    /// the line where the `expr` value starts.
+    pub column: Option<usize>,
+    /// Description of the problem
+    pub message: String,
+}
+
+impl RuleError {
+    fn new(line: usize, message: impl Into<String>) -> Self {
+        Self {
+            line,
+            column: None,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.column {
+            Some(column) => write!(f, "line {}, column {}: {}", self.line, column, self.message),
+            None => write!(f, "line {}: {}", self.line, self.message),
+        }
+    }
+}
+
+impl std::error::Error for RuleError {}
+
+/// Parse a Prometheus rule file and the expression of each of its rules.
+///
+/// Rules are returned in file order. The outer `Err` is reserved for files
+/// that are not valid YAML or do not have the rule file structure; a
+/// PromQL error only fails the [`ParsedRule::expr`] of its rule.
+pub fn parse_rule_file(input: &str) -> Result<Vec<ParsedRule>, RuleError> {
+    let mut builder = NodeBuilder::new(input);
+    Parser::new_from_str(input)
+        .load(&mut builder, false)
+        .map_err(|e| RuleError::new(e.marker().line(), e.info()))?;
+    let Some(root) = builder.root else {
+        return Err(RuleError::new(1, "empty rule file"));
+    };
+
+    let mut rules = Vec::new();
+    let groups = root
+        .get("groups")
+        .ok_or_else(|| root.error("missing groups"))?;
+    for group in groups.as_seq()? {
+        let group_name = group.get_str("name")?;
+        let Some(group_rules) = group.get("rules") else {
+            continue;
+        };
+        for rule in group_rules.as_seq()? {
+            rules.push(parse_rule(group_name, rule)?);
+        }
+    }
+    Ok(rules)
+}
+
+/// A dependency cycle between recording rules
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DependencyCycle {
+    /// Names of the rules in the cycle. Each rule reads the output of the
+    /// one before it, and the first reads the output of the last.
+    pub rules: Vec<String>,
+}
+
+impl fmt::Display for DependencyCycle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "dependency cycle between recording rules: ")?;
+        for name in &self.rules {
+            write!(f, "{} -> ", name)?;
+        }
+        write!(f, "{}", self.rules[0])
+    }
+}
+
+impl std::error::Error for DependencyCycle {}
+
+/// Order recording rules so that each runs after the rules it depends on.
+///
+/// `rules` pairs each rule's recorded metric name with its expression. A
+/// rule depends on every other rule whose name is among the
+/// [`Expr::metric_names`] it reads. A rule reading its own output places no
+/// constraint on the order and is allowed.
+///
+/// Returns indices into `rules`. Rules without a dependency between them
+/// keep their relative input order.
+///
+/// # Example
+///
+/// ```rust
+/// use rusty_promql_parser::expr;
+/// use rusty_promql_parser::rules::order_recording_rules;
+///
+/// let (_, ratio) = expr("job:errors:rate5m / job:requests:rate5m").unwrap();
+/// let (_, errors) = expr("sum by (job) (rate(errors_total[5m]))").unwrap();
+/// let (_, requests) = expr("sum by (job) (rate(requests_total[5m]))").unwrap();
+///
+/// let rules = [
+///     ("job:error_ratio:rate5m", &ratio),
+///     ("job:errors:rate5m", &errors),
+///     ("job:requests:rate5m", &requests),
+/// ];
+/// assert_eq!(order_recording_rules(&rules).unwrap(), [1, 2, 0]);
+/// ```
+pub fn order_recording_rules(rules: &[(&str, &Expr)]) -> Result<Vec<usize>, DependencyCycle> {
+    let mut producers: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, (name, _)) in rules.iter().enumerate() {
+        producers.entry(name).or_default().push(i);
+    }
+
+    // dependencies[i] lists the rules whose output rule i reads
+    let dependencies: Vec<Vec<usize>> = rules
+        .iter()
+        .enumerate()
+        .map(|(i, (_, expr))| {
+            let mut deps: Vec<usize> = expr
+                .metric_names()
+                .into_iter()
+                .filter_map(|name| producers.get(name))
+                .flatten()
+                .copied()
+                .filter(|&j| j != i)
+                .collect();
+            deps.sort_unstable();
+            deps.dedup();
+            deps
+        })
+        .collect();
+    let mut dependents = vec![Vec::new(); rules.len()];
+    for (i, deps) in dependencies.iter().enumerate() {
+        for &j in deps {
+            dependents[j].push(i);
+        }
+    }
+
+    // Kahn's algorithm, taking the earliest ready rule first
+    let mut waiting: Vec<usize> = dependencies.iter().map(Vec::len).collect();
+    let mut ready: BTreeSet<usize> = (0..rules.len()).filter(|&i| waiting[i] == 0).collect();
+    let mut order = Vec::with_capacity(rules.len());
+    while let Some(i) = ready.pop_first() {
+        order.push(i);
+        for &k in &dependents[i] {
+            waiting[k] -= 1;
+            if waiting[k] == 0 {
+                ready.insert(k);
+            }
+        }
+    }
+    if order.len() == rules.len() {
+        return Ok(order);
+    }
+
+    // Each rule left waits on another rule left, so following dependencies
+    // from any of them must come back to a rule already visited
+    let mut path = Vec::new();
+    let mut i = (0..rules.len()).find(|&i| waiting[i] > 0).unwrap();
+    while !path.contains(&i) {
+        path.push(i);
+        i = *dependencies[i].iter().find(|&&j| waiting[j] > 0).unwrap();
+    }
+    let start = path.iter().position(|&j| j == i).unwrap();
+    let mut cycle: Vec<String> = path[start..]
+        .iter()
+        .rev()
+        .map(|&j| rules[j].0.to_string())
+        .collect();
+    cycle.rotate_right(1);
+    Err(DependencyCycle { rules: cycle })
+}
+
+fn parse_rule(group: &str, rule: &Node) -> Result<ParsedRule, RuleError> {
+    let (kind, name) = match (rule.get("record"), rule.get("alert")) {
+        (Some(name), None) => (RuleKind::Record, name.as_str()?),
+        (None, Some(name)) => (RuleKind::Alert, name.as_str()?),
+        _ => return Err(rule.error("rule must have exactly one of record or alert")),
+    };
+    let expr = rule
+        .get("expr")
+        .ok_or_else(|| rule.error(format!("{} {:?} has no expr", kind, name)))?;
+    let query = expr.as_str()?;
+
+    Ok(ParsedRule {
+        group: group.to_string(),
+        name: name.to_string(),
+        kind,
+        line: expr.line,
+        expr: parse_with_options(query, &ParseOptions::default()).map_err(|e| {
+            let message = without_query_column(&e.message);
+            match expr.col {
+                Some(start) => {
+                    let (line, col) = e.line_col(query);
+                    RuleError {
+                        line: expr.line + line - 1,
+                        column: Some(start + col - 1),
+                        message: message.to_string(),
+                    }
+                }
+                None => RuleError::new(expr.line, message),
+            }
+        }),
+    })
+}
+
+/// Strip the column within the query that some parse error messages end
+/// with, since a rule error reports its own column within the file
+fn without_query_column(message: &str) -> &str {
+    match message.rsplit_once(" at column ") {
+        Some((head, col)) if !col.is_empty() && col.bytes().all(|b| b.is_ascii_digit()) => head,
+        _ => message,
+    }
+}
+
+/// A YAML value with the line it starts on
+#[derive(Debug)]
+struct Node {
+    line: usize,
+    /// 1-based column where the text of a scalar starts, if every position
+    /// in the text is at the same line and column offset in the file
+    col: Option<usize>,
+    value: Value,
+}
+
+#[derive(Debug)]
+enum Value {
+    Scalar(String),
+    Seq(Vec<Node>),
+    Map(Vec<(Node, Node)>),
+    /// Aliases are not resolved; no rule file field needs them
+    Alias,
+}
+
+impl Node {
+    fn error(&self, message: impl Into<String>) -> RuleError {
+        RuleError::new(self.line, message)
+    }
+
+    fn get(&self, key: &str) -> Option<&Node> {
+        match &self.value {
+            Value::Map(entries) => entries
+                .iter()
+                .find(|(k, _)| matches!(&k.value, Value::Scalar(s) if s == key))
+                .map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn get_str(&self, key: &str) -> Result<&str, RuleError> {
+        self.get(key)
+            .ok_or_else(|| self.error(format!("missing {}", key)))?
+            .as_str()
+    }
+
+    fn as_str(&self) -> Result<&str, RuleError> {
+        match &self.value {
+            Value::Scalar(s) => Ok(s),
+            _ => Err(self.error("expected a string")),
+        }
+    }
+
+    fn as_seq(&self) -> Result<&[Node], RuleError> {
+        match &self.value {
+            Value::Seq(items) => Ok(items),
+            _ => Err(self.error("expected a list")),
+        }
+    }
+}
+
+/// Builds a [`Node`] tree from the YAML parser's events
+struct NodeBuilder<'a> {
+    input: &'a str,
+    /// Last converted position as (char index, byte offset) in `input`
+    cursor: (usize, usize),
+    /// Open sequences and mappings, with a pending mapping key if any
+    stack: Vec<(Node, Option<Node>)>,
+    root: Option<Node>,
+}
+
+impl<'a> NodeBuilder<'a> {
+    fn new(input: &'a str) -> Self {
+        Self {
+            input,
+            cursor: (0, 0),
+            stack: Vec::new(),
+            root: None,
+        }
+    }
+
+    /// The input from the char index reported by a marker on
+    fn source_from(&mut self, index: usize) -> &'a str {
+        if index < self.cursor.0 {
+            self.cursor = (0, 0);
+        }
+        let (mut chars, mut offset) = self.cursor;
+        for c in self.input[offset..].chars() {
+            if chars == index {
+                break;
+            }
+            chars += 1;
+            offset += c.len_utf8();
+        }
+        self.cursor = (chars, offset);
+        &self.input[offset..]
+    }
+
+    /// Where the text of a scalar starts, if it maps back to the file
+    fn scalar_col(&mut self, text: &str, style: TScalarStyle, mark: Marker) -> Option<usize> {
+        let col = mark.col() + 1;
+        match style {
+            // Every line of a literal block is indented like its first one
+            TScalarStyle::Literal => Some(col),
+            // Folding and escapes make the text differ from the source
+            TScalarStyle::Plain => self
+                .source_from(mark.index())
+                .starts_with(text)
+                .then_some(col),
+            TScalarStyle::SingleQuoted | TScalarStyle::DoubleQuoted => self
+                .source_from(mark.index() + 1)
+                .starts_with(text)
+                .then_some(col + 1),
+            _ => None,
+        }
+    }
+
+    fn push(&mut self, node: Node) {
+        match self.stack.last_mut() {
+            None => self.root = Some(node),
+            Some((parent, key)) => match &mut parent.value {
+                Value::Seq(items) => items.push(node),
+                Value::Map(entries) => match key.take() {
+                    Some(k) => entries.push((k, node)),
+                    None => *key = Some(node),
+                },
+                _ => unreachable!("only collections are stacked"),
+            },
+        }
+    }
+}
+
+impl MarkedEventReceiver for NodeBuilder<'_> {
+    fn on_event(&mut self, ev: Event, mark: Marker) {
+        let line = mark.line();
+        match ev {
+            Event::Scalar(s, style, ..) => {
+                let col = self.scalar_col(&s, style, mark);
+                self.push(Node {
+                    line,
+                    col,
+                    value: Value::Scalar(s),
+                })
+            }
+            Event::Alias(_) => self.push(Node {
+                line,
+                col: None,
+                value: Value::Alias,
+            }),
+            Event::SequenceStart(..) => self.stack.push((
+                Node {
+                    line,
+                    col: None,
+                    value: Value::Seq(Vec::new()),
+                },
+                None,
+            )),
+            Event::MappingStart(..) => self.stack.push((
+                Node {
+                    line,
+                    col: None,
+                    value: Value::Map(Vec::new()),
+                },
+                None,
+            )),
+            Event::SequenceEnd | Event::MappingEnd => {
+                if let Some((node, _)) = self.stack.pop() {
+                    self.push(node);
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,709 @@
+//! Semantic validation of parsed PromQL expressions.
+//!
+//! The parser only checks syntax: `label_replace(foo, 5, "", "", "")` is a
+//! well-formed function call even though its second argument must be a
+//! string. The [`validate()`] pass walks a parsed [`Expr`] and reports these
+//! semantic problems.
+//!
+//! # Checks
+//!
+//! - Functions must be known; misspelled names get a "did you mean"
+//!   suggestion (see [`suggest_function`])
+//! - Function calls must have as many arguments as the function accepts
+//! - Function arguments must have the type declared in the function
+//!   signature (see [`FUNCTIONS`](crate::parser::function::FUNCTIONS))
+//! - Parametric aggregations must get a parameter of the right type: a
+//!   string for `count_values`, a scalar for the others. The parser accepts
+//!   any expression there
+//! - The ratio of `limit_ratio` must be between -1 and 1 when it is a
+//!   constant
+//! - Aggregations must aggregate an instant vector, so `sum(time())` is
+//!   rejected
+//! - Selectors must have a metric name or a label matcher that does not
+//!   match the empty string, so `{}` and `{job=~".*"}` are rejected as
+//!   they would select every series
+//! - Subqueries must wrap an instant vector, so `scalar(x)[5m:]` is rejected
+//!
+//! # Example
+//!
+//! ```rust
+//! use rusty_promql_parser::{expr, validate};
+//!
+//! let (_, ast) = expr(r#"label_replace(up, "dst", "$1", "src", "(.*)")"#).unwrap();
+//! assert!(validate(&ast).is_ok());
+//!
+//! let (_, ast) = expr(r#"label_replace(up, 5, "$1", "src", "(.*)")"#).unwrap();
+//! let errors = validate(&ast).unwrap_err();
+//! assert!(errors[0].message.contains("expected type string"));
+//! ```
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::ast::{Aggregation, Call, Expr, SubqueryExpr};
+use crate::parser::function::{ValueType, Variadic, get_function, suggest_function};
+use crate::parser::selector::VectorSelector;
+
+/// A semantic error found while validating an expression.
+///
+/// Displays as `validation error: <message>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    /// Description of the problem.
+    pub message: String,
+}
+
+impl ValidationError {
+    /// Create a new validation error
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "validation error: {}", self.message)
+    }
+}
+
+impl core::error::Error for ValidationError {}
+
+/// Validate the semantics of a parsed expression.
+///
+/// Walks the whole expression tree and returns every problem found, in
+/// source order.
+pub fn validate(expr: &Expr) -> Result<(), Vec<ValidationError>> {
+    let mut errors = Vec::new();
+    check_expr(expr, &mut errors);
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Infer the type of value an expression evaluates to.
+///
+/// The same as [`Expr::value_type`], which describes the rules.
+///
+/// # Example
+///
+/// ```rust
+/// use rusty_promql_parser::{expr, type_of};
+/// use rusty_promql_parser::parser::function::ValueType;
+///
+/// let (_, ast) = expr("1 > bool 2").unwrap();
+/// assert_eq!(type_of(&ast), ValueType::Scalar);
+///
+/// let (_, ast) = expr("up > bool 2").unwrap();
+/// assert_eq!(type_of(&ast), ValueType::Vector);
+/// ```
+pub fn type_of(expr: &Expr) -> ValueType {
+    expr.value_type()
+}
+
+fn check_expr(expr: &Expr, errors: &mut Vec<ValidationError>) {
+    match expr {
+        Expr::Call(call) => check_call(call, errors),
+        Expr::Aggregation(agg) => check_aggregation(agg, errors),
+        Expr::Subquery(sq) => check_subquery(sq, errors),
+        Expr::VectorSelector(vs) => check_selector(vs, errors),
+        Expr::MatrixSelector(ms) => check_selector(&ms.selector, errors),
+        _ => {}
+    }
+    for child in expr.children() {
+        check_expr(child, errors);
+    }
+}
+
+/// Check the argument count, then each argument against the type declared
+/// at its position
+fn check_call(call: &Call, errors: &mut Vec<ValidationError>) {
+    let Some(func) = get_function(&call.name) else {
+        let message = match suggest_function(&call.name) {
+            Some(suggestion) => format!(
+                "unknown function \"{}\", did you mean \"{}\"?",
+                call.name, suggestion
+            ),
+            None => format!("unknown function \"{}\"", call.name),
+        };
+        errors.push(ValidationError::new(message));
+        return;
+    };
+
+    let nargs = call.args.len();
+    let (min, max) = (func.min_args(), func.max_args());
+    let arity_error = if min == max.unwrap_or(usize::MAX) && nargs != min {
+        Some(format!("expected {} argument(s)", min))
+    } else if nargs < min {
+        Some(format!("expected at least {} argument(s)", min))
+    } else {
+        max.filter(|&max| nargs > max)
+            .map(|max| format!("expected at most {} argument(s)", max))
+    };
+    if let Some(prefix) = arity_error {
+        errors.push(ValidationError::new(format!(
+            "{} in call to \"{}\", got {}",
+            prefix, call.name, nargs
+        )));
+        return;
+    }
+
+    for (i, arg) in call.args.iter().enumerate() {
+        let expected = match func.arg_types.get(i) {
+            Some(t) => *t,
+            // Repeated arguments share the type of the last declared one
+            None if func.variadic == Variadic::Repeat => match func.arg_types.last() {
+                Some(t) => *t,
+                None => break,
+            },
+            None => break,
+        };
+
+        let actual = type_of(arg);
+        if actual != expected {
+            errors.push(ValidationError::new(format!(
+                "expected type {} in call to function \"{}\", got {}",
+                expected, call.name, actual
+            )));
+        }
+    }
+}
+
+/// Check an aggregation's parameter, then the aggregated expression, which
+/// must be an instant vector
+fn check_aggregation(agg: &Aggregation, errors: &mut Vec<ValidationError>) {
+    check_aggregation_param(agg, errors);
+
+    let actual = type_of(&agg.expr);
+    if actual != ValueType::Vector {
+        errors.push(ValidationError::new(format!(
+            "expected instant vector in aggregation, got {}",
+            actual
+        )));
+    }
+}
+
+/// Check the type of an aggregation's parameter, and its range when it is a
+/// constant
+fn check_aggregation_param(agg: &Aggregation, errors: &mut Vec<ValidationError>) {
+    let op = agg.op.to_ascii_lowercase();
+    let expected = match op.as_str() {
+        "topk" | "bottomk" | "quantile" | "limitk" | "limit_ratio" => ValueType::Scalar,
+        "count_values" => ValueType::String,
+        _ => return,
+    };
+    let Some(param) = &agg.param else {
+        return;
+    };
+
+    let actual = type_of(param);
+    if actual != expected {
+        errors.push(ValidationError::new(format!(
+            "expected type {} in aggregation parameter, got {}",
+            expected, actual
+        )));
+        return;
+    }
+
+    // A negative ratio selects from the other end of the series' hash range
+    let ratio = param.constant_value().filter(|_| op == "limit_ratio");
+    if let Some(ratio) = ratio.filter(|r| !(-1.0..=1.0).contains(r)) {
+        errors.push(ValidationError::new(format!(
+            "ratio parameter of limit_ratio must be between -1 and 1, got {}",
+            ratio
+        )));
+    }
+}
+
+/// Check that a selector cannot select every series, see
+/// [`VectorSelector::has_non_empty_matcher`]
+fn check_selector(vs: &VectorSelector, errors: &mut Vec<ValidationError>) {
+    if !vs.has_non_empty_matcher() {
+        errors.push(ValidationError::new(
+            "vector selector must contain at least one non-empty matcher",
+        ));
+    }
+}
+
+fn check_subquery(sq: &SubqueryExpr, errors: &mut Vec<ValidationError>) {
+    let actual = type_of(&sq.expr);
+    if actual != ValueType::Vector {
+        errors.push(ValidationError::new(format!(
+            "expected type {} in subquery, got {}",
+            ValueType::Vector,
+            actual
+        )));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::expr::expr;
+
+    fn validate_str(input: &str) -> Result<(), Vec<ValidationError>> {
+        let (rest, e) = expr(input).unwrap();
+        assert!(rest.is_empty(), "unparsed input: {:?}", rest);
+        validate(&e)
+    }
+
+    #[test]
+    fn test_error_display() {
+        let (_, e) = expr("rate(foo)").unwrap();
+        let errors = validate(&e).unwrap_err();
+        assert_eq!(
+            errors[0].to_string(),
+            "validation error: expected type range vector in call to function \"rate\", got instant vector"
+        );
+    }
+
+    #[test]
+    fn test_label_replace_valid() {
+        assert!(validate_str(r#"label_replace(up, "dst", "$1", "src", "(.*)")"#).is_ok());
+    }
+
+    #[test]
+    fn test_label_replace_non_string_argument() {
+        let errors = validate_str(r#"label_replace(up, 5, "$1", "src", "(.*)")"#).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].message,
+            "expected type string in call to function \"label_replace\", got scalar"
+        );
+
+        let errors = validate_str(r#"label_replace(up, "dst", "$1", "src", up)"#).unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "expected type string in call to function \"label_replace\", got instant vector"
+        );
+    }
+
+    #[test]
+    fn test_label_replace_non_vector_argument() {
+        let errors =
+            validate_str(r#"label_replace("up", "dst", "$1", "src", "(.*)")"#).unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "expected type instant vector in call to function \"label_replace\", got string"
+        );
+    }
+
+    #[test]
+    fn test_label_join_valid() {
+        assert!(validate_str(r#"label_join(up, "dst", ",", "a", "b", "c")"#).is_ok());
+        assert!(validate_str(r#"label_join(up, "dst", ",")"#).is_ok());
+    }
+
+    #[test]
+    fn test_label_join_non_string_source_label() {
+        // Repeated source labels must all be strings
+        let errors = validate_str(r#"label_join(up, "dst", ",", "a", 1)"#).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].message,
+            "expected type string in call to function \"label_join\", got scalar"
+        );
+    }
+
+    #[test]
+    fn test_vector_without_argument() {
+        let errors = validate_str("vector()").unwrap_err();
+        assert_eq!(
+            errors,
+            vec![ValidationError::new(
+                "expected 1 argument(s) in call to \"vector\", got 0"
+            )]
+        );
+    }
+
+    #[test]
+    fn test_vector_with_scalar_argument() {
+        let (_, e) = expr("vector(1)").unwrap();
+        match &e {
+            Expr::Call(call) => {
+                assert_eq!(call.args.len(), 1);
+                assert_eq!(type_of(&call.args[0]), ValueType::Scalar);
+            }
+            _ => panic!("Expected Call"),
+        }
+        assert!(validate(&e).is_ok());
+    }
+
+    #[test]
+    fn test_type_of_variants() {
+        let cases = [
+            ("1", ValueType::Scalar),
+            (r#""a""#, ValueType::String),
+            ("up", ValueType::Vector),
+            ("up[5m]", ValueType::Matrix),
+            ("rate(up[5m])", ValueType::Vector),
+            ("scalar(up)", ValueType::Scalar),
+            ("time()", ValueType::Scalar),
+            ("sum(up)", ValueType::Vector),
+            ("up[5m:1m]", ValueType::Matrix),
+            ("-1", ValueType::Scalar),
+            ("-up", ValueType::Vector),
+            ("((1))", ValueType::Scalar),
+            ("(up)", ValueType::Vector),
+            // Unknown functions default to instant vectors
+            ("no_such_function(1)", ValueType::Vector),
+        ];
+        for (input, expected) in cases {
+            let (_, e) = expr(input).unwrap();
+            assert_eq!(type_of(&e), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_type_of_binary() {
+        let cases = [
+            ("1 + 2", ValueType::Scalar),
+            ("1 + up", ValueType::Vector),
+            ("up * 2", ValueType::Vector),
+            ("up / on(job) down", ValueType::Vector),
+            ("1 == bool 1", ValueType::Scalar),
+            ("up == bool 1", ValueType::Vector),
+            ("1 < bool up", ValueType::Vector),
+            ("up > 0", ValueType::Vector),
+            ("up and down", ValueType::Vector),
+            ("scalar(up) * time()", ValueType::Scalar),
+            ("scalar(up) + vector(1)", ValueType::Vector),
+            ("-(1 + 2) ^ 2", ValueType::Scalar),
+            ("1 + 2 * up", ValueType::Vector),
+        ];
+        for (input, expected) in cases {
+            let (_, e) = expr(input).unwrap();
+            assert_eq!(type_of(&e), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_vector_with_vector_argument() {
+        let errors = validate_str("vector(metric)").unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "expected type scalar in call to function \"vector\", got instant vector"
+        );
+    }
+
+    #[test]
+    fn test_quantile_over_time_argument_order() {
+        assert!(validate_str("quantile_over_time(0.9, m[5m])").is_ok());
+        assert!(validate_str("quantile_over_time(scalar(q), m[5m:1m])").is_ok());
+
+        let errors = validate_str("quantile_over_time(m[5m], 0.9)").unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                ValidationError::new(
+                    "expected type scalar in call to function \"quantile_over_time\", got range vector"
+                ),
+                ValidationError::new(
+                    "expected type range vector in call to function \"quantile_over_time\", got scalar"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_predict_linear_argument_order() {
+        assert!(validate_str("predict_linear(m[1h], 3600)").is_ok());
+        assert!(validate_str("predict_linear(m[1h], 4 * 3600)").is_ok());
+
+        let errors = validate_str("predict_linear(3600, m[1h])").unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                ValidationError::new(
+                    "expected type range vector in call to function \"predict_linear\", got scalar"
+                ),
+                ValidationError::new(
+                    "expected type scalar in call to function \"predict_linear\", got range vector"
+                ),
+            ]
+        );
+
+        // An instant vector is not a valid time argument either
+        let errors = validate_str("predict_linear(m[1h], t)").unwrap_err();
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn test_double_exponential_smoothing_argument_types() {
+        assert!(validate_str("double_exponential_smoothing(m[5m], 0.5, 0.1)").is_ok());
+        assert!(validate_str("double_exponential_smoothing(rate(m[1m])[1h:], 0.3, 0.7)").is_ok());
+
+        let errors = validate_str("double_exponential_smoothing(0.5, m[5m], 0.1)").unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                ValidationError::new(
+                    "expected type range vector in call to function \"double_exponential_smoothing\", got scalar"
+                ),
+                ValidationError::new(
+                    "expected type scalar in call to function \"double_exponential_smoothing\", got range vector"
+                ),
+            ]
+        );
+
+        let errors = validate_str("double_exponential_smoothing(m, 0.5, 0.1)").unwrap_err();
+        assert_eq!(
+            errors,
+            vec![ValidationError::new(
+                "expected type range vector in call to function \"double_exponential_smoothing\", got instant vector"
+            )]
+        );
+
+        let errors = validate_str("double_exponential_smoothing(m[5m], 0.5, t)").unwrap_err();
+        assert_eq!(
+            errors,
+            vec![ValidationError::new(
+                "expected type scalar in call to function \"double_exponential_smoothing\", got instant vector"
+            )]
+        );
+
+        let errors = validate_str("double_exponential_smoothing(m[5m], 0.5)").unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "expected 3 argument(s) in call to \"double_exponential_smoothing\", got 2"
+        );
+    }
+
+    #[test]
+    fn test_histogram_functions() {
+        for query in [
+            "histogram_count(rate(req_duration[5m]))",
+            "histogram_sum(rate(req_duration[5m]))",
+            "histogram_avg(rate(req_duration[5m]))",
+            "histogram_stddev(rate(req_duration[5m]))",
+            "histogram_stdvar(rate(req_duration[5m]))",
+            "histogram_fraction(0, 0.2, rate(req_duration[5m]))",
+            "histogram_quantile(0.9, rate(req_duration[5m]))",
+            "histogram_quantile(0.9, sum by (le) (rate(req_duration_bucket[5m])))",
+        ] {
+            assert!(validate_str(query).is_ok(), "{}", query);
+        }
+
+        let errors = validate_str("histogram_count(req_duration[5m])").unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "expected type instant vector in call to function \"histogram_count\", got range vector"
+        );
+        let errors = validate_str("histogram_fraction(0, x, y)").unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "expected type scalar in call to function \"histogram_fraction\", got instant vector"
+        );
+        let errors = validate_str("histogram_quantile(x)").unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "expected 2 argument(s) in call to \"histogram_quantile\", got 1"
+        );
+    }
+
+    #[test]
+    fn test_variadic_arity() {
+        let errors = validate_str("round(x, 1, 2)").unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "expected at most 2 argument(s) in call to \"round\", got 3"
+        );
+
+        let errors = validate_str(r#"label_join(up, "dst")"#).unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "expected at least 3 argument(s) in call to \"label_join\", got 2"
+        );
+    }
+
+    #[test]
+    fn test_nested_calls_are_checked() {
+        let errors =
+            validate_str(r#"sum(label_replace(up, "dst", 1, "src", "(.*)")) + 1"#).unwrap_err();
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn test_conversion_functions() {
+        // vector() returns an instant vector, which can only be ranged
+        // through a subquery
+        assert!(expr("rate(vector(1)[5m])").is_err());
+        assert!(validate_str("rate(vector(1)[5m:])").is_ok());
+
+        assert!(validate_str("scalar(sum(x))").is_ok());
+        assert!(validate_str("vector(scalar(x))").is_ok());
+
+        let errors = validate_str("scalar(scalar(x))").unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "expected type instant vector in call to function \"scalar\", got scalar"
+        );
+    }
+
+    #[test]
+    fn test_unknown_function() {
+        let errors = validate_str("ratee(up[5m])").unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            r#"unknown function "ratee", did you mean "rate"?"#
+        );
+
+        let errors = validate_str("sum(hisogram_quantile(0.9, x))").unwrap_err();
+        assert!(
+            errors[0]
+                .message
+                .contains(r#"did you mean "histogram_quantile"?"#)
+        );
+
+        let errors = validate_str("non_existent_function_far_bar()").unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            r#"unknown function "non_existent_function_far_bar""#
+        );
+    }
+
+    #[test]
+    fn test_aggregation_parameter_types() {
+        for valid in [
+            "topk(5, x)",
+            "bottomk(scalar(y), x)",
+            "quantile(0.9, x) by (job)",
+            "limitk(-(1), x)",
+            r#"count_values("value", x)"#,
+        ] {
+            assert!(validate_str(valid).is_ok(), "{}", valid);
+        }
+
+        let cases = [
+            (
+                "topk(x, 5)",
+                "expected type scalar in aggregation parameter, got instant vector",
+            ),
+            (
+                "topk(x[5m], 5)",
+                "expected type scalar in aggregation parameter, got range vector",
+            ),
+            (
+                r#"quantile("0.9", x)"#,
+                "expected type scalar in aggregation parameter, got string",
+            ),
+            (
+                "count_values(5, x)",
+                "expected type string in aggregation parameter, got scalar",
+            ),
+        ];
+        for (input, message) in cases {
+            let errors = validate_str(input).unwrap_err();
+            assert_eq!(errors[0].message, message, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_limit_ratio_range() {
+        for valid in [
+            "limit_ratio(0.5, x)",
+            "limit_ratio(-0.5, x)",
+            "limit_ratio(1, x)",
+            "limit_ratio(-1, x)",
+            "limit_ratio(-(0.25), x) by (job)",
+            "limit_ratio(scalar(y), x)",
+        ] {
+            assert!(validate_str(valid).is_ok(), "{}", valid);
+        }
+
+        let errors = validate_str("limit_ratio(-1.5, x)").unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "ratio parameter of limit_ratio must be between -1 and 1, got -1.5"
+        );
+        assert!(validate_str("limit_ratio(2, x)").is_err());
+        assert!(validate_str("limit_ratio(NaN, x)").is_err());
+    }
+
+    #[test]
+    fn test_aggregation_body_type() {
+        for valid in [
+            "sum(rate(x[5m]))",
+            "avg by (job) (x + 1)",
+            "topk(5, sum(x))",
+            "count(vector(1))",
+        ] {
+            assert!(validate_str(valid).is_ok(), "{}", valid);
+        }
+
+        let cases = [
+            ("sum(time())", "scalar"),
+            ("avg(1)", "scalar"),
+            ("max(x[5m])", "range vector"),
+            ("min(rate(x[5m])[1h:])", "range vector"),
+            (r#"count("x")"#, "string"),
+            ("topk(3, scalar(x))", "scalar"),
+        ];
+        for (input, actual) in cases {
+            assert_eq!(
+                validate_str(input).unwrap_err(),
+                vec![ValidationError::new(format!(
+                    "expected instant vector in aggregation, got {}",
+                    actual
+                ))],
+                "{}",
+                input
+            );
+        }
+
+        // Nested aggregations are checked too
+        let errors = validate_str("sum(avg(1))").unwrap_err();
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn test_selector_needs_non_empty_matcher() {
+        for valid in [
+            "up",
+            r#"up{job=""}"#,
+            r#"up{job=~".*"}"#,
+            r#"{job="api"}"#,
+            r#"{job=~".+"}"#,
+            r#"{__name__=~"http_.*"}"#,
+            r#"{job="", env="prod"}"#,
+        ] {
+            assert!(validate_str(valid).is_ok(), "{}", valid);
+        }
+
+        let message = "vector selector must contain at least one non-empty matcher";
+        for invalid in [
+            r#"{job=""}"#,
+            r#"{job=~".*"}"#,
+            r#"{job!="api"}"#,
+            r#"{job!~"api"}"#,
+            r#"sum(rate({job=~".*"}[5m]))"#,
+            r#"up / on (job) {job!~".+"}"#,
+            r#"max_over_time({a=""}[1h:5m])"#,
+        ] {
+            assert_eq!(
+                validate_str(invalid).unwrap_err(),
+                vec![ValidationError::new(message)],
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_subquery_over_scalar() {
+        let errors = validate_str("rate(scalar(x)[5m:])").unwrap_err();
+        assert_eq!(
+            errors,
+            vec![ValidationError::new(
+                "expected type instant vector in subquery, got scalar"
+            )]
+        );
+        assert!(validate_str("max_over_time(1[5m:])").is_err());
+        assert!(validate_str("max_over_time(rate(x[1m])[5m:])").is_ok());
+    }
+}
@@ -0,0 +1,447 @@
+//! Traversal of the AST.
+//!
+//! Implement [`Visitor`] and override the methods for the nodes you care
+//! about; the default methods visit every child, so the rest of the tree
+//! is still walked. Start a traversal with [`Visitor::visit_expr`].
+//!
+//! An overriding method that should still descend into the node's
+//! children calls the matching `walk_*` function, such as [`walk_call`].
+//! Leaving it out skips the subtree.
+//!
+//! # Example
+//!
+//! Count the function calls in a query, not looking inside `absent`:
+//!
+//! ```rust
+//! use rusty_promql_parser::{Call, expr};
+//! use rusty_promql_parser::visit::{Visitor, walk_call};
+//!
+//! #[derive(Default)]
+//! struct CallCounter(usize);
+//!
+//! impl Visitor for CallCounter {
+//!     fn visit_call(&mut self, call: &Call) {
+//!         self.0 += 1;
+//!         if call.name != "absent" {
+//!             walk_call(self, call);
+//!         }
+//!     }
+//! }
+//!
+//! let (_, ast) = expr("rate(x[5m]) / absent(abs(y))").unwrap();
+//! let mut counter = CallCounter::default();
+//! counter.visit_expr(&ast);
+//! assert_eq!(counter.0, 2);
+//! ```
+//!
+//! [`VisitorMut`] works the same way, but hands out mutable references so
+//! that nodes can be rewritten in place. [`add_label_matcher`] is built on
+//! it.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::ast::{Aggregation, BinaryExpr, Call, Expr, SubqueryExpr, UnaryExpr};
+use crate::parser::selector::{LabelMatcher, MatrixSelector, VectorSelector};
+
+/// A read-only walk over an expression tree.
+///
+/// Each method handles one kind of node. The defaults for nodes with
+/// children call the matching `walk_*` function to visit them; leaf nodes
+/// do nothing.
+pub trait Visitor {
+    /// Visit any expression, dispatching on its variant
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr);
+    }
+
+    /// Visit a number literal
+    fn visit_number(&mut self, _value: f64) {}
+
+    /// Visit a string literal
+    fn visit_string(&mut self, _value: &str) {}
+
+    /// Visit an instant vector selector, including the one inside each
+    /// matrix selector
+    fn visit_vector_selector(&mut self, _selector: &VectorSelector) {}
+
+    /// Visit a range vector selector
+    fn visit_matrix_selector(&mut self, selector: &MatrixSelector) {
+        walk_matrix_selector(self, selector);
+    }
+
+    /// Visit a function call
+    fn visit_call(&mut self, call: &Call) {
+        walk_call(self, call);
+    }
+
+    /// Visit an aggregation
+    fn visit_aggregation(&mut self, aggregation: &Aggregation) {
+        walk_aggregation(self, aggregation);
+    }
+
+    /// Visit a binary expression
+    fn visit_binary(&mut self, binary: &BinaryExpr) {
+        walk_binary(self, binary);
+    }
+
+    /// Visit a unary expression
+    fn visit_unary(&mut self, unary: &UnaryExpr) {
+        walk_unary(self, unary);
+    }
+
+    /// Visit the expression inside parentheses
+    fn visit_paren(&mut self, inner: &Expr) {
+        self.visit_expr(inner);
+    }
+
+    /// Visit a subquery
+    fn visit_subquery(&mut self, subquery: &SubqueryExpr) {
+        walk_subquery(self, subquery);
+    }
+}
+
+/// Call the [`Visitor`] method for the variant of `expr`
+pub fn walk_expr<V: Visitor + ?Sized>(visitor: &mut V, expr: &Expr) {
+    match expr {
+        Expr::Number(n) => visitor.visit_number(n.value()),
+        Expr::String(s) => visitor.visit_string(s),
+        Expr::VectorSelector(v) => visitor.visit_vector_selector(v),
+        Expr::MatrixSelector(m) => visitor.visit_matrix_selector(m),
+        Expr::Call(c) => visitor.visit_call(c),
+        Expr::Aggregation(a) => visitor.visit_aggregation(a),
+        Expr::Binary(b) => visitor.visit_binary(b),
+        Expr::Unary(u) => visitor.visit_unary(u),
+        Expr::Paren(e) => visitor.visit_paren(e),
+        Expr::Subquery(s) => visitor.visit_subquery(s),
+    }
+}
+
+/// Visit the vector selector of a matrix selector
+pub fn walk_matrix_selector<V: Visitor + ?Sized>(visitor: &mut V, selector: &MatrixSelector) {
+    visitor.visit_vector_selector(&selector.selector);
+}
+
+/// Visit the arguments of a function call, in order
+pub fn walk_call<V: Visitor + ?Sized>(visitor: &mut V, call: &Call) {
+    for arg in &call.args {
+        visitor.visit_expr(arg);
+    }
+}
+
+/// Visit the parameter of an aggregation, if any, then the aggregated
+/// expression
+pub fn walk_aggregation<V: Visitor + ?Sized>(visitor: &mut V, aggregation: &Aggregation) {
+    if let Some(param) = &aggregation.param {
+        visitor.visit_expr(param);
+    }
+    visitor.visit_expr(&aggregation.expr);
+}
+
+/// Visit both operands of a binary expression, left first
+pub fn walk_binary<V: Visitor + ?Sized>(visitor: &mut V, binary: &BinaryExpr) {
+    visitor.visit_expr(&binary.lhs);
+    visitor.visit_expr(&binary.rhs);
+}
+
+/// Visit the operand of a unary expression
+pub fn walk_unary<V: Visitor + ?Sized>(visitor: &mut V, unary: &UnaryExpr) {
+    visitor.visit_expr(&unary.expr);
+}
+
+/// Visit the inner expression of a subquery
+pub fn walk_subquery<V: Visitor + ?Sized>(visitor: &mut V, subquery: &SubqueryExpr) {
+    visitor.visit_expr(&subquery.expr);
+}
+
+/// A walk over an expression tree that may modify it in place.
+///
+/// This mirrors [`Visitor`]: the defaults for nodes with children call the
+/// matching `walk_*_mut` function, and leaf nodes do nothing. Start a
+/// traversal with [`VisitorMut::visit_expr_mut`].
+pub trait VisitorMut {
+    /// Visit any expression, dispatching on its variant
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr);
+    }
+
+    /// Visit a number literal
+    fn visit_number_mut(&mut self, _value: &mut f64) {}
+
+    /// Visit a string literal
+    fn visit_string_mut(&mut self, _value: &mut String) {}
+
+    /// Visit an instant vector selector, including the one inside each
+    /// matrix selector
+    fn visit_vector_selector_mut(&mut self, _selector: &mut VectorSelector) {}
+
+    /// Visit a range vector selector
+    fn visit_matrix_selector_mut(&mut self, selector: &mut MatrixSelector) {
+        walk_matrix_selector_mut(self, selector);
+    }
+
+    /// Visit a function call
+    fn visit_call_mut(&mut self, call: &mut Call) {
+        walk_call_mut(self, call);
+    }
+
+    /// Visit an aggregation
+    fn visit_aggregation_mut(&mut self, aggregation: &mut Aggregation) {
+        walk_aggregation_mut(self, aggregation);
+    }
+
+    /// Visit a binary expression
+    fn visit_binary_mut(&mut self, binary: &mut BinaryExpr) {
+        walk_binary_mut(self, binary);
+    }
+
+    /// Visit a unary expression
+    fn visit_unary_mut(&mut self, unary: &mut UnaryExpr) {
+        walk_unary_mut(self, unary);
+    }
+
+    /// Visit the expression inside parentheses
+    fn visit_paren_mut(&mut self, inner: &mut Expr) {
+        self.visit_expr_mut(inner);
+    }
+
+    /// Visit a subquery
+    fn visit_subquery_mut(&mut self, subquery: &mut SubqueryExpr) {
+        walk_subquery_mut(self, subquery);
+    }
+}
+
+/// Call the [`VisitorMut`] method for the variant of `expr`
+pub fn walk_expr_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expr: &mut Expr) {
+    match expr {
+        Expr::Number(n) => {
+            let mut value = n.value();
+            visitor.visit_number_mut(&mut value);
+            // Keep the source spelling unless the visitor changed the value
+            if value.to_bits() != n.value().to_bits() {
+                n.set_value(value);
+            }
+        }
+        Expr::String(s) => visitor.visit_string_mut(s),
+        Expr::VectorSelector(v) => visitor.visit_vector_selector_mut(v),
+        Expr::MatrixSelector(m) => visitor.visit_matrix_selector_mut(m),
+        Expr::Call(c) => visitor.visit_call_mut(c),
+        Expr::Aggregation(a) => visitor.visit_aggregation_mut(a),
+        Expr::Binary(b) => visitor.visit_binary_mut(b),
+        Expr::Unary(u) => visitor.visit_unary_mut(u),
+        Expr::Paren(e) => visitor.visit_paren_mut(e),
+        Expr::Subquery(s) => visitor.visit_subquery_mut(s),
+    }
+}
+
+/// Visit the vector selector of a matrix selector
+pub fn walk_matrix_selector_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    selector: &mut MatrixSelector,
+) {
+    visitor.visit_vector_selector_mut(&mut selector.selector);
+}
+
+/// Visit the arguments of a function call, in order
+pub fn walk_call_mut<V: VisitorMut + ?Sized>(visitor: &mut V, call: &mut Call) {
+    for arg in &mut call.args {
+        visitor.visit_expr_mut(arg);
+    }
+}
+
+/// Visit the parameter of an aggregation, if any, then the aggregated
+/// expression
+pub fn walk_aggregation_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    aggregation: &mut Aggregation,
+) {
+    if let Some(param) = &mut aggregation.param {
+        visitor.visit_expr_mut(param);
+    }
+    visitor.visit_expr_mut(&mut aggregation.expr);
+}
+
+/// Visit both operands of a binary expression, left first
+pub fn walk_binary_mut<V: VisitorMut + ?Sized>(visitor: &mut V, binary: &mut BinaryExpr) {
+    visitor.visit_expr_mut(&mut binary.lhs);
+    visitor.visit_expr_mut(&mut binary.rhs);
+}
+
+/// Visit the operand of a unary expression
+pub fn walk_unary_mut<V: VisitorMut + ?Sized>(visitor: &mut V, unary: &mut UnaryExpr) {
+    visitor.visit_expr_mut(&mut unary.expr);
+}
+
+/// Visit the inner expression of a subquery
+pub fn walk_subquery_mut<V: VisitorMut + ?Sized>(visitor: &mut V, subquery: &mut SubqueryExpr) {
+    visitor.visit_expr_mut(&mut subquery.expr);
+}
+
+/// Collects the metric name of every selector, in source order.
+///
+/// Names appear once per selector, so duplicates are kept. Selectors
+/// without a metric name, like `{job="api"}`, are skipped. See
+/// [`Expr::metric_names`] for a deduplicated set.
+///
+/// # Example
+///
+/// ```rust
+/// use rusty_promql_parser::expr;
+/// use rusty_promql_parser::visit::{MetricNameCollector, Visitor};
+///
+/// let (_, ast) = expr("rate(errors[5m]) / rate(requests[5m]) > errors").unwrap();
+/// let mut collector = MetricNameCollector::default();
+/// collector.visit_expr(&ast);
+/// assert_eq!(collector.names, ["errors", "requests", "errors"]);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetricNameCollector {
+    /// The names collected so far
+    pub names: Vec<String>,
+}
+
+impl Visitor for MetricNameCollector {
+    fn visit_vector_selector(&mut self, selector: &VectorSelector) {
+        if let Some(name) = &selector.name {
+            self.names.push(name.clone());
+        }
+    }
+}
+
+/// Add `matcher` to every vector selector in `expr`, including those inside
+/// matrix selectors and subqueries.
+///
+/// This is the usual way to scope a query to one tenant. Existing matchers
+/// are kept, so a selector that already has a matcher on the same label
+/// ends up with both.
+///
+/// # Example
+///
+/// ```rust
+/// use rusty_promql_parser::{LabelMatchOp, LabelMatcher, expr};
+/// use rusty_promql_parser::visit::add_label_matcher;
+///
+/// let (_, mut ast) = expr("sum(rate(errors[5m])) / sum(rate(requests[5m]))").unwrap();
+/// add_label_matcher(&mut ast, LabelMatcher::new("tenant", LabelMatchOp::Equal, "a"));
+/// assert_eq!(
+///     ast.to_string(),
+///     r#"sum(rate(errors{tenant="a"}[5m])) / sum(rate(requests{tenant="a"}[5m]))"#
+/// );
+/// ```
+pub fn add_label_matcher(expr: &mut Expr, matcher: LabelMatcher) {
+    struct AddMatcher(LabelMatcher);
+
+    impl VisitorMut for AddMatcher {
+        fn visit_vector_selector_mut(&mut self, selector: &mut VectorSelector) {
+            selector.matchers.push(self.0.clone());
+        }
+    }
+
+    AddMatcher(matcher).visit_expr_mut(expr);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::expr::expr;
+    use crate::parser::selector::LabelMatchOp;
+    use alloc::vec;
+
+    /// Records the kind of every visited node
+    #[derive(Default)]
+    struct Recorder(Vec<&'static str>);
+
+    impl Visitor for Recorder {
+        fn visit_expr(&mut self, expr: &Expr) {
+            self.0.push(match expr {
+                Expr::Number(_) => "number",
+                Expr::String(_) => "string",
+                Expr::VectorSelector(_) => "vector",
+                Expr::MatrixSelector(_) => "matrix",
+                Expr::Call(_) => "call",
+                Expr::Aggregation(_) => "aggregation",
+                Expr::Binary(_) => "binary",
+                Expr::Unary(_) => "unary",
+                Expr::Paren(_) => "paren",
+                Expr::Subquery(_) => "subquery",
+            });
+            walk_expr(self, expr);
+        }
+    }
+
+    #[test]
+    fn test_visits_every_node_in_order() {
+        let (_, e) =
+            expr(r#"topk(3, -(rate(x[5m]) + 1)) / label_replace(y[1h:], "a", "b", "c", "d")"#)
+                .unwrap();
+        let mut recorder = Recorder::default();
+        recorder.visit_expr(&e);
+        assert_eq!(
+            recorder.0,
+            vec![
+                "binary",
+                "aggregation",
+                "number",
+                "unary",
+                "paren",
+                "binary",
+                "call",
+                "matrix",
+                "number",
+                "call",
+                "subquery",
+                "vector",
+                "string",
+                "string",
+                "string",
+                "string",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_metric_name_collector() {
+        let (_, e) = expr(
+            r#"sum by (job) (rate(http_requests_total{code="500"}[5m])) / on (job) {__name__="up"} unless {job="a"}"#,
+        )
+        .unwrap();
+        let mut collector = MetricNameCollector::default();
+        collector.visit_expr(&e);
+        assert_eq!(collector.names, vec!["http_requests_total", "up"]);
+    }
+
+    #[test]
+    fn test_visitor_mut_rewrites_nodes() {
+        struct Doubler;
+
+        impl VisitorMut for Doubler {
+            fn visit_number_mut(&mut self, value: &mut f64) {
+                *value *= 2.0;
+            }
+        }
+
+        let (_, mut e) = expr("topk(3, x) > bool (1 + -y[5m:1m] offset 1m)").unwrap();
+        Doubler.visit_expr_mut(&mut e);
+        assert_eq!(e.to_string(), "topk(6, x) > bool (2 + -y[5m:1m] offset 1m)");
+    }
+
+    #[test]
+    fn test_add_label_matcher() {
+        let input = r#"sum by (job) (rate(http_requests_total{code="500"}[5m])) / on (job) {__name__="up"} + count_over_time(x[1h:5m]) * 2"#;
+        let (_, mut e) = expr(input).unwrap();
+        add_label_matcher(
+            &mut e,
+            LabelMatcher::new("tenant", LabelMatchOp::Equal, "acme"),
+        );
+        let output = e.to_string();
+        assert_eq!(
+            output,
+            r#"sum by (job) (rate(http_requests_total{code="500", tenant="acme"}[5m])) / on (job) up{tenant="acme"} + count_over_time(x{tenant="acme"}[1h:5m]) * 2"#
+        );
+
+        // The rewritten query parses back to the same tree
+        let (rest, reparsed) = expr(&output).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(reparsed, e);
+    }
+}
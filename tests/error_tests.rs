@@ -1,10 +1,13 @@
 //! Parse error tests for PromQL
 //!
 //! These tests verify that the parser correctly rejects invalid inputs.
-//! We check that parsing fails, not the specific error message format
-//! (since nom errors are low-level and don't include position ranges).
+//! Most check that parsing fails, not the specific error message format
+//! (since nom errors are low-level and don't include position ranges); the
+//! rendered messages of `ParseError` and `ValidationError` are pinned at the
+//! end.
 
 use rusty_promql_parser::parser::expr;
+use rusty_promql_parser::{ParseError, ParseOptions, parse, parse_with_options, validate};
 
 /// Helper to assert that parsing fails
 fn assert_parse_fails(input: &str) {
@@ -94,6 +97,10 @@ fn test_invalid_duration_format() {
     assert_parse_fails("foo[5x]");
     // Negative duration in brackets
     assert_parse_error("foo[-5m]");
+    // Fractions and scientific notation
+    assert_parse_error("foo[1e3s]");
+    assert_parse_error("foo[1.5e2m]");
+    assert_parse_error("foo[1.5m]");
 }
 
 #[test]
@@ -241,3 +248,100 @@ fn test_gibberish() {
     assert_parse_error("@#$%");
     assert_parse_error("!!!");
 }
+
+// =============================================================================
+// Rendered Error Messages
+// =============================================================================
+
+#[test]
+fn test_parse_error_display() {
+    let cases = [
+        (
+            "sum(foo))",
+            "parse error at offset 8: unexpected closing delimiter ')' at column 9",
+        ),
+        (
+            "foo ;",
+            "parse error at offset 4: unexpected trailing input",
+        ),
+        ("sum(", "parse error at offset 4: unexpected end of input"),
+        (
+            "foo offset 1.5m",
+            "parse error at offset 11: expected duration",
+        ),
+    ];
+    for (input, expected) in cases {
+        let err = parse_with_options(input, &ParseOptions::default()).unwrap_err();
+        assert_eq!(err.to_string(), expected, "{}", input);
+    }
+}
+
+#[test]
+fn test_parse_requires_full_consumption() {
+    for input in [
+        "up",
+        "  up  \n",
+        "up # trailing comment",
+        "sum(rate(x[5m]))\t",
+    ] {
+        assert!(parse(input).is_ok(), "{}", input);
+    }
+
+    let cases = [
+        ("up down", ParseError::new(3, "unexpected trailing input")),
+        (
+            "rate(x[5m]) 5",
+            ParseError::new(12, "unexpected trailing input"),
+        ),
+        ("a + b c", ParseError::new(6, "unexpected trailing input")),
+        (
+            "sum(foo))",
+            ParseError::new(8, "unexpected closing delimiter ')' at column 9"),
+        ),
+        ("", ParseError::new(0, "unexpected end of input")),
+    ];
+    for (input, expected) in cases {
+        assert_eq!(parse(input).unwrap_err(), expected, "{}", input);
+    }
+}
+
+#[test]
+fn test_parse_error_line_col() {
+    let input = concat!(
+        "sum by (job) (\n",
+        "  rate(http_requests_total{code=\"500\"}[5m])\n",
+        "  / bool\n",
+        ")\n",
+    );
+    let err = parse(input).unwrap_err();
+    assert_eq!(err.line_col(input), (4, 1));
+
+    let input =
+        "histogram_quantile(0.9,\n\trate(latency_bucket{service=\"支付\"}[5m] offset 1.5m))";
+    let err = parse(input).unwrap_err();
+    assert_eq!(err.message, "expected duration");
+    assert_eq!(err.line_col(input), (2, 47));
+}
+
+#[test]
+fn test_validation_error_display() {
+    let cases = [
+        (
+            r#"label_replace(up, 5, "$1", "src", "(.*)")"#,
+            r#"validation error: expected type string in call to function "label_replace", got scalar"#,
+        ),
+        (
+            "ratee(foo[5m])",
+            r#"validation error: unknown function "ratee", did you mean "rate"?"#,
+        ),
+        (
+            "topk(foo, bar)",
+            "validation error: expected type scalar in aggregation parameter, got instant vector",
+        ),
+    ];
+    for (input, expected) in cases {
+        let (_, e) = expr(input).unwrap();
+        let errors = validate(&e).unwrap_err();
+        assert_eq!(errors[0].to_string(), expected, "{}", input);
+    }
+}
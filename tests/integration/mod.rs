@@ -158,11 +158,29 @@ pub const WHITESPACE_VARIATIONS: &[&str] = &[
     "sum( \t\n rate( \t\n http_requests_total[5m] \t\n ) \t\n ) by (job)",
 ];
 
-/// Comment handling (if supported - PromQL standard doesn't have comments)
-/// Some implementations might support line comments
-pub const WITH_COMMENTS: &[&str] = &[
-    // Standard PromQL doesn't have comments, but some tools might
-    // "some_metric # this is a comment",
+/// Queries with `#` line comments, paired with their printed form.
+///
+/// A comment runs to the end of the line and counts as whitespace, so it
+/// may appear wherever whitespace may. A `#` inside a string is not a
+/// comment.
+pub const WITH_COMMENTS: &[(&str, &str)] = &[
+    ("some_metric # this is a comment", "some_metric"),
+    ("some_metric # comment\n+ 1", "some_metric + 1"),
+    ("# leading comment\nup", "up"),
+    ("up # comment\r\n", "up"),
+    (
+        "sum by (job) ( # per job\n  rate(http_requests_total[5m]) # per second\n)",
+        "sum by (job) (rate(http_requests_total[5m]))",
+    ),
+    (
+        "up{# the job\njob=\"api\", # and the instance\ninstance=~\"a#b\"}",
+        r#"up{job="api", instance=~"a#b"}"#,
+    ),
+    (
+        r##"label_replace(up, "dst", "#$1", "src", "(.*)") # note"##,
+        r##"label_replace(up, "dst", "#$1", "src", "(.*)")"##,
+    ),
+    ("x offset # comment\n 5m", "x offset 5m"),
 ];
 
 #[cfg(test)]
@@ -185,6 +203,22 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_with_comments() {
+        use rusty_promql_parser::parse;
+
+        for (input, expected) in WITH_COMMENTS {
+            let result = parse(input);
+            assert!(
+                result.is_ok(),
+                "Failed to parse query with comments: {:?}\nError: {:?}",
+                input,
+                result.err()
+            );
+            assert_eq!(result.unwrap().to_string(), *expected, "{:?}", input);
+        }
+    }
+
     #[test]
     fn test_edge_cases() {
         // Verify edge case test data
@@ -101,6 +101,37 @@ fn test_valid_octal_from_test_data() {
     }
 }
 
+#[test]
+fn test_hex_and_octal_display() {
+    for (input, expected) in literal_tests::VALID_HEX
+        .iter()
+        .chain(literal_tests::VALID_OCTAL)
+    {
+        // Parsed literals keep their spelling
+        let (_, ast) = expr(input).unwrap();
+        assert_eq!(ast.to_string(), *input);
+
+        // Without it, they print as integers
+        let printed = Expr::Number((*expected).into()).to_string();
+        assert!(
+            printed.chars().all(|c| c.is_ascii_digit()),
+            "'{}' printed as '{}'",
+            input,
+            printed
+        );
+        assert_eq!(printed, (*expected as u64).to_string());
+
+        let (rest, reparsed) = expr(&printed).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(
+            reparsed,
+            Expr::Number((*expected).into()),
+            "round-trip of '{}'",
+            input
+        );
+    }
+}
+
 // =============================================================================
 // Special Float Values (Inf, NaN)
 // =============================================================================
@@ -209,7 +240,7 @@ fn test_expr_parses_numbers() {
         let (rest, e) = result.unwrap();
         assert!(rest.is_empty());
         match e {
-            Expr::Number(n) => assert_eq!(n, expected),
+            Expr::Number(n) => assert_eq!(n.value(), expected),
             _ => panic!("Expected Expr::Number for '{}'", input),
         }
     }
@@ -248,7 +279,7 @@ fn test_number_display_roundtrip() {
     // Test that Expr::Number Display produces valid PromQL that can be re-parsed
     let numbers = [42.0, 3.14, 1e10, f64::INFINITY, f64::NEG_INFINITY];
     for n in numbers {
-        let e = Expr::Number(n);
+        let e = Expr::Number(n.into());
         let displayed = format!("{}", e);
         // The displayed string should be parseable
         let result = number(&displayed);
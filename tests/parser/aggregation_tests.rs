@@ -111,6 +111,7 @@ pub const VALID_PARAMETRIC_AGGREGATIONS: &[&str] = &[
     // limit_ratio
     "limit_ratio(0.5, some_metric)",
     "limit_ratio(0.5, some_metric) by (job)",
+    "limit_ratio(-0.5, some_metric)",
 ];
 
 /// Nested aggregation test cases
@@ -203,7 +204,7 @@ pub const REAL_WORLD_AGGREGATIONS: &[&str] = &[
 #[cfg(test)]
 mod tests {
     use super::*;
-    use rusty_promql_parser::{Expr, expr};
+    use rusty_promql_parser::{Expr, GroupingAction, expr, parse, validate};
 
     #[test]
     fn test_simple_aggregations_parse() {
@@ -415,6 +416,121 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_aggregation_argument_count() {
+        for (input, offset, message) in [
+            (
+                "topk(5)",
+                4,
+                "wrong number of arguments for aggregate expression provided, expected 2, got 1",
+            ),
+            (
+                "sum(a, b)",
+                3,
+                "wrong number of arguments for aggregate expression provided, expected 1, got 2",
+            ),
+            (
+                "quantile by (job) (0.9, a, b)",
+                18,
+                "wrong number of arguments for aggregate expression provided, expected 2, got 3",
+            ),
+        ] {
+            let err = parse(input).unwrap_err();
+            assert_eq!(
+                (err.offset, err.message.as_str()),
+                (offset, message),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_aggregation_operator_as_metric_name() {
+        for (input, expected) in [
+            ("avg", "avg"),
+            ("rate(sum[5m])", "rate(sum[5m])"),
+            ("count + 1", "count + 1"),
+            (r#"topk{job="x"}"#, r#"topk{job="x"}"#),
+        ] {
+            assert_eq!(parse(input).unwrap().to_string(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_aggregation_quoted_grouping_labels() {
+        for (input, expected) in [
+            (r#"sum by ("a.b") (x)"#, r#"sum by ("a.b") (x)"#),
+            (
+                r#"sum without ('on', job) (x)"#,
+                r#"sum without ("on", job) (x)"#,
+            ),
+            (r#"count(x) by (`a b`)"#, r#"count by ("a b") (x)"#),
+            (r#"sum by ("job") (x)"#, "sum by (job) (x)"),
+        ] {
+            let e = parse(input).unwrap();
+            assert_eq!(e.to_string(), expected, "{}", input);
+            assert_eq!(parse(expected).unwrap(), e, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_invalid_aggregation_parameter_types() {
+        // The parser accepts any parameter expression; validation rejects
+        // those of the wrong type
+        let validation_enforced = [
+            "topk(some_metric, 5)",
+            "topk(some_metric, other_metric)",
+            "quantile(some_metric, 0.9)",
+            "count_values(5, some_metric)",
+        ];
+
+        for (input, message) in INVALID_AGGREGATIONS
+            .iter()
+            .copied()
+            .filter(|(input, _)| validation_enforced.contains(input))
+        {
+            let (remaining, parsed) = expr(input).unwrap();
+            assert!(remaining.is_empty(), "unparsed input: {:?}", remaining);
+            let errors = validate(&parsed).unwrap_err();
+            assert!(
+                errors[0].message.contains(message),
+                "Expected '{}' for '{}', got {:?}",
+                message,
+                input,
+                errors
+            );
+        }
+    }
+
+    #[test]
+    fn test_empty_grouping_ast_states() {
+        let cases = [
+            ("sum(x)", None),
+            ("sum by () (x)", Some(GroupingAction::By)),
+            ("sum without () (x)", Some(GroupingAction::Without)),
+        ];
+        for (input, expected_action) in cases {
+            let (remaining, parsed) = expr(input).unwrap();
+            assert!(remaining.is_empty(), "unparsed input: {:?}", remaining);
+            let Expr::Aggregation(agg) = &parsed else {
+                panic!("Expression '{}' should parse to Aggregation", input);
+            };
+            match (&agg.grouping, expected_action) {
+                (None, None) => {}
+                (Some(grouping), Some(action)) => {
+                    assert_eq!(grouping.action, action, "{}", input);
+                    assert!(grouping.labels.is_empty(), "{}", input);
+                }
+                (grouping, _) => panic!("unexpected grouping {:?} for '{}'", grouping, input),
+            }
+
+            // Display keeps each form, and reparsing gives the same AST
+            assert_eq!(parsed.to_string(), input);
+            assert_eq!(expr(&parsed.to_string()).unwrap().1, parsed);
+        }
+    }
+
     #[test]
     fn test_aggregation_operators() {
         assert_eq!(AGGREGATION_OPERATORS.len(), 14);
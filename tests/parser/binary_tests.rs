@@ -506,6 +506,30 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_vector_matching_quoted_labels() {
+        for (input, expected) in [
+            (r#"x / on("a.b") y"#, r#"x / on ("a.b") y"#),
+            (
+                r#"x * ignoring('on', job) group_left("c.d") y"#,
+                r#"x * ignoring ("on", job) group_left ("c.d") y"#,
+            ),
+            (
+                r#"x > bool on("job") group_right y"#,
+                "x > bool on (job) group_right y",
+            ),
+        ] {
+            let e = rusty_promql_parser::parse(input).unwrap();
+            assert_eq!(e.to_string(), expected, "{}", input);
+            assert_eq!(
+                rusty_promql_parser::parse(expected).unwrap(),
+                e,
+                "{}",
+                input
+            );
+        }
+    }
+
     #[test]
     fn test_parenthesized_f_gt_bar() {
         // "(f) > bar" - comparison with parentheses
@@ -242,7 +242,8 @@ pub const AGGREGATION_OPS: &[&str] = &[
 #[cfg(test)]
 mod tests {
     use super::*;
-    use rusty_promql_parser::{Expr, expr};
+    use rusty_promql_parser::parser::function::get_function;
+    use rusty_promql_parser::{Expr, expr, parse, validate};
 
     #[test]
     fn test_valid_function_calls_parse() {
@@ -278,23 +279,31 @@ mod tests {
 
     #[test]
     fn test_invalid_function_calls_fail() {
-        for (input, _error_desc) in INVALID_FUNCTION_CALLS {
-            let result = expr(input);
-            // Should either fail or not fully consume input
-            match result {
-                Err(_) => {
-                    // Good - it should fail
-                }
-                Ok((remaining, _)) => {
-                    // Some inputs might partially parse
-                    // Check if it's a known case where parsing succeeds
-                    // but semantic validation would fail
-                    if remaining.is_empty() {
-                        // Semantic errors (like unknown function, wrong arg count) might
-                        // not be caught by the parser, only by a later validation pass
-                    }
+        for (input, error_desc) in INVALID_FUNCTION_CALLS {
+            // Malformed calls are rejected by the parser, the rest by the
+            // semantic validation pass
+            let parsed = match parse(input) {
+                Ok(parsed) => parsed,
+                Err(err) => {
+                    assert!(
+                        err.message.contains(error_desc),
+                        "'{}' should report '{}', got {:?}",
+                        input,
+                        error_desc,
+                        err
+                    );
+                    continue;
                 }
-            }
+            };
+            let errors =
+                validate(&parsed).expect_err(&format!("'{}' should fail validation", input));
+            assert!(
+                errors.iter().any(|e| e.message.contains(error_desc)),
+                "'{}' should report '{}', got {:?}",
+                input,
+                error_desc,
+                errors
+            );
         }
     }
 
@@ -435,4 +444,23 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_double_exponential_smoothing() {
+        let (rest, parsed) = expr("double_exponential_smoothing(metric[5m], 0.5, 0.1)").unwrap();
+        assert!(rest.is_empty());
+        let Expr::Call(call) = &parsed else {
+            panic!("expected Call, got {:?}", parsed);
+        };
+        assert_eq!(call.name, "double_exponential_smoothing");
+        assert!(get_function(&call.name).unwrap().experimental);
+        assert_eq!(call.args.len(), 3);
+        assert!(matches!(call.args[0], Expr::MatrixSelector(_)));
+        assert_eq!(call.args[1], Expr::Number(0.5.into()));
+        assert_eq!(call.args[2], Expr::Number(0.1.into()));
+        assert_eq!(
+            parsed.to_string(),
+            "double_exponential_smoothing(metric[5m], 0.5, 0.1)"
+        );
+    }
 }
@@ -505,7 +505,7 @@ mod tests {
         assert!(result.is_ok());
         let (remaining, parsed) = result.unwrap();
         assert!(remaining.is_empty());
-        assert!(matches!(parsed, Expr::Number(v) if (v - 42.0).abs() < 1e-10));
+        assert!(matches!(parsed, Expr::Number(v) if (v.value() - 42.0).abs() < 1e-10));
     }
 
     #[test]
@@ -193,6 +193,18 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_zero_range_rejected() {
+        for (input, error_desc) in INVALID_MATRIX_SELECTORS
+            .iter()
+            .filter(|(_, desc)| desc.contains("greater than 0"))
+        {
+            assert!(matrix_selector(input).is_err(), "{}", input);
+            let err = rusty_promql_parser::parse(input).unwrap_err();
+            assert_eq!(err.message, *error_desc, "{}", input);
+        }
+    }
+
     #[test]
     fn test_valid_matrix_selectors() {
         for input in VALID_MATRIX_SELECTORS {
@@ -44,6 +44,11 @@ pub const VALID_VECTOR_SELECTORS: &[&str] = &[
     r#"{__name__=~"bar", __name__!~"baz"}"#,
     r#"{__name__="bar", __name__="baz"}"#,
     r#"{"bar", __name__="baz"}"#,
+    // Quoted label names
+    r#"{"http.requests"="1", job="x"}"#,
+    r#"foo{"label.with.dots"=~"a.*"}"#,
+    r#"{"my.metric", "service.name"!="api"}"#,
+    r#"up{'weird label'="x"}"#,
     // Single-quoted strings
     r#"{foo='bar'}"#,
     // Backtick strings for label values
@@ -142,6 +147,11 @@ pub const SELECTOR_WITH_AT: &[(&str, i64)] = &[
     ("foo @ 3.33", 3_330),
     ("foo @ 3.3333", 3_333), // Rounds down
     ("foo @ 3.3335", 3_334), // Rounds up
+    ("foo @ 3.3334", 3_333),
+    ("foo @ 3.0005", 3_001), // Ties round away from zero
+    ("foo @ -3.3335", -3_334),
+    ("foo @ 0.0005", 1),
+    ("foo @ 1603774568.1235", 1_603_774_568_124),
     ("foo @ 3e2", 300_000),
     ("foo @ 3e-1", 300),
     ("foo @ 0xA", 10_000),
@@ -168,7 +178,9 @@ pub const INVALID_AT_MODIFIER: &[(&str, &str)] = &[
 #[cfg(test)]
 mod tests {
     use super::*;
-    use rusty_promql_parser::parser::selector::{LabelMatchOp, vector_selector};
+    use rusty_promql_parser::Duration;
+    use rusty_promql_parser::parser::selector::{AtModifier, LabelMatchOp, vector_selector};
+    use rusty_promql_parser::{parse, validate};
 
     fn assert_selector_fails(input: &str) {
         match vector_selector(input) {
@@ -209,6 +221,7 @@ mod tests {
             r#"foo{__name__="bar" lol}"#,
             r#"foo{"a"=}"#,
             r#"foo{__name__= =}"#,
+            r#"foo{__name__="bar"}"#,
         ];
 
         for (input, _) in INVALID_VECTOR_SELECTORS
@@ -219,6 +232,23 @@ mod tests {
             assert_selector_fails(input);
         }
 
+        // Selectors that could select every series parse, but fail validation
+        for (input, error_desc) in INVALID_VECTOR_SELECTORS
+            .iter()
+            .copied()
+            .filter(|(_, desc)| desc.contains("non-empty matcher"))
+        {
+            let parsed = parse(input).unwrap();
+            let errors = validate(&parsed).unwrap_err();
+            assert!(
+                errors[0].message.contains(error_desc),
+                "'{}' should report '{}', got {:?}",
+                input,
+                error_desc,
+                errors
+            );
+        }
+
         for (input, error_desc) in INVALID_VECTOR_SELECTORS {
             assert!(
                 !error_desc.is_empty(),
@@ -233,6 +263,17 @@ mod tests {
         for (input, _error_desc) in INVALID_AT_MODIFIER {
             assert_selector_fails(input);
         }
+
+        for (input, error_desc) in INVALID_AT_MODIFIER {
+            let err = parse(input).unwrap_err();
+            assert!(
+                err.message.contains(error_desc),
+                "'{}' should report '{}', got {:?}",
+                input,
+                error_desc,
+                err
+            );
+        }
     }
 
     #[test]
@@ -350,6 +391,27 @@ mod tests {
         assert_eq!(sel.name, Some("foo".to_string()));
     }
 
+    #[test]
+    fn test_quoted_label_name() {
+        let (rest, sel) = vector_selector(r#"{"http.requests"="1", job="x"}"#).unwrap();
+        assert!(rest.is_empty());
+        assert!(sel.name.is_none());
+        assert_eq!(sel.matchers[0].name, "http.requests");
+        assert_eq!(sel.matchers[0].value, "1");
+        assert_eq!(sel.matchers[1].name, "job");
+        assert_eq!(sel.to_string(), r#"{"http.requests"="1", job="x"}"#);
+
+        // Quoting a valid label name makes no difference
+        let (_, sel) = vector_selector(r#"up{"job"="x"}"#).unwrap();
+        assert_eq!(sel.matchers[0].name, "job");
+        assert_eq!(sel.to_string(), r#"up{job="x"}"#);
+
+        // A quoted __name__ label sets the metric name
+        let (_, sel) = vector_selector(r#"{"__name__"="my.metric", env="a"}"#).unwrap();
+        assert_eq!(sel.name, Some("my.metric".to_string()));
+        assert_eq!(sel.to_string(), r#"{"my.metric", env="a"}"#);
+    }
+
     #[test]
     fn test_label_only_selector() {
         let (rest, sel) = vector_selector(r#"{job="prometheus"}"#).unwrap();
@@ -387,6 +449,68 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_at_preprocessor_keywords_as_names() {
+        use rusty_promql_parser::parser::selector::AtModifier;
+        use rusty_promql_parser::{Expr, expr};
+
+        let parse = |input: &str| {
+            let (rest, e) = expr(input).unwrap();
+            assert!(rest.is_empty(), "Unexpected remaining input: '{}'", rest);
+            e
+        };
+
+        for name in ["start", "end", "step"] {
+            match parse(name) {
+                Expr::VectorSelector(sel) => assert_eq!(sel.name.as_deref(), Some(name)),
+                other => panic!("Expected VectorSelector for '{}', got {:?}", name, other),
+            }
+        }
+
+        match parse(r#"step{start="a", end!="b", step=~"c"}"#) {
+            Expr::VectorSelector(sel) => {
+                assert_eq!(sel.name.as_deref(), Some("step"));
+                let names: Vec<&str> = sel.matchers.iter().map(|m| m.name.as_str()).collect();
+                assert_eq!(names, ["start", "end", "step"]);
+                assert_eq!(sel.at, None);
+            }
+            other => panic!("Expected VectorSelector, got {:?}", other),
+        }
+
+        // Only a `@` turns start() and end() into preprocessors
+        match parse("start @ end()") {
+            Expr::VectorSelector(sel) => {
+                assert_eq!(sel.name.as_deref(), Some("start"));
+                assert_eq!(sel.at, Some(AtModifier::End));
+            }
+            other => panic!("Expected VectorSelector, got {:?}", other),
+        }
+        match parse("end[5m] @ start()") {
+            Expr::MatrixSelector(m) => {
+                assert_eq!(m.name(), Some("end"));
+                assert_eq!(m.selector.at, Some(AtModifier::Start));
+            }
+            other => panic!("Expected MatrixSelector, got {:?}", other),
+        }
+
+        for input in [
+            "start + end",
+            "rate(step[5m])",
+            r#"{start="x"}"#,
+            "sum by (start, end, step) (start)",
+            "end / on (step) group_left (start) start",
+            "end offset 5m",
+        ] {
+            let e = parse(input);
+            assert!(
+                e.vector_selectors().iter().all(|sel| sel.at.is_none()),
+                "{}",
+                input
+            );
+            assert_eq!(e.to_string(), input);
+        }
+    }
+
     #[test]
     fn test_whitespace_in_label_matchers() {
         let (rest, sel) = vector_selector(r#"foo{ bar = "baz" }"#).unwrap();
@@ -418,6 +542,29 @@ mod tests {
         assert!(display.contains("bar"));
     }
 
+    #[test]
+    fn test_with_offset_and_with_at() {
+        let foo = parse("foo").unwrap();
+        for (input, offset_ms) in SELECTOR_WITH_OFFSET {
+            let offset = Duration::from_millis(*offset_ms);
+            assert_eq!(
+                foo.clone().with_offset(offset),
+                Ok(parse(input).unwrap()),
+                "{}",
+                input
+            );
+        }
+        for (input, timestamp_ms) in SELECTOR_WITH_AT {
+            let at = AtModifier::Timestamp(*timestamp_ms);
+            assert_eq!(
+                foo.clone().with_at(at),
+                Ok(parse(input).unwrap()),
+                "{}",
+                input
+            );
+        }
+    }
+
     // Offset modifier integration tests
     #[test]
     fn test_selectors_with_offset() {
@@ -121,9 +121,10 @@ pub const INVALID_SUBQUERIES: &[(&str, &str)] = &[
     ("some_metric[5m][5m:1m]", "unexpected character"),
     // Negative step (invalid in standard promql)
     ("some_metric[5m:-1m]", "unexpected"),
-    // Zero step is semantically invalid
-    // ("some_metric[5m:0s]", "step cannot be zero"),
-
+    // Zero range or step
+    ("some_metric[5m:0s]", "duration must be greater than 0"),
+    ("some_metric[0s:1m]", "duration must be greater than 0"),
+    ("some_metric[0m:]", "duration must be greater than 0"),
     // Step larger than range (semantically questionable but might parse)
     // ("some_metric[5m:1h]", "..."),
 
@@ -142,6 +143,7 @@ pub const HPE_SUBQUERY_TESTS: &[&str] = &[
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rusty_promql_parser::parser::function::ValueType;
     use rusty_promql_parser::{Expr, expr};
 
     #[test]
@@ -444,6 +446,41 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_subquery_of_non_vector_fails_validation() {
+        for (input, error_desc) in INVALID_SUBQUERIES
+            .iter()
+            .filter(|(_, desc)| desc.contains("expected type instant vector"))
+        {
+            let parsed = rusty_promql_parser::parse(input).unwrap();
+            let Expr::Subquery(sq) = &parsed else {
+                panic!("expected a subquery for {}", input);
+            };
+            assert_ne!(sq.expr.value_type(), ValueType::Vector, "{}", input);
+            let errors = rusty_promql_parser::validate(&parsed).unwrap_err();
+            assert!(
+                errors[0].message.contains(error_desc),
+                "'{}' should report '{}', got {:?}",
+                input,
+                error_desc,
+                errors
+            );
+        }
+    }
+
+    #[test]
+    fn test_zero_durations_rejected() {
+        for (input, error_desc) in INVALID_SUBQUERIES
+            .iter()
+            .filter(|(_, desc)| desc.contains("greater than 0"))
+        {
+            let err = rusty_promql_parser::parse(input).unwrap_err();
+            assert_eq!(err.message, *error_desc, "{}", input);
+        }
+        // The step may be left out, but not the range
+        assert!(rusty_promql_parser::parse("some_metric[5m:]").is_ok());
+    }
+
     #[test]
     fn test_hpe_subquery_tests_parse() {
         for input in HPE_SUBQUERY_TESTS {
@@ -237,11 +237,11 @@ mod tests {
             Expr::Unary(unary) => {
                 assert_eq!(unary.op, UnaryOp::Minus);
                 if let Expr::Number(n) = unary.expr {
-                    assert_eq!(n, 42.0);
+                    assert_eq!(n.value(), 42.0);
                 }
             }
             Expr::Number(n) => {
-                assert_eq!(n, -42.0);
+                assert_eq!(n.value(), -42.0);
             }
             _ => panic!("Expected Unary or Number, got {:?}", e),
         }
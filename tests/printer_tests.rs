@@ -6,6 +6,7 @@
 //! Based on test cases from:
 //! - references/prometheus/promql/parser/printer_test.go
 
+use rusty_promql_parser::Expr;
 use rusty_promql_parser::parser::expr;
 
 /// Helper to test expression display canonicalization
@@ -102,6 +103,15 @@ fn test_aggregation_quantile() {
     assert_expr_string(r#"quantile(0.9, metric)"#, None);
 }
 
+#[test]
+fn test_aggregation_limit_ratio_negative() {
+    assert_expr_string("limit_ratio(-0.5, metric)", None);
+    assert_expr_string(
+        "limit_ratio(-0.5, metric) by (job)",
+        Some("limit_ratio by (job) (-0.5, metric)"),
+    );
+}
+
 // =============================================================================
 // Binary Expression Display Tests
 // =============================================================================
@@ -190,8 +200,10 @@ fn test_offset_vector_selector() {
 
 #[test]
 fn test_offset_negative() {
-    // Note: Display may normalize negative offset representation
+    // The sign stays inside the duration, as Prometheus prints it
     assert_expr_string("a offset -7m", None);
+    assert_expr_string("a offset -1h30m", None);
+    assert_expr_string("a @ 100 offset -5m", Some("a @ 100.000 offset -5m"));
 }
 
 #[test]
@@ -199,11 +211,15 @@ fn test_offset_matrix_selector() {
     assert_expr_string(r#"a{c="d"}[5m] offset 1m"#, None);
     assert_expr_string("a[5m] offset 1m", None);
     assert_expr_string("a[12m] offset -3m", None);
+    assert_expr_string("x[5m] offset -1h", None);
+    assert_expr_string("rate(x[5m] offset -1h)", None);
 }
 
 #[test]
 fn test_offset_subquery() {
     assert_expr_string("a[1h:5m] offset 1m", None);
+    assert_expr_string("a[1h:5m] offset -1m", None);
+    assert_expr_string("rate(a[5m])[1h:] offset -10m", None);
 }
 
 // =============================================================================
@@ -281,6 +297,15 @@ fn test_subquery_nested() {
     assert_expr_string("rate(metric[5m])[30m:1m]", None);
 }
 
+#[test]
+fn test_subquery_with_at_on_inner_selector() {
+    // The inner @ pins the range selector, the outer one the subquery
+    assert_expr_string(
+        "rate(http[5m] @ 100)[30m:1m] @ 200",
+        Some("rate(http[5m] @ 100.000)[30m:1m] @ 200.000"),
+    );
+}
+
 // =============================================================================
 // Function Call Display Tests
 // =============================================================================
@@ -311,6 +336,36 @@ fn test_function_call_predict_linear() {
     assert_expr_string("predict_linear(foo[1h], 3000)", None);
 }
 
+#[test]
+fn test_function_call_info_label_selector() {
+    // The second argument is a name-less, matchers-only selector
+    let input = r#"info(http_total, {namespace="zzz"})"#;
+    assert_expr_string(input, None);
+    assert_expr_string(
+        r#"info(rate(x[5m]), {k8s_cluster_name=~"prod.*", env!="dev"})"#,
+        None,
+    );
+
+    let (_, original) = expr(input).unwrap();
+    let printed = original.to_string();
+    let (rest, reparsed) = expr(&printed).unwrap();
+    assert!(rest.is_empty());
+    match (&original, &reparsed) {
+        (Expr::Call(a), Expr::Call(b)) => {
+            assert_eq!(a.args.len(), 2);
+            assert_eq!(a.args[1], b.args[1]);
+            match &b.args[1] {
+                Expr::VectorSelector(sel) => {
+                    assert_eq!(sel.name, None);
+                    assert_eq!(sel.to_string(), r#"{namespace="zzz"}"#);
+                }
+                other => panic!("Expected VectorSelector, got {:?}", other),
+            }
+        }
+        other => panic!("Expected two calls, got {:?}", other),
+    }
+}
+
 // =============================================================================
 // Complex Expression Display Tests
 // =============================================================================
@@ -369,6 +424,37 @@ fn test_string_literal() {
     assert_expr_string(r#""hello world""#, None);
 }
 
+#[test]
+fn test_string_quote_style_normalized() {
+    // Single-quoted and raw strings print double-quoted with the same value
+    assert_expr_string(r"'it\'s'", Some(r#""it's""#));
+    assert_expr_string(r#"'say "hi"'"#, Some(r#""say \"hi\"""#));
+    assert_expr_string(r"`a\nb`", Some(r#""a\\nb""#));
+    assert_expr_string(r#"`C:\dir "x"`"#, Some(r#""C:\\dir \"x\"""#));
+    assert_expr_string(r#""tab\there""#, None);
+    assert_expr_string(r#""\x01\u0085""#, None);
+    assert_expr_string(r#""caf\u00e9""#, Some(r#""café""#));
+    assert_expr_string(r#"foo{a=`x\y`, b='\''}"#, Some(r#"foo{a="x\\y", b="'"}"#));
+}
+
+#[test]
+fn test_string_value_round_trip() {
+    for input in [
+        r"`a\nb`",
+        r"'it\'s'",
+        "`line\nbreak`",
+        r#""\a\b\f\v\\\"""#,
+        r#"`\u00e9 \x41 \101`"#,
+        r#""日本語 \U0001F600""#,
+    ] {
+        let (_, e) = expr(input).unwrap();
+        let printed = e.to_string();
+        let (rest, reparsed) = expr(&printed).unwrap();
+        assert!(rest.is_empty(), "{} printed as {}", input, printed);
+        assert_eq!(reparsed, e, "{} printed as {}", input, printed);
+    }
+}
+
 // =============================================================================
 // Parenthesis Display Tests
 // =============================================================================
@@ -383,4 +469,48 @@ fn test_paren_simple() {
 fn test_paren_nested() {
     assert_expr_string("((1))", None);
     assert_expr_string("((1 + 2))", None);
+    assert_expr_string("((x))", None);
+    assert_expr_string("(((((some_metric)))))", None);
+    assert_expr_string("(sum by (job) (rate(x[5m])))", None);
+}
+
+// =============================================================================
+// Keyword Case-Insensitivity Tests
+// =============================================================================
+
+#[test]
+fn test_keywords_case_insensitive() {
+    let cases = [
+        ("a and b", &["AND", "And", "aNd"][..]),
+        ("a or b", &["OR", "Or", "oR"]),
+        ("a unless b", &["UNLESS", "Unless", "uNLESS"]),
+        ("a atan2 b", &["ATAN2", "Atan2"]),
+        ("foo offset 5m", &["OFFSET", "Offset", "oFFsET"]),
+        ("foo @ start()", &["START()", "Start()"]),
+        ("foo @ end()", &["END()", "End()"]),
+        ("sum by (job) (x)", &["BY", "By"]),
+        ("sum without (job) (x)", &["WITHOUT", "Without"]),
+        ("1 > bool 2", &["BOOL", "Bool"]),
+        ("a / on (job) b", &["ON", "On"]),
+        ("a / ignoring (job) b", &["IGNORING", "Ignoring"]),
+        (
+            "a / on (job) group_left (x) b",
+            &["GROUP_LEFT", "Group_Left"],
+        ),
+        (
+            "a / on (job) group_right (x) b",
+            &["GROUP_RIGHT", "group_RIGHT"],
+        ),
+        ("topk(3, x)", &["TOPK", "TopK"]),
+    ];
+
+    for (canonical, variants) in cases {
+        // The keyword is the only word differing between the variants
+        let keyword = variants[0].to_lowercase();
+        for variant in variants {
+            let input = canonical.replacen(&keyword, variant, 1);
+            assert_ne!(input, canonical, "variant '{}' not substituted", variant);
+            assert_expr_string(&input, Some(canonical));
+        }
+    }
 }
@@ -0,0 +1,133 @@
+//! Tests for the Prometheus-compatible JSON AST
+
+#![cfg(feature = "json")]
+
+// Only the real-world queries are used here
+#[allow(dead_code)]
+#[path = "integration/mod.rs"]
+mod integration;
+
+use integration::REAL_WORLD_QUERIES;
+use rusty_promql_parser::json::to_prometheus_json;
+use rusty_promql_parser::parse;
+use serde_json::{Value, json};
+
+fn prometheus_json(query: &str) -> Value {
+    assert!(REAL_WORLD_QUERIES.contains(&query), "{}", query);
+    to_prometheus_json(&parse(query).unwrap())
+}
+
+fn selector(name: &str) -> Value {
+    json!({
+        "type": "vectorSelector",
+        "name": name,
+        "offset": 0,
+        "matchers": [{"name": "__name__", "type": "=", "value": name}],
+        "timestamp": null,
+        "startOrEnd": null,
+    })
+}
+
+#[test]
+fn test_group_left() {
+    assert_eq!(
+        prometheus_json("http_requests_total / on(instance) group_left(job) http_requests_errors"),
+        json!({
+            "type": "binaryExpr",
+            "op": "/",
+            "lhs": selector("http_requests_total"),
+            "rhs": selector("http_requests_errors"),
+            "matching": {
+                "card": "many-to-one",
+                "labels": ["instance"],
+                "on": true,
+                "include": ["job"],
+            },
+            "bool": false,
+        })
+    );
+}
+
+#[test]
+fn test_subquery_of_rate() {
+    assert_eq!(
+        prometheus_json("avg_over_time(rate(http_requests_total[5m])[30m:1m])"),
+        json!({
+            "type": "call",
+            "func": {
+                "name": "avg_over_time",
+                "argTypes": ["matrix"],
+                "variadic": 0,
+                "returnType": "vector",
+            },
+            "args": [{
+                "type": "subquery",
+                "expr": {
+                    "type": "call",
+                    "func": {
+                        "name": "rate",
+                        "argTypes": ["matrix"],
+                        "variadic": 0,
+                        "returnType": "vector",
+                    },
+                    "args": [{
+                        "type": "matrixSelector",
+                        "name": "http_requests_total",
+                        "range": 300000,
+                        "offset": 0,
+                        "matchers": [
+                            {"name": "__name__", "type": "=", "value": "http_requests_total"},
+                        ],
+                        "timestamp": null,
+                        "startOrEnd": null,
+                    }],
+                },
+                "range": 1800000,
+                "offset": 0,
+                "step": 60000,
+                "timestamp": null,
+                "startOrEnd": null,
+            }],
+        })
+    );
+}
+
+#[test]
+fn test_real_world_queries_are_tagged() {
+    fn check(node: &Value) {
+        match node {
+            Value::Object(map) => {
+                if let Some(Value::String(t)) = map.get("type") {
+                    assert!(
+                        [
+                            "numberLiteral",
+                            "stringLiteral",
+                            "vectorSelector",
+                            "matrixSelector",
+                            "call",
+                            "aggregation",
+                            "binaryExpr",
+                            "unaryExpr",
+                            "parenExpr",
+                            "subquery",
+                        ]
+                        .contains(&t.as_str())
+                            // label matcher types
+                            || ["=", "!=", "=~", "!~"].contains(&t.as_str()),
+                        "unknown node type {}",
+                        t
+                    );
+                }
+                map.values().for_each(check);
+            }
+            Value::Array(items) => items.iter().for_each(check),
+            _ => {}
+        }
+    }
+
+    for query in REAL_WORLD_QUERIES {
+        let value = prometheus_json(query);
+        assert!(value["type"].is_string(), "{}", query);
+        check(&value);
+    }
+}
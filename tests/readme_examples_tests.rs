@@ -27,6 +27,6 @@ fn test_aggregation_operators() {
     let (rest, ast) = expr(input).expect("failed to parse");
     assert!(rest.is_empty());
 
-    let expected = r#"Binary(BinaryExpr { op: Div, lhs: Binary(BinaryExpr { op: Div, lhs: Aggregation(Aggregation { op: "sum", expr: Binary(BinaryExpr { op: Sub, lhs: VectorSelector(VectorSelector { name: Some("instance_memory_limit_bytes"), matchers: [], offset: None, at: None }), rhs: VectorSelector(VectorSelector { name: Some("instance_memory_usage_bytes"), matchers: [], offset: None, at: None }), modifier: None }), param: None, grouping: Some(Grouping { action: By, labels: ["app", "proc"] }) }), rhs: Number(1024.0), modifier: None }), rhs: Number(1024.0), modifier: None })"#;
+    let expected = r#"Binary(BinaryExpr { op: Div, lhs: Binary(BinaryExpr { op: Div, lhs: Aggregation(Aggregation { op: "sum", expr: Binary(BinaryExpr { op: Sub, lhs: VectorSelector(VectorSelector { name: Some("instance_memory_limit_bytes"), matchers: [], offset: None, at: None }), rhs: VectorSelector(VectorSelector { name: Some("instance_memory_usage_bytes"), matchers: [], offset: None, at: None }), modifier: None }), param: None, grouping: Some(Grouping { action: By, labels: ["app", "proc"] }) }), rhs: Number(NumberLiteral { value: 1024.0, raw: Some("1024") }), modifier: None }), rhs: Number(NumberLiteral { value: 1024.0, raw: Some("1024") }), modifier: None })"#;
     assert_eq!(format!("{:?}", ast), expected);
 }
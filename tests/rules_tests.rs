@@ -0,0 +1,167 @@
+//! Tests for rule file parsing
+
+#![cfg(feature = "rules")]
+
+use rusty_promql_parser::rules::{RuleKind, order_recording_rules, parse_rule_file};
+use rusty_promql_parser::{Expr, expr};
+
+const RULES: &str = include_str!("fixtures/rules.yml");
+
+#[test]
+fn test_rule_file_valid_and_invalid() {
+    let rules = parse_rule_file(RULES).unwrap();
+    assert_eq!(rules.len(), 2);
+
+    let record = &rules[0];
+    assert_eq!(record.group, "node");
+    assert_eq!(record.name, "instance:node_cpu:rate5m");
+    assert_eq!(record.kind, RuleKind::Record);
+    assert!(matches!(record.expr, Ok(Expr::Aggregation(_))));
+
+    let alert = &rules[1];
+    assert_eq!(alert.name, "HighCpu");
+    assert_eq!(alert.kind, RuleKind::Alert);
+    let err = alert.expr.as_ref().unwrap_err();
+    assert_eq!(err.line, 11);
+    assert_eq!(err.column, Some(42));
+    assert_eq!(
+        err.to_string(),
+        "line 11, column 42: unexpected closing delimiter ')'"
+    );
+}
+
+#[test]
+fn test_rule_error_position_in_block_scalar() {
+    let yaml = "\
+groups:
+  - name: g
+    rules:
+      - record: x
+        expr: |
+          sum by (job) (
+            rate(http_requests_total[1.5m])
+          )
+";
+    let rules = parse_rule_file(yaml).unwrap();
+    let err = rules[0].expr.as_ref().unwrap_err();
+    assert_eq!((err.line, err.column), (7, Some(38)));
+    assert_eq!(rules[0].line, 6);
+}
+
+/// Rule file with a single rule whose `expr` is `expr`, indented by 8
+fn single_rule(expr: &str) -> String {
+    format!(
+        "groups:\n  - name: g\n    rules:\n      - alert: A\n        expr: {}\n",
+        expr
+    )
+}
+
+#[test]
+fn test_rule_error_position_in_single_quoted_scalar() {
+    let rules = parse_rule_file(&single_rule("'up{a=\"b\"} == )'")).unwrap();
+    let err = rules[0].expr.as_ref().unwrap_err();
+    assert_eq!((err.line, err.column), (5, Some(29)));
+}
+
+#[test]
+fn test_rule_error_position_unknown_when_text_differs_from_file() {
+    let cases = [
+        // Escapes make the text shorter than the source
+        r#""up{a=\"b\"} == )""#,
+        r#"'up{a=''b''} == )'"#,
+        // Plain and folded scalars join their lines
+        "up\n          == )",
+        ">\n          up\n          == )",
+    ];
+    for expr in cases {
+        let rules = parse_rule_file(&single_rule(expr)).unwrap();
+        let err = rules[0].expr.as_ref().unwrap_err();
+        assert_eq!((err.line, err.column), (rules[0].line, None), "{}", expr);
+        assert_eq!(
+            err.to_string(),
+            format!("line {}: unexpected closing delimiter ')'", rules[0].line),
+            "{}",
+            expr
+        );
+    }
+}
+
+#[test]
+fn test_rule_error_position_in_quoted_scalar() {
+    let yaml = "groups:\n  - name: g\n    rules:\n      - alert: A\n        expr: \"up == )\"\n";
+    let rules = parse_rule_file(yaml).unwrap();
+    let err = rules[0].expr.as_ref().unwrap_err();
+    // Past the opening quote
+    assert_eq!((err.line, err.column), (5, Some(22)));
+}
+
+#[test]
+fn test_rule_file_json() {
+    let json = r#"{"groups": [{"name": "g", "rules": [{"alert": "Down", "expr": "up == 0"}]}]}"#;
+    let rules = parse_rule_file(json).unwrap();
+    assert_eq!(rules.len(), 1);
+    assert_eq!(rules[0].expr.as_ref().unwrap().to_string(), "up == 0");
+}
+
+#[test]
+fn test_rule_file_structure_errors() {
+    let err = parse_rule_file("rules: []").unwrap_err();
+    assert_eq!(err.to_string(), "line 1: missing groups");
+
+    let yaml = "groups:\n  - name: g\n    rules:\n      - record: x\n";
+    let err = parse_rule_file(yaml).unwrap_err();
+    assert_eq!(err.to_string(), "line 4: record \"x\" has no expr");
+
+    let yaml = "groups:\n  - name: g\n    rules:\n      - expr: up\n";
+    let err = parse_rule_file(yaml).unwrap_err();
+    assert_eq!(err.line, 4);
+
+    assert!(parse_rule_file("groups: [").is_err());
+}
+
+#[test]
+fn test_order_recording_rules_chain() {
+    let queries = [
+        ("c", "b * 2"),
+        ("unrelated", "up"),
+        ("b", "sum(a) + a"),
+        ("a", "rate(x[5m])"),
+        ("b", "sum(a{job=\"other\"})"),
+    ];
+    let exprs: Vec<Expr> = queries.iter().map(|(_, q)| expr(q).unwrap().1).collect();
+    let rules: Vec<(&str, &Expr)> = queries
+        .iter()
+        .zip(&exprs)
+        .map(|((name, _), e)| (*name, e))
+        .collect();
+    assert_eq!(order_recording_rules(&rules).unwrap(), [1, 3, 2, 4, 0]);
+}
+
+#[test]
+fn test_order_recording_rules_self_reference() {
+    let (_, e) = expr("a offset 1m").unwrap();
+    assert_eq!(order_recording_rules(&[("a", &e)]).unwrap(), [0]);
+}
+
+#[test]
+fn test_order_recording_rules_cycle() {
+    let queries = [
+        ("root", "rate(x[5m])"),
+        ("a", "c + root"),
+        ("b", "a"),
+        ("c", "b"),
+        ("d", "c"),
+    ];
+    let exprs: Vec<Expr> = queries.iter().map(|(_, q)| expr(q).unwrap().1).collect();
+    let rules: Vec<(&str, &Expr)> = queries
+        .iter()
+        .zip(&exprs)
+        .map(|((name, _), e)| (*name, e))
+        .collect();
+    let cycle = order_recording_rules(&rules).unwrap_err();
+    assert_eq!(cycle.rules, ["a", "b", "c"]);
+    assert_eq!(
+        cycle.to_string(),
+        "dependency cycle between recording rules: a -> b -> c -> a"
+    );
+}
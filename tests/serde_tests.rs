@@ -0,0 +1,65 @@
+//! Tests for the serde implementations of the AST types
+
+#![cfg(feature = "serde")]
+
+use rusty_promql_parser::{Expr, parse};
+
+fn round_trip(query: &str) -> Expr {
+    let ast = parse(query).unwrap();
+    let json = serde_json::to_string(&ast).unwrap();
+    serde_json::from_str(&json).unwrap()
+}
+
+#[test]
+fn test_round_trip() {
+    let queries = [
+        r#"sum by (job) (rate(http_requests_total{code=~"5..", job!="a"}[5m] offset -1h))"#,
+        "topk(3, x @ start()) / on (instance) group_left (a, b) y @ 1609459200",
+        r#"histogram_quantile(0.9, sum without (pod) (rate(x[5m:30s])))"#,
+        "-(a > bool 1) or count_values(\"v\", b)",
+        "max_over_time(up[1h:] @ end())",
+    ];
+    for query in queries {
+        assert_eq!(round_trip(query), parse(query).unwrap(), "{}", query);
+    }
+}
+
+#[test]
+fn test_special_numbers() {
+    let ast = parse("Inf + nan").unwrap();
+    let json = serde_json::to_value(&ast).unwrap();
+    assert_eq!(
+        json["Binary"]["lhs"],
+        serde_json::json!({"Number": {"value": "+Inf", "raw": "Inf"}})
+    );
+    assert_eq!(
+        json["Binary"]["rhs"],
+        serde_json::json!({"Number": {"value": "NaN", "raw": "nan"}})
+    );
+
+    let Expr::Binary(b) = round_trip("Inf + nan") else {
+        panic!("expected a binary expression");
+    };
+    assert_eq!(b.lhs, Expr::Number(f64::INFINITY.into()));
+    assert!(matches!(&b.rhs, Expr::Number(n) if n.value().is_nan()));
+    assert_eq!(b.to_string(), "Inf + nan");
+
+    let neg: Expr = serde_json::from_str(r#"{"Number": {"value": "-Inf"}}"#).unwrap();
+    assert_eq!(neg, Expr::Number(f64::NEG_INFINITY.into()));
+    let int: Expr = serde_json::from_str(r#"{"Number": {"value": 42}}"#).unwrap();
+    assert_eq!(int, Expr::Number(42.0.into()));
+    assert!(serde_json::from_str::<Expr>(r#"{"Number": {"value": "Infinity"}}"#).is_err());
+}
+
+#[test]
+fn test_raw_spelling_must_match_value() {
+    let n: Expr = serde_json::from_str(r#"{"Number": {"value": 1.0, "raw": "2"}}"#).unwrap();
+    assert_eq!(n, Expr::Number(1.0.into()));
+    assert_eq!(n.to_string(), "1");
+
+    let n: Expr = serde_json::from_str(r#"{"Number": {"value": 1.0, "raw": "1 + 1"}}"#).unwrap();
+    assert_eq!(n.to_string(), "1");
+
+    let n: Expr = serde_json::from_str(r#"{"Number": {"value": 31, "raw": "0x1F"}}"#).unwrap();
+    assert_eq!(n.to_string(), "0x1F");
+}
@@ -0,0 +1,145 @@
+//! Stress tests guarding against pathological slowdowns on large inputs
+//!
+//! The bounds are generous so that they hold for unoptimized builds on slow
+//! machines; a quadratic path would exceed them by orders of magnitude.
+
+use std::time::{Duration, Instant};
+
+use rusty_promql_parser::parser::options::DEFAULT_MAX_DEPTH;
+use rusty_promql_parser::parser::partial::parse_partial;
+use rusty_promql_parser::{Expr, ParseOptions, expr, parse_with_options};
+
+const TIME_BOUND: Duration = Duration::from_secs(2);
+
+fn parse_timed(input: &str) -> Expr {
+    let start = Instant::now();
+    let (rest, e) = expr(input).unwrap();
+    let elapsed = start.elapsed();
+    assert!(rest.is_empty(), "unparsed input of {} bytes", rest.len());
+    assert!(
+        elapsed < TIME_BOUND,
+        "parsing {} bytes took {:?}",
+        input.len(),
+        elapsed
+    );
+    e
+}
+
+#[test]
+fn test_many_matchers() {
+    let matchers: Vec<String> = (0..1000)
+        .map(|i| format!(r#"label_{}=~"value_{}.*""#, i, i))
+        .collect();
+    let input = format!("metric{{{}}}", matchers.join(", "));
+    match parse_timed(&input) {
+        Expr::VectorSelector(sel) => assert_eq!(sel.matchers.len(), 1000),
+        other => panic!("expected a vector selector, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_long_metric_name_and_label_value() {
+    let name = "a".repeat(10 * 1024);
+    let value = "v".repeat(10 * 1024);
+    let input = format!(r#"rate({}{{job="{}"}}[5m])"#, name, value);
+    let e = parse_timed(&input);
+    let selectors = e.vector_selectors();
+    assert_eq!(selectors[0].name.as_deref(), Some(name.as_str()));
+    assert_eq!(selectors[0].matchers[0].value, value);
+}
+
+#[test]
+fn test_many_grouping_labels() {
+    let labels: Vec<String> = (0..1000).map(|i| format!("label_{}", i)).collect();
+    let input = format!("sum by ({}) (metric)", labels.join(", "));
+    match parse_timed(&input) {
+        Expr::Aggregation(agg) => assert_eq!(agg.grouping.unwrap().labels.len(), 1000),
+        other => panic!("expected an aggregation, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_long_binary_chain() {
+    let operands: Vec<String> = (0..1000).map(|i| format!("metric_{}", i)).collect();
+    let e = parse_timed(&operands.join(" + "));
+    assert_eq!(e.vector_selectors().len(), 1000);
+}
+
+#[test]
+fn test_deep_nesting_within_default_depth() {
+    let n = DEFAULT_MAX_DEPTH - 1;
+    let cases = [
+        format!("{}up{}", "(".repeat(n), ")".repeat(n)),
+        format!("{}up{}", "abs(".repeat(n), ")".repeat(n)),
+        format!("{}up{}", "sum(".repeat(n), ")".repeat(n)),
+        format!(
+            "{}up{}",
+            "label_replace(".repeat(n),
+            r#", "a", "$1", "b", "(.*)")"#.repeat(n)
+        ),
+    ];
+    for input in cases {
+        assert!(
+            parse_with_options(&input, &ParseOptions::default()).is_ok(),
+            "{}...",
+            &input[..20]
+        );
+    }
+}
+
+#[test]
+fn test_hostile_nesting_rejected() {
+    let n = 100_000;
+    let cases = [
+        format!("{}up{}", "(".repeat(n), ")".repeat(n)),
+        format!("{}up", "-".repeat(n)),
+        format!("up{}", " ^ up".repeat(n)),
+        format!("{}up{}", "abs(".repeat(n), ")".repeat(n)),
+        format!("{}up{}", "sum(".repeat(n), ")".repeat(n)),
+    ];
+    for input in cases {
+        let start = Instant::now();
+        let err = parse_with_options(&input, &ParseOptions::default()).unwrap_err();
+        assert!(start.elapsed() < TIME_BOUND);
+        assert_eq!(
+            err.message,
+            format!(
+                "expression is nested more than {} levels deep",
+                DEFAULT_MAX_DEPTH
+            ),
+            "{}...",
+            &input[..20]
+        );
+    }
+}
+
+#[test]
+fn test_partial_parse_of_hostile_nesting() {
+    let n = 100_000;
+    let cases = [
+        ("(".repeat(n), None),
+        (format!("{}up", "sum(".repeat(n)), None),
+        (format!("{}up", "-".repeat(n)), None),
+        (format!("up + {}", "(".repeat(n)), Some("up")),
+    ];
+    for (input, repaired) in cases {
+        let start = Instant::now();
+        let partial = parse_partial(&input);
+        assert!(start.elapsed() < TIME_BOUND);
+        assert_eq!(
+            partial.expr.map(|e| e.to_string()).as_deref(),
+            repaired,
+            "{}...",
+            &input[..20]
+        );
+        assert_eq!(
+            partial.error.unwrap().message,
+            format!(
+                "expression is nested more than {} levels deep",
+                DEFAULT_MAX_DEPTH
+            ),
+            "{}...",
+            &input[..20]
+        );
+    }
+}
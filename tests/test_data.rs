@@ -21,6 +21,9 @@ pub use integration::*;
 pub use lexer::*;
 pub use parser::*;
 
+// The helper macros in `common` call `$crate::parse`
+pub use rusty_promql_parser::parse;
+
 #[cfg(test)]
 mod smoke_tests {
     use super::*;
@@ -151,6 +154,7 @@ mod smoke_tests {
         total += integration::REAL_WORLD_QUERIES.len();
         total += integration::ALERT_EXPRESSIONS.len();
         total += integration::EDGE_CASES.len();
+        total += integration::WITH_COMMENTS.len();
 
         // We should have a substantial number of test cases
         assert!(
@@ -160,4 +164,11 @@ mod smoke_tests {
         );
         println!("Total test cases extracted: {}", total);
     }
+
+    /// The helper macros resolve `$crate::parse` to the crate's parser
+    #[test]
+    fn test_helper_macros() {
+        crate::assert_roundtrip!("sum by (job) (rate(x[5m]))");
+        crate::assert_parse_error!("up down", "unexpected trailing input");
+    }
 }